@@ -0,0 +1,54 @@
+use std::env;
+use std::fs;
+use std::process;
+
+fn convert(trace: &str) -> Result<(String, String), String> {
+    let mut client = String::new();
+    let mut server = String::new();
+    for (index, line) in trace
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+    {
+        let record: serde_json::Value =
+            serde_json::from_str(line).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        let text = record["line"]
+            .as_str()
+            .ok_or_else(|| format!("line {}: missing \"line\"", index + 1))?;
+        let out = match record["direction"].as_str() {
+            Some("send") => &mut client,
+            Some("recv") => &mut server,
+            other => return Err(format!("line {}: unknown direction {:?}", index + 1, other)),
+        };
+        out.push_str(text);
+        out.push('\n');
+    }
+    Ok((client, server))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (trace, name) = match args.as_slice() {
+        [trace, name] => (trace, name),
+        _ => {
+            eprintln!("usage: trace_fixture TRACE.jsonl tests/fixtures/NAME");
+            eprintln!("TRACE.jsonl should hold a single session written by --trace-file");
+            process::exit(2);
+        }
+    };
+    let result = fs::read_to_string(trace)
+        .map_err(|e| format!("{}: {}", trace, e))
+        .and_then(|trace| convert(&trace))
+        .and_then(|(client, server)| {
+            for (ext, data) in &[("client", client), ("server", server)] {
+                let path = format!("{}.{}", name, ext);
+                fs::write(&path, data).map_err(|e| format!("{}: {}", path, e))?;
+                println!("wrote {}", path);
+            }
+            Ok(())
+        });
+    if let Err(e) = result {
+        eprintln!("trace_fixture: {}", e);
+        process::exit(1);
+    }
+}
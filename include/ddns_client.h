@@ -0,0 +1,44 @@
+#ifndef DDNS_CLIENT_H
+#define DDNS_CLIENT_H
+
+/*
+ * C interface to ddns_client, built with `cargo build-ffi` (the ffi
+ * feature, as a cdylib and staticlib under target/release).
+ */
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define DDNS_OK 0
+#define DDNS_FAILURE 1
+#define DDNS_CONFIG 2
+#define DDNS_SEND_TIMEOUT 5
+#define DDNS_RECV_TIMEOUT 6
+#define DDNS_INTERRUPTED 130
+
+typedef struct ddns_params {
+    const char *user;        /* required */
+    const char *pass;        /* required */
+    const char *host;        /* NULL or "" for the zone apex */
+    const char *dom;         /* required */
+    const char *ip;          /* IPv4 address or ip_source: url:, interface:, dns: */
+    unsigned int timeout_ms; /* connect, TLS and read/write timeout, 0 for the defaults */
+} ddns_params;
+
+typedef struct ddns_result {
+    int code;                /* one of the DDNS_* codes, same as the CLI exit code */
+    char ip[16];             /* the address sent, NUL-terminated, on DDNS_OK */
+    char *error;             /* on failure; release with ddns_string_free */
+} ddns_result;
+
+/* Updates one record; returns result->code. All strings must be UTF-8. */
+int ddns_update(const ddns_params *params, ddns_result *result);
+
+void ddns_string_free(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
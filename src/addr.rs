@@ -0,0 +1,124 @@
+use std::error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::normalize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddrError {
+    Empty,
+    Invalid(String),
+    Zoned(String),
+    Ipv6(Ipv6Addr),
+}
+
+impl fmt::Display for AddrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddrError::Empty => write!(f, "empty address"),
+            AddrError::Invalid(text) => write!(f, "invalid IP address: {:?}", text),
+            AddrError::Zoned(text) => {
+                write!(f, "zone-scoped addresses are not supported: {:?}", text)
+            }
+            AddrError::Ipv6(ip) => write!(f, "{} is an IPv6 address, not IPv4", ip),
+        }
+    }
+}
+
+impl error::Error for AddrError {}
+
+pub fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+pub fn parse_ip(text: &str) -> Result<IpAddr, AddrError> {
+    let bare = normalize::address(text).map_err(|_| AddrError::Invalid(text.trim().to_owned()))?;
+    if bare.is_empty() {
+        return Err(AddrError::Empty);
+    }
+    if bare.contains('%') {
+        return Err(AddrError::Zoned(bare.to_owned()));
+    }
+    bare.parse()
+        .map(unmap)
+        .map_err(|_| AddrError::Invalid(bare.to_owned()))
+}
+
+pub fn parse_ipv4(text: &str) -> Result<Ipv4Addr, AddrError> {
+    match parse_ip(text)? {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(ip) => Err(AddrError::Ipv6(ip)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(text: &str) -> Result<&'static str, AddrError> {
+        Err(AddrError::Invalid(text.to_owned()))
+    }
+
+    fn zoned(text: &str) -> Result<&'static str, AddrError> {
+        Err(AddrError::Zoned(text.to_owned()))
+    }
+
+    #[test]
+    fn table() {
+        let cases = [
+            ("203.0.113.7", Ok("203.0.113.7")),
+            (" 203.0.113.7\n", Ok("203.0.113.7")),
+            ("::ffff:203.0.113.7", Ok("203.0.113.7")),
+            ("::FFFF:CB00:7107", Ok("203.0.113.7")),
+            ("[::ffff:203.0.113.7]", Ok("203.0.113.7")),
+            ("2001:DB8::1", Ok("2001:db8::1")),
+            ("2001:0db8:0000:0000:0000:0000:0000:0001", Ok("2001:db8::1")),
+            ("[2001:db8::1]", Ok("2001:db8::1")),
+            ("::203.0.113.7", Ok("::cb00:7107")),
+            ("", Err(AddrError::Empty)),
+            ("[]", Err(AddrError::Empty)),
+            ("fe80::1%eth0", zoned("fe80::1%eth0")),
+            ("[fe80::1%2]", zoned("fe80::1%2")),
+            ("203.0.113.7.", invalid("203.0.113.7.")),
+            ("203.0.113.256", invalid("203.0.113.256")),
+            ("0203.0.113.7", invalid("0203.0.113.7")),
+            ("2001:db8::1::1", invalid("2001:db8::1::1")),
+            (
+                "203.0.113.7 203.0.113.8",
+                invalid("203.0.113.7 203.0.113.8"),
+            ),
+        ];
+        for (input, expected) in cases {
+            let parsed = parse_ip(input).map(|ip| ip.to_string());
+            assert_eq!(expected.map(str::to_owned), parsed, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn ipv4_only_parsing_rejects_ipv6() {
+        assert_eq!(
+            Ok(Ipv4Addr::new(203, 0, 113, 7)),
+            parse_ipv4("::ffff:203.0.113.7")
+        );
+        let e = parse_ipv4("2001:db8::1").unwrap_err();
+        assert_eq!(AddrError::Ipv6("2001:db8::1".parse().unwrap()), e);
+        assert_eq!("2001:db8::1 is an IPv6 address, not IPv4", e.to_string());
+        assert_eq!(
+            "zone-scoped addresses are not supported: \"fe80::1%eth0\"",
+            parse_ipv4("fe80::1%eth0").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn unmap_leaves_other_addresses_alone() {
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        assert_eq!(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), unmap(mapped));
+        for text in ["192.0.2.1", "2001:db8::1", "::1", "::192.0.2.1"] {
+            let ip: IpAddr = text.parse().unwrap();
+            assert_eq!(ip, unmap(ip), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    next: AtomicU64,
+    sockets: Mutex<Vec<(u64, TcpStream)>>,
+    wake: Condvar,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        let mut sockets = self.inner.sockets.lock().unwrap();
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        for (_, socket) in sockets.drain(..) {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+        self.inner.wake.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        let mut sockets = self.inner.sockets.lock().unwrap();
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if deadline <= now {
+                break;
+            }
+            sockets = self
+                .inner
+                .wake
+                .wait_timeout(sockets, deadline - now)
+                .unwrap()
+                .0;
+        }
+        self.is_cancelled()
+    }
+
+    pub fn register(&self, socket: &TcpStream) -> Registration {
+        let id = self.inner.next.fetch_add(1, Ordering::SeqCst);
+        let mut sockets = self.inner.sockets.lock().unwrap();
+        if self.is_cancelled() {
+            let _ = socket.shutdown(Shutdown::Both);
+        } else if let Ok(socket) = socket.try_clone() {
+            sockets.push((id, socket));
+        }
+        Registration {
+            token: self.clone(),
+            id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Registration {
+    token: CancelToken,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut sockets = self.token.inner.sockets.lock().unwrap();
+        sockets.retain(|(id, _)| self.id != *id);
+    }
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process;
+use std::sync::Mutex;
+
+struct Held {
+    fd: RawFd,
+    saved: RawFd,
+    file: File,
+}
+
+static HELD: Mutex<Vec<Held>> = Mutex::new(Vec::new());
+
+fn flush() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+fn spool(fd: RawFd) -> io::Result<File> {
+    let path = env::temp_dir().join(format!("ddns_client.{}.{}.out", process::id(), fd));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    Ok(file)
+}
+
+pub fn begin() -> io::Result<()> {
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    if !held.is_empty() {
+        return Ok(());
+    }
+    flush();
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        let file = spool(fd)?;
+        let saved = unsafe { libc::dup(fd) };
+        if saved < 0 || unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+            let e = io::Error::last_os_error();
+            drop(held);
+            release();
+            return Err(e);
+        }
+        held.push(Held { fd, saved, file });
+    }
+    Ok(())
+}
+
+fn finish(replay: bool) {
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    if held.is_empty() {
+        return;
+    }
+    flush();
+    for mut stream in held.drain(..) {
+        unsafe {
+            libc::dup2(stream.saved, stream.fd);
+            libc::close(stream.saved);
+        }
+        if !replay || stream.file.seek(SeekFrom::Start(0)).is_err() {
+            continue;
+        }
+        let _ = match stream.fd {
+            libc::STDOUT_FILENO => io::copy(&mut stream.file, &mut io::stdout()),
+            _ => io::copy(&mut stream.file, &mut io::stderr()),
+        };
+    }
+    flush();
+}
+
+pub fn release() {
+    finish(true)
+}
+
+pub fn discard() {
+    finish(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn spool_is_private_and_unlinked() {
+        let file = spool(999).unwrap();
+        let mode = file.metadata().unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        let path = env::temp_dir().join(format!("ddns_client.{}.999.out", process::id()));
+        assert!(!path.exists());
+    }
+}
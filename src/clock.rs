@@ -0,0 +1,86 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cancel::CancelToken;
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+
+    fn instant(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration);
+
+    fn sleep_cancellable(&self, duration: Duration, cancel: &CancelToken) -> bool {
+        self.sleep(duration);
+        cancel.is_cancelled()
+    }
+
+    fn unix_now(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn sleep_cancellable(&self, duration: Duration, cancel: &CancelToken) -> bool {
+        cancel.sleep(duration)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug)]
+pub struct MockClock {
+    wall: SystemTime,
+    start: Instant,
+    elapsed: std::sync::Mutex<Duration>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MockClock {
+    pub fn new(unix: u64) -> MockClock {
+        MockClock {
+            wall: UNIX_EPOCH + Duration::from_secs(unix),
+            start: Instant::now(),
+            elapsed: std::sync::Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.wall + self.elapsed()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
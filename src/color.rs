@@ -0,0 +1,59 @@
+use std::env;
+use std::ffi::OsString;
+
+use crate::dice::{Direction, Observer};
+use crate::messages;
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+
+pub fn should_color_with<F>(is_tty: bool, no_color: bool, var: F) -> bool
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    if no_color || var("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        return false;
+    }
+    if var("CLICOLOR_FORCE").is_some_and(|v| !v.is_empty() && "0" != v) {
+        return true;
+    }
+    is_tty
+}
+
+pub fn should_color(no_color: bool) -> bool {
+    let is_tty = unsafe { 0 != libc::isatty(libc::STDERR_FILENO) };
+    should_color_with(is_tty, no_color, |name| env::var_os(name))
+}
+
+fn is_error_status(line: &str) -> bool {
+    let code = line.split(' ').next().unwrap_or_default();
+    !code.is_empty() && code.bytes().all(|b| b.is_ascii_digit()) && code.bytes().any(|b| b'0' != b)
+}
+
+pub fn paint(dir: Direction, line: &str, status: bool) -> String {
+    let (prefix, color) = match dir {
+        Direction::Send => (">> ", CYAN),
+        Direction::Recv => ("<< ", GREEN),
+    };
+    let style = if "." == line {
+        DIM
+    } else if Direction::Recv == dir && status && is_error_status(line) {
+        RED
+    } else {
+        color
+    };
+    format!("{}{}{}{}", style, prefix, line, RESET)
+}
+
+pub fn color_observer() -> Observer {
+    let mut status = true;
+    Box::new(move |dir, line| {
+        eprintln!("{}", paint(dir, &messages::sanitize(line), status));
+        if Direction::Recv == dir {
+            status = "." == line;
+        }
+    })
+}
@@ -0,0 +1,2756 @@
+use std::convert::TryFrom;
+use std::env;
+use std::error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+
+use crate::addr;
+use crate::detect::{self, IpSource};
+use crate::dice::{self, GreetingPolicy, Information, Pacing, Timeouts, ToIpAddrs};
+use crate::input::{InputError, InputSource, InputWarning, PartialInfo};
+use crate::messages;
+use crate::normalize;
+use crate::order::{self, Node};
+use crate::pool;
+use crate::retry::RetryPolicy;
+use crate::schedule::{Skip, Window};
+use crate::secret;
+use crate::socket::{self, Family, LocalBind};
+use crate::stats;
+use crate::suffix;
+use crate::tls::{Connector, TlsOptions, TlsVersion};
+use crate::KEY;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+    },
+    Env(String),
+    Cli,
+    Fd(i32),
+    Stdin,
+    Prompt,
+    Include {
+        target: String,
+        via: Box<Source>,
+    },
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File { path, line, column } => {
+                write!(f, "file:{}:{}:{}", path.display(), line, column)
+            }
+            Source::Env(var) => write!(f, "env:{}", var),
+            Source::Cli => write!(f, "cli"),
+            Source::Fd(fd) => write!(f, "fd:{}", fd),
+            Source::Stdin => write!(f, "stdin"),
+            Source::Prompt => write!(f, "prompt"),
+            Source::Include { target, via } => write!(f, "{} via {}", target, via),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    pub fn new(value: T, source: Source) -> Setting<T> {
+        Setting { value, source }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub origin: String,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(source: &Source, key: &str, message: impl Into<String>) -> Diagnostic {
+        let origin = match source {
+            Source::File { path, line, column } => {
+                format!("{}:{}:{}", path.display(), line, column)
+            }
+            other => other.to_string(),
+        };
+        Diagnostic {
+            origin,
+            key: Some(key.to_owned()),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.origin)?;
+        if let Some(key) = &self.key {
+            write!(f, ": {}", key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+fn location(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Invalid(Vec<Diagnostic>),
+    Missing(Vec<KEY>),
+    Unprompted(Vec<(KEY, Vec<String>)>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Invalid(diagnostics) => {
+                let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            ConfigError::Missing(keys) => {
+                let names: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+                write!(f, "missing required keys: {}", names.join(", "))
+            }
+            ConfigError::Unprompted(keys) => write!(f, "{}", messages::unprompted(keys)),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub fn key_name(key: &KEY) -> &'static str {
+    match key {
+        KEY::USER => "user",
+        KEY::PASS => "pass",
+        KEY::HOST => "host",
+        KEY::DOM => "dom",
+    }
+}
+
+pub fn env_name(key: &KEY) -> &'static str {
+    match key {
+        KEY::USER => "DDNS_CLIENT_USER",
+        KEY::PASS => "DDNS_CLIENT_PASS",
+        KEY::HOST => "DDNS_CLIENT_HOST",
+        KEY::DOM => "DDNS_CLIENT_DOM",
+    }
+}
+
+pub static ENV_IP: &str = "DDNS_CLIENT_IP";
+pub static INCLUDE_FILE: &str = "@file:";
+pub static INCLUDE_ENV: &str = "@env:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordConfig {
+    pub host: Setting<String>,
+    pub dom: Option<Setting<String>>,
+    pub ip: Option<Setting<IpSource>>,
+    pub extra_fields: Option<Setting<Vec<String>>>,
+    pub offline_ip: Option<Setting<Ipv4Addr>>,
+    pub enabled: Option<Setting<bool>>,
+    pub dry_run: Option<Setting<bool>>,
+    pub window: Option<Setting<Window>>,
+    pub timeout: Option<Setting<u64>>,
+    pub connect_timeout: Option<Setting<u64>>,
+    pub retries: Option<Setting<u32>>,
+    pub priority: Option<Setting<i64>>,
+    pub depends_on: Option<Setting<Vec<String>>>,
+}
+
+impl RecordConfig {
+    pub fn names(&self, dom: &str) -> Vec<String> {
+        self.host
+            .value
+            .split(',')
+            .map(|name| name_in(normalize::name(name).unwrap_or(name), dom))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProviderConfig {
+    pub timeout: Option<Setting<u64>>,
+    pub connect_timeout: Option<Setting<u64>>,
+    pub retries: Option<Setting<u32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolverSpec {
+    System,
+    Udp(Option<IpAddr>),
+}
+
+impl Default for ResolverSpec {
+    fn default() -> ResolverSpec {
+        ResolverSpec::Udp(None)
+    }
+}
+
+impl fmt::Display for ResolverSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolverSpec::System => write!(f, "system"),
+            ResolverSpec::Udp(Some(ip)) => write!(f, "udp:{}", ip),
+            ResolverSpec::Udp(None) => write!(f, "udp"),
+        }
+    }
+}
+
+impl FromStr for ResolverSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ResolverSpec, String> {
+        let text = s.trim();
+        match text.split_once(':') {
+            Some((kind, server)) if kind.eq_ignore_ascii_case("udp") => addr::parse_ip(server)
+                .map(|ip| ResolverSpec::Udp(Some(ip)))
+                .map_err(|e| e.to_string()),
+            _ if text.eq_ignore_ascii_case("system") => Ok(ResolverSpec::System),
+            _ if text.eq_ignore_ascii_case("udp") => Ok(ResolverSpec::Udp(None)),
+            _ => Err(format!(
+                "unsupported resolver {:?}: expected system, udp or udp:IP",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StateMode {
+    #[default]
+    File,
+    None,
+}
+
+impl fmt::Display for StateMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateMode::File => write!(f, "file"),
+            StateMode::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for StateMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StateMode, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "file" => Ok(StateMode::File),
+            "none" => Ok(StateMode::None),
+            _ => Err(format!(
+                "unsupported state mode {:?}: expected file or none",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Transport {
+    pub timeouts: Timeouts,
+    pub retry: RetryPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub info: Information,
+    pub ip: Setting<IpSource>,
+    pub offline_ip: Ipv4Addr,
+    pub enabled: bool,
+    pub dry_run: Option<bool>,
+    pub window: Option<Window>,
+    pub transport: Transport,
+    pub priority: i64,
+    pub depends_on: Vec<String>,
+}
+
+impl Record {
+    pub fn node(&self) -> Node<'_> {
+        Node {
+            name: self.info.name(),
+            priority: self.priority,
+            depends_on: &self.depends_on,
+        }
+    }
+
+    pub fn matches(&self, pattern: &str) -> bool {
+        let pattern = normalize::name(pattern).unwrap_or(pattern);
+        let host = if self.info.host.is_empty() {
+            "@"
+        } else {
+            &self.info.host
+        };
+        glob(pattern, &self.info.name()) || glob(pattern, host)
+    }
+
+    pub fn rehearsed(&self, forced: bool, default: bool) -> bool {
+        forced || self.dry_run.unwrap_or(default)
+    }
+
+    pub fn skip(&self, now: u64) -> Option<Skip> {
+        if !self.enabled {
+            return Some(Skip::Disabled);
+        }
+        match self.window {
+            Some(window) if !window.contains_at(now) => Some(Skip::OutsideWindow),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub user: Option<Setting<String>>,
+    pub pass: Option<Setting<String>>,
+    pub host: Option<Setting<String>>,
+    pub dom: Option<Setting<String>>,
+    pub ip: Option<Setting<IpSource>>,
+    pub offline_ip: Option<Setting<Ipv4Addr>>,
+    pub records: Vec<RecordConfig>,
+    pub pass_encrypted: Option<Setting<String>>,
+    pub pass_keyfile: Option<Setting<PathBuf>>,
+    pub state: Option<Setting<StateMode>>,
+    pub state_file: Option<Setting<PathBuf>>,
+    pub status_file: Option<Setting<PathBuf>>,
+    pub auth_failure_limit: Option<Setting<u32>>,
+    pub auth_cooldown: Option<Setting<u64>>,
+    pub max_age: Option<Setting<u64>>,
+    pub bind_address: Option<Setting<IpAddr>>,
+    pub local_port_range: Option<Setting<(u16, u16)>>,
+    pub connect_family: Option<Setting<Family>>,
+    pub tls_min_version: Option<Setting<TlsVersion>>,
+    pub tls_ciphers: Option<Setting<String>>,
+    pub ca_file: Option<Setting<PathBuf>>,
+    pub retries: Option<Setting<u32>>,
+    pub retry_delay: Option<Setting<u64>>,
+    pub check_dns_first: Option<Setting<bool>>,
+    pub dry_run: Option<Setting<bool>>,
+    pub nameserver: Option<Setting<IpAddr>>,
+    pub resolver: Option<Setting<ResolverSpec>>,
+    pub check_ownership: Option<Setting<bool>>,
+    pub strict_ownership: Option<Setting<bool>>,
+    pub strict_ip_validation: Option<Setting<bool>>,
+    pub tolerate_ack_mismatch: Option<Setting<bool>>,
+    pub ownership_suffixes: Option<Setting<Vec<String>>>,
+    pub extra_fields: Option<Setting<Vec<String>>>,
+    pub greeting: Option<Setting<GreetingPolicy>>,
+    pub pool_size: Option<Setting<usize>>,
+    pub pool_idle: Option<Setting<u64>>,
+    pub send_chunk_lines: Option<Setting<usize>>,
+    pub send_pacing_ms: Option<Setting<u64>>,
+    pub latency_window: Option<Setting<usize>>,
+    pub detect_penalty: Option<Setting<u64>>,
+    pub latency_warn_ms: Option<Setting<u64>>,
+    pub max_bytes_per_day: Option<Setting<u64>>,
+    pub max_line_length: Option<Setting<usize>>,
+    pub replace_records: Option<Setting<bool>>,
+    pub timeout: Option<Setting<u64>>,
+    pub connect_timeout: Option<Setting<u64>>,
+    pub onamae: ProviderConfig,
+}
+
+pub static PROVIDER: &str = "onamae";
+pub static MAX_TIMEOUT: u64 = 24 * 60 * 60;
+pub static DROP_IN_DIR: &str = "config.d";
+pub static DEFAULT_AUTH_FAILURE_LIMIT: u32 = 3;
+pub static DEFAULT_AUTH_COOLDOWN: u64 = 3600;
+pub static DEFAULT_OWNERSHIP_SUFFIXES: [&str; 2] = ["onamae.com", "dnsv.jp"];
+
+fn expect_str<'a>(value: &'a DeValue) -> Result<&'a str, String> {
+    match value {
+        DeValue::String(s) => Ok(s),
+        other => Err(format!("expected a string, found {}", other.type_str())),
+    }
+}
+
+fn expect_bool(value: &DeValue) -> Result<bool, String> {
+    match value {
+        DeValue::Boolean(b) => Ok(*b),
+        other => Err(format!("expected a boolean, found {}", other.type_str())),
+    }
+}
+
+fn expect_int<T: TryFrom<i64>>(value: &DeValue) -> Result<T, String> {
+    let int = match value {
+        DeValue::Integer(int) => int,
+        other => return Err(format!("expected an integer, found {}", other.type_str())),
+    };
+    i64::from_str_radix(&int.as_str().replace('_', ""), int.radix())
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("integer out of range: {}", int))
+}
+
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration {:?}: expected a unit of s, m, h, d or w",
+                value
+            ))
+        }
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("invalid duration: {:?}", value))
+}
+
+fn expect_duration(value: &DeValue) -> Result<u64, String> {
+    match value {
+        DeValue::Integer(_) => expect_int(value),
+        other => expect_str(other).and_then(parse_duration),
+    }
+}
+
+fn expect_timeout(value: &DeValue) -> Result<u64, String> {
+    match expect_duration(value)? {
+        n if MAX_TIMEOUT < n => Err(format!("must be at most {} seconds", MAX_TIMEOUT)),
+        n => Ok(n),
+    }
+}
+
+fn resolve_timeout(setting: Option<&Setting<u64>>, default: Option<Duration>) -> Option<Duration> {
+    match setting.map(|setting| setting.value) {
+        Some(0) => None,
+        Some(n) => Some(Duration::from_secs(n)),
+        None => default,
+    }
+}
+
+fn parse_ip(value: &str) -> Result<IpSource, String> {
+    value
+        .to_ip_addrs()
+        .map(IpSource::Static)
+        .map_err(|e| e.to_string())
+}
+
+fn expect_ip_source(value: &DeValue) -> Result<IpSource, String> {
+    let table = match value {
+        DeValue::Table(table) => table,
+        DeValue::Array(items) => {
+            let mut sources = Vec::new();
+            for item in items {
+                match item.get_ref() {
+                    DeValue::Array(_) => return Err("ip_source lists cannot nest".to_owned()),
+                    item => sources.push(expect_ip_source(item)?),
+                }
+            }
+            return match sources.len() {
+                0 => Err("ip_source list is empty".to_owned()),
+                1 => Ok(sources.remove(0)),
+                _ => Ok(IpSource::Fallback(sources)),
+            };
+        }
+        other => return expect_str(other).and_then(str::parse),
+    };
+    let mut argv = None;
+    let mut clear_env = false;
+    for (name, item) in table.iter() {
+        let item = item.get_ref();
+        match name.get_ref().as_ref() {
+            "cmd" => argv = Some(expect_strings(item)?),
+            "clear_env" => clear_env = expect_bool(item)?,
+            other => return Err(format!("unknown ip_source key {:?}", other)),
+        }
+    }
+    match argv {
+        Some(argv) if argv.first().is_some_and(|program| !program.is_empty()) => {
+            Ok(IpSource::Command { argv, clear_env })
+        }
+        _ => Err("ip_source table needs cmd = [\"PROGRAM\", ...]".to_owned()),
+    }
+}
+
+fn expect_offline_ip(value: &DeValue) -> Result<Ipv4Addr, String> {
+    expect_str(value).and_then(|text| addr::parse_ipv4(text).map_err(|e| e.to_string()))
+}
+
+fn file_source(text: &str, path: &Path, offset: usize) -> Source {
+    let (line, column) = location(text, offset);
+    Source::File {
+        path: path.to_owned(),
+        line,
+        column,
+    }
+}
+
+struct Include {
+    value: String,
+    target: String,
+    warning: Option<String>,
+}
+
+fn include(text: &str) -> Result<Option<Include>, String> {
+    let (value, target, warning) = if let Some(path) = text.strip_prefix(INCLUDE_FILE) {
+        let mut value = fs::read_to_string(path).map_err(|e| messages::include_failed(text, e))?;
+        if value.ends_with('\n') {
+            value.pop();
+            if value.ends_with('\r') {
+                value.pop();
+            }
+        }
+        let warning = fs::metadata(path)
+            .map(|metadata| metadata.permissions().mode() & 0o777)
+            .ok()
+            .filter(|mode| 0 != mode & 0o077)
+            .map(|mode| messages::include_permissions(path, mode));
+        (value, format!("file:{}", path), warning)
+    } else if let Some(var) = text.strip_prefix(INCLUDE_ENV) {
+        let value = env::var(var).map_err(|e| messages::include_failed(text, e))?;
+        (value, format!("env:{}", var), None)
+    } else {
+        return Ok(None);
+    };
+    if value.starts_with(INCLUDE_FILE) || value.starts_with(INCLUDE_ENV) {
+        return Err(messages::include_recursive(text));
+    }
+    Ok(Some(Include {
+        value,
+        target,
+        warning,
+    }))
+}
+
+fn resolve_includes(
+    key: &str,
+    value: &mut Spanned<DeValue>,
+    locate: &dyn Fn(usize) -> Source,
+    includes: &mut Vec<(usize, String)>,
+    diagnostics: &mut Vec<Diagnostic>,
+    warnings: &mut Vec<Diagnostic>,
+) {
+    let offset = value.span().start;
+    match value.get_mut() {
+        DeValue::String(text) => match include(text) {
+            Ok(Some(resolved)) => {
+                if let Some(warning) = resolved.warning {
+                    warnings.push(Diagnostic::new(&locate(offset), key, warning));
+                }
+                *text = resolved.value.into();
+                includes.push((offset, resolved.target));
+            }
+            Ok(None) => {}
+            Err(message) => diagnostics.push(Diagnostic::new(&locate(offset), key, message)),
+        },
+        DeValue::Array(items) => {
+            for item in items.iter_mut() {
+                resolve_includes(key, item, locate, includes, diagnostics, warnings);
+            }
+        }
+        DeValue::Table(table) => {
+            for (name, item) in table.iter_mut() {
+                resolve_includes(
+                    name.get_ref(),
+                    item,
+                    locate,
+                    includes,
+                    diagnostics,
+                    warnings,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(object) => {
+            object.retain(|_, value| !value.is_null());
+            object.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+fn parse_provider_table(
+    table: &DeTable,
+    locate: &dyn Fn(usize) -> Source,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> ProviderConfig {
+    let mut provider = ProviderConfig::default();
+    for (name, value) in table.iter() {
+        let name: &str = name.get_ref();
+        let source = locate(value.span().start);
+        let value = value.get_ref();
+        let result = match name {
+            "timeout" => expect_timeout(value)
+                .map(|n| provider.timeout = Some(Setting::new(n, source.clone()))),
+            "connect_timeout" => expect_timeout(value)
+                .map(|n| provider.connect_timeout = Some(Setting::new(n, source.clone()))),
+            "retries" => {
+                expect_int(value).map(|n| provider.retries = Some(Setting::new(n, source.clone())))
+            }
+            _ => Err("unknown key".to_owned()),
+        };
+        if let Err(message) = result {
+            let key = format!("{}.{}", PROVIDER, name);
+            diagnostics.push(Diagnostic::new(&source, &key, message));
+        }
+    }
+    provider
+}
+
+fn parse_record_table(
+    table: &DeTable,
+    at: Source,
+    index: usize,
+    locate: &dyn Fn(usize) -> Source,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<RecordConfig> {
+    let mut host = None;
+    let mut dom = None;
+    let mut ip: Option<Setting<IpSource>> = None;
+    let mut extra_fields = None;
+    let mut offline_ip = None;
+    let mut enabled = None;
+    let mut dry_run = None;
+    let mut window = None;
+    let mut timeout = None;
+    let mut connect_timeout = None;
+    let mut retries = None;
+    let mut priority = None;
+    let mut depends_on = None;
+    let mut record = None;
+    let mut ok = true;
+    for (name, value) in table.iter() {
+        let name: &str = name.get_ref();
+        let source = locate(value.span().start);
+        let value = value.get_ref();
+        let exclusive = || Err("ip and ip_source are mutually exclusive".to_owned());
+        let result = match name {
+            "host" => {
+                expect_hosts(value).map(|text| host = Some(Setting::new(text, source.clone())))
+            }
+            "dom" => expect_str(value)
+                .map(|text| dom = Some(Setting::new(text.to_owned(), source.clone()))),
+            "record" => expect_str(value).map(|text| record = Some((text, source.clone()))),
+            "extra_fields" => expect_fields(value)
+                .map(|fields| extra_fields = Some(Setting::new(fields, source.clone()))),
+            "offline_ip" => expect_offline_ip(value)
+                .map(|ip| offline_ip = Some(Setting::new(ip, source.clone()))),
+            "enabled" => {
+                expect_bool(value).map(|flag| enabled = Some(Setting::new(flag, source.clone())))
+            }
+            "dry_run" => {
+                expect_bool(value).map(|flag| dry_run = Some(Setting::new(flag, source.clone())))
+            }
+            "window" => expect_str(value)
+                .and_then(str::parse)
+                .map(|parsed| window = Some(Setting::new(parsed, source.clone()))),
+            "timeout" => {
+                expect_timeout(value).map(|n| timeout = Some(Setting::new(n, source.clone())))
+            }
+            "connect_timeout" => expect_timeout(value)
+                .map(|n| connect_timeout = Some(Setting::new(n, source.clone()))),
+            "retries" => expect_int(value).map(|n| retries = Some(Setting::new(n, source.clone()))),
+            "priority" => {
+                expect_int(value).map(|n| priority = Some(Setting::new(n, source.clone())))
+            }
+            "depends_on" => expect_strings(value)
+                .map(|names| depends_on = Some(Setting::new(names, source.clone()))),
+            "ip6" | "ip6_source" => Err(messages::ipv6_unsupported()),
+            "ip" | "ip_source" if ip.is_some() => exclusive(),
+            "ip" => expect_str(value)
+                .and_then(parse_ip)
+                .map(|parsed| ip = Some(Setting::new(parsed, source.clone()))),
+            "ip_source" => expect_ip_source(value)
+                .map(|parsed| ip = Some(Setting::new(parsed, source.clone()))),
+            _ => Err("unknown key".to_owned()),
+        };
+        if let Err(message) = result {
+            let key = format!("record[{}].{}", index, name);
+            diagnostics.push(Diagnostic::new(&source, &key, message));
+            ok = false;
+        }
+    }
+    if let Some((text, source)) = record {
+        let key = format!("record[{}].record", index);
+        if host.is_some() {
+            let message = "record and host are mutually exclusive";
+            diagnostics.push(Diagnostic::new(&source, &key, message));
+            return None;
+        }
+        let boundary = dom.as_ref().map(|dom: &Setting<String>| dom.value.clone());
+        match split_record(text, boundary.as_deref()) {
+            Ok((name, parent)) => {
+                host = Some(Setting::new(name, source.clone()));
+                dom = Some(Setting::new(parent, source));
+            }
+            Err(message) => {
+                diagnostics.push(Diagnostic::new(&source, &key, message));
+                return None;
+            }
+        }
+    }
+    match host {
+        Some(host) if ok => Some(RecordConfig {
+            host,
+            dom,
+            ip,
+            extra_fields,
+            offline_ip,
+            enabled,
+            dry_run,
+            window,
+            timeout,
+            connect_timeout,
+            retries,
+            priority,
+            depends_on,
+        }),
+        Some(_) => None,
+        None => {
+            let key = format!("record[{}]", index);
+            diagnostics.push(Diagnostic::new(&at, &key, "host or record is required"));
+            None
+        }
+    }
+}
+
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<u8> = pattern.bytes().map(|b| b.to_ascii_lowercase()).collect();
+    let text: Vec<u8> = text.bytes().map(|b| b.to_ascii_lowercase()).collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == text[t] || b'?' == c => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((at, from)) => {
+                    star = Some((at, from + 1));
+                    p = at + 1;
+                    t = from + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| b'*' == c)
+}
+
+fn name_in(host: &str, dom: &str) -> String {
+    match host {
+        "" | "@" => dom.to_owned(),
+        host => format!("{}.{}", host, dom),
+    }
+}
+
+pub fn valid_host(host: &str) -> bool {
+    let rest = match host {
+        "" | "*" => return true,
+        _ => host.strip_prefix("*.").unwrap_or(host),
+    };
+    valid_hostname(rest)
+}
+
+pub fn split_record(record: &str, dom: Option<&str>) -> Result<(String, String), String> {
+    let record = normalize::name(record)?;
+    if let Some(dom) = dom {
+        let dom = normalize::name(dom)?;
+        if record.eq_ignore_ascii_case(dom) {
+            return Ok((String::new(), dom.to_owned()));
+        }
+        return match record.len().checked_sub(dom.len() + 1) {
+            Some(end)
+                if record[end + 1..].eq_ignore_ascii_case(dom)
+                    && b'.' == record.as_bytes()[end] =>
+            {
+                Ok((record[..end].to_owned(), dom.to_owned()))
+            }
+            _ => Err(format!("{:?} is not inside the domain {:?}", record, dom)),
+        };
+    }
+    suffix::split_record(record)
+}
+
+fn expect_fields(value: &DeValue) -> Result<Vec<String>, String> {
+    let items = match value {
+        DeValue::Array(items) => items,
+        other => return Err(format!("expected an array, found {}", other.type_str())),
+    };
+    let mut fields = Vec::new();
+    for item in items.iter() {
+        let field = expect_str(item.get_ref())?;
+        dice::check_extra_field(field)?;
+        fields.push(field.to_owned());
+    }
+    Ok(fields)
+}
+
+fn expect_strings(value: &DeValue) -> Result<Vec<String>, String> {
+    let items = match value {
+        DeValue::Array(items) => items,
+        other => return Err(format!("expected an array, found {}", other.type_str())),
+    };
+    items
+        .iter()
+        .map(|item| expect_str(item.get_ref()).map(str::to_owned))
+        .collect()
+}
+
+fn expect_hosts(value: &DeValue) -> Result<String, String> {
+    let items = match value {
+        DeValue::Array(items) => items,
+        other => return expect_str(other).map(str::to_owned),
+    };
+    let mut hosts = Vec::new();
+    for item in items.iter() {
+        let host = expect_str(item.get_ref())?;
+        if host.contains(',') {
+            return Err(format!("host names cannot contain commas: {:?}", host));
+        }
+        hosts.push(host);
+    }
+    Ok(hosts.join(","))
+}
+
+fn validate_hosts(host: &Setting<String>, key: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let list = host.value.contains(',');
+    for name in host.value.split(',') {
+        let message = match normalize::name(name) {
+            Ok("") if list => "empty host name in list; use @ for the apex".to_owned(),
+            Ok(name) if "@" != name && !valid_host(name) => {
+                format!("invalid host name: {:?}", name)
+            }
+            Ok(_) => continue,
+            Err(message) => message,
+        };
+        diagnostics.push(Diagnostic::new(&host.source, key, message));
+    }
+}
+
+fn validate_dom(dom: &Setting<String>, key: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let message = match normalize::name(&dom.value) {
+        Ok(name) if valid_hostname(name) => return,
+        Ok(name) => format!("invalid domain name: {:?}", name),
+        Err(message) => message,
+    };
+    diagnostics.push(Diagnostic::new(&dom.source, key, message));
+}
+
+pub fn valid_hostname(name: &str) -> bool {
+    name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b'-' == b)
+        })
+}
+
+impl Config {
+    pub fn get(&self, key: &KEY) -> Option<&Setting<String>> {
+        match key {
+            KEY::USER => self.user.as_ref(),
+            KEY::PASS => self.pass.as_ref(),
+            KEY::HOST => self.host.as_ref(),
+            KEY::DOM => self.dom.as_ref(),
+        }
+    }
+
+    pub fn set(&mut self, key: &KEY, value: impl Into<String>, source: Source) {
+        let value = value.into();
+        let slot = match key {
+            KEY::USER => &mut self.user,
+            KEY::PASS => &mut self.pass,
+            KEY::HOST => &mut self.host,
+            KEY::DOM => &mut self.dom,
+        };
+        *slot = Some(Setting::new(value, source));
+    }
+
+    pub fn set_record(&mut self, record: &str, source: Source) -> Result<(), Diagnostic> {
+        if self.host.is_some() {
+            let message = "record and host are mutually exclusive";
+            return Err(Diagnostic::new(&source, "record", message));
+        }
+        let dom = self.dom.as_ref().map(|dom| dom.value.clone());
+        match split_record(record, dom.as_deref()) {
+            Ok((host, dom)) => {
+                self.set(&KEY::HOST, host, source.clone());
+                self.set(&KEY::DOM, dom, source);
+                Ok(())
+            }
+            Err(message) => Err(Diagnostic::new(&source, "record", message)),
+        }
+    }
+
+    pub fn merge(&mut self, other: Config) {
+        fn pick<T>(slot: &mut Option<T>, value: Option<T>) {
+            if value.is_some() {
+                *slot = value;
+            }
+        }
+        pick(&mut self.user, other.user);
+        pick(&mut self.pass, other.pass);
+        pick(&mut self.host, other.host);
+        pick(&mut self.dom, other.dom);
+        pick(&mut self.ip, other.ip);
+        pick(&mut self.offline_ip, other.offline_ip);
+        if !other.records.is_empty() {
+            self.records = other.records;
+        }
+        pick(&mut self.pass_encrypted, other.pass_encrypted);
+        pick(&mut self.pass_keyfile, other.pass_keyfile);
+        pick(&mut self.state, other.state);
+        pick(&mut self.state_file, other.state_file);
+        pick(&mut self.status_file, other.status_file);
+        pick(&mut self.auth_failure_limit, other.auth_failure_limit);
+        pick(&mut self.auth_cooldown, other.auth_cooldown);
+        pick(&mut self.max_age, other.max_age);
+        pick(&mut self.bind_address, other.bind_address);
+        pick(&mut self.local_port_range, other.local_port_range);
+        pick(&mut self.connect_family, other.connect_family);
+        pick(&mut self.tls_min_version, other.tls_min_version);
+        pick(&mut self.tls_ciphers, other.tls_ciphers);
+        pick(&mut self.ca_file, other.ca_file);
+        pick(&mut self.retries, other.retries);
+        pick(&mut self.retry_delay, other.retry_delay);
+        pick(&mut self.check_dns_first, other.check_dns_first);
+        pick(&mut self.dry_run, other.dry_run);
+        pick(&mut self.nameserver, other.nameserver);
+        pick(&mut self.resolver, other.resolver);
+        pick(&mut self.check_ownership, other.check_ownership);
+        pick(&mut self.strict_ownership, other.strict_ownership);
+        pick(&mut self.strict_ip_validation, other.strict_ip_validation);
+        pick(&mut self.tolerate_ack_mismatch, other.tolerate_ack_mismatch);
+        pick(&mut self.ownership_suffixes, other.ownership_suffixes);
+        pick(&mut self.extra_fields, other.extra_fields);
+        pick(&mut self.greeting, other.greeting);
+        pick(&mut self.pool_size, other.pool_size);
+        pick(&mut self.pool_idle, other.pool_idle);
+        pick(&mut self.send_chunk_lines, other.send_chunk_lines);
+        pick(&mut self.send_pacing_ms, other.send_pacing_ms);
+        pick(&mut self.latency_window, other.latency_window);
+        pick(&mut self.detect_penalty, other.detect_penalty);
+        pick(&mut self.latency_warn_ms, other.latency_warn_ms);
+        pick(&mut self.max_bytes_per_day, other.max_bytes_per_day);
+        pick(&mut self.max_line_length, other.max_line_length);
+        pick(&mut self.replace_records, other.replace_records);
+        pick(&mut self.timeout, other.timeout);
+        pick(&mut self.connect_timeout, other.connect_timeout);
+        pick(&mut self.onamae.timeout, other.onamae.timeout);
+        pick(
+            &mut self.onamae.connect_timeout,
+            other.onamae.connect_timeout,
+        );
+        pick(&mut self.onamae.retries, other.onamae.retries);
+    }
+
+    pub fn stack(&mut self, mut other: Config) {
+        let replace = other.replace_records.as_ref().is_some_and(|s| s.value);
+        let mut records = std::mem::take(&mut other.records);
+        if !replace {
+            records.splice(0..0, std::mem::take(&mut self.records));
+        }
+        self.merge(other);
+        self.records = records;
+    }
+
+    pub fn auth_failure_limit(&self) -> u32 {
+        self.auth_failure_limit
+            .as_ref()
+            .map_or(DEFAULT_AUTH_FAILURE_LIMIT, |setting| setting.value)
+    }
+
+    pub fn auth_cooldown(&self) -> u64 {
+        self.auth_cooldown
+            .as_ref()
+            .map_or(DEFAULT_AUTH_COOLDOWN, |setting| setting.value)
+    }
+
+    pub fn greeting(&self) -> GreetingPolicy {
+        self.greeting
+            .as_ref()
+            .map_or_else(GreetingPolicy::default, |setting| setting.value)
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.pool_size.as_ref().map_or(0, |setting| setting.value)
+    }
+
+    pub fn pool_idle(&self) -> Duration {
+        self.pool_idle
+            .as_ref()
+            .map_or(pool::DEFAULT_IDLE, |setting| {
+                Duration::from_secs(setting.value)
+            })
+    }
+
+    pub fn pacing(&self) -> Pacing {
+        Pacing {
+            lines: self
+                .send_chunk_lines
+                .as_ref()
+                .map_or(0, |setting| setting.value),
+            delay: self
+                .send_pacing_ms
+                .as_ref()
+                .map_or(Duration::ZERO, |setting| {
+                    Duration::from_millis(setting.value)
+                }),
+        }
+    }
+
+    pub fn latency_window(&self) -> usize {
+        self.latency_window
+            .as_ref()
+            .map_or(stats::DEFAULT_WINDOW, |setting| setting.value)
+    }
+
+    pub fn detect_penalty(&self) -> Duration {
+        self.detect_penalty
+            .as_ref()
+            .map_or(detect::DEFAULT_PENALTY, |setting| {
+                Duration::from_secs(setting.value)
+            })
+    }
+
+    pub fn max_line_length(&self) -> usize {
+        self.max_line_length
+            .as_ref()
+            .map_or(dice::MAX_LINE_LEN, |setting| setting.value)
+    }
+
+    pub fn latency_warn(&self) -> Option<Duration> {
+        self.latency_warn_ms
+            .as_ref()
+            .map(|setting| Duration::from_millis(setting.value))
+    }
+
+    pub fn max_bytes_per_day(&self) -> Option<u64> {
+        self.max_bytes_per_day.as_ref().map(|setting| setting.value)
+    }
+
+    pub fn ownership_suffixes(&self) -> Vec<String> {
+        match &self.ownership_suffixes {
+            Some(setting) => setting.value.clone(),
+            None => DEFAULT_OWNERSHIP_SUFFIXES
+                .iter()
+                .map(|suffix| suffix.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn state_mode(&self) -> StateMode {
+        self.state
+            .as_ref()
+            .map_or_else(StateMode::default, |setting| setting.value)
+    }
+
+    pub fn resolver(&self) -> ResolverSpec {
+        self.resolver
+            .as_ref()
+            .map_or_else(ResolverSpec::default, |setting| setting.value)
+    }
+
+    pub fn local_bind(&self) -> LocalBind {
+        LocalBind {
+            address: self.bind_address.as_ref().map(|setting| setting.value),
+            ports: self.local_port_range.as_ref().map(|setting| setting.value),
+            family: self
+                .connect_family
+                .as_ref()
+                .map_or(Family::Any, |setting| setting.value),
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Some(retries) = &self.retries {
+            policy.retries = retries.value;
+        }
+        if let Some(delay) = &self.retry_delay {
+            policy.base_delay = Duration::from_secs(delay.value);
+        }
+        policy
+    }
+
+    pub fn transport(&self, record: Option<&RecordConfig>) -> Transport {
+        let settings = self.transport_settings(record);
+        let defaults = Timeouts::default();
+        let mut retry = self.retry_policy();
+        if let Some(retries) = settings.retries {
+            retry.retries = retries.value;
+        }
+        Transport {
+            timeouts: Timeouts {
+                connect: resolve_timeout(settings.connect_timeout.as_ref(), defaults.connect),
+                io: resolve_timeout(settings.timeout.as_ref(), defaults.io),
+            },
+            retry,
+        }
+    }
+
+    fn transport_settings(&self, record: Option<&RecordConfig>) -> ProviderConfig {
+        fn layered<T: Clone>(
+            record: Option<&Option<Setting<T>>>,
+            provider: &Option<Setting<T>>,
+            global: &Option<Setting<T>>,
+        ) -> Option<Setting<T>> {
+            record
+                .and_then(Option::as_ref)
+                .or(provider.as_ref())
+                .or(global.as_ref())
+                .cloned()
+        }
+        ProviderConfig {
+            timeout: layered(
+                record.map(|record| &record.timeout),
+                &self.onamae.timeout,
+                &self.timeout,
+            ),
+            connect_timeout: layered(
+                record.map(|record| &record.connect_timeout),
+                &self.onamae.connect_timeout,
+                &self.connect_timeout,
+            ),
+            retries: layered(
+                record.map(|record| &record.retries),
+                &self.onamae.retries,
+                &self.retries,
+            ),
+        }
+    }
+
+    pub fn tls_options(&self) -> TlsOptions {
+        TlsOptions {
+            min_version: self
+                .tls_min_version
+                .as_ref()
+                .map_or_else(TlsVersion::default, |setting| setting.value),
+            ciphers: self
+                .tls_ciphers
+                .as_ref()
+                .map(|setting| setting.value.clone()),
+            ca_file: self.ca_file.as_ref().map(|setting| setting.value.clone()),
+        }
+    }
+
+    pub fn tls_connector(&self) -> Result<Connector, ConfigError> {
+        let options = TlsOptions {
+            ca_file: None,
+            ..self.tls_options()
+        };
+        let connector = options.connector().map_err(|e| {
+            let diagnostic = match &self.tls_ciphers {
+                Some(ciphers) => Diagnostic::new(
+                    &ciphers.source,
+                    "tls_ciphers",
+                    format!("invalid cipher list {:?}: {}", ciphers.value, e),
+                ),
+                None => Diagnostic {
+                    origin: "tls".to_owned(),
+                    key: None,
+                    message: e.to_string(),
+                },
+            };
+            ConfigError::Invalid(vec![diagnostic])
+        })?;
+        match &self.ca_file {
+            Some(ca_file) => self.tls_options().connector().map_err(|e| {
+                ConfigError::Invalid(vec![Diagnostic::new(&ca_file.source, "ca_file", e)])
+            }),
+            None => Ok(connector),
+        }
+    }
+
+    pub fn parse_toml(text: &str, path: &Path) -> (Config, Vec<Diagnostic>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let mut warnings = Vec::new();
+        let (mut table, errors) = DeTable::parse_recoverable(text);
+        for e in errors {
+            let origin = match e.span() {
+                Some(span) => {
+                    let (line, column) = location(text, span.start);
+                    format!("{}:{}:{}", path.display(), line, column)
+                }
+                None => path.display().to_string(),
+            };
+            diagnostics.push(Diagnostic {
+                origin,
+                key: None,
+                message: e.message().to_owned(),
+            });
+        }
+        let locate = |offset| file_source(text, path, offset);
+        let mut includes = Vec::new();
+        for (name, value) in table.get_mut().iter_mut() {
+            let (diagnostics, warnings) = (&mut diagnostics, &mut warnings);
+            let key = name.get_ref();
+            resolve_includes(key, value, &locate, &mut includes, diagnostics, warnings);
+        }
+        let locate = |offset| match includes.iter().find(|(at, _)| offset == *at) {
+            Some((_, target)) => Source::Include {
+                target: target.clone(),
+                via: Box::new(locate(offset)),
+            },
+            None => locate(offset),
+        };
+        let config = Config::parse_table(table.get_ref(), &locate, &mut diagnostics);
+        (config, diagnostics, warnings)
+    }
+
+    fn parse_table(
+        table: &DeTable,
+        locate: &dyn Fn(usize) -> Source,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Config {
+        let mut config = Config::default();
+        let mut record = None;
+        for (name, value) in table.iter() {
+            let name: &str = name.get_ref();
+            let source = locate(value.span().start);
+            let value = value.get_ref();
+            let result = match name {
+                "ip6" | "ip6_source" => Err(messages::ipv6_unsupported()),
+                "ip" | "ip_source" if config.ip.is_some() => {
+                    Err("ip and ip_source are mutually exclusive".to_owned())
+                }
+                "ip" => expect_str(value)
+                    .and_then(parse_ip)
+                    .map(|ip| config.ip = Some(Setting::new(ip, source.clone()))),
+                "ip_source" => expect_ip_source(value)
+                    .map(|ip| config.ip = Some(Setting::new(ip, source.clone()))),
+                "record" if matches!(value, DeValue::Array(_)) => {
+                    if let DeValue::Array(items) = value {
+                        for (index, item) in items.iter().enumerate() {
+                            let at = locate(item.span().start);
+                            match item.get_ref() {
+                                DeValue::Table(table) => {
+                                    if let Some(record) =
+                                        parse_record_table(table, at, index, locate, diagnostics)
+                                    {
+                                        config.records.push(record);
+                                    }
+                                }
+                                other => diagnostics.push(Diagnostic::new(
+                                    &at,
+                                    "record",
+                                    format!("expected a table, found {}", other.type_str()),
+                                )),
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+                "password_encrypted" => expect_str(value).map(|text| {
+                    config.pass_encrypted = Some(Setting::new(text.to_owned(), source.clone()))
+                }),
+                "password_keyfile" => expect_str(value).map(|text| {
+                    config.pass_keyfile = Some(Setting::new(PathBuf::from(text), source.clone()))
+                }),
+                "record" => expect_str(value).map(|text| record = Some((text, source.clone()))),
+                "onamae" => match value {
+                    DeValue::Table(table) => {
+                        config.onamae = parse_provider_table(table, locate, diagnostics);
+                        Ok(())
+                    }
+                    other => Err(format!("expected a table, found {}", other.type_str())),
+                },
+                "state" => expect_str(value)
+                    .and_then(str::parse)
+                    .map(|mode| config.state = Some(Setting::new(mode, source.clone()))),
+                "state_file" => expect_str(value).map(|text| {
+                    config.state_file = Some(Setting::new(PathBuf::from(text), source.clone()))
+                }),
+                "status_file" => expect_str(value).map(|text| {
+                    config.status_file = Some(Setting::new(PathBuf::from(text), source.clone()))
+                }),
+                "auth_failure_limit" => expect_int(value)
+                    .map(|n| config.auth_failure_limit = Some(Setting::new(n, source.clone()))),
+                "auth_cooldown" => expect_int(value)
+                    .map(|n| config.auth_cooldown = Some(Setting::new(n, source.clone()))),
+                "max_age" => expect_duration(value)
+                    .map(|n| config.max_age = Some(Setting::new(n, source.clone()))),
+                "bind_address" => expect_str(value).and_then(|text| {
+                    addr::parse_ip(text)
+                        .map(|ip| config.bind_address = Some(Setting::new(ip, source.clone())))
+                        .map_err(|e| e.to_string())
+                }),
+                "extra_fields" => expect_fields(value)
+                    .map(|fields| config.extra_fields = Some(Setting::new(fields, source.clone()))),
+                "offline_ip" => expect_offline_ip(value)
+                    .map(|ip| config.offline_ip = Some(Setting::new(ip, source.clone()))),
+                "greeting" => expect_str(value)
+                    .and_then(str::parse)
+                    .map(|policy| config.greeting = Some(Setting::new(policy, source.clone()))),
+                "pool_size" => expect_int(value)
+                    .map(|n| config.pool_size = Some(Setting::new(n, source.clone()))),
+                "pool_idle" => expect_duration(value)
+                    .map(|n| config.pool_idle = Some(Setting::new(n, source.clone()))),
+                "send_chunk_lines" => expect_int(value)
+                    .map(|n| config.send_chunk_lines = Some(Setting::new(n, source.clone()))),
+                "send_pacing_ms" => expect_int(value)
+                    .map(|n| config.send_pacing_ms = Some(Setting::new(n, source.clone()))),
+                "latency_window" => expect_int(value)
+                    .and_then(|n: usize| match n {
+                        0 => Err("must be at least 1".to_owned()),
+                        n => Ok(n),
+                    })
+                    .map(|n| config.latency_window = Some(Setting::new(n, source.clone()))),
+                "latency_warn_ms" => expect_int(value)
+                    .map(|n| config.latency_warn_ms = Some(Setting::new(n, source.clone()))),
+                "detect_penalty" => expect_duration(value)
+                    .map(|n| config.detect_penalty = Some(Setting::new(n, source.clone()))),
+                "max_bytes_per_day" => expect_int(value)
+                    .map(|n| config.max_bytes_per_day = Some(Setting::new(n, source.clone()))),
+                "max_line_length" => expect_int(value)
+                    .and_then(|n: usize| match n {
+                        0 => Err("must be at least 1".to_owned()),
+                        n => Ok(n),
+                    })
+                    .map(|n| config.max_line_length = Some(Setting::new(n, source.clone()))),
+                "replace_records" => expect_bool(value)
+                    .map(|b| config.replace_records = Some(Setting::new(b, source.clone()))),
+                "check_dns_first" => expect_bool(value)
+                    .map(|b| config.check_dns_first = Some(Setting::new(b, source.clone()))),
+                "dry_run" => expect_bool(value)
+                    .map(|b| config.dry_run = Some(Setting::new(b, source.clone()))),
+                "check_ownership" => expect_bool(value)
+                    .map(|b| config.check_ownership = Some(Setting::new(b, source.clone()))),
+                "strict_ownership" => expect_bool(value)
+                    .map(|b| config.strict_ownership = Some(Setting::new(b, source.clone()))),
+                "strict_ip_validation" => expect_bool(value)
+                    .map(|b| config.strict_ip_validation = Some(Setting::new(b, source.clone()))),
+                "tolerate_ack_mismatch" => expect_bool(value)
+                    .map(|b| config.tolerate_ack_mismatch = Some(Setting::new(b, source.clone()))),
+                "ownership_suffixes" => expect_strings(value).map(|suffixes| {
+                    config.ownership_suffixes = Some(Setting::new(suffixes, source.clone()))
+                }),
+                "nameserver" => expect_str(value).and_then(|text| {
+                    addr::parse_ip(text)
+                        .map(|ip| config.nameserver = Some(Setting::new(ip, source.clone())))
+                        .map_err(|e| e.to_string())
+                }),
+                "resolver" => expect_str(value)
+                    .and_then(str::parse)
+                    .map(|spec| config.resolver = Some(Setting::new(spec, source.clone()))),
+                "retries" => expect_int(value)
+                    .map(|n| config.retries = Some(Setting::new(n, source.clone()))),
+                "retry_delay" => expect_duration(value)
+                    .map(|n| config.retry_delay = Some(Setting::new(n, source.clone()))),
+                "timeout" => expect_timeout(value)
+                    .map(|n| config.timeout = Some(Setting::new(n, source.clone()))),
+                "connect_timeout" => expect_timeout(value)
+                    .map(|n| config.connect_timeout = Some(Setting::new(n, source.clone()))),
+                "tls_min_version" => expect_str(value).and_then(str::parse).map(|version| {
+                    config.tls_min_version = Some(Setting::new(version, source.clone()))
+                }),
+                "tls_ciphers" => expect_str(value).map(|text| {
+                    config.tls_ciphers = Some(Setting::new(text.to_owned(), source.clone()))
+                }),
+                "ca_file" => expect_str(value).map(|text| {
+                    config.ca_file = Some(Setting::new(PathBuf::from(text), source.clone()))
+                }),
+                "local_port_range" => {
+                    expect_str(value)
+                        .and_then(socket::parse_port_range)
+                        .map(|range| {
+                            config.local_port_range = Some(Setting::new(range, source.clone()))
+                        })
+                }
+                "connect_family" => expect_str(value).and_then(str::parse).map(|family| {
+                    config.connect_family = Some(Setting::new(family, source.clone()))
+                }),
+                "host" => {
+                    expect_hosts(value).map(|text| config.set(&KEY::HOST, text, source.clone()))
+                }
+                _ => match KEY::all().iter().find(|key| key_name(key) == name) {
+                    Some(key) => {
+                        expect_str(value).map(|text| config.set(key, text, source.clone()))
+                    }
+                    None => Err("unknown key".to_owned()),
+                },
+            };
+            if let Err(message) = result {
+                diagnostics.push(Diagnostic::new(&source, name, message));
+            }
+        }
+        if let Some((text, source)) = record {
+            if let Err(diagnostic) = config.set_record(text, source) {
+                diagnostics.push(diagnostic);
+            }
+        }
+        config
+    }
+
+    pub fn parse_json(text: &str) -> (Config, Vec<Diagnostic>) {
+        let invalid = |message: String| {
+            let diagnostic = Diagnostic {
+                origin: Source::Stdin.to_string(),
+                key: None,
+                message,
+            };
+            (Config::default(), vec![diagnostic])
+        };
+        let mut object = match serde_json::from_str(text) {
+            Ok(serde_json::Value::Object(object)) => object,
+            Ok(_) => return invalid("expected a JSON object".to_owned()),
+            Err(e) => {
+                let offset: usize = text
+                    .split_inclusive('\n')
+                    .take(e.line().saturating_sub(1))
+                    .map(str::len)
+                    .sum::<usize>()
+                    + e.column().saturating_sub(1);
+                return invalid(format!("invalid JSON at byte {}: {}", offset, e));
+            }
+        };
+        if let Some(records) = object.remove("records") {
+            if object.contains_key("record") {
+                return invalid("record and records are mutually exclusive".to_owned());
+            }
+            object.insert("record".to_owned(), records);
+        }
+        let mut value = serde_json::Value::Object(object);
+        strip_nulls(&mut value);
+        let text = match toml::to_string(&value) {
+            Ok(text) => text,
+            Err(e) => return invalid(e.to_string()),
+        };
+        let mut diagnostics = Vec::new();
+        let table = match DeTable::parse(&text) {
+            Ok(table) => table,
+            Err(e) => return invalid(e.message().to_owned()),
+        };
+        let config = Config::parse_table(table.get_ref(), &|_| Source::Stdin, &mut diagnostics);
+        (config, diagnostics)
+    }
+
+    pub fn from_json(text: &str) -> Result<Config, ConfigError> {
+        match Config::parse_json(text) {
+            (config, diagnostics) if diagnostics.is_empty() => Ok(config),
+            (_, diagnostics) => Err(ConfigError::Invalid(diagnostics)),
+        }
+    }
+
+    pub fn from_toml(text: &str, path: &Path) -> Result<(Config, Vec<Diagnostic>), ConfigError> {
+        match Config::parse_toml(text, path) {
+            (config, diagnostics, warnings) if diagnostics.is_empty() => Ok((config, warnings)),
+            (_, diagnostics, _) => Err(ConfigError::Invalid(diagnostics)),
+        }
+    }
+
+    pub fn layers(mut files: Vec<PathBuf>) -> Result<Vec<PathBuf>, ConfigError> {
+        if let Some(first) = files.first() {
+            let drop_ins = Config::drop_ins(first)?;
+            files.splice(1..1, drop_ins);
+        }
+        Ok(files)
+    }
+
+    pub fn drop_ins(path: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+        let dir = path.with_file_name(DROP_IN_DIR);
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(Vec::new()),
+            Err(e) => return Err(ConfigError::Io(dir, e)),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| ConfigError::Io(dir.clone(), e))?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| "toml" == ext) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    pub fn from_file(path: &Path) -> Result<(Config, Vec<Diagnostic>), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        Config::from_toml(&text, path)
+    }
+
+    pub fn from_env_with<F>(var: F) -> Result<Config, ConfigError>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let mut config = Config::default();
+        for key in KEY::all() {
+            if let Some(value) = var(env_name(key)) {
+                let value = value.to_string_lossy().into_owned();
+                config.set(key, value, Source::Env(env_name(key).to_owned()));
+            }
+        }
+        if let Some(value) = var(ENV_IP) {
+            let source = Source::Env(ENV_IP.to_owned());
+            match parse_ip(&value.to_string_lossy()) {
+                Ok(ip) => config.ip = Some(Setting::new(ip, source)),
+                Err(message) => {
+                    let diagnostic = Diagnostic::new(&source, "ip", message);
+                    return Err(ConfigError::Invalid(vec![diagnostic]));
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_env() -> Result<Config, ConfigError> {
+        Config::from_env_with(|name| env::var_os(name))
+    }
+
+    pub fn from_partial(partial: &PartialInfo, source: Source) -> Config {
+        let mut config = Config::default();
+        for key in KEY::all() {
+            if let Some(value) = partial.get(key) {
+                config.set(key, value.clone(), source.clone());
+            }
+        }
+        config
+    }
+
+    pub fn read_input(
+        &mut self,
+        input: &mut dyn InputSource,
+    ) -> Result<Vec<InputWarning>, InputError> {
+        let partial = input.bulk()?;
+        self.merge(Config::from_partial(&partial, Source::Stdin));
+        Ok(partial.warnings)
+    }
+
+    pub fn prompt_missing(
+        &mut self,
+        input: &mut dyn InputSource,
+        keys: &[KEY],
+    ) -> Result<(), InputError> {
+        for key in keys {
+            if self.is_set(key) {
+                continue;
+            }
+            let value = input.prompt(key, None, key.is_secret())?;
+            self.set(key, value, Source::Prompt);
+        }
+        Ok(())
+    }
+
+    fn required(&self, key: &KEY) -> bool {
+        match key {
+            KEY::HOST | KEY::DOM => self.records.is_empty(),
+            _ => true,
+        }
+    }
+
+    pub fn is_set(&self, key: &KEY) -> bool {
+        self.get(key)
+            .is_some_and(|setting| !setting.value.trim().is_empty())
+    }
+
+    pub fn missing(&self) -> Vec<KEY> {
+        KEY::all()
+            .iter()
+            .filter(|key| self.required(key) && !self.is_set(key))
+            .cloned()
+            .collect()
+    }
+
+    pub fn resolve_secrets(&mut self) -> Result<(), ConfigError> {
+        let encrypted = match &self.pass_encrypted {
+            Some(encrypted) => encrypted,
+            None => return Ok(()),
+        };
+        let invalid = |source: &Source, key: &str, message: String| {
+            Err(ConfigError::Invalid(vec![Diagnostic::new(
+                source, key, message,
+            )]))
+        };
+        match &self.pass {
+            Some(pass) if matches!(pass.source, Source::File { .. } | Source::Include { .. }) => {
+                let message = "pass and password_encrypted are mutually exclusive".to_owned();
+                return invalid(&encrypted.source, "password_encrypted", message);
+            }
+            Some(_) => return Ok(()),
+            None => {}
+        }
+        let keyfile = match &self.pass_keyfile {
+            Some(keyfile) => keyfile,
+            None => {
+                let message = "password_keyfile is required to decrypt it".to_owned();
+                return invalid(&encrypted.source, "password_encrypted", message);
+            }
+        };
+        let key = match secret::read_key(&keyfile.value) {
+            Ok(key) => key,
+            Err(e) => return invalid(&keyfile.source, "password_keyfile", e.to_string()),
+        };
+        match secret::decrypt(&key, &encrypted.value) {
+            Ok(pass) => {
+                self.pass = Some(Setting::new(pass, encrypted.source.clone()));
+                Ok(())
+            }
+            Err(e) => invalid(&encrypted.source, "password_encrypted", e.to_string()),
+        }
+    }
+
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for key in KEY::all() {
+            if self.required(key) && !self.is_set(key) {
+                diagnostics.push(Diagnostic {
+                    origin: "config".to_owned(),
+                    key: Some(key_name(key).to_owned()),
+                    message: format!("{} is required but not set", key),
+                });
+            }
+        }
+        if let Some(host) = &self.host {
+            validate_hosts(host, "host", &mut diagnostics);
+        }
+        if let Some(dom) = &self.dom {
+            validate_dom(dom, "dom", &mut diagnostics);
+        }
+        for (index, record) in self.records.iter().enumerate() {
+            validate_hosts(
+                &record.host,
+                &format!("record[{}].host", index),
+                &mut diagnostics,
+            );
+            let key = format!("record[{}].dom", index);
+            match record.dom.as_ref().or(self.dom.as_ref()) {
+                Some(dom) => validate_dom(dom, &key, &mut diagnostics),
+                None => diagnostics.push(Diagnostic::new(
+                    &record.host.source,
+                    &key,
+                    "dom is required but not set here or globally",
+                )),
+            }
+        }
+        let mut nodes = Vec::new();
+        if let (Some(host), Some(dom)) = (&self.host, &self.dom) {
+            for name in host.value.split(',') {
+                let name = normalize::name(name).unwrap_or(name);
+                let dom = normalize::name(&dom.value).unwrap_or(&dom.value);
+                nodes.push((Source::Default, name_in(name, dom), 0, &[][..]));
+            }
+        }
+        for record in &self.records {
+            let dom = match record.dom.as_ref().or(self.dom.as_ref()) {
+                Some(dom) => dom,
+                None => continue,
+            };
+            let priority = record.priority.as_ref().map_or(0, |setting| setting.value);
+            let (source, depends_on) = match &record.depends_on {
+                Some(setting) => (setting.source.clone(), &setting.value[..]),
+                None => (record.host.source.clone(), &[][..]),
+            };
+            for name in record.names(normalize::name(&dom.value).unwrap_or(&dom.value)) {
+                nodes.push((source.clone(), name, priority, depends_on));
+            }
+        }
+        let graph: Vec<Node> = nodes
+            .iter()
+            .map(|(_, name, priority, depends_on)| Node {
+                name: name.clone(),
+                priority: *priority,
+                depends_on,
+            })
+            .collect();
+        for (name, dep) in order::unknown(&graph) {
+            if let Some((source, ..)) = nodes.iter().find(|(_, node, ..)| name == node) {
+                let message = messages::unknown_dependency(name, dep);
+                diagnostics.push(Diagnostic::new(source, "depends_on", message));
+            }
+        }
+        if let Some(cycle) = order::find_cycle(&graph) {
+            if let Some((source, ..)) = nodes.iter().find(|(_, node, ..)| cycle[0] == *node) {
+                let message = messages::dependency_cycle(&cycle);
+                diagnostics.push(Diagnostic::new(source, "depends_on", message));
+            }
+        }
+        diagnostics
+    }
+
+    pub fn to_records(&self) -> Result<(Vec<Record>, Vec<Diagnostic>), ConfigError> {
+        let keys = self.missing();
+        if !keys.is_empty() {
+            return Err(ConfigError::Missing(keys));
+        }
+        let mut base = Information::default();
+        for key in [KEY::USER, KEY::PASS].iter() {
+            if let Some(setting) = self.get(key) {
+                base[key] = setting.value.clone();
+            }
+        }
+        let mut groups = Vec::new();
+        if let (Some(host), Some(dom)) = (&self.host, &self.dom) {
+            groups.push((
+                host,
+                dom,
+                self.ip.as_ref(),
+                self.extra_fields.as_ref(),
+                self.offline_ip.as_ref(),
+                None,
+                None,
+                None,
+                self.transport(None),
+                None,
+                None,
+            ));
+        }
+        for record in &self.records {
+            let dom = match record.dom.as_ref().or(self.dom.as_ref()) {
+                Some(dom) => dom,
+                None => return Err(ConfigError::Missing(vec![KEY::DOM])),
+            };
+            groups.push((
+                &record.host,
+                dom,
+                record.ip.as_ref().or(self.ip.as_ref()),
+                record.extra_fields.as_ref().or(self.extra_fields.as_ref()),
+                record.offline_ip.as_ref().or(self.offline_ip.as_ref()),
+                record.enabled.as_ref(),
+                record.dry_run.as_ref(),
+                record.window.as_ref(),
+                self.transport(Some(record)),
+                record.priority.as_ref(),
+                record.depends_on.as_ref(),
+            ));
+        }
+        let mut records: Vec<Record> = Vec::new();
+        let mut warnings = Vec::new();
+        for (
+            host,
+            dom,
+            ip,
+            extra,
+            offline_ip,
+            enabled,
+            dry_run,
+            window,
+            transport,
+            priority,
+            depends_on,
+        ) in groups
+        {
+            let ip = match ip {
+                Some(ip) => ip,
+                None => {
+                    let message = "no ip or ip_source for this record";
+                    let diagnostic = Diagnostic::new(&host.source, "ip", message);
+                    return Err(ConfigError::Invalid(vec![diagnostic]));
+                }
+            };
+            let invalid = |source: &Source, key: &str, message: String| {
+                ConfigError::Invalid(vec![Diagnostic::new(source, key, message)])
+            };
+            let dom = normalize::name(&dom.value).map_err(|e| invalid(&dom.source, "dom", e))?;
+            for name in host.value.split(',') {
+                let name = normalize::name(name).map_err(|e| invalid(&host.source, "host", e))?;
+                let name = if "@" == name { "" } else { name };
+                let seen = records.iter().any(|record| {
+                    record.info.host.eq_ignore_ascii_case(name)
+                        && record.info.dom.eq_ignore_ascii_case(dom)
+                });
+                if seen {
+                    let shown = if name.is_empty() { "@" } else { name };
+                    let message = format!("duplicate host {:?} ignored", shown);
+                    warnings.push(Diagnostic::new(&host.source, "host", message));
+                    continue;
+                }
+                let mut info = Information {
+                    host: name.to_owned(),
+                    dom: dom.to_owned(),
+                    extra: extra.map_or_else(Vec::new, |setting| setting.value.clone()),
+                    ..base.clone()
+                };
+                if let IpSource::Static(addr) = ip.value {
+                    info.ipaddr = addr;
+                }
+                records.push(Record {
+                    info,
+                    ip: ip.clone(),
+                    offline_ip: offline_ip.map_or(Ipv4Addr::UNSPECIFIED, |setting| setting.value),
+                    enabled: enabled.is_none_or(|setting| setting.value),
+                    dry_run: dry_run.map(|setting| setting.value),
+                    window: window.map(|setting| setting.value),
+                    transport,
+                    priority: priority.map_or(0, |setting| setting.value),
+                    depends_on: depends_on.map_or_else(Vec::new, |setting| setting.value.clone()),
+                });
+            }
+        }
+        let nodes: Vec<Node> = records.iter().map(Record::node).collect();
+        let sorted = order::sort(&nodes);
+        let mut slots: Vec<Option<Record>> = records.into_iter().map(Some).collect();
+        let records = sorted.iter().filter_map(|&at| slots[at].take()).collect();
+        Ok((records, warnings))
+    }
+
+    fn entries(&self) -> Vec<(String, Option<(toml::Value, Source)>)> {
+        fn entry<T>(
+            setting: &Option<Setting<T>>,
+            value: impl Fn(&T) -> toml::Value,
+        ) -> Option<(toml::Value, Source)> {
+            setting
+                .as_ref()
+                .map(|setting| (value(&setting.value), setting.source.clone()))
+        }
+        fn text(value: impl fmt::Display) -> toml::Value {
+            toml::Value::String(value.to_string())
+        }
+        fn integer(n: impl Into<i64>) -> toml::Value {
+            toml::Value::Integer(n.into())
+        }
+        fn seconds_entry(
+            setting: Option<&Setting<u64>>,
+            effective: Option<Duration>,
+        ) -> Option<(toml::Value, Source)> {
+            match (setting, effective) {
+                (Some(setting), _) => Some((integer(setting.value as i64), setting.source.clone())),
+                (None, Some(duration)) => {
+                    Some((integer(duration.as_secs() as i64), Source::Default))
+                }
+                (None, None) => None,
+            }
+        }
+        fn fields_value(fields: &[String]) -> toml::Value {
+            toml::Value::Array(fields.iter().map(text).collect())
+        }
+        let mut entries = Vec::new();
+        for key in KEY::all() {
+            let value = self.get(key).map(|setting| {
+                let value = if key.is_secret() {
+                    text("***")
+                } else {
+                    text(&setting.value)
+                };
+                (value, setting.source.clone())
+            });
+            entries.push((key_name(key).to_owned(), value));
+        }
+        fn source_value(source: &IpSource) -> toml::Value {
+            match source {
+                IpSource::Command { argv, clear_env } => {
+                    let mut table = toml::Table::new();
+                    let argv = argv.iter().map(text).collect();
+                    table.insert("cmd".to_owned(), toml::Value::Array(argv));
+                    table.insert("clear_env".to_owned(), toml::Value::Boolean(*clear_env));
+                    toml::Value::Table(table)
+                }
+                IpSource::Fallback(sources) => {
+                    toml::Value::Array(sources.iter().map(source_value).collect())
+                }
+                source => text(source),
+            }
+        }
+        fn ip_entry(
+            ip: &Option<Setting<IpSource>>,
+        ) -> (&'static str, Option<(toml::Value, Source)>) {
+            match ip.as_ref().map(|setting| &setting.value) {
+                Some(IpSource::Static(addr)) => ("ip", entry(ip, |_| text(addr))),
+                Some(_) => ("ip_source", entry(ip, source_value)),
+                None => ("ip", None),
+            }
+        }
+        let (name, value) = ip_entry(&self.ip);
+        entries.push((name.to_owned(), value));
+        for (index, record) in self.records.iter().enumerate() {
+            let host = Some((text(&record.host.value), record.host.source.clone()));
+            entries.push((format!("record[{}].host", index), host));
+            entries.push((
+                format!("record[{}].dom", index),
+                entry(&record.dom, |dom| text(dom)),
+            ));
+            if record.ip.is_some() {
+                let (name, value) = ip_entry(&record.ip);
+                entries.push((format!("record[{}].{}", index, name), value));
+            }
+            if record.extra_fields.is_some() {
+                entries.push((
+                    format!("record[{}].extra_fields", index),
+                    entry(&record.extra_fields, |fields| fields_value(fields)),
+                ));
+            }
+            if record.offline_ip.is_some() {
+                entries.push((
+                    format!("record[{}].offline_ip", index),
+                    entry(&record.offline_ip, |ip| text(ip)),
+                ));
+            }
+            if record.enabled.is_some() {
+                entries.push((
+                    format!("record[{}].enabled", index),
+                    entry(&record.enabled, |flag| toml::Value::Boolean(*flag)),
+                ));
+            }
+            if record.dry_run.is_some() {
+                entries.push((
+                    format!("record[{}].dry_run", index),
+                    entry(&record.dry_run, |flag| toml::Value::Boolean(*flag)),
+                ));
+            }
+            if record.window.is_some() {
+                entries.push((
+                    format!("record[{}].window", index),
+                    entry(&record.window, |window| text(window)),
+                ));
+            }
+            if record.priority.is_some() {
+                entries.push((
+                    format!("record[{}].priority", index),
+                    entry(&record.priority, |n| integer(*n)),
+                ));
+            }
+            if record.depends_on.is_some() {
+                entries.push((
+                    format!("record[{}].depends_on", index),
+                    entry(&record.depends_on, |names| fields_value(names)),
+                ));
+            }
+            let settings = self.transport_settings(Some(record));
+            let transport = self.transport(Some(record));
+            entries.push((
+                format!("record[{}].timeout", index),
+                seconds_entry(settings.timeout.as_ref(), transport.timeouts.io),
+            ));
+            entries.push((
+                format!("record[{}].connect_timeout", index),
+                seconds_entry(
+                    settings.connect_timeout.as_ref(),
+                    transport.timeouts.connect,
+                ),
+            ));
+            entries.push((
+                format!("record[{}].retries", index),
+                Some(settings.retries.map_or(
+                    (integer(transport.retry.retries), Source::Default),
+                    |setting| (integer(setting.value), setting.source.clone()),
+                )),
+            ));
+        }
+        entries.push((
+            "password_encrypted".to_owned(),
+            entry(&self.pass_encrypted, |_| text("***")),
+        ));
+        entries.push((
+            "password_keyfile".to_owned(),
+            entry(&self.pass_keyfile, |path| text(path.display())),
+        ));
+        entries.push((
+            "state_file".to_owned(),
+            entry(&self.state_file, |path| text(path.display())),
+        ));
+        entries.push((
+            "status_file".to_owned(),
+            entry(&self.status_file, |path| text(path.display())),
+        ));
+        let default = |value: toml::Value| Some((value, Source::Default));
+        entries.push((
+            "state".to_owned(),
+            entry(&self.state, |mode| text(mode)).or_else(|| default(text(StateMode::default()))),
+        ));
+        entries.push((
+            "auth_failure_limit".to_owned(),
+            entry(&self.auth_failure_limit, |n| {
+                toml::Value::Integer(i64::from(*n))
+            })
+            .or_else(|| default(toml::Value::Integer(i64::from(self.auth_failure_limit())))),
+        ));
+        entries.push((
+            "auth_cooldown".to_owned(),
+            entry(&self.auth_cooldown, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(self.auth_cooldown() as i64))),
+        ));
+        entries.push((
+            "max_age".to_owned(),
+            entry(&self.max_age, |n| toml::Value::Integer(*n as i64)),
+        ));
+        entries.push((
+            "bind_address".to_owned(),
+            entry(&self.bind_address, |ip| text(ip)),
+        ));
+        entries.push((
+            "local_port_range".to_owned(),
+            entry(&self.local_port_range, |(low, high)| {
+                text(format!("{}-{}", low, high))
+            }),
+        ));
+        entries.push((
+            "connect_family".to_owned(),
+            entry(&self.connect_family, |family| text(family)),
+        ));
+        let policy = self.retry_policy();
+        entries.push((
+            "retries".to_owned(),
+            entry(&self.retries, |n| toml::Value::Integer(i64::from(*n)))
+                .or_else(|| default(toml::Value::Integer(i64::from(policy.retries)))),
+        ));
+        entries.push((
+            "retry_delay".to_owned(),
+            entry(&self.retry_delay, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(policy.base_delay.as_secs() as i64))),
+        ));
+        let transport = self.transport(None);
+        entries.push((
+            "timeout".to_owned(),
+            seconds_entry(self.timeout.as_ref(), transport.timeouts.io),
+        ));
+        entries.push((
+            "connect_timeout".to_owned(),
+            entry(&self.connect_timeout, |n| integer(*n as i64)),
+        ));
+        entries.push((
+            format!("{}.timeout", PROVIDER),
+            entry(&self.onamae.timeout, |n| integer(*n as i64)),
+        ));
+        entries.push((
+            format!("{}.connect_timeout", PROVIDER),
+            entry(&self.onamae.connect_timeout, |n| integer(*n as i64)),
+        ));
+        entries.push((
+            format!("{}.retries", PROVIDER),
+            entry(&self.onamae.retries, |n| integer(*n)),
+        ));
+        entries.push((
+            "tls_min_version".to_owned(),
+            entry(&self.tls_min_version, |version| text(version))
+                .or_else(|| default(text(TlsVersion::default()))),
+        ));
+        entries.push((
+            "tls_ciphers".to_owned(),
+            entry(&self.tls_ciphers, |ciphers| text(ciphers)),
+        ));
+        entries.push((
+            "ca_file".to_owned(),
+            entry(&self.ca_file, |path| text(path.display())),
+        ));
+        entries.push((
+            "greeting".to_owned(),
+            entry(&self.greeting, |policy| text(policy))
+                .or_else(|| default(text(GreetingPolicy::default()))),
+        ));
+        entries.push((
+            "offline_ip".to_owned(),
+            entry(&self.offline_ip, |ip| text(ip)).or_else(|| default(text(Ipv4Addr::UNSPECIFIED))),
+        ));
+        entries.push((
+            "pool_size".to_owned(),
+            entry(&self.pool_size, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(0))),
+        ));
+        entries.push((
+            "pool_idle".to_owned(),
+            entry(&self.pool_idle, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(pool::DEFAULT_IDLE.as_secs() as i64))),
+        ));
+        entries.push((
+            "send_chunk_lines".to_owned(),
+            entry(&self.send_chunk_lines, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(0))),
+        ));
+        entries.push((
+            "send_pacing_ms".to_owned(),
+            entry(&self.send_pacing_ms, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(0))),
+        ));
+        entries.push((
+            "latency_window".to_owned(),
+            entry(&self.latency_window, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(stats::DEFAULT_WINDOW as i64))),
+        ));
+        entries.push((
+            "latency_warn_ms".to_owned(),
+            entry(&self.latency_warn_ms, |n| toml::Value::Integer(*n as i64)),
+        ));
+        entries.push((
+            "detect_penalty".to_owned(),
+            entry(&self.detect_penalty, |n| toml::Value::Integer(*n as i64)).or_else(|| {
+                default(toml::Value::Integer(
+                    detect::DEFAULT_PENALTY.as_secs() as i64
+                ))
+            }),
+        ));
+        entries.push((
+            "max_bytes_per_day".to_owned(),
+            entry(&self.max_bytes_per_day, |n| toml::Value::Integer(*n as i64)),
+        ));
+        entries.push((
+            "max_line_length".to_owned(),
+            entry(&self.max_line_length, |n| toml::Value::Integer(*n as i64))
+                .or_else(|| default(toml::Value::Integer(dice::MAX_LINE_LEN as i64))),
+        ));
+        entries.push((
+            "replace_records".to_owned(),
+            entry(&self.replace_records, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "check_dns_first".to_owned(),
+            entry(&self.check_dns_first, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "dry_run".to_owned(),
+            entry(&self.dry_run, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "extra_fields".to_owned(),
+            entry(&self.extra_fields, |fields| fields_value(fields)),
+        ));
+        entries.push((
+            "nameserver".to_owned(),
+            entry(&self.nameserver, |ip| text(ip)),
+        ));
+        entries.push((
+            "resolver".to_owned(),
+            entry(&self.resolver, |spec| text(spec))
+                .or_else(|| default(text(ResolverSpec::default()))),
+        ));
+        entries.push((
+            "check_ownership".to_owned(),
+            entry(&self.check_ownership, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "strict_ownership".to_owned(),
+            entry(&self.strict_ownership, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "strict_ip_validation".to_owned(),
+            entry(&self.strict_ip_validation, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "tolerate_ack_mismatch".to_owned(),
+            entry(&self.tolerate_ack_mismatch, |b| toml::Value::Boolean(*b))
+                .or_else(|| default(toml::Value::Boolean(false))),
+        ));
+        entries.push((
+            "ownership_suffixes".to_owned(),
+            entry(&self.ownership_suffixes, |suffixes| fields_value(suffixes))
+                .or_else(|| default(fields_value(&self.ownership_suffixes()))),
+        ));
+        entries
+    }
+
+    pub fn render_toml(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in self.entries() {
+            match value {
+                Some((value, source)) => {
+                    out.push_str(&format!("{} = {} # {}\n", name, value, source))
+                }
+                None => out.push_str(&format!("# {} is not set\n", name)),
+            }
+        }
+        out
+    }
+
+    pub fn render_json(&self) -> serde_json::Value {
+        let mut map = serde_json::Map::new();
+        for (name, value) in self.entries() {
+            let entry = match value {
+                Some((value, source)) => serde_json::json!({
+                    "value": serde_json::to_value(value).unwrap_or_default(),
+                    "source": source.to_string(),
+                }),
+                None => serde_json::Value::Null,
+            };
+            map.insert(name.to_owned(), entry);
+        }
+        serde_json::Value::Object(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Pipe;
+
+    fn scratch(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "ddns_client-config-{}-{}",
+            std::process::id(),
+            name
+        ));
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn stacked(files: &[PathBuf]) -> Config {
+        let mut config = Config::default();
+        for path in Config::layers(files.to_vec()).unwrap() {
+            let (layer, warnings) = Config::from_file(&path).unwrap();
+            assert!(warnings.is_empty());
+            config.stack(layer);
+        }
+        config
+    }
+
+    fn hosts(config: &Config) -> Vec<&str> {
+        config
+            .records
+            .iter()
+            .map(|r| r.host.value.as_str())
+            .collect()
+    }
+
+    const BASE: &str = "user = \"base\"\npass = \"p\"\ndom = \"example.com\"\nip = \"192.0.2.1\"\n[[record]]\nhost = \"a\"\n";
+    const DROP_IN: &str = "ip = \"192.0.2.2\"\n[[record]]\nhost = \"b\"\n";
+    const EXTRA: &str = "user = \"extra\"\n[[record]]\nhost = \"c\"\n";
+
+    #[test]
+    fn layers_put_drop_ins_after_the_first_file() {
+        let dir = scratch(
+            "layers",
+            &[
+                ("main.toml", ""),
+                ("config.d/20-late.toml", ""),
+                ("config.d/10-early.toml", ""),
+                ("config.d/ignored.conf", ""),
+                ("extra.toml", ""),
+            ],
+        );
+        let files = vec![dir.join("main.toml"), dir.join("extra.toml")];
+        let layers = Config::layers(files).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let expected = vec![
+            dir.join("main.toml"),
+            dir.join("config.d/10-early.toml"),
+            dir.join("config.d/20-late.toml"),
+            dir.join("extra.toml"),
+        ];
+        assert_eq!(expected, layers);
+    }
+
+    #[test]
+    fn later_layers_win_and_records_append() {
+        let dir = scratch(
+            "merge",
+            &[
+                ("main.toml", BASE),
+                ("config.d/10-ip.toml", DROP_IN),
+                ("extra.toml", EXTRA),
+            ],
+        );
+        let config = stacked(&[dir.join("main.toml"), dir.join("extra.toml")]);
+        fs::remove_dir_all(&dir).unwrap();
+        let user = config.user.as_ref().unwrap();
+        assert_eq!("extra", user.value);
+        assert!(matches!(&user.source, Source::File { path, .. } if path.ends_with("extra.toml")));
+        let ip = config.ip.as_ref().unwrap();
+        assert_eq!(IpSource::Static(Ipv4Addr::new(192, 0, 2, 2)), ip.value);
+        assert!(matches!(&ip.source, Source::File { path, .. } if path.ends_with("10-ip.toml")));
+        assert_eq!(vec!["a", "b", "c"], hosts(&config));
+        assert!(
+            matches!(&config.pass.as_ref().unwrap().source, Source::File { path, .. } if path.ends_with("main.toml"))
+        );
+    }
+
+    #[test]
+    fn replace_records_drops_earlier_records() {
+        let extra = format!("replace_records = true\n{}", EXTRA);
+        let dir = scratch(
+            "replace",
+            &[
+                ("main.toml", BASE),
+                ("config.d/10-ip.toml", DROP_IN),
+                ("extra.toml", &extra),
+            ],
+        );
+        let config = stacked(&[dir.join("main.toml"), dir.join("extra.toml")]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(vec!["c"], hosts(&config));
+    }
+
+    #[test]
+    fn ip_source_lists_become_fallbacks() {
+        let text = "ip_source = [\"url:https://ip.example.net\", { cmd = [\"getip\", \"-4\"] }]\n\
+                    detect_penalty = \"5m\"\n\
+                    [[record]]\nhost = \"a\"\nip_source = [\"interface:eth0\"]\n";
+        let (config, warnings) = Config::from_toml(text, Path::new("list.toml")).unwrap();
+        assert!(warnings.is_empty());
+        let expected = IpSource::Fallback(vec![
+            IpSource::Url("https://ip.example.net".to_owned()),
+            IpSource::Command {
+                argv: vec!["getip".to_owned(), "-4".to_owned()],
+                clear_env: false,
+            },
+        ]);
+        assert_eq!(expected, config.ip.as_ref().unwrap().value);
+        let record = config.records[0].ip.as_ref().unwrap();
+        assert_eq!(IpSource::Interface("eth0".to_owned()), record.value);
+        assert_eq!(Duration::from_secs(300), config.detect_penalty());
+        assert_eq!(detect::DEFAULT_PENALTY, Config::default().detect_penalty());
+        let rendered = config.render_toml();
+        let line = rendered
+            .lines()
+            .find(|line| line.starts_with("ip_source = "))
+            .unwrap();
+        let value = line.split(" # ").next().unwrap();
+        let (again, _) = Config::from_toml(value, Path::new("again.toml")).unwrap();
+        assert_eq!(expected, again.ip.unwrap().value);
+    }
+
+    #[test]
+    fn ip_source_lists_reject_empty_and_nested_lists() {
+        for (text, message) in [
+            ("ip_source = []\n", "ip_source list is empty"),
+            ("ip_source = [[\"url:a\"], \"url:b\"]\n", "cannot nest"),
+        ] {
+            let e = Config::from_toml(text, Path::new("bad.toml")).unwrap_err();
+            assert!(e.to_string().contains(message), "{}: {}", text, e);
+        }
+    }
+
+    fn names(text: &str) -> Vec<String> {
+        let (config, _) = Config::from_toml(text, Path::new("names.toml")).unwrap();
+        let (records, _) = config.to_records().unwrap();
+        records.iter().map(|record| record.info.name()).collect()
+    }
+
+    #[test]
+    fn records_split_into_host_and_domain() {
+        let split = |record: &str, dom: Option<&str>| split_record(record, dom);
+        let owned = |host: &str, dom: &str| Ok((host.to_owned(), dom.to_owned()));
+        assert_eq!(owned("www", "example.com"), split("www.example.com", None));
+        assert_eq!(owned("", "example.com"), split("example.com.", None));
+        assert_eq!(owned("*", "example.com"), split("*.example.com", None));
+        assert_eq!(
+            owned("a.b", "example.com"),
+            split("a.b.example.com", Some("example.com"))
+        );
+        assert_eq!(
+            owned("", "example.com"),
+            split("EXAMPLE.com", Some("example.com"))
+        );
+        assert_eq!(
+            owned("www", "example.co.jp"),
+            split("www.example.co.jp", Some("example.co.jp"))
+        );
+        assert_eq!(owned("a.b", "example.com"), split("a.b.example.com", None));
+        #[cfg(not(feature = "psl"))]
+        for record in ["www.example.co.jp", "example.co.uk"] {
+            let e = split(record, None).unwrap_err();
+            assert!(e.contains("set dom to say where"), "{}: {}", record, e);
+        }
+        #[cfg(feature = "psl")]
+        {
+            assert_eq!(
+                owned("www", "example.co.jp"),
+                split("www.example.co.jp", None)
+            );
+            assert!(split("co.uk", None).unwrap_err().contains("public suffix"));
+        }
+        assert!(split("localhost", None)
+            .unwrap_err()
+            .contains("not a fully qualified name"));
+        assert!(split("www.example.net", Some("example.com")).is_err());
+        assert!(split("wwwexample.com", Some("example.com")).is_err());
+    }
+
+    #[test]
+    fn apex_and_wildcard_hosts() {
+        assert!(valid_host(""));
+        assert!(valid_host("*"));
+        assert!(valid_host("*.dev"));
+        assert!(valid_host("a.b"));
+        assert!(!valid_host("a.*"));
+        assert!(!valid_host("**"));
+        let base = "user = \"u\"\npass = \"p\"\ndom = \"example.com\"\nip = \"192.0.2.1\"\n";
+        assert_eq!(
+            vec!["example.com", "*.example.com", "a.b.example.com"],
+            names(&format!("{}host = \"@,*,a.b\"\n", base))
+        );
+        assert_eq!(
+            vec!["example.com", "*.example.com"],
+            names(&format!(
+                "{}[[record]]\nhost = \"@\"\n[[record]]\nhost = \"*\"\n",
+                base
+            ))
+        );
+    }
+
+    #[test]
+    fn record_setting_fills_host_and_domain() {
+        let text = "user = \"u\"\npass = \"p\"\nip = \"192.0.2.1\"\nrecord = \"www.example.com\"\n";
+        assert_eq!(vec!["www.example.com"], names(text));
+        let text = "dom = \"example.com\"\nrecord = \"a.b.example.com\"\n";
+        let (config, _) = Config::from_toml(text, Path::new("dom.toml")).unwrap();
+        assert_eq!("a.b", config.host.unwrap().value);
+        let e = Config::from_toml("record = \"example\"\n", Path::new("bad.toml"))
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("not a fully qualified name"), "{}", e);
+        let mut config = Config::default();
+        config.set(&KEY::HOST, "www", Source::Cli);
+        let e = config
+            .set_record("www.example.com", Source::Cli)
+            .unwrap_err();
+        assert!(e.to_string().contains("mutually exclusive"), "{}", e);
+    }
+
+    struct Scripted {
+        bulk: PartialInfo,
+        answers: Vec<&'static str>,
+        asked: Vec<(KEY, bool)>,
+    }
+
+    impl InputSource for Scripted {
+        fn can_prompt(&self) -> bool {
+            true
+        }
+
+        fn prompt(
+            &mut self,
+            key: &KEY,
+            _: Option<&str>,
+            secret: bool,
+        ) -> Result<String, InputError> {
+            self.asked.push((key.clone(), secret));
+            if self.answers.is_empty() {
+                return Err(InputError::Eof(key.clone()));
+            }
+            Ok(self.answers.remove(0).to_owned())
+        }
+
+        fn bulk(&mut self) -> Result<PartialInfo, InputError> {
+            Ok(std::mem::take(&mut self.bulk))
+        }
+    }
+
+    fn scripted(bulk: &[(KEY, &str)], answers: &[&'static str]) -> Scripted {
+        let mut partial = PartialInfo::default();
+        for (key, value) in bulk {
+            partial.set(key, (*value).to_owned());
+        }
+        Scripted {
+            bulk: partial,
+            answers: answers.to_vec(),
+            asked: Vec::new(),
+        }
+    }
+
+    fn assemble(
+        source: &mut dyn InputSource,
+        cli: &[(KEY, &str)],
+    ) -> Result<Vec<Vec<String>>, InputError> {
+        let mut config = Config::default();
+        config.set(&KEY::USER, "file-user", Source::Default);
+        let ip = IpSource::Static(Ipv4Addr::new(192, 0, 2, 1));
+        config.ip = Some(Setting::new(ip, Source::Default));
+        config.read_input(source)?;
+        for (key, value) in cli {
+            config.set(key, *value, Source::Cli);
+        }
+        config.prompt_missing(source, &config.missing())?;
+        let (records, diagnostics) = config.to_records().unwrap();
+        assert!(diagnostics.is_empty());
+        Ok(records
+            .iter()
+            .map(|record| {
+                KEY::all()
+                    .iter()
+                    .map(|key| record.info[key].clone())
+                    .collect()
+            })
+            .collect())
+    }
+
+    #[test]
+    fn scripted_input_assembles_what_the_pipe_does() {
+        let text = "user=alice\npass=secret\nhost=www\n";
+        let mut pipe = Pipe::new(io::Cursor::new(text), false);
+        let cli = [(KEY::DOM, "example.com")];
+        let expected = assemble(&mut pipe, &cli).unwrap();
+        assert_eq!(
+            vec![vec!["alice", "secret", "www", "example.com"]],
+            expected
+        );
+
+        let bulk = [
+            (KEY::USER, "alice"),
+            (KEY::PASS, "secret"),
+            (KEY::HOST, "www"),
+        ];
+        let mut source = scripted(&bulk, &[]);
+        assert_eq!(expected, assemble(&mut source, &cli).unwrap());
+        assert!(source.asked.is_empty());
+
+        let mut source = scripted(&[(KEY::USER, "alice")], &["secret", "www"]);
+        assert_eq!(expected, assemble(&mut source, &cli).unwrap());
+        assert_eq!(vec![(KEY::PASS, true), (KEY::HOST, false)], source.asked);
+    }
+
+    #[test]
+    fn cli_beats_bulk_input_beats_files() {
+        let mut source = scripted(&[(KEY::USER, "bob"), (KEY::PASS, "secret")], &["www"]);
+        let cli = [(KEY::USER, "alice"), (KEY::DOM, "example.com")];
+        let infos = assemble(&mut source, &cli).unwrap();
+        assert_eq!(vec![vec!["alice", "secret", "www", "example.com"]], infos);
+
+        let mut source = scripted(&[(KEY::PASS, "secret")], &["www"]);
+        let infos = assemble(&mut source, &[(KEY::DOM, "example.com")]).unwrap();
+        assert_eq!("file-user", infos[0][0]);
+    }
+
+    #[test]
+    fn prompt_errors_stop_assembly() {
+        let mut source = scripted(&[], &["secret"]);
+        match assemble(&mut source, &[]) {
+            Err(InputError::Eof(KEY::HOST)) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(vec![(KEY::PASS, true), (KEY::HOST, false)], source.asked);
+
+        let mut pipe = Pipe::new(io::Cursor::new("user=alice\nuser=bob\n"), true);
+        assert!(matches!(
+            assemble(&mut pipe, &[]),
+            Err(InputError::Strict(_))
+        ));
+    }
+
+    fn private(path: &Path, mode: u32) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn file_and_env_references_resolve_with_provenance() {
+        let dir = scratch(
+            "include",
+            &[
+                ("secret", "s3cret\n"),
+                ("crlf", "user\r\n"),
+                ("host", "www"),
+            ],
+        );
+        for name in ["secret", "crlf", "host"] {
+            private(&dir.join(name), 0o600);
+        }
+        env::set_var("DDNS_CLIENT_TEST_INCLUDE_DOM", "example.com");
+        let text = format!(
+            "user = \"@file:{0}/crlf\"\npass = \"@file:{0}/secret\"\ndom = \"@env:DDNS_CLIENT_TEST_INCLUDE_DOM\"\n\n[[record]]\nhost = \"@file:{0}/host\"\n",
+            dir.display()
+        );
+        let path = dir.join("config.toml");
+        let (config, diagnostics, warnings) = Config::parse_toml(&text, &path);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let value = |key| config.get(&key).unwrap().value.clone();
+        assert_eq!("user", value(KEY::USER));
+        assert_eq!("s3cret", value(KEY::PASS));
+        assert_eq!("example.com", value(KEY::DOM));
+        assert_eq!("www", config.records[0].host.value);
+
+        let pass = &config.get(&KEY::PASS).unwrap().source;
+        let expected = format!(
+            "file:{}/secret via file:{}:2:8",
+            dir.display(),
+            path.display()
+        );
+        assert_eq!(expected, pass.to_string());
+        let dom = config.get(&KEY::DOM).unwrap().source.to_string();
+        assert!(
+            dom.starts_with("env:DDNS_CLIENT_TEST_INCLUDE_DOM via "),
+            "{}",
+            dom
+        );
+        let rendered = config.render_toml();
+        assert!(!rendered.contains("s3cret"), "{}", rendered);
+        assert!(
+            rendered.contains(&format!("# file:{}/secret via", dir.display())),
+            "{}",
+            rendered
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_references_are_reported() {
+        let dir = scratch(
+            "include-broken",
+            &[("loop", "@env:HOME\n"), ("open", "secret")],
+        );
+        private(&dir.join("loop"), 0o600);
+        private(&dir.join("open"), 0o644);
+        let path = dir.join("config.toml");
+        let parse = |value: String| Config::parse_toml(&format!("pass = {:?}\n", value), &path);
+
+        let missing = format!("@file:{}/missing", dir.display());
+        let (_, diagnostics, _) = parse(missing.clone());
+        assert_eq!(1, diagnostics.len());
+        let message = &diagnostics[0].message;
+        assert!(
+            message.starts_with(&format!("could not resolve {}: ", missing)),
+            "{}",
+            message
+        );
+
+        let (_, diagnostics, _) = parse("@env:DDNS_CLIENT_TEST_INCLUDE_UNSET".to_owned());
+        assert!(diagnostics[0]
+            .message
+            .contains("DDNS_CLIENT_TEST_INCLUDE_UNSET"));
+
+        let (_, diagnostics, _) = parse(format!("@file:{}/loop", dir.display()));
+        assert!(
+            diagnostics[0]
+                .message
+                .ends_with("resolves to another @file: or @env: reference"),
+            "{:?}",
+            diagnostics
+        );
+
+        let (config, diagnostics, warnings) = parse(format!("@file:{}/open", dir.display()));
+        assert!(diagnostics.is_empty());
+        assert_eq!("secret", config.pass.unwrap().value);
+        assert_eq!(1, warnings.len());
+        assert!(
+            warnings[0].message.contains("(mode 644); run chmod 600"),
+            "{:?}",
+            warnings
+        );
+
+        let (config, diagnostics, _) = parse("@plain".to_owned());
+        assert!(diagnostics.is_empty());
+        assert_eq!("@plain", config.pass.unwrap().value);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn canary(global: Option<bool>) -> Vec<Record> {
+        let mut text = "user = \"u\"\npass = \"p\"\nip = \"203.0.113.7\"\n".to_owned();
+        if let Some(flag) = global {
+            text += &format!("dry_run = {}\n", flag);
+        }
+        for (host, dry_run) in [
+            ("unset", ""),
+            ("live", "dry_run = false\n"),
+            ("rehearsed", "dry_run = true\n"),
+        ] {
+            text += &format!(
+                "[[record]]\nhost = \"{}\"\ndom = \"example.com\"\n{}",
+                host, dry_run
+            );
+        }
+        let (config, _) = Config::from_toml(&text, Path::new("canary.toml")).unwrap();
+        assert_eq!(global, config.dry_run.as_ref().map(|setting| setting.value));
+        config.to_records().unwrap().0
+    }
+
+    #[test]
+    fn dry_run_precedence() {
+        for (global, forced, expected) in [
+            (None, false, [false, false, true]),
+            (Some(false), false, [false, false, true]),
+            (Some(true), false, [true, false, true]),
+            (None, true, [true, true, true]),
+            (Some(false), true, [true, true, true]),
+            (Some(true), true, [true, true, true]),
+        ] {
+            let records = canary(global);
+            let default = global.unwrap_or(false);
+            let rehearsed: Vec<bool> = records
+                .iter()
+                .map(|record| record.rehearsed(forced, default))
+                .collect();
+            assert_eq!(expected.to_vec(), rehearsed, "{:?} {}", global, forced);
+        }
+    }
+
+    #[test]
+    fn only_patterns_match_names_hosts_and_globs() {
+        let records = names_and_records(
+            "user = \"u\"\npass = \"p\"\nip = \"203.0.113.7\"\n\
+             [[record]]\nhost = \"www\"\ndom = \"example.com\"\n\
+             [[record]]\nhost = \"@\"\ndom = \"example.com\"\n\
+             [[record]]\nhost = \"www\"\ndom = \"example.org\"\n\
+             [[record]]\nhost = \"api.eu\"\ndom = \"example.org\"\n",
+        );
+        let matching = |pattern: &str| -> Vec<String> {
+            records
+                .iter()
+                .filter(|record| record.matches(pattern))
+                .map(|record| record.info.name())
+                .collect()
+        };
+        assert_eq!(vec!["www.example.com"], matching("www.example.com"));
+        assert_eq!(vec!["www.example.com"], matching("WWW.Example.COM."));
+        assert_eq!(vec!["www.example.com", "www.example.org"], matching("www"));
+        assert_eq!(vec!["example.com"], matching("@"));
+        assert_eq!(vec!["example.com"], matching("example.com"));
+        assert_eq!(vec!["api.eu.example.org"], matching("api.eu"));
+        assert_eq!(
+            vec!["www.example.org", "api.eu.example.org"],
+            matching("*.example.org")
+        );
+        assert_eq!(
+            vec!["www.example.com", "www.example.org"],
+            matching("www.example.???")
+        );
+        assert_eq!(4, matching("*").len());
+        assert!(matching("ww").is_empty());
+        assert!(matching("www.example").is_empty());
+        assert!(matching("*.example.net").is_empty());
+    }
+
+    fn names_and_records(text: &str) -> Vec<Record> {
+        let (config, _) = Config::from_toml(text, Path::new("only.toml")).unwrap();
+        config.to_records().unwrap().0
+    }
+}
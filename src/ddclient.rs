@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::config::{self, Config, ConfigError, Diagnostic, RecordConfig, Setting, Source};
+use crate::detect::IpSource;
+use crate::dice::ToIpAddrs;
+use crate::messages;
+use crate::KEY;
+
+pub const PROTOCOLS: [&str; 1] = ["onamae"];
+
+const WEB_SERVICES: [(&str, &str); 2] = [
+    ("ipify-ipv4", "https://api.ipify.org/"),
+    ("ipify", "https://api.ipify.org/"),
+];
+
+type Settings = BTreeMap<String, (String, Source)>;
+
+fn tokens(line: &str, column: usize) -> Result<Vec<(usize, String)>, String> {
+    let mut out: Vec<(usize, String)> = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut quote = None;
+    for (at, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), c) => current
+                .get_or_insert((column + at, String::new()))
+                .1
+                .push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                current.get_or_insert((column + at, String::new()));
+            }
+            (None, '#') => break,
+            (None, c) if c.is_whitespace() || ',' == c => out.extend(current.take()),
+            (None, c) => current
+                .get_or_insert((column + at, String::new()))
+                .1
+                .push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_owned());
+    }
+    out.extend(current);
+    let mut merged: Vec<(usize, String)> = Vec::new();
+    for (at, token) in out {
+        match merged.last_mut() {
+            Some((_, last)) if last.ends_with('=') || token.starts_with('=') => {
+                last.push_str(&token)
+            }
+            _ => merged.push((at, token)),
+        }
+    }
+    Ok(merged)
+}
+
+fn ip_source(settings: &Settings) -> Result<IpSource, String> {
+    let get = |key: &str| settings.get(key).map(|(value, _)| value.as_str());
+    let (kind, v4) = match (get("usev4"), get("use")) {
+        (Some(kind), _) => (kind.trim_end_matches("v4"), true),
+        (None, Some(kind)) => (kind, false),
+        (None, None) => return Err("no use= directive".to_owned()),
+    };
+    let arg = |key: &str| {
+        let named = if v4 {
+            format!("{}v4", key)
+        } else {
+            key.to_owned()
+        };
+        get(&named)
+            .or_else(|| get(key))
+            .ok_or_else(|| format!("use={} needs {}=", kind, named))
+    };
+    match kind {
+        "web" => {
+            let web = arg("web")?;
+            if web.contains("://") {
+                return Ok(IpSource::Url(web.to_owned()));
+            }
+            match WEB_SERVICES.iter().find(|(name, _)| *name == web) {
+                Some((_, url)) => Ok(IpSource::Url((*url).to_owned())),
+                None if web.contains('.') => Ok(IpSource::Url(format!("http://{}", web))),
+                None => Err(format!("built-in web service {:?} is not supported", web)),
+            }
+        }
+        "if" => Ok(IpSource::Interface(arg("if")?.to_owned())),
+        "cmd" => Ok(IpSource::Command {
+            argv: arg("cmd")?.split_whitespace().map(str::to_owned).collect(),
+            clear_env: false,
+        }),
+        "ip" => arg("ip")?
+            .to_ip_addrs()
+            .map(IpSource::Static)
+            .map_err(|e| e.to_string()),
+        other => Err(format!("use={} is not supported", other)),
+    }
+}
+
+pub fn parse(text: &str, path: &Path) -> (Config, Vec<Diagnostic>, Vec<Diagnostic>) {
+    let mut config = Config::default();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut globals = Settings::new();
+    let mut ignored = BTreeSet::new();
+    let mut logical = String::new();
+    let mut start = None;
+    let lines: Vec<&str> = text.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        let start_line = *start.get_or_insert(index + 1);
+        match line.strip_suffix('\\') {
+            Some(head) if index + 1 < lines.len() => {
+                logical.push_str(head);
+                logical.push(' ');
+                continue;
+            }
+            _ => logical.push_str(line),
+        }
+        let line_text = std::mem::take(&mut logical);
+        start = None;
+        let source_at = |column: usize| Source::File {
+            path: path.to_owned(),
+            line: start_line,
+            column,
+        };
+        let words = match tokens(&line_text, 1) {
+            Ok(words) => words,
+            Err(message) => {
+                let source = source_at(1);
+                errors.push(Diagnostic::new(&source, "line", message));
+                continue;
+            }
+        };
+        let mut local = Settings::new();
+        let mut hosts = Vec::new();
+        for (column, word) in words {
+            let source = source_at(column);
+            match word.split_once('=') {
+                Some((key, value)) => {
+                    let key = key.trim().to_ascii_lowercase().replace('_', "-");
+                    local.insert(key, (value.trim().to_owned(), source));
+                }
+                None => hosts.push((word, source)),
+            }
+        }
+        for (key, (_, source)) in &local {
+            let known = matches!(
+                key.as_str(),
+                "protocol"
+                    | "login"
+                    | "password"
+                    | "use"
+                    | "usev4"
+                    | "web"
+                    | "webv4"
+                    | "if"
+                    | "ifv4"
+                    | "cmd"
+                    | "cmdv4"
+                    | "ip"
+                    | "ipv4"
+                    | "zone"
+            );
+            if !known && ignored.insert(key.clone()) {
+                let message = messages::ddclient_ignored();
+                warnings.push(Diagnostic::new(source, key, message));
+            }
+        }
+        if hosts.is_empty() {
+            globals.extend(local);
+            continue;
+        }
+        let mut settings = globals.clone();
+        settings.extend(local);
+        let get = |key: &str| settings.get(key);
+        for (host, source) in hosts {
+            let skip = |warnings: &mut Vec<Diagnostic>, message: String| {
+                let message = messages::ddclient_host_skipped(&host, &message);
+                warnings.push(Diagnostic::new(&source, "host", message));
+            };
+            let protocol = get("protocol").map_or("dyndns2", |(value, _)| value.as_str());
+            if !PROTOCOLS.contains(&protocol.to_ascii_lowercase().as_str()) {
+                skip(&mut warnings, messages::ddclient_protocol(protocol));
+                continue;
+            }
+            let credentials = [(KEY::USER, "login"), (KEY::PASS, "password")];
+            let conflict =
+                credentials
+                    .iter()
+                    .find(|(key, name)| match (config.get(key), get(name)) {
+                        (Some(existing), Some((value, _))) => existing.value != *value,
+                        _ => false,
+                    });
+            if let Some((_, name)) = conflict {
+                skip(&mut warnings, messages::ddclient_credentials(name));
+                continue;
+            }
+            let ip = match ip_source(&settings) {
+                Ok(ip) => ip,
+                Err(message) => {
+                    skip(&mut warnings, message);
+                    continue;
+                }
+            };
+            for (key, name) in credentials.iter() {
+                if let (None, Some((value, at))) = (config.get(key), get(name)) {
+                    config.set(key, value.clone(), at.clone());
+                }
+            }
+            let zone = get("zone").map(|(zone, _)| zone.as_str());
+            match config::split_record(&host, zone) {
+                Ok((name, dom)) => config.records.push(RecordConfig {
+                    host: Setting::new(name, source.clone()),
+                    dom: Some(Setting::new(dom, source.clone())),
+                    ip: Some(Setting::new(ip, source.clone())),
+                    extra_fields: None,
+                    offline_ip: None,
+                    enabled: None,
+                    dry_run: None,
+                    window: None,
+                    timeout: None,
+                    connect_timeout: None,
+                    retries: None,
+                    priority: None,
+                    depends_on: None,
+                }),
+                Err(message) => errors.push(Diagnostic::new(&source, "host", message)),
+            }
+        }
+    }
+    (config, errors, warnings)
+}
+
+pub fn from_file(path: &Path) -> Result<(Config, Vec<Diagnostic>), ConfigError> {
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    match parse(&text, path) {
+        (config, errors, warnings) if errors.is_empty() => Ok((config, warnings)),
+        (_, errors, _) => Err(ConfigError::Invalid(errors)),
+    }
+}
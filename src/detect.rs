@@ -0,0 +1,1036 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::ffi::CStr;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::addr;
+use crate::cancel::CancelToken;
+use crate::clock::Clock;
+use crate::dice::{self, CountingStream, Resolve, ToIpAddrs};
+use crate::messages;
+use crate::tls;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const COMMAND_POLL: Duration = Duration::from_millis(20);
+const COMMAND_OUTPUT_MAX: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IpSource {
+    Static(Ipv4Addr),
+    Url(String),
+    Interface(String),
+    Dns(String),
+    Command { argv: Vec<String>, clear_env: bool },
+    Fallback(Vec<IpSource>),
+}
+
+impl fmt::Display for IpSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpSource::Static(ip) => write!(f, "static:{}", ip),
+            IpSource::Url(url) => write!(f, "url:{}", url),
+            IpSource::Interface(name) => write!(f, "interface:{}", name),
+            IpSource::Dns(name) => write!(f, "dns:{}", name),
+            IpSource::Command { argv, .. } => write!(f, "cmd:{}", argv.join(" ")),
+            IpSource::Fallback(sources) => {
+                let sources: Vec<String> = sources.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", sources.join(", "))
+            }
+        }
+    }
+}
+
+pub const KINDS: [&str; 5] = ["static", "url", "interface", "dns", "cmd"];
+
+impl FromStr for IpSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IpSource, String> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(IpSource::Url(s.to_owned()));
+        }
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) if !arg.is_empty() => (kind, arg),
+            _ => {
+                return Err(format!(
+                "invalid ip_source {:?}: expected static:IP, url:URL, interface:NAME, dns:NAME \
+                 or cmd:PROGRAM",
+                s
+            ))
+            }
+        };
+        match kind {
+            "static" => arg
+                .to_ip_addrs()
+                .map(IpSource::Static)
+                .map_err(|e| e.to_string()),
+            "url" => Ok(IpSource::Url(arg.to_owned())),
+            "interface" => Ok(IpSource::Interface(arg.to_owned())),
+            "dns" => Ok(IpSource::Dns(arg.to_owned())),
+            "cmd" if arg.trim().is_empty() => Err("cmd: needs a program".to_owned()),
+            "cmd" => Ok(IpSource::Command {
+                argv: arg.split_whitespace().map(str::to_owned).collect(),
+                clear_env: false,
+            }),
+            _ => Err(format!(
+                "unknown ip_source kind: {:?} (expected {})",
+                kind,
+                KINDS.join(", ")
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DetectError {
+    Io(io::Error),
+    Url(String),
+    Http(String),
+    NoAddress(String),
+    NotPublic(Ipv4Addr),
+    Command {
+        program: String,
+        reason: String,
+        stderr: String,
+    },
+    Fallback(Vec<(String, DetectError)>),
+    Cancelled,
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DetectError::Io(e) => write!(f, "{}", e),
+            DetectError::Url(url) => write!(f, "unsupported URL: {}", url),
+            DetectError::Http(status) => write!(
+                f,
+                "unexpected HTTP response: {}",
+                messages::sanitize(status)
+            ),
+            DetectError::NoAddress(what) => {
+                write!(f, "no IPv4 address found for {}", messages::sanitize(what))
+            }
+            DetectError::NotPublic(ip) => match special_range(*ip) {
+                Some(range) => write!(f, "{}", messages::special_address(*ip, range)),
+                None => write!(f, "{} is not a public address", ip),
+            },
+            DetectError::Command {
+                program,
+                reason,
+                stderr,
+            } => {
+                write!(f, "{} {}", messages::sanitize(program), reason)?;
+                match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+                    Some(line) => write!(f, " (stderr: {})", messages::sanitize(line.trim())),
+                    None => Ok(()),
+                }
+            }
+            DetectError::Fallback(errors) if errors.is_empty() => write!(f, "no source to try"),
+            DetectError::Fallback(errors) => {
+                let errors: Vec<String> = errors
+                    .iter()
+                    .map(|(source, e)| format!("{}: {}", source, e))
+                    .collect();
+                write!(f, "every source failed: {}", errors.join("; "))
+            }
+            DetectError::Cancelled => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl error::Error for DetectError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DetectError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DetectError {
+    fn from(e: io::Error) -> DetectError {
+        DetectError::Io(e)
+    }
+}
+
+fn fetch(url: &str, cancel: &CancelToken) -> Result<String, DetectError> {
+    match fetch_body(url, cancel) {
+        Err(_) if cancel.is_cancelled() => Err(DetectError::Cancelled),
+        res => res,
+    }
+}
+
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+pub fn bytes_transferred() -> (u64, u64) {
+    (
+        BYTES_SENT.load(Ordering::Relaxed),
+        BYTES_RECEIVED.load(Ordering::Relaxed),
+    )
+}
+
+fn exchange<S: Read + Write>(stream: S, request: &str, response: &mut Vec<u8>) -> io::Result<()> {
+    let mut stream = CountingStream::new(stream);
+    let result = stream
+        .write_all(request.as_bytes())
+        .and_then(|()| stream.read_to_end(response).map(drop));
+    BYTES_SENT.fetch_add(stream.bytes_written(), Ordering::Relaxed);
+    BYTES_RECEIVED.fetch_add(stream.bytes_read(), Ordering::Relaxed);
+    result
+}
+
+fn fetch_body(url: &str, cancel: &CancelToken) -> Result<String, DetectError> {
+    let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(DetectError::Url(url.to_owned()));
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => (host, port),
+            Err(_) => return Err(DetectError::Url(url.to_owned())),
+        },
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| DetectError::NoAddress(host.to_owned()))?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    let _registration = cancel.register(&stream);
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: ddns_client\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    let mut response = Vec::new();
+    if tls {
+        let connector = tls::default_connector().map_err(io::Error::other)?;
+        let stream = tls::connect(&connector, host, stream).map_err(io::Error::other)?;
+        exchange(stream, &request, &mut response)?;
+    } else {
+        exchange(stream, &request, &mut response)?;
+    }
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.lines().next().unwrap_or_default();
+    if Some("200") != status.split(' ').nth(1) {
+        return Err(DetectError::Http(status.to_owned()));
+    }
+    Ok(body.to_owned())
+}
+
+fn run_command(
+    argv: &[String],
+    clear_env: bool,
+    cancel: &CancelToken,
+) -> Result<Ipv4Addr, DetectError> {
+    let program = argv.first().cloned().unwrap_or_default();
+    let failed = |reason: String, stderr: &str| DetectError::Command {
+        program: program.clone(),
+        reason,
+        stderr: stderr.to_owned(),
+    };
+    let mut command = Command::new(&program);
+    command
+        .args(argv.iter().skip(1))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if clear_env {
+        command.env_clear();
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| failed(format!("could not be started: {}", e), ""))?;
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut out = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.by_ref().take(COMMAND_OUTPUT_MAX).read_to_end(&mut out);
+                let _ = io::copy(&mut pipe, &mut io::sink());
+            }
+            String::from_utf8_lossy(&out).into_owned()
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() || TIMEOUT <= started.elapsed() {
+            let _ = child.kill();
+            let _ = child.wait();
+            if cancel.is_cancelled() {
+                return Err(DetectError::Cancelled);
+            }
+            let reason = format!("timed out after {}s", TIMEOUT.as_secs());
+            return Err(failed(reason, ""));
+        }
+        thread::sleep(COMMAND_POLL);
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        return Err(failed(format!("failed: {}", status), &stderr));
+    }
+    let line = stdout.lines().next().unwrap_or_default();
+    addr::parse_ipv4(line).map_err(|_| {
+        let reason = format!("printed {:?}, not an IPv4 address", line);
+        failed(reason, &stderr)
+    })
+}
+
+#[cfg(unix)]
+fn interface_addr(name: &str) -> Result<Ipv4Addr, DetectError> {
+    let mut addrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut found = None;
+    let mut cur = addrs;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let ifa_name = unsafe { CStr::from_ptr(ifa.ifa_name) };
+        let family = unsafe { (*ifa.ifa_addr).sa_family };
+        if ifa_name.to_bytes() == name.as_bytes() && libc::AF_INET == i32::from(family) {
+            let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+            found = Some(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)));
+            break;
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    found.ok_or_else(|| DetectError::NoAddress(format!("interface {}", name)))
+}
+
+#[cfg(not(unix))]
+fn interface_addr(name: &str) -> Result<Ipv4Addr, DetectError> {
+    Err(DetectError::NoAddress(format!("interface {}", name)))
+}
+
+pub const SPECIAL_RANGES: [(Ipv4Addr, u8, &str); 15] = [
+    (Ipv4Addr::new(0, 0, 0, 0), 8, "this network"),
+    (Ipv4Addr::new(10, 0, 0, 0), 8, "private"),
+    (Ipv4Addr::new(100, 64, 0, 0), 10, "carrier-grade NAT"),
+    (Ipv4Addr::new(127, 0, 0, 0), 8, "loopback"),
+    (Ipv4Addr::new(169, 254, 0, 0), 16, "link-local"),
+    (Ipv4Addr::new(172, 16, 0, 0), 12, "private"),
+    (Ipv4Addr::new(192, 0, 0, 0), 24, "IETF protocol assignments"),
+    (Ipv4Addr::new(192, 0, 2, 0), 24, "documentation"),
+    (Ipv4Addr::new(192, 168, 0, 0), 16, "private"),
+    (Ipv4Addr::new(198, 18, 0, 0), 15, "benchmarking"),
+    (Ipv4Addr::new(198, 51, 100, 0), 24, "documentation"),
+    (Ipv4Addr::new(203, 0, 113, 0), 24, "documentation"),
+    (Ipv4Addr::new(224, 0, 0, 0), 4, "multicast"),
+    (Ipv4Addr::new(240, 0, 0, 0), 4, "reserved"),
+    (Ipv4Addr::new(255, 255, 255, 255), 32, "broadcast"),
+];
+
+pub fn special_range(ip: Ipv4Addr) -> Option<(Ipv4Addr, u8, &'static str)> {
+    SPECIAL_RANGES
+        .iter()
+        .rev()
+        .find(|(network, prefix, _)| {
+            let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(*network)
+        })
+        .copied()
+}
+
+pub fn is_public(ip: Ipv4Addr) -> bool {
+    special_range(ip).is_none()
+}
+
+pub fn detect(source: &IpSource, resolver: &dyn Resolve) -> Result<Ipv4Addr, DetectError> {
+    detect_with(source, resolver, &CancelToken::new())
+}
+
+pub fn detect_with(
+    source: &IpSource,
+    resolver: &dyn Resolve,
+    cancel: &CancelToken,
+) -> Result<Ipv4Addr, DetectError> {
+    if cancel.is_cancelled() {
+        return Err(DetectError::Cancelled);
+    }
+    let ip = match source {
+        IpSource::Static(ip) => return Ok(*ip),
+        IpSource::Fallback(sources) => {
+            let mut errors = Vec::new();
+            for source in sources {
+                match detect_with(source, resolver, cancel) {
+                    Ok(ip) => return Ok(ip),
+                    Err(DetectError::Cancelled) => return Err(DetectError::Cancelled),
+                    Err(e) => errors.push((source.to_string(), e)),
+                }
+            }
+            return Err(DetectError::Fallback(errors));
+        }
+        IpSource::Url(url) => {
+            let body = fetch(url, cancel)?;
+            addr::parse_ipv4(&body)
+                .map_err(|_| DetectError::NoAddress(format!("{} (got {:?})", url, body)))?
+        }
+        IpSource::Interface(name) => interface_addr(name)?,
+        IpSource::Command { argv, clear_env } => run_command(argv, *clear_env, cancel)?,
+        IpSource::Dns(name) => dice::resolve_to_ip(resolver, name)?
+            .into_iter()
+            .find_map(|ip| match addr::unmap(ip) {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or_else(|| DetectError::NoAddress(name.clone()))?,
+    };
+    if !is_public(ip) {
+        return Err(DetectError::NotPublic(ip));
+    }
+    Ok(ip)
+}
+
+pub struct Detector<'a> {
+    cache: HashMap<IpSource, Result<Ipv4Addr, DetectError>>,
+    resolver: &'a dyn Resolve,
+    cancel: CancelToken,
+    penalties: Option<(Penalties, &'a dyn Clock)>,
+}
+
+impl<'a> Detector<'a> {
+    pub fn new(resolver: &'a dyn Resolve, cancel: CancelToken) -> Detector<'a> {
+        Detector {
+            cache: HashMap::new(),
+            resolver,
+            cancel,
+            penalties: None,
+        }
+    }
+
+    pub fn with_penalties(mut self, penalties: Penalties, clock: &'a dyn Clock) -> Detector<'a> {
+        self.penalties = Some((penalties, clock));
+        self
+    }
+
+    pub fn penalties(&self) -> Option<&Penalties> {
+        self.penalties.as_ref().map(|(penalties, _)| penalties)
+    }
+
+    pub fn detect(&mut self, source: &IpSource) -> Result<Ipv4Addr, &DetectError> {
+        let (resolver, cancel) = (self.resolver, &self.cancel);
+        let penalties = &mut self.penalties;
+        self.cache
+            .entry(source.clone())
+            .or_insert_with(|| match (source, penalties) {
+                (IpSource::Fallback(sources), Some((penalties, clock))) => {
+                    detect_first(sources, resolver, penalties, clock.unix_now(), cancel)
+                        .map(|(ip, _)| ip)
+                }
+                _ => detect_with(source, resolver, cancel),
+            })
+            .as_ref()
+            .map(|ip| *ip)
+    }
+}
+
+pub const DEFAULT_PENALTY: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Penalties {
+    window: u64,
+    failed: BTreeMap<String, u64>,
+}
+
+impl Default for Penalties {
+    fn default() -> Penalties {
+        Penalties::new(DEFAULT_PENALTY, BTreeMap::new())
+    }
+}
+
+impl Penalties {
+    pub fn new(window: Duration, failed: BTreeMap<String, u64>) -> Penalties {
+        Penalties {
+            window: window.as_secs(),
+            failed,
+        }
+    }
+
+    pub fn failed(&self) -> &BTreeMap<String, u64> {
+        &self.failed
+    }
+
+    pub fn record_failure(&mut self, source: &IpSource, now: u64) {
+        self.failed.insert(source.to_string(), now);
+    }
+
+    pub fn record_success(&mut self, source: &IpSource) {
+        self.failed.remove(&source.to_string());
+    }
+
+    pub fn remaining(&self, source: &IpSource, now: u64) -> Option<u64> {
+        self.remaining_for(&source.to_string(), now)
+    }
+
+    fn remaining_for(&self, source: &str, now: u64) -> Option<u64> {
+        let failed = self.failed.get(source)?;
+        let left = (failed + self.window).saturating_sub(now);
+        Some(left).filter(|left| 0 < *left)
+    }
+
+    pub fn expire(&mut self, now: u64) {
+        let window = self.window;
+        self.failed.retain(|_, failed| now < *failed + window);
+    }
+
+    pub fn entries(&self, now: u64) -> impl Iterator<Item = (&str, Option<u64>)> + '_ {
+        self.failed
+            .keys()
+            .map(move |source| (source.as_str(), self.remaining_for(source, now)))
+    }
+
+    pub fn to_json(&self, now: u64) -> serde_json::Value {
+        let mut status = serde_json::Map::new();
+        for (source, remaining) in self.entries(now) {
+            status.insert(
+                source.to_owned(),
+                serde_json::json!({
+                    "penalized": remaining.is_some(),
+                    "remaining": remaining.unwrap_or(0),
+                }),
+            );
+        }
+        serde_json::Value::Object(status)
+    }
+}
+
+pub fn detect_first<'a>(
+    sources: &'a [IpSource],
+    resolver: &dyn Resolve,
+    penalties: &mut Penalties,
+    now: u64,
+    cancel: &CancelToken,
+) -> Result<(Ipv4Addr, &'a IpSource), DetectError> {
+    let fresh: Vec<&IpSource> = sources
+        .iter()
+        .filter(|source| penalties.remaining(source, now).is_none())
+        .collect();
+    let tried = if fresh.is_empty() {
+        sources.iter().collect()
+    } else {
+        fresh
+    };
+    let mut errors = Vec::new();
+    for source in tried {
+        match detect_with(source, resolver, cancel) {
+            Ok(ip) => {
+                penalties.record_success(source);
+                return Ok((ip, source));
+            }
+            Err(DetectError::Cancelled) => return Err(DetectError::Cancelled),
+            Err(e) => {
+                penalties.record_failure(source, now);
+                errors.push((source.to_string(), e));
+            }
+        }
+    }
+    Err(DetectError::Fallback(errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    const CLASSIFIED: [(&str, Option<&str>); 34] = [
+        ("0.0.0.0", Some("this network")),
+        ("0.255.255.255", Some("this network")),
+        ("1.0.0.0", None),
+        ("9.255.255.255", None),
+        ("10.0.0.0", Some("private")),
+        ("10.255.255.255", Some("private")),
+        ("11.0.0.0", None),
+        ("100.63.255.255", None),
+        ("100.64.0.0", Some("carrier-grade NAT")),
+        ("100.127.255.255", Some("carrier-grade NAT")),
+        ("100.128.0.0", None),
+        ("127.0.0.1", Some("loopback")),
+        ("169.254.1.1", Some("link-local")),
+        ("169.255.0.0", None),
+        ("172.15.255.255", None),
+        ("172.16.0.0", Some("private")),
+        ("172.31.255.255", Some("private")),
+        ("172.32.0.0", None),
+        ("192.0.0.8", Some("IETF protocol assignments")),
+        ("192.0.1.0", None),
+        ("192.0.2.255", Some("documentation")),
+        ("192.168.255.255", Some("private")),
+        ("192.169.0.0", None),
+        ("198.17.255.255", None),
+        ("198.18.0.0", Some("benchmarking")),
+        ("198.19.255.255", Some("benchmarking")),
+        ("198.20.0.0", None),
+        ("198.51.100.7", Some("documentation")),
+        ("203.0.113.7", Some("documentation")),
+        ("223.255.255.255", None),
+        ("224.0.0.1", Some("multicast")),
+        ("239.255.255.255", Some("multicast")),
+        ("254.255.255.255", Some("reserved")),
+        ("255.255.255.255", Some("broadcast")),
+    ];
+
+    #[test]
+    fn special_ranges_classify_edges() {
+        for (ip, expected) in &CLASSIFIED {
+            let ip: Ipv4Addr = ip.parse().unwrap();
+            let name = special_range(ip).map(|(_, _, name)| name);
+            assert_eq!(*expected, name, "{}", ip);
+            assert_eq!(expected.is_none(), is_public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn special_ranges_cover_their_blocks() {
+        for (network, prefix, name) in &SPECIAL_RANGES {
+            let first = u32::from(*network);
+            let last = first | u32::MAX.checked_shr(u32::from(*prefix)).unwrap_or(0);
+            assert_eq!(
+                0,
+                first & !u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0)
+            );
+            for ip in [first, first + (last - first) / 2, last] {
+                let (_, found, found_name) = special_range(Ipv4Addr::from(ip)).unwrap();
+                assert!(*prefix <= found, "{}", Ipv4Addr::from(ip));
+                if *prefix == found {
+                    assert_eq!(name, &found_name, "{}", Ipv4Addr::from(ip));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn special_ranges_agree_with_std() {
+        for ip in (0..=u32::MAX).step_by(9973).map(Ipv4Addr::from) {
+            let reserved = ip.is_unspecified()
+                || ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || ip.is_broadcast();
+            if reserved {
+                assert!(!is_public(ip), "{}", ip);
+            }
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    fn stub() -> crate::dns::StubResolver {
+        crate::dns::StubResolver::new()
+            .a("home.example.net", Ipv4Addr::new(93, 184, 216, 34))
+            .a("lan.example.net", Ipv4Addr::new(192, 168, 1, 9))
+            .aaaa("v6.example.net", "2001:db8::9".parse().unwrap())
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn dns_source_uses_the_resolver() {
+        let source = IpSource::Dns("home.example.net.".to_owned());
+        assert_eq!(
+            Ipv4Addr::new(93, 184, 216, 34),
+            detect(&source, &stub()).unwrap()
+        );
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn dns_source_rejects_private_and_missing_addresses() {
+        let lan = IpSource::Dns("lan.example.net".to_owned());
+        assert!(matches!(
+            detect(&lan, &stub()),
+            Err(DetectError::NotPublic(_))
+        ));
+        for name in &["v6.example.net", "missing.example.net"] {
+            let source = IpSource::Dns(name.to_string());
+            assert!(matches!(
+                detect(&source, &stub()),
+                Err(DetectError::NoAddress(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    #[test]
+    fn detector_caches_per_source() {
+        let resolver = stub();
+        let mut detector = Detector::new(&resolver, CancelToken::new());
+        let source = IpSource::Dns("home.example.net".to_owned());
+        assert!(detector.detect(&source).is_ok());
+        assert_eq!(1, detector.cache.len());
+        assert!(detector.detect(&source).is_ok());
+        assert_eq!(1, detector.cache.len());
+    }
+
+    struct Lookups {
+        asked: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl Lookups {
+        fn new() -> Lookups {
+            Lookups {
+                asked: std::cell::RefCell::new(Vec::new()),
+            }
+        }
+
+        fn asked(&self) -> Vec<String> {
+            self.asked.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl Resolve for Lookups {
+        fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<std::net::SocketAddr>> {
+            self.asked.borrow_mut().push(host.to_owned());
+            match host {
+                "live.example.net" => Ok(vec![(Ipv4Addr::new(93, 184, 216, 34), port).into()]),
+                _ => Err(io::Error::new(io::ErrorKind::NotFound, "no such host")),
+            }
+        }
+    }
+
+    fn sources() -> Vec<IpSource> {
+        vec![
+            IpSource::Dns("dead.example.net".to_owned()),
+            IpSource::Dns("live.example.net".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn penalties_expire_after_the_window() {
+        let clock = MockClock::new(1_800_000_000);
+        let dead = &sources()[0];
+        let mut penalties = Penalties::new(Duration::from_secs(600), BTreeMap::new());
+        assert_eq!(None, penalties.remaining(dead, clock.unix_now()));
+        penalties.record_failure(dead, clock.unix_now());
+        assert_eq!(Some(600), penalties.remaining(dead, clock.unix_now()));
+        clock.advance(Duration::from_secs(599));
+        assert_eq!(Some(1), penalties.remaining(dead, clock.unix_now()));
+        penalties.expire(clock.unix_now());
+        assert_eq!(1, penalties.failed().len());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(None, penalties.remaining(dead, clock.unix_now()));
+        let json = penalties.to_json(clock.unix_now());
+        assert_eq!(false, json["dns:dead.example.net"]["penalized"]);
+        penalties.expire(clock.unix_now());
+        assert!(penalties.failed().is_empty());
+        penalties.record_failure(dead, clock.unix_now());
+        penalties.record_success(dead);
+        assert!(penalties.failed().is_empty());
+        assert_eq!(DEFAULT_PENALTY.as_secs(), Penalties::default().window);
+    }
+
+    #[test]
+    fn detect_first_skips_penalized_sources() {
+        let clock = MockClock::new(1_800_000_000);
+        let resolver = Lookups::new();
+        let cancel = CancelToken::new();
+        let sources = sources();
+        let mut penalties = Penalties::default();
+        let first = detect_first(
+            &sources,
+            &resolver,
+            &mut penalties,
+            clock.unix_now(),
+            &cancel,
+        );
+        assert_eq!(&sources[1], first.unwrap().1);
+        assert_eq!(
+            vec!["dead.example.net", "live.example.net"],
+            resolver.asked()
+        );
+        assert!(penalties.remaining(&sources[0], clock.unix_now()).is_some());
+        clock.advance(Duration::from_secs(60));
+        let next = detect_first(
+            &sources,
+            &resolver,
+            &mut penalties,
+            clock.unix_now(),
+            &cancel,
+        );
+        assert_eq!(&sources[1], next.unwrap().1);
+        assert_eq!(vec!["live.example.net"], resolver.asked());
+        clock.advance(DEFAULT_PENALTY);
+        let later = detect_first(
+            &sources,
+            &resolver,
+            &mut penalties,
+            clock.unix_now(),
+            &cancel,
+        );
+        assert!(later.is_ok());
+        assert_eq!(
+            vec!["dead.example.net", "live.example.net"],
+            resolver.asked()
+        );
+    }
+
+    #[test]
+    fn detect_first_tries_penalized_sources_when_none_is_fresh() {
+        let clock = MockClock::new(1_800_000_000);
+        let resolver = Lookups::new();
+        let sources = sources();
+        let mut penalties = Penalties::default();
+        for source in &sources {
+            penalties.record_failure(source, clock.unix_now());
+        }
+        let (ip, source) = detect_first(
+            &sources,
+            &resolver,
+            &mut penalties,
+            clock.unix_now(),
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert_eq!(Ipv4Addr::new(93, 184, 216, 34), ip);
+        assert_eq!(&sources[1], source);
+        assert_eq!(
+            vec!["dead.example.net", "live.example.net"],
+            resolver.asked()
+        );
+        assert_eq!(1, penalties.failed().len());
+    }
+
+    #[test]
+    fn one_shot_fallback_tries_every_source_in_order() {
+        let resolver = Lookups::new();
+        let fallback = IpSource::Fallback(sources());
+        for _ in 0..2 {
+            let mut detector = Detector::new(&resolver, CancelToken::new());
+            assert!(detector.detect(&fallback).is_ok());
+            assert_eq!(
+                vec!["dead.example.net", "live.example.net"],
+                resolver.asked()
+            );
+        }
+        let dead = IpSource::Fallback(vec![sources()[0].clone(), sources()[0].clone()]);
+        let e = detect(&dead, &resolver).unwrap_err();
+        assert!(matches!(&e, DetectError::Fallback(errors) if 2 == errors.len()));
+        assert!(e
+            .to_string()
+            .starts_with("every source failed: dns:dead.example.net: "));
+    }
+
+    #[test]
+    fn daemon_detector_carries_penalties_across_cycles() {
+        let clock = MockClock::new(1_800_000_000);
+        let resolver = Lookups::new();
+        let fallback = IpSource::Fallback(sources());
+        let mut penalties = Penalties::default();
+        for expected in [
+            vec!["dead.example.net", "live.example.net"],
+            vec!["live.example.net"],
+        ] {
+            let mut detector = Detector::new(&resolver, CancelToken::new())
+                .with_penalties(penalties.clone(), &clock);
+            assert!(detector.detect(&fallback).is_ok());
+            assert_eq!(expected, resolver.asked());
+            penalties = detector.penalties().unwrap().clone();
+            clock.advance(Duration::from_secs(300));
+        }
+        assert_eq!(
+            "[dns:dead.example.net, dns:live.example.net]",
+            fallback.to_string()
+        );
+    }
+
+    #[cfg(unix)]
+    fn script(dir: &std::path::Path, name: &str, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[cfg(unix)]
+    fn command(program: String, clear_env: bool) -> IpSource {
+        IpSource::Command {
+            argv: vec![program],
+            clear_env,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_source_runs_helper_scripts() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-cmd-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let resolver = Lookups::new();
+        let run = |name, body| detect(&command(script(&dir, name, body), false), &resolver);
+
+        let ip = run("lines", "echo ' 93.184.216.34 '\necho 10.0.0.1").unwrap();
+        assert_eq!(Ipv4Addr::new(93, 184, 216, 34), ip);
+        let e = run(
+            "exit",
+            "echo 93.184.216.34\necho 'modem unreachable' >&2\nexit 3",
+        );
+        let e = e.unwrap_err().to_string();
+        assert!(
+            e.ends_with("failed: exit status: 3 (stderr: modem unreachable)"),
+            "{}",
+            e
+        );
+        let e = run("garbage", "echo wan-up\necho 'no lease' >&2").unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("printed \"wan-up\", not an IPv4 address"),
+            "{}",
+            e
+        );
+        assert!(e.to_string().ends_with("(stderr: no lease)"), "{}", e);
+        let e = run("private", "echo 192.168.1.1").unwrap_err();
+        assert!(matches!(e, DetectError::NotPublic(_)));
+        let e = run("empty", "true").unwrap_err();
+        assert!(e.to_string().contains("printed \"\""), "{}", e);
+
+        let e = detect(
+            &command(dir.join("missing").display().to_string(), false),
+            &resolver,
+        );
+        assert!(e.unwrap_err().to_string().contains("could not be started"));
+        let argv = vec![
+            script(&dir, "args", "echo \"$2\""),
+            "-4".into(),
+            "93.184.216.35".into(),
+        ];
+        let source = IpSource::Command {
+            argv,
+            clear_env: false,
+        };
+        assert_eq!(
+            Ipv4Addr::new(93, 184, 216, 35),
+            detect(&source, &resolver).unwrap()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_source_scrubs_the_environment_on_request() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-env-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("DDNS_CLIENT_TEST_WAN_IP", "93.184.216.36");
+        let program = script(&dir, "env", "echo \"${DDNS_CLIENT_TEST_WAN_IP:-none}\"");
+        let resolver = Lookups::new();
+        let ip = detect(&command(program.clone(), false), &resolver).unwrap();
+        assert_eq!(Ipv4Addr::new(93, 184, 216, 36), ip);
+        let e = detect(&command(program, true), &resolver).unwrap_err();
+        assert!(e.to_string().contains("printed \"none\""), "{}", e);
+        std::env::remove_var("DDNS_CLIENT_TEST_WAN_IP");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_commands_fall_back_to_the_next_source() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-fall-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fallback = IpSource::Fallback(vec![
+            command(script(&dir, "fail", "exit 1"), false),
+            command(script(&dir, "junk", "echo junk"), false),
+            command(script(&dir, "good", "echo 93.184.216.37"), false),
+        ]);
+        let resolver = Lookups::new();
+        assert_eq!(
+            Ipv4Addr::new(93, 184, 216, 37),
+            detect(&fallback, &resolver).unwrap()
+        );
+        let dead = IpSource::Fallback(vec![command(script(&dir, "fail", "exit 1"), false)]);
+        let e = detect(&dead, &resolver).unwrap_err();
+        assert!(matches!(&e, DetectError::Fallback(errors) if 1 == errors.len()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn slow_commands_are_killed() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-slow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pid = dir.join("pid");
+        let body = format!("echo $$ > {}\nexec sleep 60", pid.display());
+        let source = command(script(&dir, "slow", &body), false);
+        let resolver = Lookups::new();
+        let alive = |pid: &std::path::Path| {
+            let pid = std::fs::read_to_string(pid).unwrap();
+            let pid: libc::pid_t = pid.trim().parse().unwrap();
+            0 == unsafe { libc::kill(pid, 0) }
+        };
+
+        let started = Instant::now();
+        let e = detect(&source, &resolver).unwrap_err();
+        assert!(e.to_string().ends_with("timed out after 10s"), "{}", e);
+        assert!(started.elapsed() < TIMEOUT + Duration::from_secs(2));
+        assert!(!alive(&pid));
+
+        std::fs::remove_file(&pid).unwrap();
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let waiter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        let e = detect_with(&source, &resolver, &cancel).unwrap_err();
+        waiter.join().unwrap();
+        assert!(matches!(e, DetectError::Cancelled));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(!alive(&pid));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn url_detection_counts_the_bytes_it_transfers() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ip", listener.local_addr().unwrap());
+        let response = "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n93.184.216.38\n";
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 512];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(0 < n);
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            request.len() as u64
+        });
+        let (sent, received) = bytes_transferred();
+        let ip = detect(&IpSource::Url(url), &Lookups::new()).unwrap();
+        assert_eq!(Ipv4Addr::new(93, 184, 216, 38), ip);
+        let request = server.join().unwrap();
+        let (sent_after, received_after) = bytes_transferred();
+        assert!(request <= sent_after - sent);
+        assert!(response.len() as u64 <= received_after - received);
+    }
+}
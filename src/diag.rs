@@ -0,0 +1,194 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const HISTORY_ENTRIES: usize = 50;
+pub const TRACE_LINES: usize = 1000;
+pub static DEFAULT_OUTPUT: &str = "ddns_client-diag.tar.gz";
+static ROOT: &str = "ddns_client-diag";
+
+const BLOCK: usize = 512;
+const STORED_MAX: usize = 0xffff;
+
+#[derive(Debug, Default)]
+pub struct Bundle {
+    secrets: Vec<String>,
+    files: Vec<(String, String)>,
+}
+
+impl Bundle {
+    pub fn new<I, S>(secrets: I) -> Bundle
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut secrets: Vec<String> = secrets
+            .into_iter()
+            .map(Into::into)
+            .filter(|secret| !secret.is_empty())
+            .collect();
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        Bundle {
+            secrets,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        for line in text.split_inclusive('\n') {
+            let (body, end) = match line.strip_suffix('\n') {
+                Some(body) => (body, "\n"),
+                None => (line, ""),
+            };
+            let mut body = crate::redact(body).into_owned();
+            for secret in &self.secrets {
+                body = body.replace(secret.as_str(), "***");
+            }
+            out.push_str(&body);
+            out.push_str(end);
+        }
+        out
+    }
+
+    pub fn add(&mut self, name: &str, text: &str) {
+        let text = self.redact(text);
+        self.files.push((format!("{}/{}", ROOT, name), text));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn to_tar_gz(&self, mtime: u64) -> Vec<u8> {
+        let mut archive = Vec::new();
+        for (name, text) in &self.files {
+            archive.extend_from_slice(&tar_header(name, text.len(), mtime));
+            archive.extend_from_slice(text.as_bytes());
+            archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+        }
+        archive.resize(archive.len() + 2 * BLOCK, 0);
+        gzip(&archive, mtime)
+    }
+
+    pub fn write(&self, path: &Path, mtime: u64) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(&self.to_tar_gz(mtime))?;
+        file.sync_all()
+    }
+}
+
+pub fn tail(path: &Path, lines: usize) -> io::Result<String> {
+    let text = fs::read_to_string(path)?;
+    let all: Vec<&str> = text.lines().collect();
+    let start = all.len().saturating_sub(lines);
+    Ok(all[start..]
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn tar_header(name: &str, size: usize, mtime: u64) -> [u8; BLOCK] {
+    let mut header = [0u8; BLOCK];
+    let name = name.as_bytes();
+    let (prefix, name) = match name.len() {
+        0..=100 => (&[][..], name),
+        _ => match name.iter().rposition(|&b| b'/' == b) {
+            Some(at) if at <= 155 && name.len() - at - 1 <= 100 => (&name[..at], &name[at + 1..]),
+            _ => (&[][..], &name[..100]),
+        },
+    };
+    header[..name.len()].copy_from_slice(name);
+    octal(&mut header[100..108], 0o600);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size as u64);
+    octal(&mut header[136..148], mtime);
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix);
+    let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    let sum = format!("{:06o}\0 ", sum);
+    header[148..156].copy_from_slice(sum.as_bytes());
+    header
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+fn gzip(data: &[u8], mtime: u64) -> Vec<u8> {
+    let mut out = vec![0x1f, 0x8b, 8, 0];
+    out.extend_from_slice(&(mtime as u32).to_le_bytes());
+    out.extend_from_slice(&[0, 0xff]);
+    let mut chunks = data.chunks(STORED_MAX).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let len = chunk.len() as u16;
+        out.push(u8::from(chunks.peek().is_none()));
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn redact_masks_secrets_and_password_lines() {
+        let bundle = Bundle::new(vec!["pass", "passphrase", ""]);
+        assert_eq!(
+            "a *** b ***\nPASSWORD:***\nkept\n",
+            bundle.redact("a passphrase b pass\nPASSWORD:other\nkept\n")
+        );
+        assert_eq!("no newline ***", bundle.redact("no newline pass"));
+    }
+
+    #[test]
+    fn archive_framing() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+        let header = tar_header("ddns_client-diag/state.json", 10, 0);
+        assert_eq!(b"ddns_client-diag/state.json\0", &header[..28]);
+        assert_eq!(b"00000000012\0", &header[124..136]);
+        let long = format!("{}/{}", "d".repeat(120), "file.txt");
+        let header = tar_header(&long, 0, 0);
+        assert_eq!(b"file.txt\0", &header[..9]);
+        assert_eq!("d".repeat(120).as_bytes(), &header[345..465]);
+
+        let mut bundle = Bundle::new(Vec::<String>::new());
+        bundle.add("a.txt", "hello\n");
+        let gz = bundle.to_tar_gz(0);
+        assert_eq!(&[0x1f, 0x8b, 8, 0], &gz[..4]);
+        let size = u32::from_le_bytes(gz[gz.len() - 4..].try_into().unwrap());
+        assert_eq!(4 * BLOCK as u32, size);
+    }
+}
@@ -1,8 +1,70 @@
+use std::borrow::Cow;
 use std::error;
 use std::fmt;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
-use std::net::{AddrParseError, Ipv4Addr};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
 use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::addr::{self, AddrError};
+use crate::cancel::{CancelToken, Registration};
+use crate::clock::{Clock, SystemClock};
+use crate::messages;
+use crate::protocol::{self, Event, ProtocolMachine};
+use crate::socket::{self, LocalBind};
+use crate::tls::{self, Connector, Stream, TlsOptions};
+use crate::KEY;
+
+const LOGOUT_TIMEOUT: Duration = Duration::from_secs(5);
+const GREETING_TIMEOUT: Duration = Duration::from_secs(2);
+pub const MAX_LINE_LEN: usize = 8 * 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GreetingPolicy {
+    #[default]
+    Required,
+    Optional,
+    None,
+}
+
+impl fmt::Display for GreetingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GreetingPolicy::Required => write!(f, "required"),
+            GreetingPolicy::Optional => write!(f, "optional"),
+            GreetingPolicy::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for GreetingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GreetingPolicy, String> {
+        match s {
+            "required" => Ok(GreetingPolicy::Required),
+            "optional" => Ok(GreetingPolicy::Optional),
+            "none" => Ok(GreetingPolicy::None),
+            _ => Err(format!(
+                "invalid greeting policy {:?}: expected required, optional or none",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    pub lines: usize,
+    pub delay: Duration,
+}
+
+impl Pacing {
+    pub fn pause_after(&self, sent: usize, total: usize) -> bool {
+        0 < self.lines && sent.is_multiple_of(self.lines) && sent < total
+    }
+}
 
 #[derive(Debug)]
 pub enum ResponseError {
@@ -40,232 +102,2027 @@ impl error::Error for ResponseError {
     }
 }
 
-pub fn res_verify(res: &str) -> Result<(), Option<ResponseError>> {
-    match res.split(' ').next().unwrap().parse::<i32>() {
-        Ok(0) => Ok(()),
-        Ok(1) => Err(Some(ResponseError::CommandError)),
-        Ok(2) => Err(Some(ResponseError::LoginError)),
-        Ok(3) => Err(Some(ResponseError::DbError)),
-        Ok(4) => Err(Some(ResponseError::IpAddressError)),
-        Ok(5) => Err(Some(ResponseError::NoConnection)),
-        Ok(6) => Err(Some(ResponseError::NotFound)),
-        _ => Err(None),
+impl ResponseError {
+    pub fn from_code(code: u32) -> Option<ResponseError> {
+        match code {
+            1 => Some(ResponseError::CommandError),
+            2 => Some(ResponseError::LoginError),
+            3 => Some(ResponseError::DbError),
+            4 => Some(ResponseError::IpAddressError),
+            5 => Some(ResponseError::NoConnection),
+            6 => Some(ResponseError::NotFound),
+            _ => None,
+        }
     }
 }
 
-pub struct Client<T>
-where
-    T: Read + Write,
-{
-    stream: BufReader<T>,
-    pub verbose: bool,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotFoundScope {
+    Host,
+    Domain,
+    Unknown,
 }
 
-impl<T> Client<T>
-where
-    T: Read + Write,
-{
-    pub fn new(stream: T) -> Client<T> {
-        Client {
-            stream: BufReader::new(stream),
-            verbose: false,
+impl fmt::Display for NotFoundScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotFoundScope::Host => write!(f, "host"),
+            NotFoundScope::Domain => write!(f, "domain"),
+            NotFoundScope::Unknown => write!(f, "unknown"),
         }
     }
 }
 
-pub trait Command {
-    fn send(&mut self, cmd: &[&str]) -> io::Result<()>;
+impl NotFoundScope {
+    pub fn classify(message: &str) -> NotFoundScope {
+        let message = message.to_ascii_uppercase();
+        match (message.contains("HOST"), message.contains("DOMAIN")) {
+            (true, false) => NotFoundScope::Host,
+            (false, true) => NotFoundScope::Domain,
+            _ => NotFoundScope::Unknown,
+        }
+    }
+}
 
-    fn recv(&mut self, buf: &mut String) -> io::Result<()>;
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub code: u32,
+    pub message: String,
+    pub body: Vec<String>,
+}
 
-    fn recv_res(&mut self) -> Result<(), Option<ResponseError>> {
-        let mut buf = String::new();
-        self.recv(&mut buf).unwrap();
-        res_verify(&buf)
+impl Response {
+    pub fn is_ok(&self) -> bool {
+        0 == self.code
     }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    InvalidCode(String),
+}
 
-    fn call(&mut self, cmd: &[&str]) -> Result<(), Option<ResponseError>> {
-        self.send(cmd).unwrap();
-        self.recv_res()
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "{}", messages::empty_response()),
+            ParseError::InvalidCode(line) => write!(f, "{}", messages::invalid_status_line(line)),
+        }
     }
 }
 
-impl<T> Command for Client<T>
-where
-    T: Read + Write,
-{
-    fn send(&mut self, cmd: &[&str]) -> io::Result<()> {
-        let mut stream = BufWriter::new(self.stream.get_mut());
-        for token in cmd {
-            if self.verbose {
-                println!("{}", token);
+impl error::Error for ParseError {}
+
+pub const STATUS_DIGITS: usize = 3;
+
+pub fn parse_response(res: &str) -> Result<Response, ParseError> {
+    let lines: Vec<&str> = res.lines().collect();
+    protocol::parse_lines(&lines)
+}
+
+pub fn res_verify(res: &str) -> Result<(), Option<ResponseError>> {
+    match parse_response(res) {
+        Ok(response) if response.is_ok() => Ok(()),
+        Ok(response) => Err(ResponseError::from_code(response.code)),
+        Err(_) => Err(None),
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    Parse(ParseError),
+    Response(ResponseError, Response),
+    Unexpected(Response),
+    Open(OpenError),
+    SendTimeout(String, Duration),
+    RecvTimeout(String, Duration),
+    InvalidField(InvalidField),
+    LineTooLong(usize),
+    ServerMismatch {
+        record: String,
+        sent: Ipv4Addr,
+        acknowledged: Ipv4Addr,
+    },
+    Cancelled,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::Parse(e) => write!(f, "{}", e),
+            ClientError::Response(e, response) => {
+                write!(f, "{}", messages::response_error(e, &response.message))
             }
-            writeln!(stream, "{}", token)?;
+            ClientError::Open(e) => write!(f, "{}", e),
+            ClientError::SendTimeout(command, after) => {
+                write!(f, "{}", messages::send_timeout(command, *after))
+            }
+            ClientError::RecvTimeout(command, after) => {
+                write!(f, "{}", messages::recv_timeout(command, *after))
+            }
+            ClientError::InvalidField(e) => write!(f, "{}", e),
+            ClientError::LineTooLong(limit) => write!(f, "{}", LineTooLong(*limit)),
+            ClientError::ServerMismatch {
+                record,
+                sent,
+                acknowledged,
+            } => write!(f, "{}", messages::ack_mismatch(record, sent, acknowledged)),
+            ClientError::Cancelled => write!(f, "{}", messages::interrupted()),
+            ClientError::Unexpected(response) => write!(
+                f,
+                "{}",
+                messages::unexpected_response(
+                    format!("{:0width$}", response.code, width = STATUS_DIGITS),
+                    &response.message
+                )
+            ),
+        }
+    }
+}
+
+impl error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::Parse(e) => Some(e),
+            ClientError::Response(e, _) => Some(e),
+            ClientError::Open(e) => Some(e),
+            ClientError::InvalidField(e) => Some(e),
+            ClientError::Unexpected(_)
+            | ClientError::SendTimeout(..)
+            | ClientError::RecvTimeout(..)
+            | ClientError::LineTooLong(_)
+            | ClientError::ServerMismatch { .. }
+            | ClientError::Cancelled => None,
         }
-        if self.verbose {
-            println!(".");
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<LineTooLong>())
+        {
+            Some(LineTooLong(limit)) => ClientError::LineTooLong(*limit),
+            None => ClientError::Io(e),
         }
-        writeln!(stream, ".")?;
-        Ok(())
     }
+}
 
-    fn recv(&mut self, buf: &mut String) -> io::Result<()> {
-        loop {
-            let len = buf.len();
-            self.stream.read_line(buf)?;
-            if self.verbose {
-                print!("{}", &buf[len..]);
+impl From<OpenError> for ClientError {
+    fn from(e: OpenError) -> ClientError {
+        ClientError::Open(e)
+    }
+}
+
+impl ClientError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Io(_) | ClientError::SendTimeout(..) | ClientError::RecvTimeout(..) => {
+                true
             }
-            if ".\n" == &buf[len..] {
-                break;
+            ClientError::Open(e) => matches!(
+                e,
+                OpenError::Resolve(_) | OpenError::Connect(_) | OpenError::PortsExhausted(..)
+            ),
+            ClientError::Response(e, _) => {
+                matches!(e, ResponseError::DbError | ResponseError::NoConnection)
             }
+            _ => false,
+        }
+    }
+
+    pub fn is_ambiguous(&self) -> bool {
+        matches!(self, ClientError::RecvTimeout(command, _) if "MODIP" == command)
+    }
+
+    pub fn not_found_scope(&self) -> Option<NotFoundScope> {
+        match self {
+            ClientError::Response(ResponseError::NotFound, response) => {
+                Some(NotFoundScope::classify(&response.message))
+            }
+            _ => None,
         }
-        Ok(())
     }
 }
 
-trait CommandAuth<T> {
-    fn send_logout(&mut self) -> Result<(), Option<ResponseError>>;
-    fn send_login(&mut self, user: &str, pass: &str) -> Result<(), Option<ResponseError>>;
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
 }
 
-impl<T> CommandAuth<T> for T
-where
-    T: Command,
-{
-    fn send_logout(&mut self) -> Result<(), Option<ResponseError>> {
-        self.call(&["LOGOUT"])
+impl From<ParseError> for ClientError {
+    fn from(e: ParseError) -> ClientError {
+        ClientError::Parse(e)
     }
+}
 
-    fn send_login(&mut self, user: &str, pass: &str) -> Result<(), Option<ResponseError>> {
-        match self.call(&[
-            "LOGIN",
-            &format!("USERID:{}", user),
-            &format!("PASSWORD:{}", pass),
-        ]) {
-            Err(r) => {
-                self.send(&["LOGOUT"]).unwrap();
-                Err(r)
-            }
-            Ok(r) => Ok(r),
+impl From<Response> for ClientError {
+    fn from(response: Response) -> ClientError {
+        match ResponseError::from_code(response.code) {
+            Some(e) => ClientError::Response(e, response),
+            None => ClientError::Unexpected(response),
         }
     }
 }
 
-pub trait CommandModip<T> {
-    fn send_modip(
-        &mut self,
-        host: &str,
-        dom: &str,
-        ipv4: &str,
-    ) -> Result<(), Option<ResponseError>>;
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Direction {
+    Send,
+    Recv,
 }
 
-impl<T> CommandModip<T> for T
-where
-    T: Command,
-{
-    fn send_modip(
-        &mut self,
-        host: &str,
-        dom: &str,
-        ipv4: &str,
-    ) -> Result<(), Option<ResponseError>> {
-        match self.call(&[
-            "MODIP",
-            &format!("HOSTNAME:{}", host),
-            &format!("DOMNAME:{}", dom),
-            &format!("IPV4:{}", ipv4),
-        ]) {
-            Err(r) => {
-                self.send(&["LOGOUT"]).unwrap();
-                Err(r)
-            }
-            Ok(r) => Ok(r),
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::Send => write!(f, "send"),
+            Direction::Recv => write!(f, "recv"),
         }
     }
 }
 
-pub trait ToIpAddrs {
-    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrParseError>;
+pub type Observer = Box<dyn FnMut(Direction, &str) + Send>;
+
+pub fn stderr_observer() -> Observer {
+    Box::new(|_, line| eprintln!("{}", messages::sanitize(line)))
 }
 
-impl ToIpAddrs for &str {
-    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrParseError> {
-        Ipv4Addr::from_str(self)
+pub fn stdout_observer() -> Observer {
+    Box::new(|_, line| println!("{}", line))
+}
+
+pub struct CountingStream<T> {
+    inner: T,
+    read: u64,
+    written: u64,
+}
+
+impl<T> CountingStream<T> {
+    pub fn new(inner: T) -> CountingStream<T> {
+        CountingStream {
+            inner,
+            read: 0,
+            written: 0,
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
     }
 }
 
-impl ToIpAddrs for String {
-    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrParseError> {
-        Ipv4Addr::from_str(self)
+impl<T: Read> Read for CountingStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.read += len as u64;
+        Ok(len)
     }
 }
 
-impl ToIpAddrs for Ipv4Addr {
-    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrParseError> {
-        Ok(*self)
+impl<T: Write> Write for CountingStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
 
-#[derive(Debug)]
-pub struct Information {
-    pub user: String,
-    pub pass: String,
-    pub host: String,
-    pub dom: String,
-    pub ipaddr: Ipv4Addr,
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClientStats {
+    pub commands_sent: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub connect_time: Duration,
+    pub pool_hits: u64,
+    pub pool_misses: u64,
+    pub trust_reloads: u64,
 }
 
-impl Information {
-    pub fn new<T: ToIpAddrs>(
-        user: impl Into<String>,
-        pass: impl Into<String>,
-        host: impl Into<String>,
-        dom: impl Into<String>,
-        ipaddr: T,
-    ) -> Information {
-        Information {
-            user: user.into(),
-            pass: pass.into(),
-            host: host.into(),
-            dom: dom.into(),
-            ipaddr: ipaddr.to_ip_addrs().unwrap(),
+impl ClientStats {
+    pub fn add(&mut self, other: &ClientStats) {
+        self.commands_sent += other.commands_sent;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.connect_time += other.connect_time;
+        self.pool_hits += other.pool_hits;
+        self.pool_misses += other.pool_misses;
+        self.trust_reloads += other.trust_reloads;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "commands_sent": self.commands_sent,
+            "bytes_sent": self.bytes_sent,
+            "bytes_received": self.bytes_received,
+            "connect_time": self.connect_time.as_secs_f64(),
+            "pool_hits": self.pool_hits,
+            "pool_misses": self.pool_misses,
+            "trust_reloads": self.trust_reloads,
+        })
+    }
+}
+
+impl fmt::Display for ClientStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>10}: {} commands, {} bytes sent, {} bytes received, {:.3}s connecting",
+            "stats",
+            self.commands_sent,
+            self.bytes_sent,
+            self.bytes_received,
+            self.connect_time.as_secs_f64()
+        )?;
+        if 0 < self.pool_hits + self.pool_misses {
+            write!(
+                f,
+                ", {} pooled, {} opened",
+                self.pool_hits, self.pool_misses
+            )?;
+        }
+        if 0 < self.trust_reloads {
+            write!(f, ", {} CA reloads", self.trust_reloads)?;
         }
+        Ok(())
     }
 }
 
-pub trait CommandModipExt<T> {
-    fn run_modip(&mut self, info: &Information) -> Result<(), Option<ResponseError>>;
+pub struct Client<T>
+where
+    T: Read + Write,
+{
+    stream: CountingStream<T>,
+    machine: ProtocolMachine,
+    connect_time: Duration,
+    observer: Option<Observer>,
+    cancel: Option<CancelToken>,
+    registration: Option<Registration>,
+    socket: Option<TcpStream>,
+    timings: Timings,
+    pacing: Pacing,
+    policy: SessionPolicy,
+    authenticated: bool,
 }
 
-impl<T> CommandModipExt<T> for T
+impl<T> Client<T>
 where
-    T: Command,
+    T: Read + Write,
 {
-    fn run_modip(&mut self, info: &Information) -> Result<(), Option<ResponseError>> {
-        self.send_login(&info.user, &info.pass)?;
-        self.send_modip(&info.host, &info.dom, &info.ipaddr.to_string())?;
-        self.send_logout()?;
-        Ok(())
+    pub fn new(stream: T) -> Client<T> {
+        Client {
+            stream: CountingStream::new(stream),
+            machine: ProtocolMachine::new(),
+            connect_time: Duration::ZERO,
+            observer: None,
+            cancel: None,
+            registration: None,
+            socket: None,
+            timings: Timings::default(),
+            pacing: Pacing::default(),
+            policy: SessionPolicy::default(),
+            authenticated: false,
+        }
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub fn logout(&mut self) -> Result<Response, ClientError> {
+        self.send_logout()
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    pub fn set_connect_time(&mut self, elapsed: Duration) {
+        self.connect_time = elapsed;
+    }
+
+    pub fn stats(&self) -> ClientStats {
+        ClientStats {
+            commands_sent: self.machine.commands(),
+            bytes_sent: self.stream.bytes_written(),
+            bytes_received: self.stream.bytes_read(),
+            connect_time: self.connect_time,
+            ..ClientStats::default()
+        }
+    }
+
+    pub fn set_cancel_token(&mut self, cancel: CancelToken) {
+        self.cancel = Some(cancel);
+        self.register();
+    }
+
+    pub fn set_socket(&mut self, socket: TcpStream) {
+        self.socket = Some(socket);
+        self.register();
+    }
+
+    fn register(&mut self) {
+        self.registration = match (&self.cancel, &self.socket) {
+            (Some(cancel), Some(socket)) => Some(cancel.register(socket)),
+            _ => None,
+        };
+    }
+
+    pub fn set_observer(&mut self, observer: Observer) {
+        self.observer = Some(observer);
+        self.machine.set_transcript(true);
+    }
+
+    pub fn clear_observer(&mut self) -> Option<Observer> {
+        self.machine.set_transcript(false);
+        self.observer.take()
+    }
+
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+    }
+
+    pub fn set_max_line_len(&mut self, max_len: usize) {
+        self.machine.set_max_line_len(max_len);
+    }
+
+    pub fn set_redact(&mut self, redact: bool) {
+        self.machine.set_redact(redact);
+    }
+
+    pub fn set_verbose(&mut self, verbose: bool) {
+        if verbose {
+            self.set_observer(stderr_observer());
+        } else {
+            self.clear_observer();
+        }
+    }
+
+    fn observe(&mut self) {
+        let transcript = self.machine.take_transcript();
+        if let Some(observer) = self.observer.as_mut() {
+            for (dir, line) in transcript {
+                observer(dir, &line);
+            }
+        }
     }
 }
 
-pub static HOST: &str = "ddnsclient.onamae.com";
-pub static PORT: u16 = 65010;
-pub static DOMAIN: &str = "ddnsclient.onamae.com";
+pub trait Command {
+    fn send(&mut self, cmd: &[&str]) -> io::Result<()>;
+
+    fn recv(&mut self) -> Result<Response, ClientError>;
+
+    fn cancelled(&self) -> bool {
+        false
+    }
+
+    fn record_timing(&mut self, _stage: &str, _elapsed: Duration) {}
+
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<Option<Duration>> {
+        Ok(None)
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        SessionPolicy::default()
+    }
+
+    fn set_session_policy(&mut self, _policy: SessionPolicy) {}
+
+    fn set_authenticated(&mut self, _authenticated: bool) {}
+
+    fn logout_on_error(&mut self) {
+        if self.session_policy().logout_on_error {
+            self.discard_logout();
+        }
+    }
+
+    fn discard_logout(&mut self) {
+        self.set_authenticated(false);
+        if self.send(&["LOGOUT"]).is_err() {
+            return;
+        }
+        let previous = self.set_read_timeout(Some(LOGOUT_TIMEOUT));
+        let _ = self.recv_raw();
+        if let Ok(previous) = previous {
+            let _ = self.set_read_timeout(previous);
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), ClientError> {
+        if self.cancelled() {
+            Err(ClientError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn recv_raw(&mut self) -> Result<Response, ClientError> {
+        match self.recv() {
+            Err(_) if self.cancelled() => Err(ClientError::Cancelled),
+            res => res,
+        }
+    }
 
-pub fn open() -> impl Read + Write {
-    use openssl::ssl::{SslConnector, SslMethod};
-    use std::net::TcpStream;
-    use std::time::Duration;
-    let connector = SslConnector::builder(SslMethod::tls()).unwrap().build();
-    let stream = TcpStream::connect((HOST, PORT)).unwrap();
-    stream
-        .set_write_timeout(Some(Duration::from_secs(60)))
-        .unwrap();
-    stream
-        .set_read_timeout(Some(Duration::from_secs(60)))
-        .unwrap();
-    connector.connect(DOMAIN, stream).unwrap()
+    fn recv_res(&mut self) -> Result<Response, ClientError> {
+        let response = self.recv_raw()?;
+        if response.is_ok() {
+            Ok(response)
+        } else {
+            Err(response.into())
+        }
+    }
+
+    fn read_greeting(&mut self, policy: GreetingPolicy) -> Result<(), ClientError> {
+        match policy {
+            GreetingPolicy::Required => self.recv_res().map(drop),
+            GreetingPolicy::None => Ok(()),
+            GreetingPolicy::Optional => {
+                let previous = self.set_read_timeout(Some(GREETING_TIMEOUT))?;
+                let res = self.recv_res();
+                self.set_read_timeout(previous)?;
+                match res {
+                    Err(ClientError::Io(e)) if is_timeout(&e) => Ok(()),
+                    res => res.map(drop),
+                }
+            }
+        }
+    }
+
+    fn call_raw(&mut self, cmd: &[&str]) -> Result<Response, ClientError> {
+        check_command(cmd).map_err(ClientError::InvalidField)?;
+        let start = Instant::now();
+        let name = cmd.first().copied().unwrap_or_default();
+        match self.send(cmd) {
+            Err(_) if self.cancelled() => return Err(ClientError::Cancelled),
+            Err(e) if is_timeout(&e) => {
+                return Err(ClientError::SendTimeout(name.to_owned(), start.elapsed()))
+            }
+            res => res?,
+        }
+        let res = match self.recv_raw() {
+            Err(ClientError::Io(e)) if is_timeout(&e) => {
+                Err(ClientError::RecvTimeout(name.to_owned(), start.elapsed()))
+            }
+            res => res,
+        };
+        if let Some(name) = cmd.first() {
+            self.record_timing(&name.to_ascii_lowercase(), start.elapsed());
+        }
+        res
+    }
+
+    fn call(&mut self, cmd: &[&str]) -> Result<Response, ClientError> {
+        let response = self.call_raw(cmd)?;
+        if response.is_ok() {
+            Ok(response)
+        } else {
+            Err(response.into())
+        }
+    }
+}
+
+impl<T> Command for Client<T>
+where
+    T: Read + Write,
+{
+    fn record_timing(&mut self, stage: &str, elapsed: Duration) {
+        self.timings.record(stage, elapsed);
+    }
+
+    fn session_policy(&self) -> SessionPolicy {
+        self.policy
+    }
+
+    fn set_session_policy(&mut self, policy: SessionPolicy) {
+        self.policy = policy;
+    }
+
+    fn set_authenticated(&mut self, authenticated: bool) {
+        self.authenticated = authenticated;
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<Option<Duration>> {
+        match &self.socket {
+            Some(socket) => {
+                let previous = socket.read_timeout()?;
+                socket.set_read_timeout(timeout)?;
+                Ok(previous)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+
+    fn send(&mut self, cmd: &[&str]) -> io::Result<()> {
+        let chunks = self
+            .machine
+            .send(cmd, self.pacing)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let written = transmit(&mut self.stream, &chunks, self.pacing);
+        self.observe();
+        written
+    }
+
+    fn recv(&mut self) -> Result<Response, ClientError> {
+        let mut buf = [0; 4096];
+        let mut eof = false;
+        loop {
+            let event = self.machine.poll();
+            self.observe();
+            match event? {
+                Event::GreetingReceived(response) | Event::ResponseReceived(response) => {
+                    return Ok(response)
+                }
+                Event::NeedMoreData if eof => {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+                }
+                Event::NeedMoreData => {}
+            }
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.machine.finish();
+                    eof = true;
+                }
+                Ok(len) => self.machine.receive(&buf[..len]),
+                Err(e) if io::ErrorKind::Interrupted == e.kind() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Client<T>
+where
+    T: Read + Write,
+{
+    fn drop(&mut self) {
+        if self.authenticated {
+            self.discard_logout();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DryRun {
+    pub lines: Vec<String>,
+}
+
+impl Command for DryRun {
+    fn send(&mut self, cmd: &[&str]) -> io::Result<()> {
+        let mut wire = Vec::new();
+        write_command(&mut wire, cmd, Pacing::default())?;
+        for line in String::from_utf8_lossy(&wire).lines() {
+            self.lines.push(crate::redact(line).into_owned());
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Response, ClientError> {
+        let mut machine = ProtocolMachine::new();
+        machine.receive(b"000 COMMAND SUCCESSFUL\n.\n");
+        match machine.poll()? {
+            Event::GreetingReceived(response) | Event::ResponseReceived(response) => Ok(response),
+            Event::NeedMoreData => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
+
+pub trait CommandAuth: Command {
+    fn send_logout(&mut self) -> Result<Response, ClientError> {
+        self.set_authenticated(false);
+        self.call(&["LOGOUT"])
+    }
+
+    fn send_login(&mut self, user: &str, pass: &str) -> Result<Response, ClientError> {
+        match self.call(&[
+            "LOGIN",
+            &format!("USERID:{}", user),
+            &format!("PASSWORD:{}", pass),
+        ]) {
+            Err(e @ ClientError::InvalidField(_)) => Err(e),
+            Err(r) => {
+                self.logout_on_error();
+                Err(r)
+            }
+            Ok(r) => {
+                self.set_authenticated(true);
+                Ok(r)
+            }
+        }
+    }
+}
+
+impl<T: Command> CommandAuth for T {}
+
+const RESERVED_FIELDS: [&str; 5] = ["HOSTNAME", "DOMNAME", "IPV4", "USERID", "PASSWORD"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidField {
+    pub command: String,
+    pub line: String,
+}
+
+impl fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "refusing to send {}: {:?} would break the command framing",
+            self.command, self.line
+        )
+    }
+}
+
+impl error::Error for InvalidField {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineTooLong(pub usize);
+
+impl fmt::Display for LineTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "server sent a line longer than {} bytes", self.0)
+    }
+}
+
+impl error::Error for LineTooLong {}
+
+pub fn check_command(cmd: &[&str]) -> Result<(), InvalidField> {
+    match cmd
+        .iter()
+        .find(|line| "." == **line || line.contains(['\r', '\n']))
+    {
+        Some(line) => Err(InvalidField {
+            command: cmd.first().copied().unwrap_or_default().to_owned(),
+            line: crate::redact(line).into_owned(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn transmit<W: Write>(out: &mut W, chunks: &[Vec<u8>], pacing: Pacing) -> io::Result<()> {
+    for (index, chunk) in chunks.iter().enumerate() {
+        if 0 < index {
+            thread::sleep(pacing.delay);
+        }
+        out.write_all(chunk)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+pub fn write_command<W: Write>(out: &mut W, cmd: &[&str], pacing: Pacing) -> io::Result<()> {
+    let chunks = ProtocolMachine::new()
+        .send(cmd, pacing)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    transmit(out, &chunks, pacing)
+}
+
+pub fn check_extra_field(field: &str) -> Result<(), String> {
+    let (name, value) = match field.split_once(':') {
+        Some((name, value)) if !name.is_empty() => (name, value),
+        _ => return Err(format!("expected FIELD:VALUE, found {:?}", field)),
+    };
+    if !name
+        .bytes()
+        .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b'_' == b)
+    {
+        return Err(format!("invalid field name {:?}: use A-Z, 0-9 and _", name));
+    }
+    if RESERVED_FIELDS.contains(&name) {
+        return Err(format!(
+            "{} is set by the client and cannot be overridden",
+            name
+        ));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(format!("control characters are not allowed in {:?}", field));
+    }
+    Ok(())
+}
+
+pub trait CommandModip: Command {
+    fn send_modip(&mut self, host: &str, dom: &str, ipv4: &str) -> Result<Response, ClientError> {
+        self.send_modip_extra(host, dom, ipv4, &[])
+    }
+
+    fn send_modip_extra(
+        &mut self,
+        host: &str,
+        dom: &str,
+        ipv4: &str,
+        extra: &[String],
+    ) -> Result<Response, ClientError> {
+        let host = format!("HOSTNAME:{}", host);
+        let dom = format!("DOMNAME:{}", dom);
+        let ipv4 = format!("IPV4:{}", ipv4);
+        let mut cmd = vec!["MODIP", &host, &dom, &ipv4];
+        cmd.extend(extra.iter().map(String::as_str));
+        match self.call(&cmd) {
+            Err(r) => {
+                self.logout_on_error();
+                Err(r)
+            }
+            Ok(r) => Ok(r),
+        }
+    }
+}
+
+impl<T: Command> CommandModip for T {}
+
+pub trait ToIpAddrs {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError>;
+}
+
+impl ToIpAddrs for &str {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        addr::parse_ipv4(self)
+    }
+}
+
+impl ToIpAddrs for String {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        addr::parse_ipv4(self)
+    }
+}
+
+impl ToIpAddrs for &String {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        addr::parse_ipv4(self)
+    }
+}
+
+impl ToIpAddrs for Cow<'_, str> {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        addr::parse_ipv4(self)
+    }
+}
+
+impl ToIpAddrs for Ipv4Addr {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        Ok(*self)
+    }
+}
+
+impl ToIpAddrs for [u8; 4] {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        Ok(Ipv4Addr::from(*self))
+    }
+}
+
+impl ToIpAddrs for Ipv6Addr {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        self.to_ipv4_mapped().ok_or(AddrError::Ipv6(*self))
+    }
+}
+
+impl ToIpAddrs for IpAddr {
+    fn to_ip_addrs(&self) -> Result<Ipv4Addr, AddrError> {
+        match self {
+            IpAddr::V4(addr) => addr.to_ip_addrs(),
+            IpAddr::V6(addr) => addr.to_ip_addrs(),
+        }
+    }
+}
+
+pub fn resolve_to_ip(resolver: &dyn Resolve, host: &str) -> io::Result<Vec<IpAddr>> {
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for addr in resolver.resolve(host, 0)? {
+        if !addrs.contains(&addr.ip()) {
+            addrs.push(addr.ip());
+        }
+    }
+    Ok(addrs)
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Information {
+    pub user: String,
+    pub pass: String,
+    pub host: String,
+    pub dom: String,
+    pub ipaddr: Ipv4Addr,
+    pub extra: Vec<String>,
+}
+
+impl Information {
+    pub fn new(
+        user: impl Into<String>,
+        pass: impl Into<String>,
+        host: impl Into<String>,
+        dom: impl Into<String>,
+        ipaddr: Ipv4Addr,
+    ) -> Information {
+        Information {
+            user: user.into(),
+            pass: pass.into(),
+            host: host.into(),
+            dom: dom.into(),
+            ipaddr,
+            extra: Vec::new(),
+        }
+    }
+
+    pub fn try_new<T: ToIpAddrs>(
+        user: impl Into<String>,
+        pass: impl Into<String>,
+        host: impl Into<String>,
+        dom: impl Into<String>,
+        ipaddr: T,
+    ) -> Result<Information, AddrError> {
+        Ok(Information::new(
+            user,
+            pass,
+            host,
+            dom,
+            ipaddr.to_ip_addrs()?,
+        ))
+    }
+
+    pub fn name(&self) -> String {
+        if self.host.is_empty() {
+            self.dom.clone()
+        } else {
+            format!("{}.{}", self.host, self.dom)
+        }
+    }
+}
+
+impl Default for Information {
+    fn default() -> Information {
+        Information::new("", "", "", "", Ipv4Addr::UNSPECIFIED)
+    }
+}
+
+impl fmt::Debug for Information {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Information")
+            .field("user", &self.user)
+            .field("pass", &"***")
+            .field("host", &self.host)
+            .field("dom", &self.dom)
+            .field("ipaddr", &self.ipaddr)
+            .field("extra", &self.extra)
+            .finish()
+    }
+}
+
+impl<T> std::ops::Index<T> for Information
+where
+    T: AsRef<KEY>,
+{
+    type Output = String;
+
+    fn index(&self, key: T) -> &Self::Output {
+        match key.as_ref() {
+            KEY::USER => &self.user,
+            KEY::PASS => &self.pass,
+            KEY::HOST => &self.host,
+            KEY::DOM => &self.dom,
+        }
+    }
+}
+
+impl<T> std::ops::IndexMut<T> for Information
+where
+    T: AsRef<KEY>,
+{
+    fn index_mut(&mut self, key: T) -> &mut Self::Output {
+        match key.as_ref() {
+            KEY::USER => &mut self.user,
+            KEY::PASS => &mut self.pass,
+            KEY::HOST => &mut self.host,
+            KEY::DOM => &mut self.dom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionPolicy {
+    pub logout_on_success: bool,
+    pub logout_on_error: bool,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> SessionPolicy {
+        SessionPolicy {
+            logout_on_success: true,
+            logout_on_error: true,
+        }
+    }
+}
+
+pub fn acknowledged_ip(response: &Response) -> Option<Ipv4Addr> {
+    let mut found = response
+        .body
+        .iter()
+        .filter_map(|line| line.trim().strip_prefix("IPV4:"));
+    let ip = found.next()?.trim().parse().ok()?;
+    found.next().is_none().then_some(ip)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateOutcome {
+    pub changed: bool,
+    pub response: Response,
+    pub duration: Duration,
+    pub acknowledged: Option<Ipv4Addr>,
+}
+
+impl UpdateOutcome {
+    pub fn new(response: Response, duration: Duration) -> UpdateOutcome {
+        UpdateOutcome {
+            changed: true,
+            acknowledged: acknowledged_ip(&response),
+            response,
+            duration,
+        }
+    }
+
+    pub fn mismatch(&self, info: &Information) -> Option<ClientError> {
+        let acknowledged = self.acknowledged.filter(|ip| *ip != info.ipaddr)?;
+        Some(ClientError::ServerMismatch {
+            record: info.name(),
+            sent: info.ipaddr,
+            acknowledged,
+        })
+    }
+}
+
+pub trait CommandModipExt: CommandAuth + CommandModip {
+    fn run_auth_check(&mut self, user: &str, pass: &str) -> Result<(), ClientError> {
+        self.check_cancelled()?;
+        self.send_login(user, pass)?;
+        self.send_logout()?;
+        Ok(())
+    }
+
+    fn run_modip(
+        &mut self,
+        info: &Information,
+        policy: Option<SessionPolicy>,
+    ) -> Result<UpdateOutcome, ClientError> {
+        let previous = self.session_policy();
+        if let Some(policy) = policy {
+            self.set_session_policy(policy);
+        }
+        let result = self.run_modip_batch(std::slice::from_ref(info));
+        self.set_session_policy(previous);
+        Ok(result?.remove(0))
+    }
+
+    fn run_modip_batch(
+        &mut self,
+        records: &[Information],
+    ) -> Result<Vec<UpdateOutcome>, ClientError> {
+        let mut outcomes = Vec::new();
+        self.run_modip_each(records, &mut outcomes)?;
+        Ok(outcomes)
+    }
+
+    fn run_modip_each(
+        &mut self,
+        records: &[Information],
+        outcomes: &mut Vec<UpdateOutcome>,
+    ) -> Result<(), ClientError> {
+        outcomes.clear();
+        let first = match records.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        self.check_cancelled()?;
+        self.send_login(&first.user, &first.pass)?;
+        for info in records {
+            if self.cancelled() {
+                self.logout_on_error();
+                return Err(ClientError::Cancelled);
+            }
+            let start = Instant::now();
+            let response = self.send_modip_extra(
+                &info.host,
+                &info.dom,
+                &info.ipaddr.to_string(),
+                &info.extra,
+            )?;
+            outcomes.push(UpdateOutcome::new(response, start.elapsed()));
+        }
+        if self.session_policy().logout_on_success {
+            self.send_logout()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Command> CommandModipExt for T {}
+
+pub static HOST: &str = "ddnsclient.onamae.com";
+pub static PORT: u16 = 65010;
+pub static DOMAIN: &str = "ddnsclient.onamae.com";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Timings {
+    pub stages: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    pub fn record(&mut self, stage: impl Into<String>, elapsed: Duration) {
+        self.stages.push((stage.into(), elapsed));
+    }
+
+    pub fn extend(&mut self, other: &Timings) {
+        self.stages.extend(other.stages.iter().cloned());
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut map = serde_json::Map::new();
+        for (stage, elapsed) in &self.stages {
+            map.insert(stage.clone(), serde_json::json!(elapsed.as_secs_f64()));
+        }
+        serde_json::Value::Object(map)
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (stage, elapsed)) in self.stages.iter().enumerate() {
+            if 0 < index {
+                writeln!(f)?;
+            }
+            write!(f, "{:>10}: {:.3}s", stage, elapsed.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum OpenError {
+    Resolve(io::Error),
+    Bind(io::Error),
+    PortsExhausted(u16, u16),
+    NoFamily(socket::Family),
+    Connect(io::Error),
+    Tls(String),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenError::Resolve(e) => write!(f, "{}", messages::resolve_failed(HOST, e)),
+            OpenError::Bind(e) => write!(f, "{}", messages::bind_failed(e)),
+            OpenError::PortsExhausted(low, high) => {
+                write!(f, "{}", messages::ports_exhausted(*low, *high))
+            }
+            OpenError::NoFamily(family) => write!(f, "{}", messages::no_family(HOST, family)),
+            OpenError::Connect(e) => write!(f, "{}", messages::connect_failed(HOST, PORT, e)),
+            OpenError::Tls(e) => write!(f, "{}", messages::tls_failed(DOMAIN, e)),
+        }
+    }
+}
+
+impl error::Error for OpenError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            OpenError::Resolve(e) | OpenError::Bind(e) | OpenError::Connect(e) => Some(e),
+            OpenError::PortsExhausted(..) | OpenError::NoFamily(_) | OpenError::Tls(_) => None,
+        }
+    }
+}
+
+pub const RESOLVE_ATTEMPTS: u32 = 3;
+pub const RESOLVE_DELAY: Duration = Duration::from_secs(1);
+
+pub trait Resolve {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedResolver(pub Vec<SocketAddr>);
+
+#[cfg(any(test, feature = "testing"))]
+impl Resolve for FixedResolver {
+    fn resolve(&self, _: &str, _: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok(self.0.clone())
+    }
+}
+
+pub fn resolve_with(resolver: &dyn Resolve, clock: &dyn Clock) -> io::Result<Vec<SocketAddr>> {
+    let mut attempt = 1;
+    loop {
+        let result = match resolver.resolve(HOST, PORT) {
+            Ok(addrs) if addrs.is_empty() => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                messages::no_addresses(HOST),
+            )),
+            result => result,
+        };
+        match result {
+            Err(_) if attempt < RESOLVE_ATTEMPTS => {
+                attempt += 1;
+                clock.sleep(RESOLVE_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ResolveCache {
+    addrs: Option<Vec<SocketAddr>>,
+}
+
+impl ResolveCache {
+    pub fn new() -> ResolveCache {
+        ResolveCache::default()
+    }
+
+    pub fn resolve(
+        &mut self,
+        resolver: &dyn Resolve,
+        clock: &dyn Clock,
+    ) -> Result<Vec<SocketAddr>, OpenError> {
+        if let Some(addrs) = self.addrs.as_ref() {
+            return Ok(addrs.clone());
+        }
+        let addrs = resolve_with(resolver, clock).map_err(OpenError::Resolve)?;
+        self.addrs = Some(addrs.clone());
+        Ok(addrs)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub io: Option<Duration>,
+}
+
+pub const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: None,
+            io: Some(IO_TIMEOUT),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn apply(&self, socket: &TcpStream) -> io::Result<()> {
+        socket.set_write_timeout(self.io)?;
+        socket.set_read_timeout(self.io)
+    }
+}
+
+pub fn open_with(
+    resolver: &dyn Resolve,
+    timeouts: Timeouts,
+    timings: &mut Timings,
+) -> Result<Stream, OpenError> {
+    let connector = TlsOptions::default().connector().map_err(OpenError::Tls)?;
+    open_with_connector(
+        &connector,
+        &LocalBind::default(),
+        resolver,
+        &mut ResolveCache::new(),
+        timeouts,
+        timings,
+    )
+}
+
+pub fn open_with_connector(
+    connector: &Connector,
+    bind: &LocalBind,
+    resolver: &dyn Resolve,
+    resolved: &mut ResolveCache,
+    timeouts: Timeouts,
+    timings: &mut Timings,
+) -> Result<Stream, OpenError> {
+    let start = Instant::now();
+    let addrs = resolved.resolve(resolver, &SystemClock)?;
+    timings.record("resolve", start.elapsed());
+
+    let start = Instant::now();
+    let stream = socket::connect(&addrs, bind, timeouts.connect)?;
+    timeouts.apply(&stream).map_err(OpenError::Connect)?;
+    timings.record("connect", start.elapsed());
+
+    let start = Instant::now();
+    let stream = tls::connect(connector, DOMAIN, stream).map_err(OpenError::Tls)?;
+    timings.record("tls", start.elapsed());
+    Ok(stream)
+}
+
+pub fn open() -> Result<impl Read + Write, ClientError> {
+    let stream = open_with(
+        &SystemResolver,
+        Timeouts::default(),
+        &mut Timings::default(),
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::cell::Cell;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+
+    struct FlakyResolver {
+        failures: u32,
+        calls: Cell<u32>,
+        addrs: Vec<SocketAddr>,
+    }
+
+    impl FlakyResolver {
+        fn new(failures: u32, addrs: &[&str]) -> FlakyResolver {
+            FlakyResolver {
+                failures,
+                calls: Cell::new(0),
+                addrs: addrs.iter().map(|addr| addr.parse().unwrap()).collect(),
+            }
+        }
+    }
+
+    impl Resolve for FlakyResolver {
+        fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            assert_eq!((HOST, PORT), (host, port));
+            self.calls.set(self.calls.get() + 1);
+            if self.calls.get() <= self.failures {
+                return Err(io::Error::other("temporary failure"));
+            }
+            Ok(self.addrs.clone())
+        }
+    }
+
+    const ADDR: &str = "192.0.2.1:65010";
+
+    #[test]
+    fn resolve_retries_transient_failures() {
+        let resolver = FlakyResolver::new(RESOLVE_ATTEMPTS - 1, &[ADDR]);
+        let clock = MockClock::new(0);
+        let addrs = resolve_with(&resolver, &clock).unwrap();
+        assert_eq!(vec![ADDR.parse::<SocketAddr>().unwrap()], addrs);
+        assert_eq!(RESOLVE_ATTEMPTS, resolver.calls.get());
+        assert_eq!(RESOLVE_DELAY * (RESOLVE_ATTEMPTS - 1), clock.elapsed());
+    }
+
+    #[test]
+    fn resolve_gives_up_after_attempts() {
+        let resolver = FlakyResolver::new(RESOLVE_ATTEMPTS, &[ADDR]);
+        let clock = MockClock::new(0);
+        assert!(resolve_with(&resolver, &clock).is_err());
+        assert_eq!(RESOLVE_ATTEMPTS, resolver.calls.get());
+    }
+
+    #[test]
+    fn resolve_treats_no_addresses_as_failure() {
+        let resolver = FlakyResolver::new(0, &[]);
+        let clock = MockClock::new(0);
+        let e = resolve_with(&resolver, &clock).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, e.kind());
+        assert_eq!(RESOLVE_ATTEMPTS, resolver.calls.get());
+    }
+
+    #[test]
+    fn cache_is_per_instance() {
+        let resolver = FlakyResolver::new(0, &[ADDR]);
+        let clock = MockClock::new(0);
+        let mut cache = ResolveCache::new();
+        cache.resolve(&resolver, &clock).unwrap();
+        cache.resolve(&resolver, &clock).unwrap();
+        assert_eq!(1, resolver.calls.get());
+        ResolveCache::new().resolve(&resolver, &clock).unwrap();
+        assert_eq!(2, resolver.calls.get());
+    }
+
+    #[test]
+    fn cache_does_not_keep_failures() {
+        let resolver = FlakyResolver::new(RESOLVE_ATTEMPTS, &[ADDR]);
+        let clock = MockClock::new(0);
+        let mut cache = ResolveCache::new();
+        assert!(matches!(
+            cache.resolve(&resolver, &clock),
+            Err(OpenError::Resolve(_))
+        ));
+        assert!(cache.resolve(&resolver, &clock).is_ok());
+        assert_eq!(RESOLVE_ATTEMPTS + 1, resolver.calls.get());
+    }
+
+    #[test]
+    fn cancel_unblocks_recv() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _server = listener.accept().unwrap();
+        let cancel = CancelToken::new();
+        let mut client = Client::new(stream.try_clone().unwrap());
+        client.set_socket(stream);
+        client.set_cancel_token(cancel.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancel.cancel();
+        });
+        let start = Instant::now();
+        let result = client.read_greeting(GreetingPolicy::Required);
+        canceller.join().unwrap();
+        assert!(matches!(result, Err(ClientError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    fn acknowledged(body: &[&str]) -> Option<Ipv4Addr> {
+        acknowledged_ip(&Response {
+            code: 0,
+            message: "COMMAND SUCCESSFUL".to_owned(),
+            body: body.iter().map(|line| line.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn acknowledged_ip_reads_the_ipv4_line() {
+        let ip = Some(Ipv4Addr::new(203, 0, 113, 7));
+        assert_eq!(ip, acknowledged(&["IPV4:203.0.113.7"]));
+        assert_eq!(ip, acknowledged(&["IPV4: 203.0.113.7 "]));
+        assert_eq!(
+            ip,
+            acknowledged(&["HOSTNAME:www", "DOMNAME:example.com", "IPV4:203.0.113.7"])
+        );
+    }
+
+    #[test]
+    fn acknowledged_ip_ignores_other_lines() {
+        assert_eq!(None, acknowledged(&[]));
+        assert_eq!(None, acknowledged(&["203.0.113.7"]));
+        assert_eq!(None, acknowledged(&["PREVIOUS:198.51.100.2"]));
+        assert_eq!(None, acknowledged(&["SERIAL:2026.10.14.1"]));
+        assert_eq!(None, acknowledged(&["NOTE:IPV4:203.0.113.7"]));
+        assert_eq!(None, acknowledged(&["ipv4:203.0.113.7"]));
+    }
+
+    #[test]
+    fn acknowledged_ip_rejects_ambiguous_bodies() {
+        assert_eq!(None, acknowledged(&["IPV4:"]));
+        assert_eq!(None, acknowledged(&["IPV4:203.0.113"]));
+        assert_eq!(None, acknowledged(&["IPV4:203.0.113.7 seconds"]));
+        assert_eq!(
+            None,
+            acknowledged(&["IPV4:203.0.113.7", "IPV4:203.0.113.7"])
+        );
+        assert_eq!(
+            None,
+            acknowledged(&["IPV4:203.0.113.7", "IPV4:198.51.100.2"])
+        );
+        assert_eq!(None, acknowledged(&["IPV4:203.0.113.7", "IPV4:junk"]));
+    }
+
+    #[test]
+    fn mismatch_compares_with_the_sent_address() {
+        let info = Information::new(
+            "user",
+            "secret",
+            "www",
+            "example.com",
+            Ipv4Addr::new(203, 0, 113, 7),
+        );
+        let response = |body: &[&str]| Response {
+            code: 0,
+            message: String::new(),
+            body: body.iter().map(|line| line.to_string()).collect(),
+        };
+        let outcome = UpdateOutcome::new(response(&["IPV4:203.0.113.7"]), Duration::ZERO);
+        assert!(outcome.mismatch(&info).is_none());
+        let outcome = UpdateOutcome::new(response(&[]), Duration::ZERO);
+        assert!(outcome.mismatch(&info).is_none());
+        let outcome = UpdateOutcome::new(response(&["IPV4:203.0.113.8"]), Duration::ZERO);
+        match outcome.mismatch(&info) {
+            Some(ClientError::ServerMismatch {
+                sent, acknowledged, ..
+            }) => {
+                assert_eq!(Ipv4Addr::new(203, 0, 113, 7), sent);
+                assert_eq!(Ipv4Addr::new(203, 0, 113, 8), acknowledged);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_new_converts_addresses() {
+        let v4 = Ipv4Addr::new(203, 0, 113, 7);
+        let new = |ip| Information::try_new("user", "secret", "www", "example.com", ip);
+        assert_eq!(v4, new("203.0.113.7").unwrap().ipaddr);
+        assert_eq!(v4, new(" [::ffff:203.0.113.7] ").unwrap().ipaddr);
+        let mapped = Information::try_new("u", "p", "h", "d", v4.to_ipv6_mapped()).unwrap();
+        assert_eq!(v4, mapped.ipaddr);
+        let bytes = Information::try_new("u", "p", "h", "d", [203, 0, 113, 7]).unwrap();
+        assert_eq!(v4, bytes.ipaddr);
+    }
+
+    #[test]
+    fn try_new_rejects_ipv6_and_garbage() {
+        let new = |ip| Information::try_new("user", "secret", "www", "example.com", ip);
+        let v6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(
+            Err(AddrError::Ipv6(v6)),
+            Information::try_new("u", "p", "h", "d", v6).map(|info| info.ipaddr)
+        );
+        assert_eq!(
+            Err(AddrError::Ipv6(v6)),
+            Information::try_new("u", "p", "h", "d", IpAddr::V6(v6)).map(|info| info.ipaddr)
+        );
+        assert_eq!(
+            Err(AddrError::Ipv6(v6)),
+            new("2001:db8::1").map(|info| info.ipaddr)
+        );
+        assert_eq!(Err(AddrError::Empty), new("").map(|info| info.ipaddr));
+        assert!(matches!(new("fe80::1%eth0"), Err(AddrError::Zoned(_))));
+        assert!(matches!(new("203.0.113"), Err(AddrError::Invalid(_))));
+    }
+
+    struct Wire {
+        replies: io::Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Wire {
+        fn new(replies: &str) -> Wire {
+            Wire {
+                replies: io::Cursor::new(replies.as_bytes().to_vec()),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Wire {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Wire {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const OK: &str = "000 COMMAND SUCCESSFUL\n.\n";
+
+    fn sent_commands(sent: &[u8]) -> Vec<String> {
+        let sent = String::from_utf8_lossy(sent);
+        let mut commands = Vec::new();
+        let mut first = true;
+        for line in sent.lines() {
+            if first {
+                commands.push(line.to_owned());
+            }
+            first = "." == line;
+        }
+        commands
+    }
+
+    const FORGED: [&str; 4] = [".", "a\n.", "a\r\n.", "a\n.\nLOGOUT"];
+    const HARMLESS: [&str; 3] = ["..", ".a", "a."];
+
+    #[test]
+    fn check_command_rejects_framing() {
+        assert!(check_command(&["MODIP", "HOSTNAME:www"]).is_ok());
+        assert!(check_command(&["MODIP", ".."]).is_ok());
+        assert!(check_command(&["MODIP", ".HOSTNAME:www"]).is_ok());
+        for line in &[".", "a\n.", "a\r\n.", "a\r", "\n"] {
+            let e = check_command(&["MODIP", line]).unwrap_err();
+            assert_eq!("MODIP", e.command, "{:?}", line);
+        }
+        assert!(check_command(&["."]).is_err());
+    }
+
+    #[test]
+    fn raw_commands_cannot_forge_the_terminator() {
+        for value in &FORGED {
+            let mut client = Client::new(Wire::new(OK));
+            let e = client.call_raw(&["NOOP", value]).unwrap_err();
+            assert!(matches!(e, ClientError::InvalidField(_)), "{:?}", value);
+            assert!(client.get_ref().sent.is_empty(), "{:?}", value);
+        }
+        for value in &HARMLESS {
+            let mut client = Client::new(Wire::new(OK));
+            client.call_raw(&["NOOP", value]).unwrap();
+            assert_eq!(vec!["NOOP"], sent_commands(&client.get_ref().sent));
+        }
+    }
+
+    #[test]
+    fn login_values_cannot_forge_the_terminator() {
+        for value in FORGED.iter().filter(|value| "." != **value) {
+            for (user, pass) in &[(*value, "secret"), ("user", *value)] {
+                let mut client = Client::new(Wire::new(OK));
+                let e = client.send_login(user, pass).unwrap_err();
+                assert!(matches!(e, ClientError::InvalidField(_)), "{:?}", value);
+                assert!(client.get_ref().sent.is_empty(), "{:?}", value);
+            }
+        }
+        for value in HARMLESS.iter().chain(&["."]) {
+            let mut client = Client::new(Wire::new(&OK.repeat(2)));
+            client.send_login(value, value).unwrap();
+            client.send_logout().unwrap();
+            let commands = sent_commands(&client.get_ref().sent);
+            assert_eq!(vec!["LOGIN", "LOGOUT"], commands, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn modip_values_cannot_forge_the_terminator() {
+        for value in FORGED.iter().filter(|value| "." != **value) {
+            let fields: [(&str, &str, &str, Vec<String>); 4] = [
+                (value, "example.com", "203.0.113.7", vec![]),
+                ("www", value, "203.0.113.7", vec![]),
+                ("www", "example.com", value, vec![]),
+                ("www", "example.com", "203.0.113.7", vec![value.to_string()]),
+            ];
+            for (host, dom, ip, extra) in &fields {
+                let mut client = Client::new(Wire::new(OK));
+                let e = client.send_modip_extra(host, dom, ip, extra).unwrap_err();
+                assert!(matches!(e, ClientError::InvalidField(_)), "{:?}", value);
+                let commands = sent_commands(&client.get_ref().sent);
+                assert!(!commands.iter().any(|cmd| "MODIP" == cmd), "{:?}", value);
+            }
+        }
+        let mut client = Client::new(Wire::new(OK));
+        let e = client
+            .send_modip_extra("www", "example.com", "203.0.113.7", &[".".to_owned()])
+            .unwrap_err();
+        assert!(matches!(e, ClientError::InvalidField(_)));
+        for value in &HARMLESS {
+            let mut client = Client::new(Wire::new(OK));
+            client
+                .send_modip_extra(value, value, "203.0.113.7", &[format!("TTL:{}", value)])
+                .unwrap();
+            assert_eq!(vec!["MODIP"], sent_commands(&client.get_ref().sent));
+        }
+    }
+
+    #[cfg(not(feature = "tls-openssl"))]
+    #[test]
+    fn open_without_tls_is_an_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let resolver = FixedResolver(vec![listener.local_addr().unwrap()]);
+        match open_with(&resolver, Timeouts::default(), &mut Timings::default())
+            .map_err(ClientError::from)
+        {
+            Err(ClientError::Open(OpenError::Tls(message))) => {
+                assert_eq!(messages::tls_unsupported(), message)
+            }
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("connected without TLS"),
+        }
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn open_reports_unreachable_hosts() {
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let resolver = FixedResolver(vec![closed]);
+        let timeouts = Timeouts {
+            connect: Some(Duration::from_secs(1)),
+            io: Some(Duration::from_secs(1)),
+        };
+        let e = open_with(&resolver, timeouts, &mut Timings::default()).unwrap_err();
+        assert!(matches!(e, OpenError::Connect(_)), "{}", e);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let resolver = FixedResolver(vec![listener.local_addr().unwrap()]);
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.write_all(b"000 COMMAND SUCCESSFUL\n.\n");
+        });
+        let e = open_with(&resolver, timeouts, &mut Timings::default()).unwrap_err();
+        assert!(matches!(e, OpenError::Tls(_)), "{}", e);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn information_debug_masks_the_password() {
+        let info = Information::new(
+            "alice",
+            "hunter2",
+            "www",
+            "example.com",
+            Ipv4Addr::LOCALHOST,
+        );
+        let debug = format!("{:?}", info);
+        assert!(debug.contains("pass: \"***\""), "{}", debug);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        assert!(debug.contains("user: \"alice\""), "{}", debug);
+        assert!(!format!("{:#?}", info).contains("hunter2"));
+    }
+
+    #[test]
+    fn information_default_clone_and_index() {
+        let default = Information::default();
+        assert_eq!(Ipv4Addr::UNSPECIFIED, default.ipaddr);
+        assert!(KEY::all().iter().all(|key| default[key].is_empty()));
+        let mut info = default.clone();
+        for key in KEY::all() {
+            info[key] = key.to_string().to_lowercase();
+        }
+        assert_eq!("userid", info.user);
+        assert_eq!("password", info[KEY::PASS]);
+        assert_eq!("hostname.domname", info.name());
+        assert_ne!(default, info);
+        let mut copy = info.clone();
+        assert_eq!(info, copy);
+        copy.extra.push("ttl=60".to_owned());
+        assert_ne!(info, copy);
+    }
+
+    #[test]
+    fn parse_response_reads_single_and_multi_line_replies() {
+        let response = parse_response("000 COMMAND SUCCESSFUL\n.\n").unwrap();
+        assert_eq!(0, response.code);
+        assert_eq!("COMMAND SUCCESSFUL", response.message);
+        assert!(response.body.is_empty());
+        let response =
+            parse_response("000 COMMAND SUCCESSFUL\r\nIPV4:203.0.113.7\r\nTTL:60\r\n.\r\n")
+                .unwrap();
+        assert_eq!(vec!["IPV4:203.0.113.7", "TTL:60"], response.body);
+        let response = parse_response("006 NOT FOUND\nno such host\n.\nignored\n").unwrap();
+        assert_eq!(6, response.code);
+        assert_eq!(vec!["no such host"], response.body);
+        assert!(res_verify("000 OK\n.").is_ok());
+        assert!(matches!(
+            res_verify("002 LOGIN ERROR\n."),
+            Err(Some(ResponseError::LoginError))
+        ));
+        assert!(matches!(res_verify("042 ANSWER\n."), Err(None)));
+    }
+
+    #[test]
+    fn parse_response_rejects_pathological_replies() {
+        for text in &["", "\n\n", ".\n", "\r\n.\r\n"] {
+            assert!(
+                matches!(parse_response(text), Err(ParseError::Empty)),
+                "{:?}",
+                text
+            );
+        }
+        for text in &["OK\n.", "0 short\n.", "0000 long\n.", " 000 indented\n."] {
+            assert!(
+                matches!(parse_response(text), Err(ParseError::InvalidCode(_))),
+                "{:?}",
+                text
+            );
+            assert!(matches!(res_verify(text), Err(None)));
+        }
+        let response = parse_response("000 message  with  spaces \nbody\n").unwrap();
+        assert_eq!("message  with  spaces", response.message);
+        assert_eq!(vec!["body"], response.body);
+        let response = parse_response("000\n..\n.").unwrap();
+        assert_eq!(vec![".."], response.body);
+    }
+
+    #[test]
+    fn call_returns_the_response_or_carries_it_in_the_error() {
+        let replies = "000 COMMAND SUCCESSFUL\nIPV4:203.0.113.7\n.\n006 NOT FOUND\nwww\n.\n";
+        let mut client = Client::new(Wire::new(replies));
+        let response = client.call(&["MODIP"]).unwrap();
+        assert_eq!(vec!["IPV4:203.0.113.7"], response.body);
+        match client.call(&["MODIP"]) {
+            Err(ClientError::Response(ResponseError::NotFound, response)) => {
+                assert_eq!("NOT FOUND", response.message);
+                assert_eq!(vec!["www"], response.body);
+            }
+            other => panic!("{:?}", other),
+        }
+        let mut client = Client::new(Wire::new("042 ANSWER\n.\n"));
+        assert_eq!(42, client.call_raw(&["NOOP"]).unwrap().code);
+        let mut client = Client::new(Wire::new("042 ANSWER\n.\n"));
+        assert!(matches!(
+            client.call(&["NOOP"]),
+            Err(ClientError::Unexpected(Response { code: 42, .. }))
+        ));
+    }
+
+    #[test]
+    fn failed_modip_drains_the_logout_response() {
+        let replies = "006 NOT FOUND\n.\n000 LOGOUT\nBYE\n.\n000 COMMAND SUCCESSFUL\n.\n";
+        let mut client = Client::new(Wire::new(replies));
+        let e = client
+            .send_modip("www", "example.com", "203.0.113.7")
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            ClientError::Response(ResponseError::NotFound, _)
+        ));
+        assert_eq!(
+            vec!["MODIP", "LOGOUT"],
+            sent_commands(&client.get_ref().sent)
+        );
+        let response = client.call(&["NOOP"]).unwrap();
+        assert_eq!("COMMAND SUCCESSFUL", response.message);
+    }
+
+    #[test]
+    fn failed_login_survives_a_closed_connection() {
+        let mut client = Client::new(Wire::new("002 LOGIN ERROR\n.\n"));
+        let e = client.send_login("user", "pass").unwrap_err();
+        assert!(matches!(
+            e,
+            ClientError::Response(ResponseError::LoginError, _)
+        ));
+        assert_eq!(
+            vec!["LOGIN", "LOGOUT"],
+            sent_commands(&client.get_ref().sent)
+        );
+        let mut client = Client::new(Wire::new("006 NOT FOUND\n.\n"));
+        client.set_session_policy(SessionPolicy {
+            logout_on_success: true,
+            logout_on_error: false,
+        });
+        assert!(client
+            .send_modip("www", "example.com", "203.0.113.7")
+            .is_err());
+        assert_eq!(vec!["MODIP"], sent_commands(&client.get_ref().sent));
+    }
+
+    fn greeter(greet: bool) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            if greet {
+                stream.write_all(b"000 WELCOME\n.\n").unwrap();
+            }
+            let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while 0 < reader.read_line(&mut line).unwrap_or(0) {
+                if ".\n" == line {
+                    let _ = stream.write_all(b"000 COMMAND SUCCESSFUL\n.\n");
+                }
+                line.clear();
+            }
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        (stream, server)
+    }
+
+    fn greeted(policy: GreetingPolicy, greet: bool) -> Result<String, ClientError> {
+        let (stream, server) = greeter(greet);
+        let mut client = Client::new(stream.try_clone().unwrap());
+        client.set_socket(stream);
+        let result = client
+            .read_greeting(policy)
+            .and_then(|()| client.call(&["NOOP"]))
+            .map(|response| response.message);
+        drop(client);
+        server.join().unwrap();
+        result
+    }
+
+    #[test]
+    fn greeting_policies_against_greeting_and_silent_servers() {
+        use GreetingPolicy::*;
+
+        assert_eq!("COMMAND SUCCESSFUL", greeted(Required, true).unwrap());
+        assert!(matches!(greeted(Required, false), Err(ClientError::Io(e)) if is_timeout(&e)));
+        assert_eq!("COMMAND SUCCESSFUL", greeted(Optional, true).unwrap());
+        assert_eq!("COMMAND SUCCESSFUL", greeted(Optional, false).unwrap());
+        assert_eq!("COMMAND SUCCESSFUL", greeted(None, false).unwrap());
+        assert_eq!("WELCOME", greeted(None, true).unwrap());
+    }
+
+    #[test]
+    fn greeting_policies_parse() {
+        for policy in [
+            GreetingPolicy::Required,
+            GreetingPolicy::Optional,
+            GreetingPolicy::None,
+        ] {
+            assert_eq!(Ok(policy), policy.to_string().parse());
+        }
+        assert!("Required".parse::<GreetingPolicy>().is_err());
+        assert_eq!(GreetingPolicy::Required, GreetingPolicy::default());
+    }
+
+    #[test]
+    fn not_found_messages_are_classified() {
+        for (message, scope) in [
+            ("HOSTNAME NOT FOUND", NotFoundScope::Host),
+            ("host not found", NotFoundScope::Host),
+            ("DOMAIN NOT FOUND IN ACCOUNT", NotFoundScope::Domain),
+            ("NOT FOUND", NotFoundScope::Unknown),
+            ("HOST OR DOMAIN NOT FOUND", NotFoundScope::Unknown),
+            ("", NotFoundScope::Unknown),
+        ] {
+            assert_eq!(scope, NotFoundScope::classify(message), "{}", message);
+        }
+    }
+
+    fn session(
+        replies: &str,
+        policy: SessionPolicy,
+        logout: bool,
+    ) -> (bool, Vec<String>, Vec<String>) {
+        let mut wire = Wire::new(replies);
+        let mut client = Client::new(&mut wire);
+        let info = Information::new("user", "pass", "www", "example.com", Ipv4Addr::LOCALHOST);
+        let ok = client.run_modip(&info, Some(policy)).is_ok();
+        assert_eq!(SessionPolicy::default(), client.session_policy());
+        if logout {
+            client.logout().unwrap();
+        }
+        let before = sent_commands(&client.get_ref().sent);
+        drop(client);
+        (ok, before, sent_commands(&wire.sent))
+    }
+
+    #[test]
+    fn session_policies_send_the_expected_commands() {
+        let full = vec!["LOGIN", "MODIP", "LOGOUT"];
+        let kept = vec!["LOGIN", "MODIP"];
+        let success = [OK, OK, OK].concat();
+        let failure = [OK, "006 NOT FOUND\n.\n", OK].concat();
+        for logout_on_success in [true, false] {
+            for logout_on_error in [true, false] {
+                let policy = SessionPolicy {
+                    logout_on_success,
+                    logout_on_error,
+                };
+                let (ok, before, after) = session(&success, policy, false);
+                assert!(ok);
+                let expected = if logout_on_success { &full } else { &kept };
+                assert_eq!(*expected, before, "{:?}", policy);
+                assert_eq!(full, after, "{:?}", policy);
+
+                let (ok, before, after) = session(&failure, policy, false);
+                assert!(!ok);
+                let expected = if logout_on_error { &full } else { &kept };
+                assert_eq!(*expected, before, "{:?}", policy);
+                assert_eq!(full, after, "{:?}", policy);
+            }
+        }
+    }
+
+    #[test]
+    fn deferred_logout_is_sent_once() {
+        let policy = SessionPolicy {
+            logout_on_success: false,
+            logout_on_error: true,
+        };
+        let (ok, before, after) = session(&[OK, OK, OK].concat(), policy, true);
+        assert!(ok);
+        assert_eq!(vec!["LOGIN", "MODIP", "LOGOUT"], before);
+        assert_eq!(before, after);
+    }
 }
@@ -0,0 +1,568 @@
+#[cfg(any(test, feature = "testing"))]
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::config::ResolverSpec;
+use crate::dice::{self, Resolve, SystemResolver};
+use crate::secret;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+const TYPE_A: u16 = 1;
+const TYPE_NS: u16 = 2;
+const TYPE_SOA: u16 = 6;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+#[derive(Debug)]
+pub enum DnsError {
+    Io(io::Error),
+    Format(String),
+    Rcode(u8),
+    NoServers(String),
+    Unsupported(&'static str, &'static str),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::Io(e) => write!(f, "{}", e),
+            DnsError::Format(message) => write!(f, "malformed DNS response: {}", message),
+            DnsError::Rcode(rcode) => write!(f, "DNS server returned rcode {}", rcode),
+            DnsError::NoServers(what) => write!(f, "no nameservers found for {}", what),
+            DnsError::Unsupported(resolver, rtype) => {
+                write!(
+                    f,
+                    "the {} resolver cannot look up {} records",
+                    resolver, rtype
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DnsError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> DnsError {
+        DnsError::Io(e)
+    }
+}
+
+impl From<DnsError> for io::Error {
+    fn from(e: DnsError) -> io::Error {
+        match e {
+            DnsError::Io(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+}
+
+fn malformed<T>(what: &str) -> Result<T, DnsError> {
+    Err(DnsError::Format(what.to_owned()))
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16, recursive: bool) -> Result<Vec<u8>, DnsError> {
+    let flags: u16 = if recursive { 0x0100 } else { 0 };
+    let mut packet = Vec::with_capacity(512);
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&flags.to_be_bytes());
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || 63 < label.len() {
+            return Err(DnsError::Format(format!("invalid name {:?}", name)));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(packet)
+}
+
+fn read_u16(packet: &[u8], at: usize) -> Result<u16, DnsError> {
+    match packet.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => malformed("truncated"),
+    }
+}
+
+fn read_name(packet: &[u8], mut at: usize) -> Result<(String, usize), DnsError> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let len = match packet.get(at) {
+            Some(len) => *len as usize,
+            None => return malformed("truncated name"),
+        };
+        if 0xc0 == len & 0xc0 {
+            let pointer = (read_u16(packet, at)? & 0x3fff) as usize;
+            end.get_or_insert(at + 2);
+            at = pointer;
+            continue;
+        }
+        if 0 == len {
+            return Ok((labels.join("."), end.unwrap_or(at + 1)));
+        }
+        match packet.get(at + 1..at + 1 + len) {
+            Some(label) => labels.push(String::from_utf8_lossy(label).into_owned()),
+            None => return malformed("truncated label"),
+        }
+        at += 1 + len;
+    }
+    malformed("name compression loop")
+}
+
+struct Answer {
+    rtype: u16,
+    rdata: usize,
+    len: usize,
+}
+
+fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    recursive: bool,
+) -> Result<(Vec<u8>, Vec<Answer>), DnsError> {
+    let mut id = [0; 2];
+    secret::random_bytes(&mut id)?;
+    let id = u16::from_be_bytes(id);
+    let request = encode_query(id, name, qtype, recursive)?;
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(&request)?;
+    let mut packet = vec![0; 4096];
+    let len = loop {
+        let len = socket.recv(&mut packet)?;
+        if 2 <= len && read_u16(&packet, 0)? == id {
+            break len;
+        }
+    };
+    packet.truncate(len);
+    let flags = read_u16(&packet, 2)?;
+    if 0 == flags & 0x8000 {
+        return malformed("not a response");
+    }
+    if 0 != flags & 0x0200 {
+        return malformed("truncated response");
+    }
+    let rcode = (flags & 0x000f) as u8;
+    if RCODE_NXDOMAIN == rcode {
+        return Ok((packet, Vec::new()));
+    }
+    if 0 != rcode {
+        return Err(DnsError::Rcode(rcode));
+    }
+    let questions = read_u16(&packet, 4)?;
+    let answers = read_u16(&packet, 6)?;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = read_name(&packet, at)?.1 + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        at = read_name(&packet, at)?.1;
+        let rtype = read_u16(&packet, at)?;
+        let len = read_u16(&packet, at + 8)? as usize;
+        let rdata = at + 10;
+        if packet.len() < rdata + len {
+            return malformed("truncated record");
+        }
+        records.push(Answer { rtype, rdata, len });
+        at = rdata + len;
+    }
+    Ok((packet, records))
+}
+
+pub trait Resolver: Resolve {
+    fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError>;
+
+    fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError>;
+
+    fn lookup_ns(&self, zone: &str) -> Result<Vec<String>, DnsError>;
+
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError>;
+
+    fn via(&self, servers: Vec<IpAddr>, recursive: bool) -> Box<dyn Resolver> {
+        Box::new(UdpResolver { servers, recursive })
+    }
+}
+
+pub fn open(spec: &ResolverSpec) -> Box<dyn Resolver> {
+    match spec {
+        ResolverSpec::System => Box::new(SystemResolver),
+        ResolverSpec::Udp(Some(server)) => Box::new(UdpResolver::new(vec![*server])),
+        ResolverSpec::Udp(None) => Box::new(UdpResolver::system()),
+    }
+}
+
+fn lookup_host(resolver: &dyn Resolver, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    match (resolver.lookup_a(host), resolver.lookup_aaaa(host)) {
+        (Err(e), Err(_)) => Err(e.into()),
+        (v4, v6) => Ok(v4
+            .unwrap_or_default()
+            .into_iter()
+            .map(IpAddr::V4)
+            .chain(v6.unwrap_or_default().into_iter().map(IpAddr::V6))
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect()),
+    }
+}
+
+fn system_lookup(name: &str) -> Result<Vec<IpAddr>, DnsError> {
+    Ok(dice::resolve_to_ip(
+        &SystemResolver,
+        name.trim_end_matches('.'),
+    )?)
+}
+
+impl Resolver for SystemResolver {
+    fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        Ok(system_lookup(name)?
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            })
+            .collect())
+    }
+
+    fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        Ok(system_lookup(name)?
+            .into_iter()
+            .filter_map(|ip| match ip {
+                IpAddr::V4(_) => None,
+                IpAddr::V6(ip) => Some(ip),
+            })
+            .collect())
+    }
+
+    fn lookup_ns(&self, _: &str) -> Result<Vec<String>, DnsError> {
+        Err(DnsError::Unsupported("system", "NS"))
+    }
+
+    fn lookup_txt(&self, _: &str) -> Result<Vec<String>, DnsError> {
+        Err(DnsError::Unsupported("system", "TXT"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UdpResolver {
+    servers: Vec<IpAddr>,
+    recursive: bool,
+}
+
+impl UdpResolver {
+    pub fn new(servers: Vec<IpAddr>) -> UdpResolver {
+        UdpResolver {
+            servers,
+            recursive: true,
+        }
+    }
+
+    pub fn system() -> UdpResolver {
+        UdpResolver::new(system_nameservers())
+    }
+
+    pub fn authoritative(servers: Vec<IpAddr>) -> UdpResolver {
+        UdpResolver {
+            servers,
+            recursive: false,
+        }
+    }
+
+    fn query(&self, name: &str, qtype: u16) -> Result<(Vec<u8>, Vec<Answer>), DnsError> {
+        let mut last = None;
+        for server in &self.servers {
+            match query((*server, 53).into(), name, qtype, self.recursive) {
+                Ok((packet, answers)) => {
+                    let answers = answers
+                        .into_iter()
+                        .filter(|answer| qtype == answer.rtype)
+                        .collect();
+                    return Ok((packet, answers));
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(last.unwrap_or_else(|| DnsError::NoServers(name.to_owned())))
+    }
+
+    fn names(&self, name: &str, qtype: u16) -> Result<Vec<String>, DnsError> {
+        let (packet, answers) = self.query(name, qtype)?;
+        answers
+            .iter()
+            .map(|answer| read_name(&packet, answer.rdata).map(|(name, _)| name))
+            .collect()
+    }
+}
+
+impl Resolve for UdpResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        lookup_host(self, host, port)
+    }
+}
+
+impl Resolver for UdpResolver {
+    fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        let (packet, answers) = self.query(name, TYPE_A)?;
+        Ok(answers
+            .iter()
+            .filter(|answer| 4 == answer.len)
+            .map(|answer| {
+                let at = answer.rdata;
+                Ipv4Addr::new(packet[at], packet[at + 1], packet[at + 2], packet[at + 3])
+            })
+            .collect())
+    }
+
+    fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        let (packet, answers) = self.query(name, TYPE_AAAA)?;
+        Ok(answers
+            .iter()
+            .filter(|answer| 16 == answer.len)
+            .map(|answer| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&packet[answer.rdata..answer.rdata + 16]);
+                Ipv6Addr::from(octets)
+            })
+            .collect())
+    }
+
+    fn lookup_ns(&self, zone: &str) -> Result<Vec<String>, DnsError> {
+        let names = self.names(zone, TYPE_NS)?;
+        if names.is_empty() {
+            return self.names(zone, TYPE_SOA);
+        }
+        Ok(names)
+    }
+
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        let (packet, answers) = self.query(name, TYPE_TXT)?;
+        let mut texts = Vec::new();
+        for answer in &answers {
+            let rdata = &packet[answer.rdata..answer.rdata + answer.len];
+            let mut text = Vec::new();
+            let mut at = 0;
+            while at < rdata.len() {
+                let len = rdata[at] as usize;
+                match rdata.get(at + 1..at + 1 + len) {
+                    Some(chunk) => text.extend_from_slice(chunk),
+                    None => return malformed("truncated TXT string"),
+                }
+                at += 1 + len;
+            }
+            texts.push(String::from_utf8_lossy(&text).into_owned());
+        }
+        Ok(texts)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Default, Clone)]
+pub struct StubResolver {
+    a: HashMap<String, Vec<Ipv4Addr>>,
+    aaaa: HashMap<String, Vec<Ipv6Addr>>,
+    ns: HashMap<String, Vec<String>>,
+    txt: HashMap<String, Vec<String>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+fn stub_key(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl StubResolver {
+    pub fn new() -> StubResolver {
+        StubResolver::default()
+    }
+
+    pub fn a(mut self, name: &str, ip: Ipv4Addr) -> StubResolver {
+        self.a.entry(stub_key(name)).or_default().push(ip);
+        self
+    }
+
+    pub fn aaaa(mut self, name: &str, ip: Ipv6Addr) -> StubResolver {
+        self.aaaa.entry(stub_key(name)).or_default().push(ip);
+        self
+    }
+
+    pub fn ns(mut self, zone: &str, host: &str) -> StubResolver {
+        self.ns
+            .entry(stub_key(zone))
+            .or_default()
+            .push(host.to_owned());
+        self
+    }
+
+    pub fn txt(mut self, name: &str, text: &str) -> StubResolver {
+        self.txt
+            .entry(stub_key(name))
+            .or_default()
+            .push(text.to_owned());
+        self
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Resolve for StubResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        lookup_host(self, host, port)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Resolver for StubResolver {
+    fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+        Ok(self.a.get(&stub_key(name)).cloned().unwrap_or_default())
+    }
+
+    fn lookup_aaaa(&self, name: &str) -> Result<Vec<Ipv6Addr>, DnsError> {
+        Ok(self.aaaa.get(&stub_key(name)).cloned().unwrap_or_default())
+    }
+
+    fn lookup_ns(&self, zone: &str) -> Result<Vec<String>, DnsError> {
+        Ok(self.ns.get(&stub_key(zone)).cloned().unwrap_or_default())
+    }
+
+    fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        Ok(self.txt.get(&stub_key(name)).cloned().unwrap_or_default())
+    }
+
+    fn via(&self, _: Vec<IpAddr>, _: bool) -> Box<dyn Resolver> {
+        Box::new(self.clone())
+    }
+}
+
+pub fn system_nameservers() -> Vec<IpAddr> {
+    let text = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse().ok())
+        .collect()
+}
+
+pub fn nameserver_names(resolver: &dyn Resolver, zone: &str) -> Result<Vec<String>, DnsError> {
+    let names = resolver.lookup_ns(zone)?;
+    if names.is_empty() {
+        return Err(DnsError::NoServers(zone.to_owned()));
+    }
+    Ok(names)
+}
+
+pub fn nameservers(resolver: &dyn Resolver, zone: &str) -> Result<Vec<IpAddr>, DnsError> {
+    let mut servers = Vec::new();
+    for host in resolver.lookup_ns(zone)? {
+        let v4 = resolver.lookup_a(&host).unwrap_or_default();
+        let v6 = resolver.lookup_aaaa(&host).unwrap_or_default();
+        let ips = v4
+            .into_iter()
+            .map(IpAddr::V4)
+            .chain(v6.into_iter().map(IpAddr::V6));
+        for ip in ips {
+            if !servers.contains(&ip) {
+                servers.push(ip);
+            }
+        }
+    }
+    if servers.is_empty() {
+        return Err(DnsError::NoServers(zone.to_owned()));
+    }
+    Ok(servers)
+}
+
+pub fn has_suffix(host: &str, suffixes: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    suffixes.iter().any(|suffix| {
+        let suffix = suffix.trim_matches('.').to_ascii_lowercase();
+        host == suffix || host.ends_with(&format!(".{}", suffix))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub() -> StubResolver {
+        StubResolver::new()
+            .ns("example.com", "ns1.example.net")
+            .ns("example.com", "ns2.example.net")
+            .a("ns1.example.net", Ipv4Addr::new(192, 0, 2, 53))
+            .a("ns2.example.net", Ipv4Addr::new(192, 0, 2, 53))
+            .aaaa("ns2.example.net", "2001:db8::53".parse().unwrap())
+    }
+
+    #[test]
+    fn nameservers_merge_addresses() {
+        let servers = nameservers(&stub(), "Example.COM.").unwrap();
+        let expected: Vec<IpAddr> = vec![
+            Ipv4Addr::new(192, 0, 2, 53).into(),
+            "2001:db8::53".parse().unwrap(),
+        ];
+        assert_eq!(expected, servers);
+    }
+
+    #[test]
+    fn nameservers_require_delegation() {
+        assert!(matches!(
+            nameservers(&stub(), "example.org"),
+            Err(DnsError::NoServers(_))
+        ));
+        assert!(matches!(
+            nameserver_names(&stub(), "example.org"),
+            Err(DnsError::NoServers(_))
+        ));
+        assert_eq!(2, nameserver_names(&stub(), "example.com").unwrap().len());
+    }
+
+    #[test]
+    fn resolvers_resolve_hosts() {
+        let resolver = stub();
+        let addrs = resolver.resolve("ns2.example.net", 65010).unwrap();
+        let expected: Vec<SocketAddr> = vec![
+            "192.0.2.53:65010".parse().unwrap(),
+            "[2001:db8::53]:65010".parse().unwrap(),
+        ];
+        assert_eq!(expected, addrs);
+        let upcast: &dyn Resolve = &resolver as &dyn Resolver;
+        assert!(upcast.resolve("missing.example.net", 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn via_keeps_stub_answers() {
+        let resolver = stub().via(vec![Ipv4Addr::LOCALHOST.into()], false);
+        assert_eq!(
+            vec![Ipv4Addr::new(192, 0, 2, 53)],
+            resolver.lookup_a("ns1.example.net").unwrap()
+        );
+    }
+
+    #[test]
+    fn suffixes_match_whole_labels() {
+        let suffixes = vec!["onamae.com".to_owned()];
+        assert!(has_suffix("01.dnsv.jp.onamae.com.", &suffixes));
+        assert!(has_suffix("ONAMAE.com", &suffixes));
+        assert!(!has_suffix("notonamae.com", &suffixes));
+    }
+}
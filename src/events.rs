@@ -0,0 +1,66 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use crate::config::Record;
+use crate::dice::UpdateOutcome;
+use crate::schedule::Skip;
+use crate::state::Outcome;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    CycleStarted {
+        now: u64,
+        records: usize,
+    },
+    DetectionResult {
+        record: &'a Record,
+        result: Result<Ipv4Addr, &'a str>,
+    },
+    RecordUpdated {
+        record: &'a Record,
+        outcome: Outcome,
+        previous: Option<Ipv4Addr>,
+        update: &'a UpdateOutcome,
+    },
+    RecordUnchanged {
+        record: &'a Record,
+        previous: Option<Ipv4Addr>,
+    },
+    RecordFailed {
+        record: &'a Record,
+        error: &'a str,
+    },
+    RecordSkipped {
+        record: &'a Record,
+        reason: Skip,
+    },
+    CycleFinished {
+        ok: bool,
+        elapsed: Duration,
+    },
+}
+
+pub trait EventSink {
+    fn handle(&mut self, event: &Event);
+}
+
+#[derive(Default)]
+pub struct EventBus<'a> {
+    sinks: Vec<&'a mut dyn EventSink>,
+}
+
+impl<'a> EventBus<'a> {
+    pub fn new() -> EventBus<'a> {
+        EventBus::default()
+    }
+
+    pub fn subscribe(&mut self, sink: &'a mut dyn EventSink) {
+        self.sinks.push(sink);
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        for sink in self.sinks.iter_mut() {
+            sink.handle(&event);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::ffi::{CStr, CString};
+use std::net::Ipv4Addr;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+
+use crate::detect::{self, IpSource};
+use crate::dice::{
+    self, Command, CommandModipExt, GreetingPolicy, Information, Resolve, Timeouts, Timings,
+    ToIpAddrs,
+};
+use crate::normalize;
+use crate::report::{self, EXIT_CONFIG, EXIT_FAILURE};
+use crate::tls;
+
+pub const DDNS_OK: c_int = 0;
+
+#[repr(C)]
+pub struct Params {
+    pub user: *const c_char,
+    pub pass: *const c_char,
+    pub host: *const c_char,
+    pub dom: *const c_char,
+    pub ip: *const c_char,
+    pub timeout_ms: c_uint,
+}
+
+#[repr(C)]
+pub struct UpdateResult {
+    pub code: c_int,
+    pub ip: [c_char; 16],
+    pub error: *mut c_char,
+}
+
+type Failure = (c_int, String);
+
+fn text<'a>(ptr: *const c_char, name: &str) -> Result<Option<&'a str>, Failure> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map(Some)
+        .map_err(|_| (EXIT_CONFIG, format!("{} is not valid UTF-8", name)))
+}
+
+fn required<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    match text(ptr, name)? {
+        Some(value) if !value.trim().is_empty() => Ok(value),
+        _ => Err((EXIT_CONFIG, format!("{} is required", name))),
+    }
+}
+
+fn timeouts(timeout_ms: c_uint) -> Timeouts {
+    if 0 == timeout_ms {
+        return Timeouts::default();
+    }
+    let timeout = Some(Duration::from_millis(u64::from(timeout_ms)));
+    Timeouts {
+        connect: timeout,
+        io: timeout,
+    }
+}
+
+#[cfg(not(feature = "testing"))]
+fn registrar() -> Box<dyn Resolve> {
+    Box::new(dice::SystemResolver)
+}
+
+#[cfg(feature = "testing")]
+fn registrar() -> Box<dyn Resolve> {
+    let addr = std::env::var("DDNS_CLIENT_TEST_REGISTRAR").ok();
+    match addr.and_then(|addr| addr.parse().ok()) {
+        Some(addr) => Box::new(dice::FixedResolver(vec![addr])),
+        None => Box::new(dice::SystemResolver),
+    }
+}
+
+fn update(params: &Params) -> Result<Ipv4Addr, Failure> {
+    let user = required(params.user, "user")?;
+    let pass = required(params.pass, "pass")?;
+    let dom = required(params.dom, "dom")?;
+    let host = text(params.host, "host")?.unwrap_or_default();
+    let invalid = |e| (EXIT_CONFIG, e);
+    let dom = normalize::name(dom).map_err(invalid)?;
+    let host = normalize::name(host).map_err(invalid)?;
+    let ip = required(params.ip, "ip")?;
+    let source = match ip.to_ip_addrs() {
+        Ok(addr) => IpSource::Static(addr),
+        Err(_) => ip.parse().map_err(|e| (EXIT_CONFIG, e))?,
+    };
+    let mut info = Information::new(user, pass, host, dom, Ipv4Addr::UNSPECIFIED);
+    info.ipaddr = detect::detect(&source, &dice::SystemResolver)
+        .map_err(|e| (EXIT_FAILURE, e.to_string()))?;
+
+    let stream = dice::open_with(
+        registrar().as_ref(),
+        timeouts(params.timeout_ms),
+        &mut Timings::default(),
+    )
+    .map_err(|e| (EXIT_FAILURE, e.to_string()))?;
+    let socket = tls::tcp(&stream)
+        .try_clone()
+        .map_err(|e| (EXIT_FAILURE, e.to_string()))?;
+    let mut client = dice::Client::new(stream);
+    client.set_socket(socket);
+    client
+        .read_greeting(GreetingPolicy::default())
+        .and_then(|()| client.run_modip(&info, None))
+        .and_then(|outcome| outcome.mismatch(&info).map_or(Ok(()), Err))
+        .map_err(|e| (report::exit_code(&e), e.to_string()))?;
+    Ok(info.ipaddr)
+}
+
+fn string(text: String) -> *mut c_char {
+    CString::new(text.replace('\0', " ")).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// # Safety
+///
+/// `params` must be null or point to a valid `ddns_params` whose strings are
+/// null or NUL-terminated; `result` must be null or point to writable memory
+/// for a `ddns_result`.
+#[no_mangle]
+pub unsafe extern "C" fn ddns_update(params: *const Params, result: *mut UpdateResult) -> c_int {
+    let outcome = match params.as_ref() {
+        Some(params) => panic::catch_unwind(AssertUnwindSafe(|| update(params)))
+            .unwrap_or_else(|_| Err((EXIT_FAILURE, "internal error".to_owned()))),
+        None => Err((EXIT_CONFIG, "params is NULL".to_owned())),
+    };
+    let code = outcome.as_ref().map_or_else(|(code, _)| *code, |_| DDNS_OK);
+    if let Some(result) = result.as_mut() {
+        result.code = code;
+        result.ip = [0; 16];
+        result.error = ptr::null_mut();
+        match outcome {
+            Ok(ip) => {
+                for (slot, b) in result.ip.iter_mut().zip(ip.to_string().bytes()) {
+                    *slot = b as c_char;
+                }
+            }
+            Err((_, message)) => result.error = string(message),
+        }
+    }
+    code
+}
+
+/// # Safety
+///
+/// `text` must be null or a string returned by this library that has not
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ddns_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
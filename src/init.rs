@@ -0,0 +1,275 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::detect::IpSource;
+use crate::input::{InputError, InputSource, PROMPT_ATTEMPTS};
+use crate::messages;
+use crate::KEY;
+
+pub static DEFAULT_IP_SOURCE: &str = "url:https://api.ipify.org/";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Invocation {
+    pub bare: bool,
+    pub config_found: bool,
+    pub stdin_terminal: bool,
+    pub stdout_terminal: bool,
+    pub interactive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offer {
+    Usage,
+    Init,
+}
+
+pub fn offer(invocation: &Invocation) -> Offer {
+    let Invocation {
+        bare,
+        config_found,
+        stdin_terminal,
+        stdout_terminal,
+        interactive,
+    } = *invocation;
+    if bare && !config_found && stdin_terminal && stdout_terminal && interactive {
+        Offer::Init
+    } else {
+        Offer::Usage
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answers {
+    pub user: String,
+    pub pass: String,
+    pub host: String,
+    pub dom: String,
+    pub ip_source: IpSource,
+}
+
+pub fn ask(source: &mut dyn InputSource) -> Result<Answers, InputError> {
+    let user = source.prompt(&KEY::USER, None, false)?;
+    let pass = source.prompt(&KEY::PASS, None, true)?;
+    let host = source.prompt(&KEY::HOST, None, false)?;
+    let dom = source.prompt(&KEY::DOM, None, false)?;
+    let mut ip_source = None;
+    for _ in 0..PROMPT_ATTEMPTS {
+        let text = source.ask(messages::init_ip_source(), DEFAULT_IP_SOURCE)?;
+        match text.parse() {
+            Ok(parsed) => {
+                ip_source = Some(parsed);
+                break;
+            }
+            Err(e) => source.notice(&messages::warning(e))?,
+        }
+    }
+    let ip_source = match ip_source {
+        Some(ip_source) => ip_source,
+        None => DEFAULT_IP_SOURCE.parse().map_err(io::Error::other)?,
+    };
+    Ok(Answers {
+        user,
+        pass,
+        host,
+        dom,
+        ip_source,
+    })
+}
+
+pub fn render(answers: &Answers) -> String {
+    let text = |value: &str| toml::Value::String(value.to_owned()).to_string();
+    format!(
+        "user = {}\npass = {}\nip_source = {}\n\n[[record]]\nhost = {}\ndom = {}\n",
+        text(&answers.user),
+        text(&answers.pass),
+        text(&answers.ip_source.to_string()),
+        text(&answers.host),
+        text(&answers.dom)
+    )
+}
+
+pub fn write(path: &Path, text: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::input::PartialInfo;
+
+    struct Scripted {
+        answers: Vec<&'static str>,
+        asked: Vec<String>,
+    }
+
+    impl Scripted {
+        fn next(&mut self, what: String) -> Option<&'static str> {
+            self.asked.push(what);
+            if self.answers.is_empty() {
+                None
+            } else {
+                Some(self.answers.remove(0))
+            }
+        }
+    }
+
+    impl InputSource for Scripted {
+        fn can_prompt(&self) -> bool {
+            true
+        }
+
+        fn prompt(
+            &mut self,
+            key: &KEY,
+            _: Option<&str>,
+            secret: bool,
+        ) -> Result<String, InputError> {
+            let answer = self.next(format!("{}{}", key, if secret { " (secret)" } else { "" }));
+            answer
+                .map(str::to_owned)
+                .ok_or_else(|| InputError::Eof(key.clone()))
+        }
+
+        fn bulk(&mut self) -> Result<PartialInfo, InputError> {
+            Ok(PartialInfo::default())
+        }
+
+        fn ask(&mut self, question: &str, default: &str) -> Result<String, InputError> {
+            let answer = self.next(question.to_owned()).unwrap_or_default();
+            if answer.is_empty() {
+                Ok(default.to_owned())
+            } else {
+                Ok(answer.to_owned())
+            }
+        }
+
+        fn notice(&mut self, text: &str) -> Result<(), InputError> {
+            self.asked.push(format!("notice: {}", text));
+            Ok(())
+        }
+    }
+
+    fn scripted(answers: &[&'static str]) -> Scripted {
+        Scripted {
+            answers: answers.to_vec(),
+            asked: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_a_bare_interactive_first_run_offers_init() {
+        let first_run = Invocation {
+            bare: true,
+            config_found: false,
+            stdin_terminal: true,
+            stdout_terminal: true,
+            interactive: true,
+        };
+        assert_eq!(Offer::Init, offer(&first_run));
+        for change in [
+            Invocation {
+                bare: false,
+                ..first_run
+            },
+            Invocation {
+                config_found: true,
+                ..first_run
+            },
+            Invocation {
+                stdin_terminal: false,
+                ..first_run
+            },
+            Invocation {
+                stdout_terminal: false,
+                ..first_run
+            },
+            Invocation {
+                interactive: false,
+                ..first_run
+            },
+            Invocation::default(),
+        ] {
+            assert_eq!(Offer::Usage, offer(&change), "{:?}", change);
+        }
+    }
+
+    #[test]
+    fn answers_render_into_a_loadable_config() {
+        let mut source = scripted(&["me", "p\"w=1", "www", "example.com", "", ""]);
+        let answers = ask(&mut source).unwrap();
+        assert_eq!(DEFAULT_IP_SOURCE, answers.ip_source.to_string());
+        assert!(source.asked[1].ends_with(" (secret)"), "{:?}", source.asked);
+        assert_eq!(messages::init_ip_source(), source.asked[4]);
+
+        let (config, errors, warnings) = Config::parse_toml(&render(&answers), Path::new("new"));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!("me", config.user.unwrap().value);
+        assert_eq!("p\"w=1", config.pass.unwrap().value);
+        assert_eq!(DEFAULT_IP_SOURCE, config.ip.unwrap().value.to_string());
+        assert_eq!(1, config.records.len());
+        assert_eq!("www", config.records[0].host.value);
+        assert_eq!("example.com", config.records[0].dom.as_ref().unwrap().value);
+    }
+
+    #[test]
+    fn bad_ip_sources_are_asked_again_then_defaulted() {
+        let answers = ["u", "p", "@", "example.com", "nope", "dns:wan.example.net"];
+        let mut source = scripted(&answers);
+        let answers = ask(&mut source).unwrap();
+        assert_eq!("dns:wan.example.net", answers.ip_source.to_string());
+        assert_eq!(
+            1,
+            source
+                .asked
+                .iter()
+                .filter(|q| q.starts_with("notice: "))
+                .count()
+        );
+
+        let answers = ["u", "p", "@", "example.com", "nope", "nope:", "x"];
+        let mut source = scripted(&answers);
+        let answers = ask(&mut source).unwrap();
+        assert_eq!(DEFAULT_IP_SOURCE, answers.ip_source.to_string());
+        let notices = source.asked.iter().filter(|q| q.starts_with("notice: "));
+        assert_eq!(PROMPT_ATTEMPTS, notices.count());
+
+        let mut source = scripted(&["u", "p"]);
+        assert!(matches!(ask(&mut source), Err(InputError::Eof(KEY::HOST))));
+    }
+
+    #[test]
+    fn write_creates_a_private_file_and_never_overwrites() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-init-{}", std::process::id()));
+        let path = dir.join("nested/config.toml");
+        write(&path, "user = \"u\"\n").unwrap();
+        assert_eq!("user = \"u\"\n", fs::read_to_string(&path).unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+        let e = write(&path, "user = \"other\"\n").unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, e.kind());
+        assert!(e.to_string().contains("config.toml"), "{}", e);
+        assert_eq!("user = \"u\"\n", fs::read_to_string(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,540 @@
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::dice::Information;
+use crate::messages;
+use crate::signal;
+use crate::KEY;
+
+pub const PROMPT_ATTEMPTS: usize = 3;
+
+pub fn parse_line(line: &str) -> Option<(KEY, String)> {
+    let index = line.find('=')?;
+    let key = line[..index].parse::<KEY>().ok()?;
+    Some((key, line[index + 1..line.len()].to_owned()))
+}
+
+fn mask<'a>(key: &KEY, value: &'a str) -> &'a str {
+    if key.is_secret() {
+        "***"
+    } else {
+        value
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputWarning {
+    UnknownKey {
+        line: usize,
+        key: String,
+    },
+    Malformed {
+        line: usize,
+    },
+    Duplicate {
+        line: usize,
+        key: KEY,
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for InputWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputWarning::UnknownKey { line, key } => {
+                write!(f, "line {}: unknown key {:?}", line, key)
+            }
+            InputWarning::Malformed { line } => {
+                write!(f, "line {}: expected KEY=VALUE", line)
+            }
+            InputWarning::Duplicate {
+                line,
+                key,
+                old,
+                new,
+            } => write!(
+                f,
+                "line {}: {} set again ({:?} -> {:?})",
+                line,
+                key,
+                mask(key, old),
+                mask(key, new)
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InputError {
+    Io(io::Error),
+    Strict(InputWarning),
+    Eof(KEY),
+    Empty(KEY),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Io(e) => write!(f, "{}", e),
+            InputError::Strict(warning) => write!(f, "{}", warning),
+            InputError::Eof(key) => write!(f, "{}", messages::input_ended(key)),
+            InputError::Empty(key) => {
+                write!(f, "{}", messages::empty_input(key, PROMPT_ATTEMPTS))
+            }
+        }
+    }
+}
+
+impl error::Error for InputError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            InputError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for InputError {
+    fn from(e: io::Error) -> InputError {
+        InputError::Io(e)
+    }
+}
+
+pub fn prompt<W, F>(out: &mut W, key: &KEY, text: &str, mut read: F) -> Result<String, InputError>
+where
+    W: Write,
+    F: FnMut(&mut String) -> io::Result<usize>,
+{
+    for _ in 0..PROMPT_ATTEMPTS {
+        write!(out, "{}", text)?;
+        out.flush()?;
+        let mut value = String::new();
+        if 0 == read(&mut value)? {
+            return Err(InputError::Eof(key.clone()));
+        }
+        let value = value.trim_end_matches(['\r', '\n']);
+        if !value.is_empty() {
+            return Ok(value.to_owned());
+        }
+    }
+    Err(InputError::Empty(key.clone()))
+}
+
+#[cfg(unix)]
+pub fn read_secret(value: &mut String) -> io::Result<usize> {
+    let mut term = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { 0 != libc::tcgetattr(libc::STDIN_FILENO, term.as_mut_ptr()) } {
+        return io::stdin().read_line(value);
+    }
+    let term = unsafe { term.assume_init() };
+    let mut noecho = term;
+    noecho.c_lflag &= !libc::ECHO;
+    let guard = signal::guard_terminal(term);
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &noecho) };
+    let res = io::stdin().read_line(value);
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) };
+    drop(guard);
+    println!();
+    res
+}
+
+#[cfg(not(unix))]
+pub fn read_secret(value: &mut String) -> io::Result<usize> {
+    io::stdin().read_line(value)
+}
+
+#[cfg(unix)]
+pub fn stdin_is_terminal() -> bool {
+    unsafe { 0 != libc::isatty(libc::STDIN_FILENO) }
+}
+
+#[cfg(not(unix))]
+pub fn stdin_is_terminal() -> bool {
+    false
+}
+
+#[cfg(unix)]
+pub fn stdout_is_terminal() -> bool {
+    unsafe { 0 != libc::isatty(libc::STDOUT_FILENO) }
+}
+
+#[cfg(not(unix))]
+pub fn stdout_is_terminal() -> bool {
+    false
+}
+
+pub trait InputSource {
+    fn can_prompt(&self) -> bool;
+
+    fn prompt(
+        &mut self,
+        key: &KEY,
+        default: Option<&str>,
+        secret: bool,
+    ) -> Result<String, InputError>;
+
+    fn bulk(&mut self) -> Result<PartialInfo, InputError>;
+
+    fn ask(&mut self, _question: &str, default: &str) -> Result<String, InputError> {
+        Ok(default.to_owned())
+    }
+
+    fn confirm(&mut self, _question: &str, _default: bool) -> Result<bool, InputError> {
+        Ok(false)
+    }
+
+    fn notice(&mut self, _text: &str) -> Result<(), InputError> {
+        Ok(())
+    }
+}
+
+pub struct Terminal<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Terminal<W> {
+    pub fn new(out: W) -> Terminal<W> {
+        Terminal { out }
+    }
+}
+
+impl<W: Write> InputSource for Terminal<W> {
+    fn can_prompt(&self) -> bool {
+        true
+    }
+
+    fn prompt(
+        &mut self,
+        key: &KEY,
+        default: Option<&str>,
+        secret: bool,
+    ) -> Result<String, InputError> {
+        let read = |value: &mut String| {
+            if secret {
+                read_secret(value)
+            } else {
+                io::stdin().read_line(value)
+            }
+        };
+        let default = match default {
+            Some(default) => default,
+            None => return prompt(&mut self.out, key, &messages::prompt(key), read),
+        };
+        let shown = if secret { "***" } else { default };
+        let text = messages::prompt_default(key, shown);
+        write!(self.out, "{}", text)?;
+        self.out.flush()?;
+        let mut value = String::new();
+        if 0 == read(&mut value)? {
+            return Err(InputError::Eof(key.clone()));
+        }
+        match value.trim_end_matches(['\r', '\n']) {
+            "" => Ok(default.to_owned()),
+            value => Ok(value.to_owned()),
+        }
+    }
+
+    fn bulk(&mut self) -> Result<PartialInfo, InputError> {
+        Ok(PartialInfo::default())
+    }
+
+    fn ask(&mut self, question: &str, default: &str) -> Result<String, InputError> {
+        write!(self.out, "{}", messages::prompt_default(question, default))?;
+        self.out.flush()?;
+        let mut value = String::new();
+        io::stdin().read_line(&mut value)?;
+        match value.trim() {
+            "" => Ok(default.to_owned()),
+            value => Ok(value.to_owned()),
+        }
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> Result<bool, InputError> {
+        for _ in 0..PROMPT_ATTEMPTS {
+            write!(self.out, "{}", messages::confirm(question, default))?;
+            self.out.flush()?;
+            let mut value = String::new();
+            if 0 == io::stdin().read_line(&mut value)? {
+                return Ok(false);
+            }
+            match value.trim().to_ascii_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+
+    fn notice(&mut self, text: &str) -> Result<(), InputError> {
+        writeln!(self.out, "{}", text)?;
+        Ok(())
+    }
+}
+
+pub struct Pipe<R: BufRead> {
+    reader: Option<R>,
+    strict: bool,
+}
+
+impl<R: BufRead> Pipe<R> {
+    pub fn new(reader: R, strict: bool) -> Pipe<R> {
+        Pipe {
+            reader: Some(reader),
+            strict,
+        }
+    }
+}
+
+impl<R: BufRead> InputSource for Pipe<R> {
+    fn can_prompt(&self) -> bool {
+        false
+    }
+
+    fn prompt(&mut self, key: &KEY, _: Option<&str>, _: bool) -> Result<String, InputError> {
+        Err(InputError::Eof(key.clone()))
+    }
+
+    fn bulk(&mut self) -> Result<PartialInfo, InputError> {
+        match self.reader.take() {
+            Some(reader) => read_kv_config(reader, self.strict),
+            None => Ok(PartialInfo::default()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PartialInfo {
+    pub user: Option<String>,
+    pub pass: Option<String>,
+    pub host: Option<String>,
+    pub dom: Option<String>,
+    pub warnings: Vec<InputWarning>,
+}
+
+impl PartialInfo {
+    pub fn get(&self, key: &KEY) -> Option<&String> {
+        match key {
+            KEY::USER => self.user.as_ref(),
+            KEY::PASS => self.pass.as_ref(),
+            KEY::HOST => self.host.as_ref(),
+            KEY::DOM => self.dom.as_ref(),
+        }
+    }
+
+    pub fn set(&mut self, key: &KEY, value: String) -> Option<String> {
+        let slot = match key {
+            KEY::USER => &mut self.user,
+            KEY::PASS => &mut self.pass,
+            KEY::HOST => &mut self.host,
+            KEY::DOM => &mut self.dom,
+        };
+        slot.replace(value)
+    }
+
+    pub fn missing(&self) -> Vec<KEY> {
+        KEY::all()
+            .iter()
+            .filter(|key| self.get(key).is_none())
+            .cloned()
+            .collect()
+    }
+
+    pub fn apply(&self, info: &mut Information) {
+        for key in KEY::all() {
+            if let Some(value) = self.get(key) {
+                info[key] = value.clone();
+            }
+        }
+    }
+}
+
+pub fn read_kv_config<R: BufRead>(reader: R, strict: bool) -> Result<PartialInfo, InputError> {
+    let mut partial = PartialInfo::default();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_start();
+        let warning = if line.is_empty() || line.starts_with('#') {
+            None
+        } else if let Some((key, value)) = parse_line(line) {
+            let new = value.clone();
+            partial.set(&key, value).map(|old| InputWarning::Duplicate {
+                line: index + 1,
+                key,
+                old,
+                new,
+            })
+        } else if let Some(end) = line.find('=') {
+            Some(InputWarning::UnknownKey {
+                line: index + 1,
+                key: line[..end].trim().to_owned(),
+            })
+        } else {
+            Some(InputWarning::Malformed { line: index + 1 })
+        };
+        if let Some(warning) = warning {
+            if strict {
+                return Err(InputError::Strict(warning));
+            }
+            partial.warnings.push(warning);
+        }
+    }
+    Ok(partial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_accepts_every_key_name() {
+        assert_eq!(
+            Some((KEY::USER, "alice".to_owned())),
+            parse_line("user=alice")
+        );
+        assert_eq!(
+            Some((KEY::PASS, "a=b ".to_owned())),
+            parse_line("PASSWORD=a=b ")
+        );
+        assert_eq!(Some((KEY::HOST, String::new())), parse_line(" host ="));
+        assert_eq!(
+            Some((KEY::DOM, "example.com".to_owned())),
+            parse_line("DOMNAME=example.com")
+        );
+        assert_eq!(None, parse_line("domain=example.com"));
+        assert_eq!(None, parse_line("user alice"));
+    }
+
+    #[test]
+    fn missing_keys_follow_prompt_order() {
+        let mut partial = PartialInfo::default();
+        assert_eq!(KEY::all().to_vec(), partial.missing());
+        partial.set(&KEY::HOST, "www".to_owned());
+        partial.set(&KEY::USER, "alice".to_owned());
+        assert_eq!(vec![KEY::PASS, KEY::DOM], partial.missing());
+    }
+
+    fn read(text: &str, strict: bool) -> Result<PartialInfo, InputError> {
+        read_kv_config(text.as_bytes(), strict)
+    }
+
+    #[test]
+    fn kv_config_reads_every_key() {
+        let text = "# generated\n\nuser=alice\n  pass=hunter2\nhost=www\ndom=example.com\n";
+        let partial = read(text, true).unwrap();
+        assert_eq!(Some(&"alice".to_owned()), partial.get(&KEY::USER));
+        assert_eq!(Some(&"hunter2".to_owned()), partial.get(&KEY::PASS));
+        assert!(partial.missing().is_empty());
+        assert!(partial.warnings.is_empty());
+        let mut info = Information::default();
+        partial.apply(&mut info);
+        assert_eq!("www.example.com", info.name());
+    }
+
+    #[test]
+    fn kv_config_warns_about_unknown_and_duplicate_keys() {
+        let text = "uesr=alice\nuser=alice\npass=old\nno equals sign\npass=new\n";
+        let partial = read(text, false).unwrap();
+        let warnings: Vec<String> = partial.warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "line 1: unknown key \"uesr\"",
+                "line 4: expected KEY=VALUE",
+                "line 5: PASSWORD set again (\"***\" -> \"***\")",
+            ],
+            warnings
+        );
+        assert_eq!(Some(&"new".to_owned()), partial.get(&KEY::PASS));
+        assert_eq!(vec![KEY::HOST, KEY::DOM], partial.missing());
+
+        let partial = read("host=a\nhost=b\n", false).unwrap();
+        assert_eq!(
+            "line 2: HOSTNAME set again (\"a\" -> \"b\")",
+            partial.warnings[0].to_string()
+        );
+    }
+
+    #[test]
+    fn strict_kv_config_fails_on_the_first_warning() {
+        match read("user=alice\nuesr=bob\npass=x\npass=y\n", true) {
+            Err(InputError::Strict(InputWarning::UnknownKey { line: 2, key })) => {
+                assert_eq!("uesr", key)
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            read("pass=x\npass=y\n", true),
+            Err(InputError::Strict(InputWarning::Duplicate { line: 2, .. }))
+        ));
+        assert!(matches!(
+            read("garbage\n", true),
+            Err(InputError::Strict(InputWarning::Malformed { line: 1 }))
+        ));
+    }
+
+    fn prompt_all(input: &str) -> (Result<Vec<String>, InputError>, String) {
+        let mut reader = io::Cursor::new(input.as_bytes());
+        let mut out = Vec::new();
+        let values = KEY::all()
+            .iter()
+            .map(|key| {
+                let text = messages::prompt(key);
+                prompt(&mut out, key, &text, |value: &mut String| {
+                    reader.read_line(value)
+                })
+            })
+            .collect();
+        (values, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn prompt_fails_when_input_ends_at_any_field() {
+        let lines = ["alice\n", "secret\n", "www\n", "example.com"];
+        for (at, key) in KEY::all().iter().enumerate() {
+            match prompt_all(&lines[..at].concat()) {
+                (Err(InputError::Eof(eof)), _) => assert_eq!(key, &eof),
+                (result, _) => panic!("{}: {:?}", key, result),
+            }
+        }
+        let (values, out) = prompt_all(&lines.concat());
+        assert_eq!(
+            vec!["alice", "secret", "www", "example.com"],
+            values.unwrap()
+        );
+        let prompts: Vec<_> = KEY::all().iter().map(messages::prompt).collect();
+        assert_eq!(prompts.concat(), out);
+        assert_eq!(
+            "input ended while reading PASSWORD",
+            prompt_all("alice\n").0.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn prompt_asks_again_after_empty_input() {
+        let (values, out) = prompt_all("\n\r\nalice\n\nsecret\nwww\nexample.com\n");
+        assert_eq!(
+            vec!["alice", "secret", "www", "example.com"],
+            values.unwrap()
+        );
+        assert_eq!(3, out.matches(&messages::prompt(KEY::USER)).count());
+        assert_eq!(2, out.matches(&messages::prompt(KEY::PASS)).count());
+
+        match prompt_all("alice\n\n\n\n") {
+            (Err(e @ InputError::Empty(KEY::PASS)), out) => {
+                assert_eq!("PASSWORD is required but was empty 3 times", e.to_string());
+                assert_eq!(
+                    PROMPT_ATTEMPTS,
+                    out.matches(&messages::prompt(KEY::PASS)).count()
+                );
+            }
+            (result, _) => panic!("{:?}", result),
+        }
+        assert!(matches!(
+            prompt_all("alice\n\n\n").0,
+            Err(InputError::Eof(KEY::PASS))
+        ));
+    }
+}
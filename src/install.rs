@@ -0,0 +1,201 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub const NAME: &str = "ddns_client";
+pub const SYSTEMD_DIR: &str = "/etc/systemd/system";
+pub const CRON_DIR: &str = "/etc/cron.d";
+pub const CREDENTIAL_PATH: &str = "/etc/ddns_client/credentials";
+
+fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_./:=@,+".contains(&b))
+    {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn command_line(exe: &Path, args: &[String]) -> String {
+    let mut line = quote(&exe.display().to_string());
+    for arg in args {
+        line.push(' ');
+        line.push_str(&quote(arg));
+    }
+    line
+}
+
+pub fn systemd_units(exe: &Path, args: &[String], interval: u64) -> (String, String) {
+    let command = command_line(exe, args)
+        .replace('%', "%%")
+        .replace('$', "$$");
+    let service = format!(
+        "[Unit]\n\
+         Description=Update dynamic DNS records\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         DynamicUser=yes\n\
+         StateDirectory={name}\n\
+         StateDirectoryMode=0700\n\
+         RuntimeDirectory={name}\n\
+         RuntimeDirectoryMode=0700\n\
+         LoadCredential=credentials:{credentials}\n\
+         StandardInput=file:%d/credentials\n\
+         ExecStart={command} -q\n\
+         NoNewPrivileges=yes\n\
+         PrivateTmp=yes\n\
+         PrivateDevices=yes\n\
+         ProtectSystem=strict\n\
+         ProtectHome=yes\n\
+         ProtectKernelTunables=yes\n\
+         ProtectControlGroups=yes\n\
+         RestrictAddressFamilies=AF_INET AF_INET6 AF_NETLINK\n\
+         SystemCallArchitectures=native\n",
+        name = NAME,
+        credentials = CREDENTIAL_PATH,
+        command = command,
+    );
+    let timer = format!(
+        "[Unit]\n\
+         Description=Update dynamic DNS records every {interval}s\n\
+         \n\
+         [Timer]\n\
+         OnBootSec=1min\n\
+         OnUnitActiveSec={interval}s\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        interval = interval,
+    );
+    (service, timer)
+}
+
+pub fn cron_schedule(interval: u64) -> Result<String, String> {
+    let minutes = interval / 60;
+    let hours = minutes / 60;
+    if 0 < minutes && interval.is_multiple_of(60) {
+        if 1 == minutes {
+            return Ok("* * * * *".to_owned());
+        }
+        if 60u64.is_multiple_of(minutes) && minutes < 60 {
+            return Ok(format!("*/{} * * * *", minutes));
+        }
+        if 1 == hours && minutes.is_multiple_of(60) {
+            return Ok("0 * * * *".to_owned());
+        }
+        if minutes.is_multiple_of(60) && 24u64.is_multiple_of(hours) {
+            return Ok(format!("0 */{} * * *", hours));
+        }
+    }
+    Err(format!(
+        "an interval of {}s cannot be expressed as a cron schedule",
+        interval
+    ))
+}
+
+pub fn cron_line(
+    exe: &Path,
+    args: &[String],
+    interval: u64,
+    user: Option<&str>,
+) -> Result<String, String> {
+    let schedule = cron_schedule(interval)?;
+    let command = command_line(exe, args).replace('%', "\\%");
+    Ok(match user {
+        Some(user) => format!("{} {} {} -q\n", schedule, user, command),
+        None => format!("{} {} -q\n", schedule, command),
+    })
+}
+
+pub fn write_new(path: &Path, text: &str, force: bool) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => io::Error::new(
+            e.kind(),
+            format!(
+                "{} already exists (use --force to replace it)",
+                path.display()
+            ),
+        ),
+        _ => io::Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+    })?;
+    file.write_all(text.as_bytes())
+}
+
+pub fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_map_to_cron_schedules() {
+        for (interval, schedule) in [
+            (60, "* * * * *"),
+            (300, "*/5 * * * *"),
+            (3600, "0 * * * *"),
+            (6 * 3600, "0 */6 * * *"),
+        ] {
+            assert_eq!(
+                Ok(schedule.to_owned()),
+                cron_schedule(interval),
+                "{}",
+                interval
+            );
+        }
+        for interval in [0, 30, 90, 7 * 60, 5 * 3600, 86_400 * 2] {
+            assert!(cron_schedule(interval).is_err(), "{}", interval);
+        }
+    }
+
+    #[test]
+    fn arguments_are_quoted_for_the_shell_and_escaped_for_the_scheduler() {
+        let args = vec![
+            "--config".to_owned(),
+            "/etc/my config.toml".to_owned(),
+            "it's".to_owned(),
+        ];
+        let line = command_line(Path::new("/usr/bin/ddns_client"), &args);
+        assert_eq!(
+            "/usr/bin/ddns_client --config '/etc/my config.toml' 'it'\\''s'",
+            line
+        );
+        let args = vec!["--extra".to_owned(), "TTL:100%".to_owned()];
+        let cron = cron_line(Path::new("/bin/d"), &args, 60, Some("root")).unwrap();
+        assert_eq!("* * * * * root /bin/d --extra 'TTL:100\\%' -q\n", cron);
+        let (service, _) = systemd_units(Path::new("/bin/d"), &args, 60);
+        assert!(
+            service.contains("ExecStart=/bin/d --extra 'TTL:100%%' -q\n"),
+            "{}",
+            service
+        );
+    }
+
+    #[test]
+    fn systemd_unit_lets_systemd_own_the_state_directories() {
+        let (service, _) = systemd_units(Path::new("/bin/d"), &[], 60);
+        for directive in [
+            "DynamicUser=yes\n",
+            "StateDirectory=ddns_client\n",
+            "StateDirectoryMode=0700\n",
+            "RuntimeDirectory=ddns_client\n",
+            "RuntimeDirectoryMode=0700\n",
+        ] {
+            assert!(service.contains(directive), "{}", service);
+        }
+        assert!(!service.contains("--state-file"), "{}", service);
+    }
+}
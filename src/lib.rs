@@ -0,0 +1,169 @@
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+pub mod addr;
+pub mod cancel;
+pub mod cli;
+pub mod clock;
+pub mod color;
+pub mod config;
+pub mod ddclient;
+pub mod detect;
+pub mod diag;
+pub mod dice;
+#[cfg(feature = "dns")]
+pub mod dns;
+pub mod dsn;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod init;
+pub mod input;
+pub mod install;
+pub mod messages;
+pub mod normalize;
+pub mod order;
+pub mod paths;
+pub mod pool;
+pub mod protocol;
+pub mod report;
+pub mod retry;
+pub mod schedule;
+pub mod secret;
+pub mod signal;
+pub mod socket;
+pub mod state;
+pub mod stats;
+pub mod status;
+pub mod suffix;
+pub mod tls;
+
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub enum KEY {
+    USER,
+    PASS,
+    HOST,
+    DOM,
+}
+
+impl fmt::Display for KEY {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hint = match self {
+            KEY::USER => "USERID",
+            KEY::PASS => "PASSWORD",
+            KEY::HOST => "HOSTNAME",
+            KEY::DOM => "DOMNAME",
+        };
+        write!(f, "{}", hint)
+    }
+}
+
+impl KEY {
+    pub const fn all() -> &'static [KEY] {
+        &[KEY::USER, KEY::PASS, KEY::HOST, KEY::DOM]
+    }
+
+    pub fn is_secret(&self) -> bool {
+        matches!(self, KEY::PASS)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseKeyError(String);
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown key: {}", self.0)
+    }
+}
+
+impl error::Error for ParseKeyError {}
+
+impl FromStr for KEY {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        for key in KEY::all() {
+            let short = match key {
+                KEY::USER => "user",
+                KEY::PASS => "pass",
+                KEY::HOST => "host",
+                KEY::DOM => "dom",
+            };
+            if name.eq_ignore_ascii_case(short) || name.eq_ignore_ascii_case(&key.to_string()) {
+                return Ok(key.clone());
+            }
+        }
+        Err(ParseKeyError(name.to_owned()))
+    }
+}
+
+pub fn redact(line: &str) -> Cow<'_, str> {
+    for key in KEY::all().iter().filter(|key| key.is_secret()) {
+        let hint = key.to_string();
+        if let Some(value) = line.strip_prefix(&hint).and_then(|s| s.strip_prefix(':')) {
+            if !value.is_empty() {
+                return Cow::Owned(format!("{}:***", hint));
+            }
+        }
+    }
+    Cow::Borrowed(line)
+}
+
+impl AsRef<KEY> for KEY {
+    fn as_ref(&self) -> &KEY {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_parse_from_short_and_long_names() {
+        let cases = [
+            ("user", KEY::USER),
+            ("USERID", KEY::USER),
+            ("pass", KEY::PASS),
+            ("Password", KEY::PASS),
+            ("host", KEY::HOST),
+            ("HOSTNAME", KEY::HOST),
+            (" dom ", KEY::DOM),
+            ("domname", KEY::DOM),
+        ];
+        for (name, key) in cases.iter() {
+            assert_eq!(Ok(key), name.parse::<KEY>().as_ref().map_err(|_| name));
+        }
+        for name in &["", "users", "login", "hostname:", "DOMAIN"] {
+            let error = name.parse::<KEY>().unwrap_err();
+            assert_eq!(format!("unknown key: {}", name.trim()), error.to_string());
+        }
+    }
+
+    #[test]
+    fn keys_iterate_in_prompt_order() {
+        let names: Vec<String> = KEY::all().iter().map(KEY::to_string).collect();
+        assert_eq!(vec!["USERID", "PASSWORD", "HOSTNAME", "DOMNAME"], names);
+        for key in KEY::all() {
+            assert_eq!(
+                Ok(key),
+                key.to_string().parse::<KEY>().as_ref().map_err(|_| key)
+            );
+        }
+        let secret: Vec<&KEY> = KEY::all().iter().filter(|key| key.is_secret()).collect();
+        assert_eq!(vec![&KEY::PASS], secret);
+    }
+
+    #[test]
+    fn redact_masks_only_secret_values() {
+        assert_eq!("PASSWORD:***", redact("PASSWORD:hunter2"));
+        assert_eq!("PASSWORD:", redact("PASSWORD:"));
+        assert_eq!("USERID:alice", redact("USERID:alice"));
+        assert_eq!("PASSWORDS:hunter2", redact("PASSWORDS:hunter2"));
+        assert!(matches!(redact("HOSTNAME:www"), Cow::Borrowed(_)));
+    }
+}
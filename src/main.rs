@@ -1,150 +1,3129 @@
-use getopts::Options;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fmt;
-use std::io::{self, Write};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-mod dice;
-use dice::{Command, CommandModipExt, ToIpAddrs};
+mod capture;
 
-fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} IPV4 [options]", program);
-    print!("{}", opts.usage(&brief));
+use ddns_client::addr::{self, AddrError};
+use ddns_client::cli;
+use ddns_client::clock::{Clock, SystemClock};
+use ddns_client::color;
+use ddns_client::config::{
+    self, Config, ConfigError, Record, Setting, Source, StateMode, Transport,
+};
+use ddns_client::ddclient;
+use ddns_client::detect::{self, DetectError, Detector, IpSource, Penalties};
+use ddns_client::diag::{self, Bundle};
+use ddns_client::dice::{
+    self, ClientError, ClientStats, Command, CommandModipExt, DryRun, GreetingPolicy, Information,
+    NotFoundScope, OpenError, Pacing, ResolveCache, ResponseError, Timings, ToIpAddrs,
+    UpdateOutcome,
+};
+#[cfg(feature = "dns")]
+use ddns_client::dns::{self, DnsError};
+use ddns_client::dsn::Dsn;
+use ddns_client::events::{Event, EventBus, EventSink};
+use ddns_client::init::{self, Offer};
+use ddns_client::input::{self, InputSource, Pipe, Terminal};
+use ddns_client::install;
+use ddns_client::messages;
+use ddns_client::order::{self, Node};
+use ddns_client::paths;
+use ddns_client::pool::{self, Pool, PoolKey, Pooled};
+use ddns_client::report::{
+    self, ExitOptions, Report, EXIT_AUTH_BREAKER, EXIT_CONFIG, EXIT_FAILURE, EXIT_RECV_TIMEOUT,
+    EXIT_SEND_TIMEOUT,
+};
+use ddns_client::retry;
+use ddns_client::schedule::{self, Skip};
+use ddns_client::secret;
+use ddns_client::signal;
+use ddns_client::socket;
+use ddns_client::state::{self, BreakerEvent, Outcome, Persistence, SessionStrategy, State};
+use ddns_client::stats::LatencyWindow;
+use ddns_client::status::{self, Health, Status};
+use ddns_client::tls;
+use ddns_client::KEY;
+
+fn print_usage(program: &str) {
+    print!("{}", cli::usage(program));
+}
+
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+struct Fatal {
+    kind: &'static str,
+    message: String,
+    detail: serde_json::Value,
+}
+
+impl fmt::Display for Fatal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Report for Fatal {
+    fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    fn detail(&self) -> serde_json::Value {
+        self.detail.clone()
+    }
+}
+
+fn exit_with(e: &dyn Report, code: i32) -> ! {
+    capture::release();
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!("{}", e.to_json(code));
+    } else {
+        eprintln!("{}", messages::error(e));
+    }
+    process::exit(code);
+}
+
+fn fail_client(e: ClientError) -> ! {
+    match e {
+        ClientError::Cancelled => exit_with(&e, signal::EXIT_INTERRUPTED),
+        ClientError::SendTimeout(..) => exit_with(&e, EXIT_SEND_TIMEOUT),
+        ClientError::RecvTimeout(..) => {
+            if e.is_ambiguous() && !JSON_ERRORS.load(Ordering::Relaxed) {
+                eprintln!("{}", messages::warning(messages::maybe_applied()));
+            }
+            exit_with(&e, EXIT_RECV_TIMEOUT)
+        }
+        ClientError::Response(ResponseError::NotFound, _) => {
+            let hint = match e.not_found_scope() {
+                Some(NotFoundScope::Domain) => Some(messages::domain_not_found()),
+                Some(NotFoundScope::Host) => Some(messages::host_not_found()),
+                _ => None,
+            };
+            if let Some(hint) = hint.filter(|_| !JSON_ERRORS.load(Ordering::Relaxed)) {
+                eprintln!("{}", messages::hint(hint));
+            }
+            fail(e)
+        }
+        e => fail(e),
+    }
+}
+
+fn fail(e: impl Report) -> ! {
+    exit_with(&e, EXIT_FAILURE)
+}
+
+fn fail_usage(program: &str, e: getopts::Fail) -> ! {
+    eprintln!("{}", messages::hint(messages::see_help(program)));
+    let fatal = Fatal {
+        kind: "usage",
+        message: e.to_string(),
+        detail: serde_json::Value::Null,
+    };
+    exit_with(&fatal, EXIT_CONFIG)
+}
+
+fn ddclient_format(matches: &getopts::Matches) -> bool {
+    match matches.opt_str("config-format").as_deref() {
+        None | Some("toml") => false,
+        Some("ddclient") => true,
+        Some(other) => fail(messages::unknown_format("config", other)),
+    }
+}
+
+fn config_files(matches: &getopts::Matches) -> Vec<PathBuf> {
+    let files: Vec<PathBuf> = matches
+        .opt_strs("config")
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    if !files.is_empty() {
+        return files;
+    }
+    paths::default_paths()
+        .map(|paths| paths.config.clone())
+        .filter(|path| path.exists())
+        .into_iter()
+        .collect()
+}
+
+fn with_drop_ins(files: Vec<PathBuf>, ddclient: bool) -> Vec<PathBuf> {
+    if ddclient {
+        return files;
+    }
+    Config::layers(files).unwrap_or_else(|e| fail(e))
+}
+
+fn check_config(files: Vec<String>, ddclient: bool) -> i32 {
+    let mut files: Vec<PathBuf> = files.into_iter().map(PathBuf::from).collect();
+    if files.is_empty() {
+        match paths::default_paths() {
+            Some(paths) => files.push(paths.config.clone()),
+            None => fail(messages::no_home()),
+        }
+    }
+    let files = with_drop_ins(files, ddclient);
+    let mut config = Config::default();
+    let mut problems = Vec::new();
+    for path in &files {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => fail(ConfigError::Io(path.clone(), e)),
+        };
+        let (layer, diagnostics, warnings) = if ddclient {
+            ddclient::parse(&text, path)
+        } else {
+            Config::parse_toml(&text, path)
+        };
+        for warning in &warnings {
+            eprintln!("{}", messages::warning(warning));
+        }
+        config.stack(layer);
+        problems.extend(diagnostics);
+    }
+    match Config::from_env() {
+        Ok(env) => config.merge(env),
+        Err(ConfigError::Invalid(diagnostics)) => problems.extend(diagnostics),
+        Err(e) => fail(e),
+    }
+    match config.resolve_secrets() {
+        Ok(()) => {}
+        Err(ConfigError::Invalid(diagnostics)) => problems.extend(diagnostics),
+        Err(e) => fail(e),
+    }
+    problems.extend(config.validate());
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.is_empty() {
+        for path in &files {
+            println!("{}", messages::config_ok(path));
+        }
+        0
+    } else {
+        eprintln!("{}", messages::problems_found(problems.len()));
+        1
+    }
+}
+
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "dns") {
+        features.push("dns");
+    }
+    if cfg!(feature = "psl") {
+        features.push("psl");
+    }
+    if cfg!(feature = "tls-openssl") {
+        features.push("tls-openssl");
+    }
+    features
+}
+
+fn capabilities() -> serde_json::Value {
+    let subcommands: Vec<&str> = cli::SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+    let options: Vec<&str> = cli::OPTIONS.iter().map(|spec| spec.long).collect();
+    let exit_codes: serde_json::Map<String, serde_json::Value> = report::EXIT_CODES
+        .iter()
+        .map(|(name, code)| ((*name).to_owned(), (*code).into()))
+        .collect();
+    serde_json::json!({
+        "capabilities_version": report::CAPABILITIES_VERSION,
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "tls_backend": tls::BACKEND,
+        "features": features(),
+        "providers": [config::PROVIDER],
+        "ip_sources": detect::KINDS,
+        "output_formats": report::OUTPUT_FORMATS,
+        "subcommands": subcommands,
+        "options": options,
+        "exit_codes": exit_codes,
+    })
+}
+
+fn capabilities_command(matches: &getopts::Matches) -> i32 {
+    let document = capabilities();
+    match matches.opt_str("output").as_deref() {
+        Some("json") => println!("{}", document),
+        None | Some("text") => {
+            for (key, value) in document.as_object().into_iter().flatten() {
+                let text = match value {
+                    serde_json::Value::Array(items) => items
+                        .iter()
+                        .filter_map(serde_json::Value::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    serde_json::Value::Object(map) => map
+                        .iter()
+                        .map(|(name, code)| format!("{}={}", name, code))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    serde_json::Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                println!("{}: {}", key, text);
+            }
+        }
+        Some(other) => fail(messages::unknown_format("output", other)),
+    }
+    0
+}
+
+fn ci() -> bool {
+    match env::var("CI") {
+        Ok(value) => !value.is_empty() && "false" != value && "0" != value,
+        Err(_) => false,
+    }
+}
+
+fn consulted(
+    config: &Config,
+    key: &KEY,
+    files: &[PathBuf],
+    stdin: bool,
+    prompt: &str,
+) -> Vec<String> {
+    let mut sources = Vec::new();
+    if let Some(setting) = config.get(key) {
+        sources.push(messages::consulted_empty(&setting.source));
+    }
+    match key {
+        KEY::USER => sources.push("--user".to_owned()),
+        KEY::HOST | KEY::DOM => {
+            sources.push(format!("--{}", config::key_name(key)));
+            sources.push("--record".to_owned());
+        }
+        KEY::PASS => {}
+    }
+    sources.push(format!("${}", config::env_name(key)));
+    if stdin {
+        sources.push(messages::consulted_stdin(key));
+    }
+    for path in files {
+        sources.push(messages::consulted_file(config::key_name(key), path));
+    }
+    if files.is_empty() {
+        sources.push(messages::no_config_file());
+    }
+    sources.push(messages::prompt_skipped(prompt));
+    sources
+}
+
+fn credential(action: Option<&str>, keyfile: Option<String>, interactive: bool) -> i32 {
+    if Some("encrypt") != action {
+        fail(messages::credential_usage());
+    }
+    let keyfile = match keyfile {
+        Some(keyfile) => PathBuf::from(keyfile),
+        None => fail(messages::keyfile_required()),
+    };
+    let key = if keyfile.exists() {
+        secret::read_key(&keyfile)
+    } else {
+        eprintln!("{}", messages::creating_key_file(&keyfile));
+        secret::generate_key(&keyfile)
+    }
+    .unwrap_or_else(|e| fail(e));
+    let mut pass = String::new();
+    if interactive {
+        let ask = |text: String| {
+            input::prompt(&mut io::stderr(), &KEY::PASS, &text, input::read_secret)
+                .unwrap_or_else(|e| fail(e))
+        };
+        pass = ask(messages::prompt(KEY::PASS));
+        if pass != ask(messages::prompt_again(KEY::PASS)) {
+            fail(messages::passwords_differ());
+        }
+    } else if let Err(e) = io::stdin().read_line(&mut pass) {
+        fail(messages::stdin_error(e));
+    }
+    let pass = pass.trim_end_matches('\n');
+    let encrypted = secret::encrypt(&key, pass).unwrap_or_else(|e| fail(e));
+    println!("password_encrypted = {}", toml::Value::String(encrypted));
+    println!(
+        "password_keyfile = {}",
+        toml::Value::String(keyfile.display().to_string())
+    );
+    0
+}
+
+const PASS_THROUGH: [&str; 22] = [
+    "config-format",
+    "user",
+    "host",
+    "dom",
+    "record",
+    "bind-address",
+    "local-port-range",
+    "connect-family",
+    "tls-min-version",
+    "max-age",
+    "retries",
+    "retry-delay",
+    "nameserver",
+    "resolver",
+    "pool-size",
+    "pool-idle",
+    "status-file",
+    "latency-window",
+    "latency-warn-ms",
+    "max-bytes-per-day",
+    "send-chunk-lines",
+    "send-pacing-ms",
+];
+
+fn init_command(source: &mut dyn InputSource, program: &str) -> i32 {
+    let path = match paths::default_paths() {
+        Some(paths) => paths.config.clone(),
+        None => fail(messages::no_home()),
+    };
+    if !source
+        .confirm(messages::init_offer(), true)
+        .unwrap_or_else(|e| fail(e))
+    {
+        print_usage(program);
+        return 0;
+    }
+    let answers = init::ask(source).unwrap_or_else(|e| fail(e));
+    init::write(&path, &init::render(&answers)).unwrap_or_else(|e| fail(e.to_string()));
+    eprintln!("{}", messages::wrote(&path));
+    eprintln!("{}", messages::hint(messages::init_next_steps(&path)));
+    if !source
+        .confirm(messages::init_update(), true)
+        .unwrap_or_else(|e| fail(e))
+    {
+        return 0;
+    }
+    let exe = env::current_exe().unwrap_or_else(|e| fail(messages::current_exe(e)));
+    match process::Command::new(exe)
+        .arg("--config")
+        .arg(&path)
+        .status()
+    {
+        Ok(status) => status.code().unwrap_or(EXIT_FAILURE),
+        Err(e) => fail(e.to_string()),
+    }
+}
+
+fn install_command(matches: &getopts::Matches) -> i32 {
+    let kind = matches.free.get(1).map(String::as_str);
+    let interval = match matches.opt_str("timer") {
+        Some(value) => config::parse_duration(&value).unwrap_or_else(|e| fail(e)),
+        None => 300,
+    };
+    if 0 == interval {
+        fail(messages::timer_zero());
+    }
+    let exe = env::current_exe().unwrap_or_else(|e| fail(messages::current_exe(e)));
+    let mut args = vec!["--interval".to_owned(), interval.to_string()];
+    for path in config_files(matches) {
+        args.push("--config".to_owned());
+        args.push(install::absolute(&path).display().to_string());
+    }
+    if let Some(path) = matches.opt_str("ca-file") {
+        args.push("--ca-file".to_owned());
+        args.push(install::absolute(Path::new(&path)).display().to_string());
+    }
+    for name in PASS_THROUGH.iter() {
+        if let Some(value) = matches.opt_str(name) {
+            args.push(format!("--{}", name));
+            args.push(value);
+        }
+    }
+    for flag in [
+        "check-dns-first",
+        "check-ownership",
+        "strict-ownership",
+        "strict-ip-validation",
+        "tolerate-ack-mismatch",
+        "no-state",
+        "no-greeting",
+        "resolve-positional",
+    ]
+    .iter()
+    {
+        if matches.opt_present(flag) {
+            args.push(format!("--{}", flag));
+        }
+    }
+    let force = matches.opt_present("force");
+    let write = |dir: &str, name: &str, text: &str| {
+        let dir = matches.opt_str("write").unwrap_or_else(|| dir.to_owned());
+        let path = Path::new(&dir).join(name);
+        install::write_new(&path, text, force).unwrap_or_else(|e| fail(e.to_string()));
+        eprintln!("{}", messages::wrote(&path));
+    };
+    match kind {
+        Some("systemd") => {
+            let (service, timer) = install::systemd_units(&exe, &args, interval);
+            if matches.opt_present("write") {
+                let service_name = format!("{}.service", install::NAME);
+                let timer_name = format!("{}.timer", install::NAME);
+                write(install::SYSTEMD_DIR, &service_name, &service);
+                write(install::SYSTEMD_DIR, &timer_name, &timer);
+                eprintln!(
+                    "{}",
+                    messages::systemd_next_steps(install::CREDENTIAL_PATH, &timer_name)
+                );
+            } else {
+                println!("# {}.service\n{}", install::NAME, service);
+                println!("# {}.timer\n{}", install::NAME, timer);
+            }
+        }
+        Some("cron") => {
+            if matches.opt_present("write") {
+                let line = install::cron_line(&exe, &args, interval, Some("root"))
+                    .unwrap_or_else(|e| fail(e));
+                write(install::CRON_DIR, install::NAME, &line);
+            } else {
+                let line =
+                    install::cron_line(&exe, &args, interval, None).unwrap_or_else(|e| fail(e));
+                print!("{}", line);
+            }
+        }
+        _ => fail(messages::install_usage()),
+    }
+    0
+}
+
+struct Planned {
+    record: Record,
+    outcome: Outcome,
+    previous: Option<Ipv4Addr>,
+}
+
+fn transport_of(plan: &[Planned], info: &Information) -> Transport {
+    plan.iter()
+        .find(|planned| planned.record.info.name() == info.name())
+        .map(|planned| planned.record.transport)
+        .unwrap_or_default()
+}
+
+fn state_paths(config: &Config) -> (PathBuf, PathBuf) {
+    let state = match &config.state_file {
+        Some(setting) => setting.value.clone(),
+        None => match paths::default_paths() {
+            Some(paths) => paths.state.clone(),
+            None => fail(messages::no_home()),
+        },
+    };
+    let status = match &config.status_file {
+        Some(setting) => setting.value.clone(),
+        None => state.with_file_name("status.json"),
+    };
+    (state, status)
+}
+
+fn persistence(config: &Config) -> Persistence {
+    match config.state_mode() {
+        StateMode::None => Persistence::Disabled,
+        StateMode::File => {
+            let (state, status) = state_paths(config);
+            let lock = match (&config.state_file, paths::default_paths()) {
+                (None, Some(paths)) => paths.lock.clone(),
+                _ => state.with_file_name("lock"),
+            };
+            Persistence::Files {
+                state,
+                status,
+                lock,
+            }
+        }
+    }
+}
+
+fn breaker_json(state: &State, now: u64) -> serde_json::Value {
+    let remaining = state.auth.check(now).err();
+    serde_json::json!({
+        "failures": state.auth.failures,
+        "open": remaining.is_some(),
+        "retry_after_seconds": remaining,
+        "retry_at": remaining.map(|remaining| schedule::local_timestamp(now + remaining)),
+    })
+}
+
+fn traffic_json(config: &Config, state: &State, now: u64) -> serde_json::Value {
+    let mut traffic = state.traffic.summary_json(now);
+    traffic["max_bytes_per_day"] = config.max_bytes_per_day().into();
+    traffic["suspended"] = state
+        .traffic
+        .exceeded(now, config.max_bytes_per_day())
+        .into();
+    traffic
+}
+
+fn traffic_line(state: &State, now: u64) -> Option<String> {
+    let traffic = &state.traffic;
+    traffic.since?;
+    let total = (traffic.total_sent, traffic.total_received);
+    Some(messages::status_traffic(traffic.today(now), total))
+}
+
+fn status_command(config: &Config, now: u64, json: bool) -> i32 {
+    let (state_path, status_path) = state_paths(config);
+    let state = State::load(&state_path).unwrap_or_else(|e| fail(e));
+    let breaker = state.auth.check(now).err().map(|remaining| {
+        let retry_at = schedule::local_timestamp(now + remaining);
+        messages::status_breaker(state.auth.failures, remaining, &retry_at)
+    });
+    let status = match Status::load(&status_path).unwrap_or_else(|e| fail(e)) {
+        Some(status) => status,
+        None => {
+            let records: BTreeMap<String, Option<u64>> = state
+                .records
+                .iter()
+                .map(|(name, record)| (name.clone(), record.last_success))
+                .collect();
+            if json {
+                let report = serde_json::json!({
+                    "status_file": null,
+                    "records": records,
+                    "auth_breaker": breaker_json(&state, now),
+                    "traffic": traffic_json(config, &state, now),
+                });
+                println!("{}", report);
+            } else {
+                println!("{}", messages::no_status_file(&status_path));
+                if let Some(line) = &breaker {
+                    println!("{}", line);
+                }
+                if let Some(line) = traffic_line(&state, now) {
+                    println!("{}", line);
+                }
+                for (name, last) in &records {
+                    println!("{}", messages::status_record(name, *last, now));
+                }
+            }
+            return 0;
+        }
+    };
+    let health = status.health(now);
+    if json {
+        let mut report = status.to_json();
+        report["health"] = serde_json::Value::from(health.to_string());
+        report["auth_breaker"] = breaker_json(&state, now);
+        report["traffic"] = traffic_json(config, &state, now);
+        println!("{}", report);
+    } else {
+        println!(
+            "{}",
+            messages::status_health(health, status.last_cycle, now)
+        );
+        if let Some(line) = &breaker {
+            println!("{}", line);
+        }
+        if let Some(ip) = status.ip {
+            println!("{}", messages::status_ip(ip));
+        }
+        if let Some(line) = traffic_line(&state, now) {
+            println!("{}", line);
+        }
+        for (name, last) in &status.records {
+            println!("{}", messages::status_record(name, *last, now));
+        }
+        for (stage, summary) in status.latency.summary() {
+            println!("{}", messages::status_latency(&stage, &summary));
+        }
+    }
+    match health {
+        Health::Ok => 0,
+        _ => EXIT_FAILURE,
+    }
+}
+
+#[cfg(feature = "dns")]
+type Resolver = Box<dyn dns::Resolver>;
+
+#[cfg(not(feature = "dns"))]
+type Resolver = Box<dyn dice::Resolve>;
+
+#[cfg(feature = "dns")]
+fn open_resolver(config: &Config) -> Resolver {
+    dns::open(&config.resolver())
+}
+
+#[cfg(not(feature = "dns"))]
+fn open_resolver(config: &Config) -> Resolver {
+    if config.resolver.is_some() {
+        fail(messages::dns_unsupported("--resolver"));
+    }
+    Box::new(dice::SystemResolver)
+}
+
+fn host_resolver<'a>(config: &Config, resolver: &'a Resolver) -> &'a dyn dice::Resolve {
+    if config.resolver.is_some() {
+        resolver.as_ref()
+    } else {
+        &dice::SystemResolver
+    }
+}
+
+#[cfg(feature = "dns")]
+fn resolve_positional(resolver: &Resolver, name: &str, nameserver: Option<IpAddr>) -> Ipv4Addr {
+    let addrs = match nameserver {
+        Some(ip) => resolver.via(vec![ip], true).lookup_a(name),
+        None => resolver.lookup_a(name),
+    }
+    .unwrap_or_else(|e| fail(messages::positional_resolve_failed(name, e)));
+    let ip = match addrs.first() {
+        Some(ip) => *ip,
+        None => fail(messages::positional_no_address(name)),
+    };
+    if 1 < addrs.len() {
+        let message = messages::positional_many(name, addrs.len(), ip);
+        eprintln!("{}", messages::warning(message));
+    }
+    ip
+}
+
+#[cfg(not(feature = "dns"))]
+fn resolve_positional(_: &Resolver, _: &str, _: Option<IpAddr>) -> Ipv4Addr {
+    fail(messages::dns_unsupported("--resolve-positional"))
+}
+
+fn detect_command(resolver: &dyn dice::Resolve, sources: &[IpSource], json: bool) -> i32 {
+    if sources.is_empty() {
+        fail(messages::no_ip_source());
+    }
+    let mut errors = Vec::new();
+    for source in sources {
+        let started = Instant::now();
+        match detect::detect(source, resolver) {
+            Ok(ip) => {
+                let elapsed = started.elapsed();
+                let special = detect::special_range(ip);
+                if json {
+                    let report = serde_json::json!({
+                        "ip": ip.to_string(),
+                        "ip_source": source.to_string(),
+                        "elapsed": elapsed.as_secs_f64(),
+                        "special": special.map(|(network, prefix, name)| serde_json::json!({
+                            "range": format!("{}/{}", network, prefix),
+                            "name": name,
+                        })),
+                        "errors": errors,
+                    });
+                    println!("{}", report);
+                } else {
+                    if let Some(range) = special {
+                        eprintln!(
+                            "{}",
+                            messages::warning(messages::special_address(ip, range))
+                        );
+                    }
+                    println!("{}", ip);
+                }
+                return 0;
+            }
+            Err(e) => {
+                if !JSON_ERRORS.load(Ordering::Relaxed) {
+                    eprintln!("{}", messages::warning(messages::source_failed(source, &e)));
+                }
+                errors.push(serde_json::json!({
+                    "ip_source": source.to_string(),
+                    "error": e.to_string(),
+                }));
+            }
+        }
+    }
+    let fatal = Fatal {
+        kind: "detect_failed",
+        message: "no ip source produced a public address".to_owned(),
+        detail: serde_json::json!({ "errors": errors }),
+    };
+    exit_with(&fatal, EXIT_FAILURE);
+}
+
+#[cfg(feature = "dns")]
+fn check_ownership(resolver: &Resolver, dom: &str, suffixes: &[String]) -> Result<(), String> {
+    match dns::nameserver_names(resolver.as_ref(), dom) {
+        Ok(names) if names.iter().all(|name| dns::has_suffix(name, suffixes)) => Ok(()),
+        Ok(names) => Err(messages::delegated_elsewhere(dom, &names)),
+        Err(e) => Err(messages::ownership_unknown(dom, e)),
+    }
+}
+
+#[cfg(not(feature = "dns"))]
+fn check_ownership(_: &Resolver, _: &str, _: &[String]) -> Result<(), String> {
+    Err(messages::dns_unsupported("--check-ownership"))
+}
+
+#[cfg(feature = "dns")]
+fn dns_has(
+    resolver: &Resolver,
+    info: &Information,
+    nameserver: Option<IpAddr>,
+    zones: &mut HashMap<String, Vec<IpAddr>>,
+) -> Result<bool, DnsError> {
+    let servers = match nameserver {
+        Some(ip) => vec![ip],
+        None => match zones.get(&info.dom) {
+            Some(servers) => servers.clone(),
+            None => {
+                let servers = dns::nameservers(resolver.as_ref(), &info.dom)?;
+                zones.insert(info.dom.clone(), servers.clone());
+                servers
+            }
+        },
+    };
+    let addrs = resolver.via(servers, false).lookup_a(&info.name())?;
+    Ok(addrs == [info.ipaddr])
+}
+
+#[cfg(not(feature = "dns"))]
+fn dns_has(
+    _: &Resolver,
+    _: &Information,
+    _: Option<IpAddr>,
+    _: &mut HashMap<String, Vec<IpAddr>>,
+) -> Result<bool, String> {
+    Err(messages::dns_unsupported("--check-dns-first"))
+}
+
+fn dry_run_command(
+    resolver: &Resolver,
+    plan: &[Planned],
+    pending: &[Information],
+    skipped: &[(Record, Skip)],
+    state: &State,
+    offline: bool,
+    json: bool,
+) -> Option<serde_json::Value> {
+    let mut records = Vec::new();
+    for planned in plan {
+        let info = &planned.record.info;
+        let name = info.name();
+        let known = state.record(&name).and_then(|known| known.ip);
+        let resolved = if offline {
+            None
+        } else {
+            lookup_ipv4(resolver, &name)
+        };
+        if json {
+            records.push(serde_json::json!({
+                "name": name,
+                "ip": info.ipaddr.to_string(),
+                "state_ip": known.map(|ip| ip.to_string()),
+                "dns_ip": resolved.map(|ip| ip.to_string()),
+                "ip_source": planned.record.ip.value.to_string(),
+                "outcome": planned.outcome.as_str(),
+            }));
+        } else {
+            let line =
+                messages::dry_run_record(&name, known, resolved, info.ipaddr, planned.outcome);
+            println!("{}", line);
+        }
+    }
+    for (record, skip) in skipped {
+        let name = record.info.name();
+        if json {
+            records.push(serde_json::json!({
+                "name": name,
+                "ip_source": record.ip.value.to_string(),
+                "outcome": "skipped",
+                "reason": skip.as_str(),
+            }));
+        } else {
+            println!("{}", messages::dry_run_skipped(&name, *skip));
+        }
+    }
+    let mut sessions = Vec::new();
+    let key = |info: &Information| {
+        let transport = transport_of(plan, info);
+        (info.user.clone(), info.pass.clone(), transport)
+    };
+    for group in group_by(pending, plan, key) {
+        let batches = match state.session_strategy {
+            SessionStrategy::Shared => vec![group],
+            SessionStrategy::PerDomain => group_by(&group, plan, |info| info.dom.clone()),
+        };
+        for batch in batches {
+            let mut client = DryRun::default();
+            let _ = client.run_modip_batch(&batch);
+            sessions.push(client.lines);
+        }
+    }
+    if json {
+        return Some(serde_json::json!({
+            "dry_run": true,
+            "records": records,
+            "sessions": sessions,
+        }));
+    }
+    for (i, lines) in sessions.iter().enumerate() {
+        println!("{}", messages::dry_run_session(i + 1));
+        for line in lines {
+            println!("  {}", line);
+        }
+    }
+    None
+}
+
+fn group_by<K, F>(records: &[Information], plan: &[Planned], key: F) -> Vec<Vec<Information>>
+where
+    K: PartialEq,
+    F: Fn(&Information) -> K,
+{
+    let nodes: Vec<(K, Node)> = records
+        .iter()
+        .map(|info| {
+            let node = match plan.iter().find(|planned| planned.record.info == *info) {
+                Some(planned) => planned.record.node(),
+                None => Node {
+                    name: info.name(),
+                    priority: 0,
+                    depends_on: &[],
+                },
+            };
+            (key(info), node)
+        })
+        .collect();
+    order::group(&nodes)
+        .into_iter()
+        .map(|group| group.into_iter().map(|at| records[at].clone()).collect())
+        .collect()
+}
+
+#[cfg(feature = "dns")]
+fn lookup_ipv4(resolver: &Resolver, name: &str) -> Option<Ipv4Addr> {
+    resolver.lookup_a(name).ok()?.first().copied()
+}
+
+#[cfg(not(feature = "dns"))]
+fn lookup_ipv4(resolver: &Resolver, name: &str) -> Option<Ipv4Addr> {
+    dice::resolve_to_ip(resolver.as_ref(), name)
+        .ok()?
+        .into_iter()
+        .find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+}
+
+fn summary_line(
+    record: &Record,
+    outcome: Outcome,
+    previous: Option<Ipv4Addr>,
+    elapsed: Duration,
+) -> String {
+    let via: Option<&dyn fmt::Display> = match &record.ip.value {
+        IpSource::Static(_) => None,
+        source => Some(source),
+    };
+    messages::summary(
+        outcome,
+        &record.info.name(),
+        previous,
+        record.info.ipaddr,
+        via,
+        elapsed,
+    )
+}
+
+struct SummarySink;
+
+impl EventSink for SummarySink {
+    fn handle(&mut self, event: &Event) {
+        match *event {
+            Event::RecordUpdated {
+                record,
+                outcome,
+                previous,
+                update,
+            } => println!(
+                "{}",
+                summary_line(record, outcome, previous, update.duration)
+            ),
+            Event::RecordUnchanged { record, previous } => {
+                let line = summary_line(record, Outcome::Unchanged, previous, Duration::ZERO);
+                println!("{}", line)
+            }
+            Event::RecordSkipped { record, reason } => {
+                println!("{}", messages::skipped(&record.info.name(), reason))
+            }
+            _ => {}
+        }
+    }
+}
+
+struct DetectErrorSink;
+
+impl EventSink for DetectErrorSink {
+    fn handle(&mut self, event: &Event) {
+        if let Event::DetectionResult {
+            record,
+            result: Err(message),
+        } = *event
+        {
+            let name = record.info.name();
+            let message = messages::record_detect_failed(&name, &record.ip.value, message);
+            eprintln!("{}", messages::error(message));
+        }
+    }
+}
+
+#[derive(Default)]
+struct JsonSink {
+    entries: Vec<serde_json::Value>,
+}
+
+impl EventSink for JsonSink {
+    fn handle(&mut self, event: &Event) {
+        let entry = match *event {
+            Event::RecordUpdated {
+                record,
+                outcome,
+                previous,
+                update,
+            } => serde_json::json!({
+                "name": record.info.name(),
+                "ip": record.info.ipaddr.to_string(),
+                "previous_ip": previous.map(|ip| ip.to_string()),
+                "ip_source": record.ip.value.to_string(),
+                "outcome": outcome.as_str(),
+                "changed": update.changed,
+                "response": update.response.message,
+                "elapsed": update.duration.as_secs_f64(),
+            }),
+            Event::RecordUnchanged { record, previous } => serde_json::json!({
+                "name": record.info.name(),
+                "ip": record.info.ipaddr.to_string(),
+                "previous_ip": previous.map(|ip| ip.to_string()),
+                "ip_source": record.ip.value.to_string(),
+                "outcome": Outcome::Unchanged.as_str(),
+                "elapsed": null,
+            }),
+            Event::RecordFailed { record, error } => serde_json::json!({
+                "name": record.info.name(),
+                "ip_source": record.ip.value.to_string(),
+                "outcome": "failed",
+                "error": error,
+            }),
+            Event::RecordSkipped { record, reason } => serde_json::json!({
+                "name": record.info.name(),
+                "ip_source": record.ip.value.to_string(),
+                "outcome": "skipped",
+                "reason": reason.as_str(),
+            }),
+            _ => return,
+        };
+        self.entries.push(entry);
+    }
+}
+
+type Connection = dice::Client<Pooled<tls::Stream>>;
+
+struct Trust {
+    connector: tls::Connector,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+struct Endpoint<'a> {
+    trust: RefCell<Trust>,
+    tls: tls::TlsOptions,
+    pool: Arc<Pool<tls::Stream>>,
+    key: PoolKey,
+    bind: socket::LocalBind,
+    resolver: &'a dyn dice::Resolve,
+    resolved: RefCell<ResolveCache>,
+    greeting: GreetingPolicy,
+    pacing: Pacing,
+    max_line: usize,
+}
+
+impl<'a> Endpoint<'a> {
+    fn new(config: &Config, resolver: &'a dyn dice::Resolve) -> Endpoint<'a> {
+        let connector = config.tls_connector().unwrap_or_else(|e| fail(e));
+        let tls = config.tls_options();
+        let trust = Trust {
+            connector,
+            modified: tls.ca_file.as_deref().and_then(modified),
+        };
+        let key = PoolKey::new(
+            dice::HOST,
+            dice::PORT,
+            &(config.tls_options(), config.local_bind()),
+        );
+        Endpoint {
+            trust: RefCell::new(trust),
+            tls,
+            pool: Arc::new(Pool::new(config.pool_size(), config.pool_idle())),
+            key,
+            bind: config.local_bind(),
+            resolver,
+            resolved: RefCell::new(ResolveCache::new()),
+            greeting: config.greeting(),
+            pacing: config.pacing(),
+            max_line: config.max_line_length(),
+        }
+    }
+
+    fn connector(&self, stats: &mut ClientStats) -> tls::Connector {
+        let mut trust = self.trust.borrow_mut();
+        if let Some(path) = &self.tls.ca_file {
+            let now = modified(path);
+            if now != trust.modified {
+                trust.modified = now;
+                match self.tls.load() {
+                    Ok((connector, count)) => {
+                        trust.connector = connector;
+                        stats.trust_reloads += 1;
+                        eprintln!("{}", messages::ca_reloaded(path, count));
+                    }
+                    Err(e) => eprintln!("{}", messages::error(messages::ca_reload_failed(e))),
+                }
+            }
+        }
+        trust.connector.clone()
+    }
+}
+
+fn session<F>(
+    matches: &getopts::Matches,
+    endpoint: &Endpoint<'_>,
+    transport: &Transport,
+    clock: &dyn Clock,
+    timings: &mut Timings,
+    stats: &mut ClientStats,
+    mut op: F,
+) -> Result<(), ClientError>
+where
+    F: FnMut(&mut Connection) -> Result<(), ClientError>,
+{
+    let trace = match matches.opt_str("trace-file") {
+        Some(path) => match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => Some(file),
+            Err(e) => fail(messages::file_error(&path, e)),
+        },
+        None => None,
+    };
+    let cancel = signal::install();
+    let greeting = endpoint.greeting;
+    let policy = transport.retry;
+    let certificates =
+        matches.opt_present("v") || Some("check") == matches.free.first().map(String::as_str);
+    let timings = RefCell::new(timings);
+    let stats = RefCell::new(stats);
+    let mut connect = || {
+        let opened = clock.instant();
+        let pooled = endpoint
+            .pool
+            .take(&endpoint.key, |stream| pool::alive(tls::tcp(stream)));
+        if endpoint.pool.is_enabled() {
+            let mut stats = stats.borrow_mut();
+            match pooled {
+                Some(_) => stats.pool_hits += 1,
+                None => stats.pool_misses += 1,
+            }
+        }
+        let reused = pooled.is_some();
+        let stream = match pooled {
+            Some(stream) => stream,
+            None => {
+                let connector = endpoint.connector(&mut stats.borrow_mut());
+                let timings = &mut timings.borrow_mut();
+                let stream = dice::open_with_connector(
+                    &connector,
+                    &endpoint.bind,
+                    endpoint.resolver,
+                    &mut endpoint.resolved.borrow_mut(),
+                    transport.timeouts,
+                    timings,
+                )?;
+                if certificates {
+                    for (depth, cert) in tls::peer_certificates(&stream).iter().enumerate() {
+                        eprintln!("{}", messages::peer_certificate(depth, cert));
+                    }
+                }
+                stream
+            }
+        };
+        let socket = tls::tcp(&stream).try_clone().map_err(OpenError::Connect)?;
+        transport
+            .timeouts
+            .apply(&socket)
+            .map_err(OpenError::Connect)?;
+        let stream = Pooled::new(stream, endpoint.pool.clone(), endpoint.key.clone(), reused);
+        let mut client = dice::Client::new(stream);
+        client.set_connect_time(opened.elapsed());
+        client.set_socket(socket);
+        client.set_cancel_token(cancel.clone());
+        let compat = matches.opt_present("compat-output");
+        client.set_redact(!compat);
+        client.set_pacing(endpoint.pacing);
+        client.set_max_line_len(endpoint.max_line);
+        let mut transcript = if compat {
+            Some(dice::stdout_observer())
+        } else if matches.opt_present("v") {
+            if color::should_color(matches.opt_present("no-color")) {
+                Some(color::color_observer())
+            } else {
+                Some(dice::stderr_observer())
+            }
+        } else {
+            None
+        };
+        match trace.as_ref().and_then(|file| file.try_clone().ok()) {
+            Some(mut file) => client.set_observer(Box::new(move |dir, line| {
+                if let Some(observer) = transcript.as_mut() {
+                    observer(dir, line);
+                }
+                let record = serde_json::json!({
+                    "time": SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs_f64())
+                        .unwrap_or_default(),
+                    "direction": dir.to_string(),
+                    "line": ddns_client::redact(line),
+                });
+                let _ = writeln!(file, "{}", record);
+            })),
+            None => {
+                if let Some(observer) = transcript {
+                    client.set_observer(observer);
+                }
+            }
+        }
+        Ok(client)
+    };
+    let stale = Cell::new(false);
+    let mut attempt = |client: &mut Connection| {
+        let started = Instant::now();
+        let reused = client.get_ref().reused();
+        let greeted = if reused {
+            Ok(())
+        } else {
+            client
+                .read_greeting(greeting)
+                .map(|()| timings.borrow_mut().record("greeting", started.elapsed()))
+        };
+        let result = greeted.and_then(|()| op(client));
+        client.get_mut().set_reusable(result.is_ok());
+        stale.set(
+            reused
+                && 0 == client.stats().bytes_received
+                && matches!(result, Err(ClientError::Io(_)) | Err(ClientError::Parse(_))),
+        );
+        timings.borrow_mut().extend(client.timings());
+        stats.borrow_mut().add(&client.stats());
+        result
+    };
+    loop {
+        let result = retry::with_retries_notify(
+            &policy,
+            clock,
+            &cancel,
+            &mut connect,
+            &mut attempt,
+            |retry, e, delay| {
+                let message = messages::retrying(e, delay, retry, policy.retries);
+                eprintln!("{}", messages::warning(message));
+            },
+        );
+        if !stale.replace(false) {
+            return result;
+        }
+    }
+}
+
+fn check_credentials(
+    matches: &getopts::Matches,
+    config: &Config,
+    resolver: &Resolver,
+    clock: &dyn Clock,
+) -> Result<(), ClientError> {
+    let endpoint = Endpoint::new(config, host_resolver(config, resolver));
+    let user = config.user.as_ref().map_or("", |setting| &setting.value);
+    let pass = config.pass.as_ref().map_or("", |setting| &setting.value);
+    session(
+        matches,
+        &endpoint,
+        &config.transport(None),
+        clock,
+        &mut Timings::default(),
+        &mut ClientStats::default(),
+        |client| client.run_auth_check(user, pass),
+    )
+}
+
+fn diag_command(
+    matches: &getopts::Matches,
+    mut config: Config,
+    files: &[PathBuf],
+    resolver: &Resolver,
+    clock: &dyn Clock,
+) -> i32 {
+    let output = PathBuf::from(
+        matches
+            .opt_str("output")
+            .unwrap_or_else(|| diag::DEFAULT_OUTPUT.to_owned()),
+    );
+    let rendered = config.render_toml();
+    let resolved = config.resolve_secrets();
+    let secrets = config
+        .user
+        .iter()
+        .chain(config.pass.iter())
+        .map(|setting| setting.value.clone());
+    let mut bundle = Bundle::new(secrets);
+    let unavailable = |e: io::Error| messages::diag_unavailable(e);
+    bundle.add("version.txt", &messages::version(tls::BACKEND, &features()));
+    let (state_path, status_path) = state_paths(&config);
+    let mut paths = match paths::default_paths() {
+        Some(paths) => format!("{}\n", paths),
+        None => format!("{}\n", messages::no_home()),
+    };
+    for path in files {
+        paths.push_str(&format!("config_file: {}\n", path.display()));
+    }
+    paths.push_str(&format!("state_file: {}\n", state_path.display()));
+    paths.push_str(&format!("status_file: {}\n", status_path.display()));
+    bundle.add("paths.txt", &paths);
+    bundle.add("config.toml", &rendered);
+    let state = fs::read_to_string(&state_path).unwrap_or_else(unavailable);
+    bundle.add("state.json", &state);
+    if let Some(paths) = paths::default_paths() {
+        let history = diag::tail(&paths.history, diag::HISTORY_ENTRIES);
+        bundle.add("history.jsonl", &history.unwrap_or_else(unavailable));
+    }
+    let check = match (resolved, config.tls_connector()) {
+        (Err(e), _) => e.to_string(),
+        (_, Err(e)) => e.to_string(),
+        _ if config.user.is_none() || config.pass.is_none() => messages::diag_check_skipped(),
+        _ => match check_credentials(matches, &config, resolver, clock) {
+            Ok(()) => messages::credentials_ok(),
+            Err(e) => e.to_string(),
+        },
+    };
+    bundle.add("check.txt", &format!("{}\n", check));
+    if let Some(path) = matches.opt_str("trace-file") {
+        let trace = diag::tail(Path::new(&path), diag::TRACE_LINES);
+        bundle.add("trace.jsonl", &trace.unwrap_or_else(unavailable));
+    }
+    if let Err(e) = bundle.write(&output, clock.unix_now()) {
+        fail(messages::file_error(output.display(), e));
+    }
+    println!("{}", messages::diag_written(&output));
+    for name in bundle.names() {
+        println!("  {}", name);
+    }
+    0
+}
+
+struct RunOptions {
+    interval: Option<u64>,
+    park: bool,
+    exit: ExitOptions,
+}
+
+fn checked_records(matches: &getopts::Matches, config: &Config) -> Vec<Record> {
+    let problems = config.validate();
+    if !problems.is_empty() {
+        fail(ConfigError::Invalid(problems));
+    }
+    let (records, warnings) = config.to_records().unwrap_or_else(|e| fail(e));
+    for warning in &warnings {
+        eprintln!("{}", messages::warning(warning));
+    }
+    for pattern in &matches.opt_strs("only") {
+        if !records.iter().any(|record| record.matches(pattern)) {
+            fail(messages::invalid_option(
+                "only",
+                messages::only_unmatched(),
+                pattern,
+            ));
+        }
+    }
+    records
+}
+
+fn run_lock(
+    matches: &getopts::Matches,
+    persistence: &Persistence,
+    config_hash: &str,
+    now: u64,
+) -> Option<state::RunLock> {
+    let daemon = persistence
+        .status_path()
+        .filter(|_| !matches.opt_present("force"))
+        .and_then(|path| Status::load(path).ok().flatten())
+        .filter(|status| status.is_daemon(now));
+    let waiting = || match daemon {
+        Some(status) if status.manages(config_hash) => {
+            capture::release();
+            eprintln!("{}", messages::daemon_managing());
+            process::exit(0)
+        }
+        Some(_) => fail(messages::daemon_conflict()),
+        None => eprintln!("{}", messages::lock_waiting(state::LOCK_TIMEOUT)),
+    };
+    match persistence.run_lock(state::LOCK_TIMEOUT, waiting) {
+        Ok(None) => {
+            eprintln!("{}", messages::warning(messages::socket_lock_unsupported()));
+            None
+        }
+        Ok(lock) => lock,
+        Err(e) => fail(messages::lock_failed(e)),
+    }
+}
+
+fn check_clock(config: &Config, state: &mut State, now: u64) -> Option<state::ClockSkew> {
+    let floor = state::time_floor();
+    let skew = state.check_clock(now, floor);
+    match skew {
+        Some(skew) => {
+            eprintln!("{}", messages::warning(messages::clock_skew(now, skew)));
+            state.mark_skew(floor);
+        }
+        None if state.repair_clock(now, config.auth_cooldown()) => {
+            eprintln!("{}", messages::warning(messages::clock_repaired()))
+        }
+        None => {}
+    }
+    skew
+}
+
+fn breaker_gate(state: &State, skew: bool, now: u64) -> Result<(), Fatal> {
+    let remaining = match state.auth.check(now) {
+        _ if skew && state.auth.open_until.is_some() => None,
+        Ok(()) => return Ok(()),
+        Err(remaining) => Some(remaining),
+    };
+    let message = match remaining {
+        Some(remaining) => messages::auth_breaker_open(
+            state.auth.failures,
+            remaining,
+            &schedule::local_timestamp(now + remaining),
+        ),
+        None => messages::auth_breaker_skewed(state.auth.failures),
+    };
+    Err(Fatal {
+        kind: "auth_breaker_open",
+        message,
+        detail: serde_json::json!({
+            "failures": state.auth.failures,
+            "retry_in": remaining,
+            "retry_after_seconds": remaining,
+            "retry_at": remaining.map(|remaining| schedule::local_timestamp(now + remaining)),
+            "clock_skew": skew,
+        }),
+    })
+}
+
+fn byte_budget(matches: &getopts::Matches, config: &Config, state: &mut State, now: u64) -> bool {
+    let budget = config.max_bytes_per_day();
+    let suspended = !matches.opt_present("force") && state.traffic.exceeded(now, budget);
+    if suspended && state.traffic.mark_suspended(now) {
+        let resume_at = schedule::local_timestamp((now / state::DAY + 1) * state::DAY);
+        let used = state.traffic.used_today(now);
+        let message = messages::byte_budget_spent(used, budget.unwrap_or(0), &resume_at);
+        eprintln!("{}", messages::warning(message));
+    }
+    suspended
+}
+
+struct Planner<'a> {
+    resolver: &'a Resolver,
+    detector: Detector<'a>,
+    now: u64,
+    park: bool,
+    offline: bool,
+    strict_ip: bool,
+    check_dns: bool,
+    max_age: Option<u64>,
+    nameserver: Option<IpAddr>,
+    zones: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Planner<'_> {
+    fn plan(
+        &mut self,
+        mut record: Record,
+        state: &mut State,
+        bus: &mut EventBus<'_>,
+        cycle: &mut Cycle,
+    ) {
+        let (now, park) = (self.now, self.park);
+        let detected = if park {
+            Ok(record.offline_ip)
+        } else {
+            self.detector.detect(&record.ip.value)
+        };
+        let addr = match detected {
+            Ok(addr) => addr,
+            Err(e @ DetectError::Cancelled) => exit_with(e, signal::EXIT_INTERRUPTED),
+            Err(e) => {
+                let message = e.to_string();
+                bus.emit(Event::DetectionResult {
+                    record: &record,
+                    result: Err(&message),
+                });
+                cycle.failed.push((record, message));
+                return;
+            }
+        };
+        let special = match (park, &record.ip.value) {
+            (false, IpSource::Static(_)) => detect::special_range(addr),
+            _ => None,
+        };
+        if let Some(range) = special {
+            if self.strict_ip {
+                let message = messages::special_address(addr, range);
+                bus.emit(Event::DetectionResult {
+                    record: &record,
+                    result: Err(&message),
+                });
+                cycle.failed.push((record, message));
+                return;
+            }
+            let message = messages::special_target(&record.info.name(), addr, range);
+            eprintln!("{}", messages::warning(message));
+        }
+        record.info.ipaddr = addr;
+        bus.emit(Event::DetectionResult {
+            record: &record,
+            result: Ok(addr),
+        });
+        let name = record.info.name();
+        let mut outcome = if park {
+            state.plan_offline(&name, addr)
+        } else {
+            state.plan(&name, addr, now, self.max_age)
+        };
+        if self.check_dns && Outcome::Updated == outcome {
+            match dns_has(
+                self.resolver,
+                &record.info,
+                self.nameserver,
+                &mut self.zones,
+            ) {
+                Ok(true) => {
+                    state.record_success(&name, addr, now);
+                    outcome = Outcome::Unchanged;
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!(
+                    "{}",
+                    messages::warning(messages::dns_precheck_failed(&name, e))
+                ),
+            }
+        }
+        let previous = state
+            .record(&name)
+            .and_then(|known| known.ip)
+            .or_else(|| match outcome {
+                Outcome::Updated if !self.offline => lookup_ipv4(self.resolver, &name),
+                _ => None,
+            });
+        cycle.plan.push(Planned {
+            record,
+            outcome,
+            previous,
+        });
+    }
+}
+
+#[derive(Default)]
+struct Cycle {
+    plan: Vec<Planned>,
+    failed: Vec<(Record, String)>,
+    skipped: Vec<(Record, Skip)>,
+}
+
+fn plan_records(
+    records: Vec<Record>,
+    only: &[String],
+    suspended: bool,
+    planner: &mut Planner<'_>,
+    state: &mut State,
+    bus: &mut EventBus<'_>,
+) -> Cycle {
+    let mut cycle = Cycle::default();
+    for record in records {
+        if !only.is_empty() && !only.iter().any(|pattern| record.matches(pattern)) {
+            cycle.skipped.push((record, Skip::FilteredOut));
+            continue;
+        }
+        if let Some(skip) = record.skip(planner.now) {
+            cycle.skipped.push((record, skip));
+            continue;
+        }
+        if suspended {
+            cycle.skipped.push((record, Skip::ByteBudget));
+            continue;
+        }
+        let node = record.node();
+        let broken = cycle.failed.iter().map(|(failed, _)| failed).chain(
+            cycle
+                .skipped
+                .iter()
+                .filter(|(_, skip)| Skip::DependencyFailed == *skip)
+                .map(|(skipped, _)| skipped),
+        );
+        if broken
+            .map(|dep| dep.info.name())
+            .any(|name| node.depends(&name))
+        {
+            cycle.skipped.push((record, Skip::DependencyFailed));
+            continue;
+        }
+        planner.plan(record, state, bus, &mut cycle);
+    }
+    cycle
+}
+
+fn pending_of(plan: &[Planned]) -> Vec<Information> {
+    plan.iter()
+        .filter(|planned| Outcome::Unchanged != planned.outcome)
+        .map(|planned| planned.record.info.clone())
+        .collect()
+}
+
+fn enabled(setting: &Option<Setting<bool>>) -> bool {
+    setting.as_ref().is_some_and(|setting| setting.value)
+}
+
+fn verify_ownership<'a, I>(
+    resolver: &Resolver,
+    config: &Config,
+    state: &mut State,
+    pending: I,
+    now: u64,
+) where
+    I: IntoIterator<Item = &'a Information>,
+{
+    let strict = enabled(&config.strict_ownership);
+    if !strict && !enabled(&config.check_ownership) {
+        return;
+    }
+    let suffixes = config.ownership_suffixes();
+    let mut checked = Vec::new();
+    for info in pending {
+        if state.verified_domains.contains_key(&info.dom) || checked.contains(&info.dom) {
+            continue;
+        }
+        checked.push(info.dom.clone());
+        match check_ownership(resolver, &info.dom, &suffixes) {
+            Ok(()) => {
+                state.verified_domains.insert(info.dom.clone(), now);
+            }
+            Err(message) if strict => fail(message),
+            Err(message) => eprintln!("{}", messages::warning(message)),
+        }
+    }
+}
+
+type Updated = Vec<(Information, UpdateOutcome)>;
+
+fn run_updates<F>(
+    pending: &[Information],
+    plan: &[Planned],
+    strategy: &mut SessionStrategy,
+    mut run: F,
+) -> (Updated, Result<(), ClientError>)
+where
+    F: FnMut(&Transport, &[Information], &mut Vec<UpdateOutcome>) -> Result<(), ClientError>,
+{
+    let mut updated = Vec::new();
+    let key = |info: &Information| {
+        let transport = transport_of(plan, info);
+        (info.user.clone(), info.pass.clone(), transport)
+    };
+    for group in group_by(pending, plan, key) {
+        let transport = transport_of(plan, &group[0]);
+        let batches = match strategy {
+            SessionStrategy::Shared => vec![group],
+            SessionStrategy::PerDomain => group_by(&group, plan, |info| info.dom.clone()),
+        };
+        for batch in batches {
+            let mut run = |batch: &[Information], outcomes: &mut Vec<UpdateOutcome>| {
+                run(&transport, batch, outcomes)
+            };
+            let mut outcomes = Vec::new();
+            let mut outcome = run(&batch, &mut outcomes);
+            let done = outcomes.len();
+            updated.extend(batch.iter().cloned().zip(outcomes));
+            let rejected = match &outcome {
+                Err(ClientError::Response(ResponseError::NotFound, _))
+                | Err(ClientError::Response(ResponseError::DbError, _)) => batch.get(done),
+                _ => None,
+            };
+            if let Some(rejected) = rejected.filter(|info| info.dom != batch[0].dom) {
+                let kept: Vec<String> = batch[..done].iter().map(Information::name).collect();
+                let message = messages::cross_domain_retry(&rejected.name(), &batch[0].dom, &kept);
+                eprintln!("{}", messages::warning(message));
+                outcome = Ok(());
+                for rest in group_by(&batch[done..], plan, |info| info.dom.clone()) {
+                    let mut rest_outcomes = Vec::new();
+                    let rest_outcome = run(&rest, &mut rest_outcomes);
+                    updated.extend(rest.iter().cloned().zip(rest_outcomes));
+                    if rest_outcome.is_err() {
+                        outcome = rest_outcome;
+                        break;
+                    }
+                }
+                if outcome.is_ok() {
+                    *strategy = SessionStrategy::PerDomain;
+                }
+            }
+            if outcome.is_err() {
+                return (updated, outcome);
+            }
+        }
+    }
+    (updated, Ok(()))
+}
+
+fn check_acks(
+    config: &Config,
+    plan: &[Planned],
+    updated: &mut Updated,
+    result: &mut Result<(), ClientError>,
+) {
+    for (info, update) in updated.iter_mut() {
+        if let Some(planned) = plan.iter().find(|planned| planned.record.info == *info) {
+            update.changed = Outcome::Refreshed != planned.outcome
+                && planned
+                    .previous
+                    .is_none_or(|previous| previous != info.ipaddr);
+        }
+    }
+    let tolerate_mismatch = enabled(&config.tolerate_ack_mismatch);
+    let mut mismatched = Vec::new();
+    for (info, update) in updated.iter() {
+        match update.mismatch(info) {
+            Some(e) if tolerate_mismatch => eprintln!("{}", messages::warning(e)),
+            Some(e) => {
+                mismatched.push(info.clone());
+                if result.is_ok() {
+                    *result = Err(e);
+                }
+            }
+            None => {}
+        }
+    }
+    updated.retain(|(info, _)| !mismatched.contains(info));
+}
+
+fn emit_records(bus: &mut EventBus<'_>, cycle: &Cycle, updated: &Updated, error: Option<&str>) {
+    for planned in &cycle.plan {
+        let record = &planned.record;
+        let previous = planned.previous;
+        let update = updated
+            .iter()
+            .find(|(info, _)| *info == record.info)
+            .map(|(_, update)| update);
+        bus.emit(match (planned.outcome, update, error) {
+            (Outcome::Unchanged, _, _) | (_, None, None) => {
+                Event::RecordUnchanged { record, previous }
+            }
+            (outcome, Some(update), _) => Event::RecordUpdated {
+                record,
+                outcome,
+                previous,
+                update,
+            },
+            (_, None, Some(error)) => Event::RecordFailed { record, error },
+        });
+    }
+    for (record, error) in &cycle.failed {
+        bus.emit(Event::RecordFailed { record, error });
+    }
+    for (record, reason) in &cycle.skipped {
+        let reason = *reason;
+        bus.emit(Event::RecordSkipped { record, reason });
+    }
+}
+
+struct Traffic {
+    sent: u64,
+    received: u64,
+}
+
+fn save_status(path: &Path, config: &Config, timings: &Timings, mut status: Status, ok: bool) {
+    let previous = Status::load(path).ok().flatten();
+    if let Some(previous) = &previous {
+        status.latency = previous.latency.clone();
+    }
+    if status.interval.is_some() && !timings.stages.is_empty() {
+        let latency = &mut status.latency;
+        latency.set_capacity(config.latency_window());
+        latency.push(
+            timings
+                .stages
+                .iter()
+                .filter(|(stage, _)| "total" != stage)
+                .cloned()
+                .collect(),
+        );
+        if let Some(threshold) = config.latency_warn() {
+            for (stage, summary) in latency.summary() {
+                if threshold < summary.p95 {
+                    let message = messages::latency_high(&stage, summary.p95, threshold);
+                    eprintln!("{}", messages::warning(message));
+                }
+            }
+        }
+    }
+    if !ok {
+        status.failures = previous.map_or(0, |status| status.failures) + 1;
+    }
+    if let Err(e) = status.save(path) {
+        eprintln!("{}", messages::warning(e));
+    }
+}
+
+fn report_breaker(event: Option<BreakerEvent>, config: &Config, state: &State, now: u64) {
+    match event {
+        Some(BreakerEvent::Opened) => {
+            let message = messages::auth_breaker_opened(
+                state.auth.failures,
+                config.auth_cooldown(),
+                &schedule::local_timestamp(state.auth.open_until.unwrap_or(now)),
+            );
+            eprintln!("{}", messages::error(message))
+        }
+        Some(BreakerEvent::Closed) => eprintln!("{}", messages::auth_breaker_closed()),
+        None => {}
+    }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Hash)]
-pub enum KEY {
-    USER,
-    PASS,
-    HOST,
-    DOM,
+fn detect_failed(failed: &[(Record, String)]) -> Fatal {
+    let names: Vec<String> = failed
+        .iter()
+        .map(|(record, _)| record.info.name())
+        .collect();
+    Fatal {
+        kind: "detect_failed",
+        message: messages::no_address_detected(&names),
+        detail: serde_json::json!({
+            "records": failed
+                .iter()
+                .map(|(record, message)| serde_json::json!({
+                    "name": record.info.name(),
+                    "ip_source": record.ip.value.to_string(),
+                    "error": message,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+    }
 }
 
-impl fmt::Display for KEY {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let hint = match self {
-            KEY::USER => "USERID",
-            KEY::PASS => "PASSWORD",
-            KEY::HOST => "HOSTNAME",
-            KEY::DOM => "DOMNAME",
+fn update_command(
+    matches: &getopts::Matches,
+    config: &Config,
+    resolver: &Resolver,
+    clock: &dyn Clock,
+    json: bool,
+    options: &RunOptions,
+) -> i32 {
+    if matches.opt_present("quiet-unchanged") {
+        if let Err(e) = capture::begin() {
+            eprintln!("{}", messages::warning(messages::capture_failed(e)));
+        }
+    }
+    let records = checked_records(matches, config);
+    let endpoint = Endpoint::new(config, host_resolver(config, resolver));
+    let persistence = persistence(config);
+    if let Some(dir) = persistence.state_dir() {
+        if let Err(e) = paths::check_writable(dir) {
+            fail(messages::state_dir_unwritable(dir.display(), e));
+        }
+    }
+    let identities: Vec<String> = records
+        .iter()
+        .map(|record| format!("{}@{}", record.info.user, record.info.name()))
+        .collect();
+    let config_hash = status::config_hash(identities.iter().map(String::as_str));
+    let _lock = run_lock(matches, &persistence, &config_hash, clock.unix_now());
+    let store = persistence.store();
+    let before = match &store {
+        Some(store) => store.load().unwrap_or_else(|e| fail(e)),
+        None => State::default(),
+    };
+    let mut state = before.clone();
+    if matches.opt_present("reset-auth-backoff") {
+        state.auth.reset();
+    }
+    let now = clock.unix_now();
+    let skew = check_clock(config, &mut state, now);
+    if let Err(fatal) = breaker_gate(&state, skew.is_some(), now) {
+        if let Some(store) = store.as_ref().filter(|_| state != before) {
+            store
+                .update(|current| current.apply(&before, &state))
+                .unwrap_or_else(|e| fail(e));
+        }
+        exit_with(&fatal, EXIT_AUTH_BREAKER);
+    }
+
+    let offline = matches.opt_present("dry-run-offline");
+    let dry_run = offline || matches.opt_present("dry-run");
+    let check_dns = !offline && enabled(&config.check_dns_first);
+    if !persistence.is_enabled() && !check_dns && !dry_run {
+        eprintln!(
+            "{}",
+            messages::warning(messages::state_disabled_unchanged())
+        );
+    }
+    let cancel = signal::install();
+    let mut summary = SummarySink;
+    let mut detect_errors = DetectErrorSink;
+    let mut json_records = JsonSink::default();
+    let mut bus = EventBus::new();
+    if !JSON_ERRORS.load(Ordering::Relaxed) {
+        bus.subscribe(&mut detect_errors);
+    }
+    if json {
+        bus.subscribe(&mut json_records);
+    } else if !matches.opt_present("quiet") && !matches.opt_present("compat-output") {
+        bus.subscribe(&mut summary);
+    }
+    bus.emit(Event::CycleStarted {
+        now,
+        records: records.len(),
+    });
+    let suspended = byte_budget(matches, config, &mut state, now);
+    let mut detector = Detector::new(host_resolver(config, resolver), cancel);
+    if options.interval.is_some() {
+        let failed = persistence
+            .status_path()
+            .and_then(|path| Status::load(path).ok().flatten())
+            .map(|status| status.detection)
+            .unwrap_or_default();
+        let mut penalties = Penalties::new(config.detect_penalty(), failed);
+        penalties.expire(now);
+        detector = detector.with_penalties(penalties, clock);
+    }
+    let mut planner = Planner {
+        resolver,
+        detector,
+        now,
+        park: options.park,
+        offline,
+        strict_ip: enabled(&config.strict_ip_validation),
+        check_dns,
+        max_age: match (skew, &config.max_age) {
+            (Some(_), Some(_)) => Some(0),
+            (_, max_age) => max_age.as_ref().map(|setting| setting.value),
+        },
+        nameserver: config.nameserver.as_ref().map(|setting| setting.value),
+        zones: HashMap::new(),
+    };
+    let only = matches.opt_strs("only");
+    let mut cycle = plan_records(
+        records,
+        &only,
+        suspended,
+        &mut planner,
+        &mut state,
+        &mut bus,
+    );
+    let rehearse = enabled(&config.dry_run);
+    let (rehearsed, plan): (Vec<Planned>, Vec<Planned>) = std::mem::take(&mut cycle.plan)
+        .into_iter()
+        .partition(|planned| planned.record.rehearsed(dry_run, rehearse));
+    cycle.plan = plan;
+    let pending = pending_of(&cycle.plan);
+    let rehearsed_pending = pending_of(&rehearsed);
+    if !offline {
+        let checked = pending.iter().chain(&rehearsed_pending);
+        verify_ownership(resolver, config, &mut state, checked, now);
+    }
+    if cycle.plan.is_empty() && (dry_run || !rehearsed.is_empty()) {
+        let outcomes: Vec<Outcome> = rehearsed.iter().map(|planned| planned.outcome).collect();
+        capture::release();
+        let preview = dry_run_command(
+            resolver,
+            &rehearsed,
+            &rehearsed_pending,
+            &cycle.skipped,
+            &state,
+            offline,
+            json,
+        );
+        if let Some(report) = preview {
+            println!("{}", report);
+        }
+        return report::compute_exit_code(&outcomes, cycle.failed.len(), &options.exit);
+    }
+    let outcomes: Vec<Outcome> = cycle.plan.iter().map(|planned| planned.outcome).collect();
+    let preview = if rehearsed.is_empty() {
+        None
+    } else {
+        dry_run_command(
+            resolver,
+            &rehearsed,
+            &rehearsed_pending,
+            &[],
+            &state,
+            offline,
+            json,
+        )
+    };
+    let started = clock.instant();
+    let mut timings = Timings::default();
+    let mut stats = ClientStats::default();
+    let (mut updated, mut result) = run_updates(
+        &pending,
+        &cycle.plan,
+        &mut state.session_strategy,
+        |transport, batch, outcomes| {
+            session(
+                matches,
+                &endpoint,
+                transport,
+                clock,
+                &mut timings,
+                &mut stats,
+                |client| client.run_modip_each(batch, outcomes),
+            )
+        },
+    );
+    let elapsed = started.elapsed();
+    let (detect_sent, detect_received) = detect::bytes_transferred();
+    let traffic = Traffic {
+        sent: stats.bytes_sent + detect_sent,
+        received: stats.bytes_received + detect_received,
+    };
+    if 0 < traffic.sent + traffic.received {
+        state.traffic.add(now, traffic.sent, traffic.received);
+    }
+    let mut report = serde_json::Map::new();
+    if matches.opt_present("timings") {
+        timings.record("total", started.elapsed());
+        let penalties = planner.detector.penalties();
+        if json {
+            report.insert("timings".to_owned(), timings.to_json());
+            report.insert("stats".to_owned(), stats.to_json());
+            if let Some(penalties) = penalties {
+                report.insert("detection".to_owned(), penalties.to_json(now));
+            }
+        } else {
+            eprintln!("{}", timings);
+            eprintln!("{}", stats);
+            for (source, remaining) in penalties.iter().flat_map(|p| p.entries(now)) {
+                eprintln!("{}", messages::source_penalty(source, remaining));
+            }
+        }
+    }
+    check_acks(config, &cycle.plan, &mut updated, &mut result);
+    for (info, _) in &updated {
+        if options.park {
+            state.record_offline(&info.name(), info.ipaddr, now);
+        } else {
+            state.record_success(&info.name(), info.ipaddr, now);
+        }
+    }
+    let error = result.as_ref().err().map(ToString::to_string);
+    emit_records(&mut bus, &cycle, &updated, error.as_deref());
+    let ok = result.is_ok() && cycle.failed.is_empty();
+    bus.emit(Event::CycleFinished { ok, elapsed });
+    drop(bus);
+    if json {
+        let entries = std::mem::take(&mut json_records.entries);
+        report.insert("records".to_owned(), serde_json::Value::Array(entries));
+        let mut summary = traffic_json(config, &state, now);
+        summary["cycle_sent"] = traffic.sent.into();
+        summary["cycle_received"] = traffic.received.into();
+        report.insert("traffic".to_owned(), summary);
+    }
+    if let Some(preview) = preview {
+        report.insert("rehearsal".to_owned(), preview);
+    }
+    if !report.is_empty() {
+        println!("{}", serde_json::Value::Object(report));
+    }
+    let event = match &result {
+        _ if pending.is_empty() => None,
+        Ok(()) => state.auth.record_success(),
+        Err(ClientError::Response(ResponseError::LoginError, _)) => {
+            state
+                .auth
+                .record_failure(now, config.auth_failure_limit(), config.auth_cooldown())
+        }
+        Err(_) => None,
+    };
+    if let Some(store) = store.as_ref().filter(|_| state != before) {
+        if let Err(e) = store.update(|current| current.apply(&before, &state)) {
+            eprintln!("{}", messages::warning(e));
+        }
+    }
+    if let Some(path) = persistence.status_path() {
+        let status = Status {
+            last_cycle: now,
+            interval: options.interval,
+            ip: cycle.plan.first().map(|planned| planned.record.info.ipaddr),
+            failures: 0,
+            records: state
+                .records
+                .iter()
+                .map(|(name, record)| (name.clone(), record.last_success))
+                .collect(),
+            latency: LatencyWindow::default(),
+            bytes_sent: traffic.sent,
+            bytes_received: traffic.received,
+            config_hash: Some(config_hash),
+            detection: planner
+                .detector
+                .penalties()
+                .map(|penalties| penalties.failed().clone())
+                .unwrap_or_default(),
         };
-        write!(f, "{}", hint)
+        save_status(path, config, &timings, status, ok);
     }
+    report_breaker(event, config, &state, now);
+    match result {
+        Ok(()) => {}
+        Err(e @ ClientError::Response(ResponseError::CommandError, _))
+            if pending.iter().any(|info| !info.extra.is_empty()) =>
+        {
+            if !JSON_ERRORS.load(Ordering::Relaxed) {
+                eprintln!("{}", messages::hint(messages::extra_fields_unsupported()));
+            }
+            fail(e)
+        }
+        Err(e) => fail_client(e),
+    }
+    if !cycle.failed.is_empty() {
+        let code = report::compute_exit_code(&outcomes, cycle.failed.len(), &options.exit);
+        exit_with(&detect_failed(&cycle.failed), code);
+    }
+    if outcomes
+        .iter()
+        .all(|outcome| Outcome::Unchanged == *outcome)
+    {
+        capture::discard();
+    } else {
+        capture::release();
+    }
+    report::compute_exit_code(&outcomes, 0, &options.exit)
+}
+
+fn load_config(files: &[PathBuf], ddclient: bool) -> Config {
+    let mut config = Config::default();
+    for path in files {
+        let loaded = if ddclient {
+            ddclient::from_file(path)
+        } else {
+            Config::from_file(path)
+        };
+        let (layer, warnings) = loaded.unwrap_or_else(|e| fail(e));
+        for warning in &warnings {
+            eprintln!("{}", messages::warning(warning));
+        }
+        config.stack(layer);
+    }
+    config.merge(Config::from_env().unwrap_or_else(|e| fail(e)));
+    config
 }
 
-impl AsRef<KEY> for KEY {
-    fn as_ref(&self) -> &KEY {
-        self
+fn read_input(matches: &getopts::Matches, config: &mut Config, source: &mut dyn InputSource) {
+    if matches.opt_present("stdin-json") {
+        let mut text = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut text) {
+            fail(messages::stdin_error(e));
+        }
+        config.merge(Config::from_json(&text).unwrap_or_else(|e| fail(e)));
+    } else {
+        for warning in config.read_input(source).unwrap_or_else(|e| fail(e)) {
+            eprintln!("{}", messages::warning(warning));
+        }
     }
 }
 
-impl<T> std::ops::Index<T> for dice::Information
-where
-    T: AsRef<KEY>,
-{
-    type Output = String;
+fn pass_fd(config: &Config, value: &str) -> Setting<String> {
+    let fd = match value.parse::<i32>() {
+        Ok(fd) if 0 <= fd => fd,
+        Ok(_) => fail(messages::invalid_option("pass-fd", "negative", value)),
+        Err(e) => fail(messages::invalid_option("pass-fd", e, value)),
+    };
+    if 0 == fd {
+        fail(messages::pass_fd_stdin());
+    }
+    let other = config
+        .pass
+        .as_ref()
+        .map(|setting| &setting.source)
+        .or(config
+            .pass_encrypted
+            .as_ref()
+            .map(|setting| &setting.source));
+    if let Some(source) = other {
+        fail(messages::pass_fd_conflict(source));
+    }
+    let pass = secret::read_fd(fd).unwrap_or_else(|e| fail(e));
+    Setting::new(pass, Source::Fd(fd))
+}
 
-    fn index(&self, key: T) -> &Self::Output {
-        match key.as_ref() {
-            KEY::USER => &self.user,
-            KEY::PASS => &self.pass,
-            KEY::HOST => &self.host,
-            KEY::DOM => &self.dom,
+fn detect_sources(matches: &getopts::Matches, config: &Config) -> Vec<IpSource> {
+    let mut sources = Vec::new();
+    for value in matches.opt_strs("ip-source") {
+        sources.push(value.parse::<IpSource>().unwrap_or_else(|e| fail(e)));
+    }
+    for name in matches.opt_strs("interface") {
+        sources.push(IpSource::Interface(name));
+    }
+    if sources.is_empty() {
+        let configured = config
+            .ip
+            .iter()
+            .chain(config.records.iter().filter_map(|r| r.ip.as_ref()));
+        for setting in configured {
+            if !sources.contains(&setting.value) {
+                sources.push(setting.value.clone());
+            }
         }
     }
+    sources
 }
 
-impl<T> std::ops::IndexMut<T> for dice::Information
-where
-    T: AsRef<KEY>,
-{
-    fn index_mut(&mut self, key: T) -> &mut Self::Output {
-        match key.as_ref() {
-            KEY::USER => &mut self.user,
-            KEY::PASS => &mut self.pass,
-            KEY::HOST => &mut self.host,
-            KEY::DOM => &mut self.dom,
-        }
-    }
-}
-
-fn parse_line(line: &str) -> Option<(KEY, String)> {
-    let index = line.find("=")?;
-    let key = if line.starts_with("user") {
-        KEY::USER
-    } else if line.starts_with("pass") {
-        KEY::PASS
-    } else if line.starts_with("host") {
-        KEY::HOST
-    } else if line.starts_with("dom") {
-        KEY::DOM
+fn print_config_command(config: &Config, json: bool) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&config.render_json()).unwrap()
+        );
+    } else {
+        print!("{}", config.render_toml());
+    }
+}
+
+struct Prompt<'a> {
+    files: &'a [PathBuf],
+    interactive: bool,
+    stdin_read: bool,
+    check: bool,
+}
+
+fn prompt_missing(
+    config: &mut Config,
+    source: &mut dyn InputSource,
+    prompt: &Prompt<'_>,
+) -> Vec<KEY> {
+    let mut missing = config.missing();
+    if prompt.check {
+        missing.retain(|key| KEY::USER == *key || KEY::PASS == *key);
+    }
+    let skipped = if !prompt.interactive {
+        Some("non-interactive mode")
+    } else if !source.can_prompt() {
+        Some("stdin is not a terminal")
     } else {
-        return None;
+        None
     };
-    Some((key, line[index + 1..line.len()].to_owned()))
+    if let (Some(reason), false) = (skipped, missing.is_empty()) {
+        let keys = missing
+            .iter()
+            .map(|key| {
+                let consulted = consulted(config, key, prompt.files, prompt.stdin_read, reason);
+                (key.clone(), consulted)
+            })
+            .collect();
+        exit_with(&ConfigError::Unprompted(keys), EXIT_CONFIG);
+    }
+    if source.can_prompt() {
+        config
+            .prompt_missing(source, &std::mem::take(&mut missing))
+            .unwrap_or_else(|e| fail(e));
+    }
+    missing
+}
+
+fn cli_config(matches: &getopts::Matches) -> Config {
+    let mut cli = match matches.opt_str("dsn") {
+        Some(text) => {
+            for name in ["user", "host", "dom", "record"].iter() {
+                if matches.opt_present(name) {
+                    fail(messages::dsn_conflict(&format!("--{}", name)));
+                }
+            }
+            let dsn: Dsn = text.parse().unwrap_or_else(|e| fail(e));
+            dsn.to_config(Source::Cli).unwrap_or_else(|e| fail(e))
+        }
+        None => Config::default(),
+    };
+    for (key, name) in [(KEY::USER, "user"), (KEY::HOST, "host"), (KEY::DOM, "dom")].iter() {
+        if let Some(value) = matches.opt_str(name) {
+            cli.set(key, value, Source::Cli);
+        }
+    }
+    if let Some(record) = matches.opt_str("record") {
+        if let Err(e) = cli.set_record(&record, Source::Cli) {
+            fail(e);
+        }
+    }
+    if let Some(value) = matches.opt_str("retries") {
+        match value.parse() {
+            Ok(n) => cli.retries = Some(Setting::new(n, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("retries", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("retry-delay") {
+        match config::parse_duration(&value) {
+            Ok(secs) => cli.retry_delay = Some(Setting::new(secs, Source::Cli)),
+            Err(e) => fail(e),
+        }
+    }
+    if let Some(value) = matches.opt_str("pool-size") {
+        match value.parse() {
+            Ok(n) => cli.pool_size = Some(Setting::new(n, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("pool-size", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("send-chunk-lines") {
+        match value.parse() {
+            Ok(n) => cli.send_chunk_lines = Some(Setting::new(n, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("send-chunk-lines", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("send-pacing-ms") {
+        match value.parse() {
+            Ok(ms) => cli.send_pacing_ms = Some(Setting::new(ms, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("send-pacing-ms", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("latency-window") {
+        match value.parse() {
+            Ok(0) => fail(messages::invalid_option(
+                "latency-window",
+                "must be at least 1",
+                &value,
+            )),
+            Ok(n) => cli.latency_window = Some(Setting::new(n, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("latency-window", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("detect-penalty") {
+        match config::parse_duration(&value) {
+            Ok(secs) => cli.detect_penalty = Some(Setting::new(secs, Source::Cli)),
+            Err(e) => fail(e),
+        }
+    }
+    if let Some(value) = matches.opt_str("latency-warn-ms") {
+        match value.parse() {
+            Ok(ms) => cli.latency_warn_ms = Some(Setting::new(ms, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("latency-warn-ms", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("max-bytes-per-day") {
+        match value.parse() {
+            Ok(n) => cli.max_bytes_per_day = Some(Setting::new(n, Source::Cli)),
+            Err(e) => fail(messages::invalid_option("max-bytes-per-day", e, &value)),
+        }
+    }
+    if let Some(value) = matches.opt_str("pool-idle") {
+        match config::parse_duration(&value) {
+            Ok(secs) => cli.pool_idle = Some(Setting::new(secs, Source::Cli)),
+            Err(e) => fail(e),
+        }
+    }
+    if let Some(value) = matches.opt_str("max-age") {
+        match config::parse_duration(&value) {
+            Ok(secs) => cli.max_age = Some(Setting::new(secs, Source::Cli)),
+            Err(e) => fail(e),
+        }
+    }
+    if let Some(value) = matches.opt_str("bind-address") {
+        match addr::parse_ip(&value) {
+            Ok(ip) => cli.bind_address = Some(Setting::new(ip, Source::Cli)),
+            Err(e) => fail(messages::option_error("bind-address", e)),
+        }
+    }
+    let extra = matches.opt_strs("extra");
+    if !extra.is_empty() {
+        for field in &extra {
+            if let Err(e) = dice::check_extra_field(field) {
+                fail(messages::option_error("extra", e));
+            }
+        }
+        cli.extra_fields = Some(Setting::new(extra, Source::Cli));
+    }
+    if matches.opt_present("no-greeting") {
+        cli.greeting = Some(Setting::new(GreetingPolicy::None, Source::Cli));
+    }
+    if matches.opt_present("check-dns-first") {
+        cli.check_dns_first = Some(Setting::new(true, Source::Cli));
+    }
+    if matches.opt_present("check-ownership") {
+        cli.check_ownership = Some(Setting::new(true, Source::Cli));
+    }
+    if matches.opt_present("strict-ownership") {
+        cli.strict_ownership = Some(Setting::new(true, Source::Cli));
+    }
+    if matches.opt_present("strict-ip-validation") {
+        cli.strict_ip_validation = Some(Setting::new(true, Source::Cli));
+    }
+    if matches.opt_present("tolerate-ack-mismatch") {
+        cli.tolerate_ack_mismatch = Some(Setting::new(true, Source::Cli));
+    }
+    let suffixes = matches.opt_strs("ownership-suffix");
+    if !suffixes.is_empty() {
+        cli.ownership_suffixes = Some(Setting::new(suffixes, Source::Cli));
+    }
+    if let Some(value) = matches.opt_str("nameserver") {
+        match addr::parse_ip(&value) {
+            Ok(ip) => cli.nameserver = Some(Setting::new(ip, Source::Cli)),
+            Err(e) => fail(messages::option_error("nameserver", e)),
+        }
+    }
+    if let Some(value) = matches.opt_str("resolver") {
+        match value.parse() {
+            Ok(spec) => cli.resolver = Some(Setting::new(spec, Source::Cli)),
+            Err(e) => fail(messages::option_error("resolver", e)),
+        }
+    }
+    if let Some(value) = matches.opt_str("tls-min-version") {
+        match value.parse() {
+            Ok(version) => cli.tls_min_version = Some(Setting::new(version, Source::Cli)),
+            Err(e) => fail(e),
+        }
+    }
+    if let Some(path) = matches.opt_str("ca-file") {
+        cli.ca_file = Some(Setting::new(PathBuf::from(path), Source::Cli));
+    }
+    if let Some(value) = matches.opt_str("local-port-range") {
+        match socket::parse_port_range(&value) {
+            Ok(range) => cli.local_port_range = Some(Setting::new(range, Source::Cli)),
+            Err(e) => fail(e),
+        }
+    }
+    if let Some(value) = matches.opt_str("connect-family") {
+        match value.parse() {
+            Ok(family) => cli.connect_family = Some(Setting::new(family, Source::Cli)),
+            Err(e) => fail(messages::option_error("connect-family", e)),
+        }
+    }
+    if matches.opt_present("no-state") {
+        cli.state = Some(Setting::new(StateMode::None, Source::Cli));
+    }
+    if let Some(path) = matches.opt_str("state-file") {
+        cli.state_file = Some(Setting::new(PathBuf::from(path), Source::Cli));
+    }
+    if let Some(path) = matches.opt_str("status-file") {
+        cli.status_file = Some(Setting::new(PathBuf::from(path), Source::Cli));
+    }
+    cli
+}
+
+fn positional_ip<'a>(
+    matches: &getopts::Matches,
+    program: &str,
+    ip: &'a str,
+    cli: &mut Config,
+    park: bool,
+) -> Option<&'a str> {
+    if cli.ip.is_some() {
+        fail(messages::dsn_conflict("an IPV4 argument"));
+    }
+    let unspecified = "0" == ip || ip.to_ip_addrs().is_ok_and(|addr| addr.is_unspecified());
+    match (unspecified, park) {
+        (true, true) => None,
+        (true, false) => fail(messages::unspecified_needs_offline(ip)),
+        (false, true) => fail(messages::offline_positional(ip)),
+        (false, false) => {
+            let source = match ip.to_ip_addrs() {
+                Ok(addr) => Ok(IpSource::Static(addr)),
+                Err(e @ AddrError::Zoned(_)) | Err(e @ AddrError::Ipv6(_)) => fail(e.to_string()),
+                Err(_) => ip.parse::<IpSource>(),
+            };
+            match source {
+                Ok(source) => {
+                    cli.ip = Some(Setting::new(source, Source::Cli));
+                    None
+                }
+                Err(_) if matches.opt_present("resolve-positional") => Some(ip),
+                Err(_) => usage(program),
+            }
+        }
+    }
+}
+
+fn usage(program: &str) -> ! {
+    print_usage(program);
+    process::exit(0)
 }
 
 fn main() {
-    let mut info = dice::Information::new(
-        String::default(),
-        String::default(),
-        String::default(),
-        String::default(),
-        std::net::Ipv4Addr::UNSPECIFIED.to_ip_addrs().unwrap(),
-    );
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
-    let mut opts = Options::new();
-    opts.optflag("h", "help", "print this help menu");
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(f) => {
-            panic!("{}", f.to_string())
+    let opts = cli::options();
+    let matches = opts
+        .parse(&args[1..])
+        .unwrap_or_else(|e| fail_usage(&program, e));
+    match matches.opt_str("error-format").as_deref() {
+        None | Some("text") => {}
+        Some("json") => JSON_ERRORS.store(true, Ordering::Relaxed),
+        Some(other) => fail(messages::unknown_format("error", other)),
+    }
+    if let Some(value) = matches.opt_str("message-limit") {
+        match value.parse() {
+            Ok(limit) => messages::set_limit(limit),
+            Err(e) => fail(messages::invalid_option("message-limit", e, &value)),
         }
-    };
+    }
     if matches.opt_present("h") {
-        print_usage(&program, opts);
+        print_usage(&program);
         return;
     }
-    if 1 > matches.free.len() {
-        print_usage(&program, opts);
+    if matches.opt_present("version") {
+        print!("{}", messages::version(tls::BACKEND, &features()));
         return;
     }
-    info.ipaddr = match (&matches.free[0]).to_ip_addrs() {
-        Ok(addrs) => addrs,
-        Err(_) => {
-            print_usage(&program, opts);
-            return;
+    if matches.opt_present("print-paths") {
+        match paths::default_paths() {
+            Some(paths) => println!("{}", paths),
+            None => fail(messages::no_home()),
         }
-    };
+        return;
+    }
+    if Some("capabilities") == matches.free.first().map(String::as_str) {
+        process::exit(capabilities_command(&matches));
+    }
+    if Some("check-config") == matches.free.first().map(String::as_str) {
+        let ddclient = ddclient_format(&matches);
+        process::exit(check_config(matches.opt_strs("config"), ddclient));
+    }
+    if Some("install") == matches.free.first().map(String::as_str) {
+        process::exit(install_command(&matches));
+    }
+    let interactive = !matches.opt_present("non-interactive") && !ci();
+    if Some("credential") == matches.free.first().map(String::as_str) {
+        let action = matches.free.get(1).map(String::as_str);
+        process::exit(credential(action, matches.opt_str("keyfile"), interactive));
+    }
+    let check = Some("check") == matches.free.first().map(String::as_str);
+    let detect = Some("detect") == matches.free.first().map(String::as_str);
+    let status = Some("status") == matches.free.first().map(String::as_str);
+    let diag = Some("diag") == matches.free.first().map(String::as_str);
     if 1 < matches.free.len() {
-        print_usage(&program, opts);
+        usage(&program);
+    }
+    let clock = SystemClock;
+    let json = match matches.opt_str("output").as_deref() {
+        _ if diag => false,
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(other) => fail(messages::unknown_format("output", other)),
+    };
+
+    let ddclient = ddclient_format(&matches);
+    let config_files = with_drop_ins(config_files(&matches), ddclient);
+    let mut config = load_config(&config_files, ddclient);
+    let mut cli = cli_config(&matches);
+    let options = RunOptions {
+        interval: matches
+            .opt_str("interval")
+            .map(|value| config::parse_duration(&value).unwrap_or_else(|e| fail(e))),
+        park: matches.opt_present("offline"),
+        exit: ExitOptions {
+            unchanged: matches
+                .opt_str("exit-code-unchanged")
+                .map_or(Ok(0), |value| report::parse_exit_code(&value))
+                .unwrap_or_else(|e| fail(messages::option_error("exit-code-unchanged", e))),
+        },
+    };
+    let park = options.park;
+    let positional = match matches
+        .free
+        .first()
+        .filter(|_| !check && !detect && !status && !diag)
+    {
+        Some(ip) => positional_ip(&matches, &program, ip, &mut cli, park),
+        None => None,
+    };
+
+    if status {
+        config.merge(cli);
+        process::exit(status_command(&config, clock.unix_now(), json));
+    }
+    let print_config = matches.opt_present("print-config");
+    let terminal = input::stdin_is_terminal();
+    let stdin_read = matches.opt_present("stdin-json") || !terminal;
+    let mut source: Box<dyn InputSource> = if terminal {
+        Box::new(Terminal::new(io::stdout()))
+    } else {
+        Box::new(Pipe::new(
+            io::stdin().lock(),
+            matches.opt_present("strict-input"),
+        ))
+    };
+    read_input(&matches, &mut config, &mut *source);
+    config.merge(cli);
+    if let Some(value) = matches.opt_str("pass-fd") {
+        config.pass = Some(pass_fd(&config, &value));
+    }
+    if park && config.ip.is_none() {
+        let placeholder = IpSource::Static(Ipv4Addr::UNSPECIFIED);
+        config.ip = Some(Setting::new(placeholder, Source::Cli));
+    }
+    let resolver = open_resolver(&config);
+    if let Some(name) = positional {
+        let nameserver = config.nameserver.as_ref().map(|setting| setting.value);
+        let ip = resolve_positional(&resolver, name, nameserver);
+        config.ip = Some(Setting::new(IpSource::Static(ip), Source::Cli));
+    }
+    if detect {
+        process::exit(detect_command(
+            host_resolver(&config, &resolver),
+            &detect_sources(&matches, &config),
+            json,
+        ));
+    }
+    if diag {
+        process::exit(diag_command(
+            &matches,
+            config,
+            &config_files,
+            &resolver,
+            &clock,
+        ));
+    }
+    config.resolve_secrets().unwrap_or_else(|e| fail(e));
+
+    if print_config {
+        print_config_command(&config, json);
+        return;
+    }
+    if config.ip.is_none() && config.records.is_empty() && !check {
+        let invocation = init::Invocation {
+            bare: 1 == args.len(),
+            config_found: !config_files.is_empty(),
+            stdin_terminal: terminal,
+            stdout_terminal: input::stdout_is_terminal(),
+            interactive,
+        };
+        if Offer::Init == init::offer(&invocation) {
+            process::exit(init_command(&mut *source, &program));
+        }
+        usage(&program);
+    }
+    let prompt = Prompt {
+        files: &config_files,
+        interactive,
+        stdin_read,
+        check,
+    };
+    let missing = prompt_missing(&mut config, &mut *source, &prompt);
+    if check {
+        if !missing.is_empty() {
+            fail(ConfigError::Missing(missing));
+        }
+        match check_credentials(&matches, &config, &resolver, &clock) {
+            Ok(()) => println!("{}", messages::credentials_ok()),
+            Err(e) => fail_client(e),
+        }
         return;
     }
+    process::exit(update_command(
+        &matches, &config, &resolver, &clock, json, &options,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(host: &str, dom: &str) -> Information {
+        Information::new("user", "pass", host, dom, Ipv4Addr::new(192, 0, 2, 1))
+    }
+
+    fn done() -> UpdateOutcome {
+        let response = dice::Response {
+            code: 0,
+            message: "COMMAND SUCCESSFUL".to_owned(),
+            body: Vec::new(),
+        };
+        UpdateOutcome::new(response, Duration::ZERO)
+    }
+
+    fn not_found() -> ClientError {
+        let response = dice::Response {
+            code: 210,
+            message: "DOMAIN NOT FOUND".to_owned(),
+            body: Vec::new(),
+        };
+        ClientError::Response(ResponseError::NotFound, response)
+    }
 
-    if unsafe { 0 != libc::isatty(libc::STDIN_FILENO) } {
-        for key in [KEY::USER, KEY::PASS, KEY::HOST, KEY::DOM].iter() {
-            let mut value = String::new();
-            print!("{}: ", key);
-            io::stdout().flush().unwrap();
-            io::stdin().read_line(&mut value).unwrap();
-            value.pop();
-            info[key] = value;
+    fn names(batch: &[Information]) -> Vec<String> {
+        batch.iter().map(Information::name).collect()
+    }
+
+    #[test]
+    fn failure_after_every_update_is_not_cross_domain() {
+        let pending = vec![info("a", "one.example"), info("b", "two.example")];
+        let mut strategy = SessionStrategy::Shared;
+        let mut calls = Vec::new();
+        let (updated, result) = run_updates(&pending, &[], &mut strategy, |_, batch, outcomes| {
+            calls.push(names(batch));
+            outcomes.extend(batch.iter().map(|_| done()));
+            Err(not_found())
+        });
+        assert!(matches!(
+            result,
+            Err(ClientError::Response(ResponseError::NotFound, _))
+        ));
+        assert_eq!(2, updated.len());
+        assert_eq!(1, calls.len());
+        assert_eq!(SessionStrategy::Shared, strategy);
+    }
+
+    #[test]
+    fn cross_domain_rejection_keeps_earlier_updates_and_retries_the_rest() {
+        let pending = vec![
+            info("a", "one.example"),
+            info("b", "one.example"),
+            info("c", "two.example"),
+        ];
+        let mut strategy = SessionStrategy::Shared;
+        let mut calls = Vec::new();
+        let (updated, result) = run_updates(&pending, &[], &mut strategy, |_, batch, outcomes| {
+            calls.push(names(batch));
+            let accepted = batch
+                .iter()
+                .take_while(|info| 1 < calls.len() || "one.example" == info.dom);
+            outcomes.extend(accepted.map(|_| done()));
+            if outcomes.len() == batch.len() {
+                Ok(())
+            } else {
+                Err(not_found())
+            }
+        });
+        assert!(result.is_ok());
+        let updated: Vec<String> = updated.iter().map(|(info, _)| info.name()).collect();
+        assert_eq!(
+            vec!["a.one.example", "b.one.example", "c.two.example"],
+            updated
+        );
+        assert_eq!(
+            vec![
+                vec!["a.one.example", "b.one.example", "c.two.example"],
+                vec!["c.two.example"],
+            ],
+            calls
+        );
+        assert_eq!(SessionStrategy::PerDomain, strategy);
+    }
+
+    #[test]
+    fn rejection_within_one_domain_is_an_error() {
+        let pending = vec![info("a", "one.example"), info("b", "one.example")];
+        let mut strategy = SessionStrategy::Shared;
+        let (updated, result) = run_updates(&pending, &[], &mut strategy, |_, _, outcomes| {
+            outcomes.push(done());
+            Err(not_found())
+        });
+        assert!(result.is_err());
+        assert_eq!(1, updated.len());
+        assert_eq!(SessionStrategy::Shared, strategy);
+    }
+
+    fn record(host: &str, dom: &str) -> Record {
+        let info = info(host, dom);
+        Record {
+            ip: Setting::new(IpSource::Static(info.ipaddr), Source::Cli),
+            info,
+            offline_ip: Ipv4Addr::UNSPECIFIED,
+            enabled: true,
+            dry_run: None,
+            window: None,
+            transport: Transport::default(),
+            priority: 0,
+            depends_on: Vec::new(),
         }
-    } else {
-        let mut line = String::new();
-        loop {
-            if 0 == std::io::stdin().read_line(&mut line).unwrap() {
-                break;
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl EventSink for Recorder {
+        fn handle(&mut self, event: &Event) {
+            let entry = match *event {
+                Event::RecordUpdated { record, .. } => format!("updated {}", record.info.name()),
+                Event::RecordUnchanged { record, .. } => {
+                    format!("unchanged {}", record.info.name())
+                }
+                Event::RecordFailed { record, error } => {
+                    format!("failed {}: {}", record.info.name(), error)
+                }
+                Event::RecordSkipped { record, reason } => {
+                    format!("skipped {}: {}", record.info.name(), reason.as_str())
+                }
+                _ => return,
             };
-            line.pop();
-            if let Some((key, value)) = parse_line(line.trim_start()) {
-                info[key] = value;
+            self.events.push(entry);
+        }
+    }
+
+    fn emitted(cycle: &Cycle, updated: &Updated, error: Option<&str>) -> Vec<String> {
+        let mut recorder = Recorder::default();
+        let mut bus = EventBus::new();
+        bus.subscribe(&mut recorder);
+        emit_records(&mut bus, cycle, updated, error);
+        drop(bus);
+        recorder.events
+    }
+
+    fn cycle() -> Cycle {
+        let planned = |host, outcome| Planned {
+            record: record(host, "example.com"),
+            outcome,
+            previous: None,
+        };
+        Cycle {
+            plan: vec![
+                planned("a", Outcome::Updated),
+                planned("b", Outcome::Unchanged),
+                planned("c", Outcome::Updated),
+            ],
+            failed: vec![(record("d", "example.com"), "no address".to_owned())],
+            skipped: vec![(record("e", "example.com"), Skip::FilteredOut)],
+        }
+    }
+
+    #[test]
+    fn partial_failure_still_reports_updated_records() {
+        let cycle = cycle();
+        let updated = vec![(cycle.plan[0].record.info.clone(), done())];
+        assert_eq!(
+            vec![
+                "updated a.example.com",
+                "unchanged b.example.com",
+                "failed c.example.com: session lost",
+                "failed d.example.com: no address",
+                "skipped e.example.com: filtered_out",
+            ],
+            emitted(&cycle, &updated, Some("session lost"))
+        );
+    }
+
+    #[test]
+    fn success_reports_every_record() {
+        let cycle = cycle();
+        let updated = vec![
+            (cycle.plan[0].record.info.clone(), done()),
+            (cycle.plan[2].record.info.clone(), done()),
+        ];
+        assert_eq!(
+            vec![
+                "updated a.example.com",
+                "unchanged b.example.com",
+                "updated c.example.com",
+                "failed d.example.com: no address",
+                "skipped e.example.com: filtered_out",
+            ],
+            emitted(&cycle, &updated, None)
+        );
+    }
+
+    #[test]
+    fn summary_lines_for_each_outcome() {
+        let mut static_ip = record("www", "example.com");
+        static_ip.info.ipaddr = Ipv4Addr::new(203, 0, 113, 9);
+        let previous = Some(Ipv4Addr::new(203, 0, 113, 5));
+        let took = Duration::from_millis(1234);
+        let line =
+            |record: &Record, outcome, previous| summary_line(record, outcome, previous, took);
+        assert_eq!(
+            "updated www.example.com: 203.0.113.5 -> 203.0.113.9 (1.2s)",
+            line(&static_ip, Outcome::Updated, previous)
+        );
+        assert_eq!(
+            "updated www.example.com: 203.0.113.9 (1.2s)",
+            line(&static_ip, Outcome::Updated, None)
+        );
+        assert_eq!(
+            "unchanged www.example.com: 203.0.113.9",
+            line(&static_ip, Outcome::Unchanged, previous)
+        );
+        assert_eq!(
+            "refreshed www.example.com: 203.0.113.9 (unchanged, max-age exceeded) (1.2s)",
+            line(&static_ip, Outcome::Refreshed, previous)
+        );
+        let mut parked = static_ip.clone();
+        parked.info.ipaddr = Ipv4Addr::UNSPECIFIED;
+        assert_eq!(
+            "set offline www.example.com: 203.0.113.5 -> 0.0.0.0 (1.2s)",
+            line(&parked, Outcome::Offline, previous)
+        );
+        let mut detected = static_ip;
+        detected.ip.value = IpSource::Url("https://ip.example.net".to_owned());
+        assert_eq!(
+            "updated www.example.com: 203.0.113.5 -> 203.0.113.9 via url:https://ip.example.net (1.2s)",
+            line(&detected, Outcome::Updated, previous)
+        );
+        assert_eq!(
+            "unchanged www.example.com: 203.0.113.9 via url:https://ip.example.net",
+            line(&detected, Outcome::Unchanged, None)
+        );
+    }
+
+    struct Hosts(Vec<(&'static str, Ipv4Addr)>);
+
+    impl Hosts {
+        fn lookup(&self, name: &str) -> Vec<Ipv4Addr> {
+            let found = self.0.iter().filter(|(host, _)| name == *host);
+            found.map(|(_, ip)| *ip).collect()
+        }
+    }
+
+    impl dice::Resolve for Hosts {
+        fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<std::net::SocketAddr>> {
+            let addrs = self.lookup(host).into_iter().map(|ip| (ip, port).into());
+            Ok(addrs.collect())
+        }
+    }
+
+    #[cfg(feature = "dns")]
+    impl dns::Resolver for Hosts {
+        fn lookup_a(&self, name: &str) -> Result<Vec<Ipv4Addr>, DnsError> {
+            match self.lookup(name) {
+                ips if ips.is_empty() => Err(DnsError::Rcode(3)),
+                ips => Ok(ips),
             }
-            line.clear();
         }
+
+        fn lookup_aaaa(&self, _: &str) -> Result<Vec<std::net::Ipv6Addr>, DnsError> {
+            Err(DnsError::Unsupported("hosts", "AAAA"))
+        }
+
+        fn lookup_ns(&self, _: &str) -> Result<Vec<String>, DnsError> {
+            Err(DnsError::Unsupported("hosts", "NS"))
+        }
+
+        fn lookup_txt(&self, _: &str) -> Result<Vec<String>, DnsError> {
+            Err(DnsError::Unsupported("hosts", "TXT"))
+        }
+    }
+
+    #[test]
+    fn dry_run_previews_records_with_and_without_current_values() {
+        let old = Ipv4Addr::new(203, 0, 113, 5);
+        let published = Ipv4Addr::new(198, 51, 100, 7);
+        let new = Ipv4Addr::new(192, 0, 2, 1);
+        let resolver: Resolver = Box::new(Hosts(vec![
+            ("a.example.com", published),
+            ("b.example.com", published),
+        ]));
+        let mut state = State::default();
+        state.record_success("a.example.com", old, 1);
+        state.record_success("c.example.com", new, 1);
+        let plan: Vec<Planned> = [
+            ("a", Outcome::Updated),
+            ("b", Outcome::Updated),
+            ("c", Outcome::Unchanged),
+            ("d", Outcome::Updated),
+        ]
+        .iter()
+        .map(|(host, outcome)| Planned {
+            record: record(host, "example.com"),
+            outcome: *outcome,
+            previous: None,
+        })
+        .collect();
+        let pending: Vec<Information> = plan
+            .iter()
+            .filter(|planned| Outcome::Updated == planned.outcome)
+            .map(|planned| planned.record.info.clone())
+            .collect();
+        let preview = |offline| {
+            dry_run_command(&resolver, &plan, &pending, &[], &state, offline, true).unwrap()
+        };
+
+        let report = preview(false);
+        let column = |report: &serde_json::Value, key: &str| -> Vec<Option<String>> {
+            let records = report["records"].as_array().unwrap();
+            let value = |record: &serde_json::Value| record[key].as_str().map(str::to_owned);
+            records.iter().map(value).collect()
+        };
+        let some = |ip: Ipv4Addr| Some(ip.to_string());
+        assert_eq!(
+            vec![some(old), None, some(new), None],
+            column(&report, "state_ip")
+        );
+        assert_eq!(
+            vec![some(published), some(published), None, None],
+            column(&report, "dns_ip")
+        );
+        let outcomes = column(&report, "outcome");
+        assert_eq!(
+            vec!["updated", "updated", "unchanged", "updated"],
+            outcomes.iter().flatten().collect::<Vec<_>>()
+        );
+        let sessions = report["sessions"].as_array().unwrap();
+        assert_eq!(1, sessions.len());
+        let lines: Vec<&str> = sessions[0]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(serde_json::Value::as_str)
+            .collect();
+        assert!(lines.contains(&"PASSWORD:***"), "{:?}", lines);
+        assert!(!lines.contains(&"PASSWORD:pass"), "{:?}", lines);
+        assert_eq!(3, lines.iter().filter(|line| "MODIP" == **line).count());
+        assert!(lines.contains(&"HOSTNAME:d"), "{:?}", lines);
+
+        let offline = preview(true);
+        assert_eq!(vec![None; 4], column(&offline, "dns_ip"));
+        assert_eq!(column(&report, "state_ip"), column(&offline, "state_ip"));
+    }
+
+    #[test]
+    fn dry_run_lines_show_the_current_value() {
+        let old = Some(Ipv4Addr::new(203, 0, 113, 5));
+        let published = Some(Ipv4Addr::new(198, 51, 100, 7));
+        let new = Ipv4Addr::new(198, 51, 100, 7);
+        let name = "www.example.com";
+        assert_eq!(
+            "www.example.com A: 203.0.113.5 -> 198.51.100.7 (would update)",
+            messages::dry_run_record(name, old, None, new, Outcome::Updated)
+        );
+        assert_eq!(
+            "www.example.com A: 203.0.113.5 -> 198.51.100.7 (would update)",
+            messages::dry_run_record(name, old, old, new, Outcome::Updated)
+        );
+        assert_eq!(
+            "www.example.com A: 203.0.113.5 -> 198.51.100.7 (would refresh) [DNS has 198.51.100.7]",
+            messages::dry_run_record(name, old, published, new, Outcome::Refreshed)
+        );
+        assert_eq!(
+            "www.example.com A: 198.51.100.7 -> 198.51.100.7 (no change)",
+            messages::dry_run_record(name, None, published, new, Outcome::Unchanged)
+        );
+        assert_eq!(
+            "www.example.com A: unknown -> 198.51.100.7 (would update)",
+            messages::dry_run_record(name, None, None, new, Outcome::Updated)
+        );
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn changed_ca_file_rebuilds_the_connector() {
+        let bundle = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls/ca-bundle.pem");
+        let bundle = fs::read_to_string(bundle).unwrap();
+        let end = "-----END CERTIFICATE-----\n";
+        let first = &bundle[..bundle.find(end).unwrap() + end.len()];
+        let path = env::temp_dir().join(format!("ddns_client-ca-{}.pem", process::id()));
+        let write = |text: &str, at: u64| {
+            fs::write(&path, text).unwrap();
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(at))
+                .unwrap();
+        };
+        write(first, 1000);
+        let config = Config {
+            ca_file: Some(Setting::new(path.clone(), Source::Cli)),
+            ..Config::default()
+        };
+        let endpoint = Endpoint::new(&config, &dice::SystemResolver);
+        let mut stats = ClientStats::default();
+
+        endpoint.connector(&mut stats);
+        assert_eq!(0, stats.trust_reloads);
+        write(&bundle, 2000);
+        endpoint.connector(&mut stats);
+        endpoint.connector(&mut stats);
+        assert_eq!(1, stats.trust_reloads);
+        assert!(stats.to_string().contains(", 1 CA reloads"), "{}", stats);
+
+        write("not a certificate\n", 3000);
+        endpoint.connector(&mut stats);
+        assert_eq!(1, stats.trust_reloads);
+        write(first, 4000);
+        endpoint.connector(&mut stats);
+        assert_eq!(2, stats.trust_reloads);
+        fs::remove_file(&path).unwrap();
+        endpoint.connector(&mut stats);
+        assert_eq!(2, stats.trust_reloads);
+    }
+
+    #[test]
+    fn breaker_gate_counts_down_to_the_window_end() {
+        let until = 1_800_000_450;
+        let mut state = State::default();
+        state.auth.failures = 3;
+        state.auth.open_until = Some(until);
+        for (now, left, text) in [
+            (until - 450, 450, "7m 30s"),
+            (until - 61, 61, "1m 1s"),
+            (until - 1, 1, "1s"),
+        ] {
+            let fatal = breaker_gate(&state, false, now).unwrap_err();
+            let retry_at = schedule::local_timestamp(until);
+            assert_eq!("auth_breaker_open", fatal.kind);
+            assert!(
+                fatal
+                    .message
+                    .contains(&format!("retry in {} (at {})", text, retry_at)),
+                "{}",
+                fatal.message
+            );
+            assert_eq!(left, fatal.detail["retry_after_seconds"]);
+            assert_eq!(retry_at, fatal.detail["retry_at"]);
+            let json = breaker_json(&state, now);
+            assert_eq!(true, json["open"]);
+            assert_eq!(left, json["retry_after_seconds"]);
+            assert_eq!(retry_at, json["retry_at"]);
+        }
+        for now in [until, until + 1] {
+            assert!(breaker_gate(&state, false, now).is_ok());
+            let json = breaker_json(&state, now);
+            assert_eq!(false, json["open"]);
+            assert!(json["retry_after_seconds"].is_null());
+            assert!(json["retry_at"].is_null());
+        }
+        let fatal = breaker_gate(&state, true, until + 100).unwrap_err();
+        assert!(fatal.detail["retry_after_seconds"].is_null());
+        assert_eq!(true, fatal.detail["clock_skew"]);
+    }
+
+    #[test]
+    fn byte_budget_suspends_until_the_utc_day_rolls_over() {
+        let config = Config {
+            max_bytes_per_day: Some(Setting::new(1000, Source::Cli)),
+            ..Config::default()
+        };
+        let plain = cli::options().parse(Vec::<String>::new()).unwrap();
+        let forced = cli::options().parse(["--force"]).unwrap();
+        let midnight = (1_800_000_000 / state::DAY + 1) * state::DAY;
+        let mut state = State::default();
+        state.traffic.add(midnight - 60, 400, 500);
+        assert!(!byte_budget(&plain, &config, &mut state, midnight - 60));
+        state.traffic.add(midnight - 30, 50, 50);
+        assert!(byte_budget(&plain, &config, &mut state, midnight - 30));
+        assert_eq!(Some(midnight / state::DAY - 1), state.traffic.suspended_day);
+        assert!(byte_budget(&plain, &config, &mut state, midnight - 1));
+        assert!(!byte_budget(&forced, &config, &mut state, midnight - 1));
+        assert!(!byte_budget(&plain, &config, &mut state, midnight));
+        assert!(!byte_budget(
+            &plain,
+            &Config::default(),
+            &mut state,
+            midnight - 1
+        ));
+
+        let json = traffic_json(&config, &state, midnight - 1);
+        assert_eq!(true, json["suspended"]);
+        assert_eq!(1000, json["max_bytes_per_day"]);
+        assert_eq!(450, json["day_sent"]);
+        let json = traffic_json(&config, &state, midnight);
+        assert_eq!(false, json["suspended"]);
+        assert_eq!(0, json["day_received"]);
+        assert_eq!(550, json["total_received"]);
     }
-    let mut client = dice::Client::new(dice::open());
-    client.verbose = true;
-    client.recv_res().unwrap();
-    client.run_modip(&info).unwrap();
 }
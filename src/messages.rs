@@ -0,0 +1,882 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::ddclient;
+use crate::schedule::Skip;
+use crate::state::{ClockSkew, Outcome};
+use crate::stats::Summary;
+use crate::tls::Certificate;
+use crate::KEY;
+
+pub const DEFAULT_LIMIT: usize = 512;
+
+static LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_LIMIT);
+
+pub fn set_limit(limit: usize) {
+    LIMIT.store(limit, Ordering::Relaxed);
+}
+
+pub fn sanitize(text: &str) -> Cow<'_, str> {
+    let limit = LIMIT.load(Ordering::Relaxed);
+    let clean = |c: char| !c.is_control() || '\n' == c;
+    if text.len() <= limit && text.chars().all(clean) {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::new();
+    for (i, c) in text.char_indices() {
+        if limit <= out.len() {
+            out.push_str(&format!("... ({} bytes, {} shown)", text.len(), i));
+            break;
+        }
+        if clean(c) {
+            out.push(c);
+        } else {
+            out.extend(c.escape_default());
+        }
+    }
+    Cow::Owned(out)
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    match n {
+        1 => format!("1 {}", one),
+        n => format!("{} {}", n, many),
+    }
+}
+
+pub fn humanize(secs: u64) -> String {
+    let mut parts = Vec::new();
+    let mut left = secs;
+    for (size, unit) in [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")].iter() {
+        let count = left / size;
+        left %= size;
+        if 0 < count && parts.len() < 2 {
+            parts.push(format!("{}{}", count, unit));
+        } else if !parts.is_empty() {
+            break;
+        }
+    }
+    if parts.is_empty() {
+        "0s".to_owned()
+    } else {
+        parts.join(" ")
+    }
+}
+
+fn seconds(after: Duration) -> f64 {
+    after.as_secs_f64().round()
+}
+
+pub fn error(e: impl Display) -> String {
+    format!("error: {}", e)
+}
+
+pub fn warning(e: impl Display) -> String {
+    format!("warning: {}", e)
+}
+
+pub fn hint(text: impl Display) -> String {
+    format!("hint: {}", text)
+}
+
+pub fn no_home() -> String {
+    "could not determine the home directory".to_owned()
+}
+
+pub fn state_dir_unwritable(dir: impl Display, e: impl Display) -> String {
+    format!("state directory {} is not writable: {}", dir, e)
+}
+
+pub fn file_error(path: impl Display, e: impl Display) -> String {
+    format!("{}: {}", path, e)
+}
+
+pub fn stdin_error(e: impl Display) -> String {
+    format!("stdin: {}", e)
+}
+
+pub fn see_help(program: &str) -> String {
+    format!("run '{} --help' for the list of options", program)
+}
+
+pub fn unknown_format(what: &str, value: &str) -> String {
+    format!("unknown {} format: {}", what, value)
+}
+
+pub fn invalid_option(option: &str, e: impl Display, value: &str) -> String {
+    format!("--{}: {}: {:?}", option, e, sanitize(value))
+}
+
+pub fn only_unmatched() -> String {
+    "matches no configured record".to_owned()
+}
+
+pub fn option_error(option: &str, e: impl Display) -> String {
+    format!("--{}: {}", option, e)
+}
+
+pub fn pass_fd_conflict(source: impl Display) -> String {
+    format!(
+        "--pass-fd cannot be combined with another password source (already set by {})",
+        source
+    )
+}
+
+pub fn pass_fd_stdin() -> String {
+    "--pass-fd 0 would read stdin, which carries key=value input; use another descriptor".to_owned()
+}
+
+pub fn unspecified_needs_offline(ip: &str) -> String {
+    format!(
+        "{:?} is the unspecified address; use --offline to point records at their offline target",
+        sanitize(ip)
+    )
+}
+
+pub fn offline_positional(ip: &str) -> String {
+    format!(
+        "--offline uses each record's offline_ip and takes no address, found {:?}",
+        sanitize(ip)
+    )
+}
+
+pub fn version(tls: &str, features: &[&str]) -> String {
+    format!(
+        "{} {}\ntls: {}\nfeatures: {}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        tls,
+        match features {
+            [] => "none".to_owned(),
+            features => features.join(", "),
+        }
+    )
+}
+
+pub fn tls_unsupported() -> String {
+    "built without TLS support; rebuild with the tls-openssl feature".to_owned()
+}
+
+pub fn crypto_unsupported() -> String {
+    "built without OpenSSL; encrypted passwords need the tls-openssl feature".to_owned()
+}
+
+pub fn dsn_conflict(what: &str) -> String {
+    format!("--dsn cannot be combined with {}", what)
+}
+
+pub fn unprompted(keys: &[(KEY, Vec<String>)]) -> String {
+    let mut text = "missing required keys, not connecting:".to_owned();
+    for (key, consulted) in keys {
+        text.push_str(&format!("\n  {}: not set by {}", key, consulted.join(", ")));
+    }
+    text
+}
+
+pub fn consulted_stdin(key: impl Display) -> String {
+    format!("{} on stdin", key)
+}
+
+pub fn consulted_empty(source: impl Display) -> String {
+    format!("{} (empty value)", source)
+}
+
+pub fn prompt_skipped(reason: &str) -> String {
+    format!("prompt skipped ({})", reason)
+}
+
+pub fn consulted_file(name: &str, path: &Path) -> String {
+    format!("{} in {}", name, path.display())
+}
+
+pub fn no_config_file() -> String {
+    "no configuration file".to_owned()
+}
+
+pub fn ddclient_ignored() -> String {
+    "ddclient directive not supported; ignored".to_owned()
+}
+
+pub fn ddclient_protocol(protocol: &str) -> String {
+    format!(
+        "protocol={} is not supported (only {} is)",
+        sanitize(protocol),
+        ddclient::PROTOCOLS.join(", ")
+    )
+}
+
+pub fn ddclient_credentials(name: &str) -> String {
+    format!(
+        "its {} differs from an earlier host's; only one account is supported",
+        name
+    )
+}
+
+pub fn ddclient_host_skipped(host: &str, reason: &str) -> String {
+    format!("{} skipped: {}", sanitize(host), reason)
+}
+
+pub fn include_failed(reference: &str, e: impl Display) -> String {
+    format!("could not resolve {}: {}", reference, e)
+}
+
+pub fn include_recursive(reference: &str) -> String {
+    format!(
+        "{} resolves to another @file: or @env: reference",
+        reference
+    )
+}
+
+pub fn include_permissions(path: &str, mode: u32) -> String {
+    format!(
+        "{} is accessible by group or others (mode {:03o}); run chmod 600 on it",
+        path, mode
+    )
+}
+
+pub fn unknown_dependency(record: &str, dependency: &str) -> String {
+    format!(
+        "{} depends on {}, which is not a configured record",
+        record, dependency
+    )
+}
+
+pub fn dependency_cycle(cycle: &[String]) -> String {
+    format!("dependency cycle: {}", cycle.join(" -> "))
+}
+
+pub fn special_address(ip: Ipv4Addr, (network, prefix, name): (Ipv4Addr, u8, &str)) -> String {
+    format!(
+        "{} is not a public address ({}, {}/{})",
+        ip, name, network, prefix
+    )
+}
+
+pub fn special_target(name: &str, ip: Ipv4Addr, range: (Ipv4Addr, u8, &str)) -> String {
+    format!(
+        "{}: {}; inbound connections to it will not work",
+        name,
+        special_address(ip, range)
+    )
+}
+
+pub fn config_ok(path: &Path) -> String {
+    format!("{}: ok", path.display())
+}
+
+pub fn problems_found(count: usize) -> String {
+    format!("{} found", plural(count, "problem", "problems"))
+}
+
+pub fn credential_usage() -> String {
+    "usage: credential encrypt --keyfile FILE".to_owned()
+}
+
+pub fn keyfile_required() -> String {
+    "credential encrypt requires --keyfile FILE".to_owned()
+}
+
+pub fn creating_key_file(path: &Path) -> String {
+    format!("creating new key file {}", path.display())
+}
+
+pub fn prompt(key: impl Display) -> String {
+    format!("{}: ", key)
+}
+
+pub fn prompt_default(key: impl Display, default: &str) -> String {
+    format!("{} [{}]: ", key, default)
+}
+
+pub fn confirm(question: &str, default: bool) -> String {
+    format!("{} [{}] ", question, if default { "Y/n" } else { "y/N" })
+}
+
+pub fn init_offer() -> &'static str {
+    "No configuration found. Create one now?"
+}
+
+pub fn init_update() -> &'static str {
+    "Run a first update now?"
+}
+
+pub fn init_ip_source() -> &'static str {
+    "IP source (url:URL, interface:NAME, dns:NAME, cmd:PROGRAM or static:IP)"
+}
+
+pub fn init_next_steps(path: &Path) -> String {
+    format!(
+        "the password is stored in plain text in {}; `credential encrypt --keyfile FILE` \
+         prints an encrypted replacement, and more [[record]] tables can be added there",
+        path.display()
+    )
+}
+
+pub fn prompt_again(key: impl Display) -> String {
+    format!("{} (again): ", key)
+}
+
+pub fn input_ended(key: &KEY) -> String {
+    format!("input ended while reading {}", key)
+}
+
+pub fn empty_input(key: &KEY, attempts: usize) -> String {
+    format!(
+        "{} is required but was empty {}",
+        key,
+        plural(attempts, "time", "times")
+    )
+}
+
+pub fn passwords_differ() -> String {
+    "passwords do not match".to_owned()
+}
+
+pub fn install_usage() -> String {
+    "usage: install systemd|cron [--timer DURATION] [--write[=DIR] [--force]]".to_owned()
+}
+
+pub fn timer_zero() -> String {
+    "--timer must be greater than zero".to_owned()
+}
+
+pub fn current_exe(e: impl Display) -> String {
+    format!("current executable: {}", e)
+}
+
+pub fn wrote(path: &Path) -> String {
+    format!("wrote {}", path.display())
+}
+
+pub fn systemd_next_steps(credentials: &str, timer: &str) -> String {
+    format!(
+        "put PASS=... in {} and run: systemctl daemon-reload && systemctl enable --now {}",
+        credentials, timer
+    )
+}
+
+pub fn no_ip_source() -> String {
+    "no ip source configured: use --ip-source or --interface".to_owned()
+}
+
+pub fn source_failed(source: impl Display, e: impl Display) -> String {
+    format!("{}: {}", source, e)
+}
+
+pub fn record_detect_failed(name: &str, source: impl Display, e: impl Display) -> String {
+    format!("{}: {}: {}", sanitize(name), source, e)
+}
+
+pub fn no_address_detected(names: &[String]) -> String {
+    format!("no address detected for {}", names.join(", "))
+}
+
+pub fn positional_resolve_failed(name: &str, e: impl Display) -> String {
+    format!("could not resolve {}: {}", sanitize(name), e)
+}
+
+pub fn positional_no_address(name: &str) -> String {
+    format!("{} has no A record", sanitize(name))
+}
+
+pub fn positional_many(name: &str, count: usize, ip: Ipv4Addr) -> String {
+    format!(
+        "{} has {}; using {}",
+        sanitize(name),
+        plural(count, "address", "addresses"),
+        ip
+    )
+}
+
+pub fn dry_run_record(
+    name: &str,
+    known: Option<Ipv4Addr>,
+    resolved: Option<Ipv4Addr>,
+    ip: Ipv4Addr,
+    outcome: Outcome,
+) -> String {
+    let current = known.or(resolved);
+    let from = current.map_or_else(|| "unknown".to_owned(), |ip| ip.to_string());
+    let dns = match resolved {
+        Some(resolved) if Some(resolved) != current => format!(" [DNS has {}]", resolved),
+        _ => String::new(),
+    };
+    let action = match outcome {
+        Outcome::Updated => "would update",
+        Outcome::Refreshed => "would refresh",
+        Outcome::Unchanged => "no change",
+        Outcome::Offline => "would set offline",
+    };
+    format!(
+        "{} A: {} -> {} ({}){}",
+        sanitize(name),
+        from,
+        ip,
+        action,
+        dns
+    )
+}
+
+pub fn dry_run_session(index: usize) -> String {
+    format!("session {}:", index)
+}
+
+pub fn dns_unsupported(what: &str) -> String {
+    format!("{} requires the dns feature", what)
+}
+
+pub fn delegated_elsewhere(dom: &str, names: &[String]) -> String {
+    format!(
+        "{} is delegated to {}, not the registrar's nameservers; updates will have no visible effect",
+        sanitize(dom),
+        sanitize(&names.join(", "))
+    )
+}
+
+pub fn ownership_unknown(dom: &str, e: impl Display) -> String {
+    format!("could not check the delegation of {}: {}", sanitize(dom), e)
+}
+
+pub fn dns_precheck_failed(name: &str, e: impl Display) -> String {
+    format!("{}: DNS pre-check failed: {}", sanitize(name), e)
+}
+
+pub fn summary(
+    outcome: Outcome,
+    name: &str,
+    previous: Option<Ipv4Addr>,
+    ip: Ipv4Addr,
+    via: Option<&dyn Display>,
+    elapsed: Duration,
+) -> String {
+    let via = via.map_or_else(String::new, |source| format!(" via {}", source));
+    let name = sanitize(name);
+    let secs = elapsed.as_secs_f64();
+    match (outcome, previous) {
+        (Outcome::Updated, Some(previous)) => format!(
+            "updated {}: {} -> {}{} ({:.1}s)",
+            name, previous, ip, via, secs
+        ),
+        (Outcome::Updated, None) => format!("updated {}: {}{} ({:.1}s)", name, ip, via, secs),
+        (Outcome::Refreshed, _) => format!(
+            "refreshed {}: {} (unchanged, max-age exceeded){} ({:.1}s)",
+            name, ip, via, secs
+        ),
+        (Outcome::Unchanged, _) => format!("unchanged {}: {}{}", name, ip, via),
+        (Outcome::Offline, Some(previous)) => format!(
+            "set offline {}: {} -> {} ({:.1}s)",
+            name, previous, ip, secs
+        ),
+        (Outcome::Offline, None) => format!("set offline {}: {} ({:.1}s)", name, ip, secs),
+    }
+}
+
+pub fn skipped(name: &str, skip: Skip) -> String {
+    format!("skipped {} ({})", sanitize(name), skip.reason())
+}
+
+pub fn dry_run_skipped(name: &str, skip: Skip) -> String {
+    format!("{} A: {}", sanitize(name), skip)
+}
+
+pub fn no_status_file(path: &Path) -> String {
+    format!("{}: no status file; showing the state file", path.display())
+}
+
+pub fn status_health(health: impl Display, last_cycle: u64, now: u64) -> String {
+    format!(
+        "{} (last run {}s ago)",
+        health,
+        now.saturating_sub(last_cycle)
+    )
+}
+
+pub fn status_ip(ip: Ipv4Addr) -> String {
+    format!("address: {}", ip)
+}
+
+pub fn status_traffic(today: (u64, u64), total: (u64, u64)) -> String {
+    format!(
+        "traffic: {} bytes sent, {} received today (UTC); {} sent, {} received in total",
+        today.0, today.1, total.0, total.1
+    )
+}
+
+pub fn status_latency(stage: &str, summary: &Summary) -> String {
+    format!(
+        "{}: p50 {}ms, p95 {}ms, max {}ms over {} samples",
+        sanitize(stage),
+        summary.p50.as_millis(),
+        summary.p95.as_millis(),
+        summary.max.as_millis(),
+        summary.samples
+    )
+}
+
+pub fn latency_high(stage: &str, p95: Duration, threshold: Duration) -> String {
+    format!(
+        "{} p95 latency is {}ms, above the {}ms threshold",
+        sanitize(stage),
+        p95.as_millis(),
+        threshold.as_millis()
+    )
+}
+
+pub fn status_record(name: &str, last_success: Option<u64>, now: u64) -> String {
+    match last_success {
+        Some(last) => format!(
+            "{}: last updated {}s ago",
+            sanitize(name),
+            now.saturating_sub(last)
+        ),
+        None => format!("{}: never updated", sanitize(name)),
+    }
+}
+
+pub fn credentials_ok() -> String {
+    "credentials ok".to_owned()
+}
+
+pub fn diag_unavailable(e: impl Display) -> String {
+    format!("(not available: {})\n", e)
+}
+
+pub fn diag_check_skipped() -> String {
+    "skipped: user and pass are not both set".to_owned()
+}
+
+pub fn diag_written(path: &Path) -> String {
+    format!(
+        "wrote {} (secrets redacted; review it before sharing)",
+        path.display()
+    )
+}
+
+pub fn retrying(e: impl Display, delay: Duration, retry: u32, retries: u32) -> String {
+    format!(
+        "{}; retrying in {:.1}s ({}/{})",
+        e,
+        delay.as_secs_f64(),
+        retry,
+        retries
+    )
+}
+
+pub fn cross_domain_retry(name: &str, dom: &str, kept: &[String]) -> String {
+    let message = format!(
+        "{} was rejected in a session shared with {}; retrying with one session per domain",
+        name, dom
+    );
+    if kept.is_empty() {
+        return message;
+    }
+    format!("{} (already updated: {})", message, kept.join(", "))
+}
+
+pub fn domain_not_found() -> String {
+    "the domain is probably not registered to this account; check dom in the configuration"
+        .to_owned()
+}
+
+pub fn host_not_found() -> String {
+    "the server has no such host record; check host (or the record's name) in the configuration"
+        .to_owned()
+}
+
+pub fn extra_fields_unsupported() -> String {
+    "the server may not support one of the extra MODIP fields".to_owned()
+}
+
+pub fn maybe_applied() -> String {
+    "the update may have been applied before the timeout".to_owned()
+}
+
+pub fn auth_breaker_open(failures: u32, remaining: u64, retry_at: &str) -> String {
+    format!(
+        "auth circuit breaker open after {}; \
+         retry in {} (at {}), or fix the credentials and run with --reset-auth-backoff",
+        plural(
+            failures as usize,
+            "login failure",
+            "consecutive login failures"
+        ),
+        humanize(remaining),
+        retry_at
+    )
+}
+
+pub fn auth_breaker_opened(failures: u32, cooldown: u64, retry_at: &str) -> String {
+    format!(
+        "auth circuit breaker opened after {}; \
+         further logins are refused for {} (until {}; fix the credentials or use \
+         --reset-auth-backoff)",
+        plural(
+            failures as usize,
+            "login failure",
+            "consecutive login failures"
+        ),
+        humanize(cooldown),
+        retry_at
+    )
+}
+
+pub fn status_breaker(failures: u32, remaining: u64, retry_at: &str) -> String {
+    format!(
+        "auth circuit breaker: open after {}, retry in {} (at {})",
+        plural(failures as usize, "login failure", "login failures"),
+        humanize(remaining),
+        retry_at
+    )
+}
+
+pub fn auth_breaker_skewed(failures: u32) -> String {
+    format!(
+        "auth circuit breaker open after {}; \
+         the system clock is not trustworthy, so it stays open until the clock is fixed \
+         (or run with --reset-auth-backoff)",
+        plural(
+            failures as usize,
+            "login failure",
+            "consecutive login failures"
+        )
+    )
+}
+
+pub fn clock_skew(now: u64, skew: ClockSkew) -> String {
+    let reason = match skew {
+        ClockSkew::BeforeFloor(floor) => format!("is before {}, when this build was made", floor),
+        ClockSkew::Behind(latest) => format!(
+            "is {}s behind the newest state-file timestamp ({})",
+            latest - now,
+            latest
+        ),
+    };
+    format!(
+        "system time {} {}; ignoring max-age and keeping an open auth breaker shut",
+        now, reason
+    )
+}
+
+pub fn state_disabled_unchanged() -> String {
+    "state is disabled: unchanged records are sent again on every run \
+     (use --check-dns-first to skip them)"
+        .to_owned()
+}
+
+pub fn socket_lock_unsupported() -> String {
+    "state is disabled and this platform has no lock socket: concurrent runs are not serialized"
+        .to_owned()
+}
+
+pub fn lock_failed(e: impl Display) -> String {
+    format!("cannot take the run lock: {}", e)
+}
+
+pub fn source_penalty(source: &str, remaining: Option<u64>) -> String {
+    match remaining {
+        Some(left) => format!("{}: failed, skipped for the next {}s", source, left),
+        None => format!("{}: failed, tried again next run", source),
+    }
+}
+
+pub fn daemon_managing() -> String {
+    "daemon already managing these records".to_owned()
+}
+
+pub fn daemon_conflict() -> String {
+    "already running: a daemon with a different configuration holds the run lock \
+     (use --force to wait for it)"
+        .to_owned()
+}
+
+pub fn lock_waiting(timeout: Duration) -> String {
+    format!(
+        "waiting up to {}s for another run to finish",
+        timeout.as_secs()
+    )
+}
+
+pub fn lock_timeout(timeout: Duration) -> String {
+    format!("another run still held it after {}s", timeout.as_secs())
+}
+
+pub fn capture_failed(e: impl Display) -> String {
+    format!("cannot hold output for --quiet-unchanged: {}", e)
+}
+
+pub fn clock_repaired() -> String {
+    "system time is plausible again; cleared timestamps written while it was not".to_owned()
+}
+
+pub fn byte_budget_spent(used: u64, limit: u64, resume_at: &str) -> String {
+    format!(
+        "{} of the {}-byte daily budget used; updates are suspended until {} \
+         unless --force is given",
+        used, limit, resume_at
+    )
+}
+
+pub fn auth_breaker_closed() -> String {
+    "auth circuit breaker closed".to_owned()
+}
+
+pub fn ipv6_unsupported() -> String {
+    "AAAA records cannot be updated: the DICE MODIP command only takes IPV4".to_owned()
+}
+
+pub fn empty_response() -> String {
+    "empty response".to_owned()
+}
+
+pub fn invalid_status_line(line: &str) -> String {
+    format!("invalid status line: {:?}", sanitize(line))
+}
+
+pub fn response_error(e: impl Display, message: &str) -> String {
+    format!("{}: {}", e, sanitize(message))
+}
+
+pub fn send_timeout(command: &str, after: Duration) -> String {
+    format!(
+        "could not send {} to the server within {}s",
+        command,
+        seconds(after)
+    )
+}
+
+pub fn recv_timeout(command: &str, after: Duration) -> String {
+    format!(
+        "server did not respond to {} within {}s",
+        command,
+        seconds(after)
+    )
+}
+
+pub fn interrupted() -> String {
+    "interrupted".to_owned()
+}
+
+pub fn ack_mismatch(record: &str, sent: impl Display, acknowledged: impl Display) -> String {
+    format!(
+        "{}: the server acknowledged {} but {} was sent",
+        record, acknowledged, sent
+    )
+}
+
+pub fn unexpected_response(code: impl Display, message: &str) -> String {
+    format!("unexpected response code {}: {}", code, sanitize(message))
+}
+
+pub fn no_addresses(host: &str) -> String {
+    format!("{} has no addresses", host)
+}
+
+pub fn resolve_failed(host: &str, e: impl Display) -> String {
+    format!("could not resolve {}: {}", host, e)
+}
+
+pub fn bind_failed(e: impl Display) -> String {
+    format!("could not bind the local socket: {}", e)
+}
+
+pub fn ports_exhausted(low: u16, high: u16) -> String {
+    format!("every local port in {}-{} is in use", low, high)
+}
+
+pub fn no_family(host: &str, family: impl Display) -> String {
+    format!(
+        "cannot reach the registrar over IPv{}: {} has no IPv{} address",
+        family, host, family
+    )
+}
+
+pub fn ca_file_failed(path: &Path, e: impl Display) -> String {
+    format!(
+        "could not load CA certificates from {}: {}",
+        path.display(),
+        e
+    )
+}
+
+pub fn ca_reloaded(path: &Path, count: usize) -> String {
+    format!(
+        "reloaded {} from {}",
+        plural(count, "CA certificate", "CA certificates"),
+        path.display()
+    )
+}
+
+pub fn ca_reload_failed(e: impl Display) -> String {
+    format!("{}; keeping the previous trust configuration", e)
+}
+
+pub fn connect_failed(host: &str, port: u16, e: impl Display) -> String {
+    format!("could not connect to {}:{}: {}", host, port, e)
+}
+
+pub fn certificate_rejected(reason: &str, chain: &[Certificate]) -> String {
+    let mut out = format!("certificate verify failed: {}", reason);
+    match chain.len() {
+        0 => out.push_str("; no certificate was presented"),
+        _ => {
+            for (depth, cert) in chain.iter().enumerate() {
+                out.push_str(&format!("; presented [{}] {}", depth, cert));
+            }
+        }
+    }
+    out
+}
+
+pub fn peer_certificate(depth: usize, cert: &Certificate) -> String {
+    format!("TLS peer certificate [{}]: {}", depth, cert)
+}
+
+pub fn tls_failed(domain: &str, e: impl Display) -> String {
+    format!("TLS handshake with {} failed: {}", domain, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn humanize_keeps_the_two_largest_units() {
+        for (secs, text) in [
+            (0, "0s"),
+            (1, "1s"),
+            (59, "59s"),
+            (60, "1m"),
+            (450, "7m 30s"),
+            (3599, "59m 59s"),
+            (3600, "1h"),
+            (3661, "1h 1m"),
+            (7205, "2h"),
+            (86400, "1d"),
+            (90061, "1d 1h"),
+            (86401, "1d"),
+        ] {
+            assert_eq!(text, humanize(secs), "{}", secs);
+        }
+    }
+
+    #[test]
+    fn breaker_messages_say_when_logins_resume() {
+        let at = "2026-10-14T13:37:30+02:00";
+        assert_eq!(
+            "auth circuit breaker open after 3 consecutive login failures; retry in 7m 30s \
+             (at 2026-10-14T13:37:30+02:00), or fix the credentials and run with \
+             --reset-auth-backoff",
+            auth_breaker_open(3, 450, at)
+        );
+        assert!(auth_breaker_opened(1, 3600, at)
+            .contains("after 1 login failure; further logins are refused for 1h (until 2026"));
+        assert_eq!(
+            "auth circuit breaker: open after 2 login failures, retry in 1s (at 2026-10-14T13:37:30+02:00)",
+            status_breaker(2, 1, at)
+        );
+    }
+}
@@ -0,0 +1,82 @@
+pub fn text(value: &str) -> Result<&str, String> {
+    let value = value.trim();
+    if value.contains(char::is_whitespace) {
+        return Err(format!("{:?} contains whitespace", value));
+    }
+    Ok(value)
+}
+
+pub fn name(value: &str) -> Result<&str, String> {
+    let value = text(value)?;
+    Ok(value.strip_suffix('.').unwrap_or(value))
+}
+
+pub fn address(value: &str) -> Result<&str, String> {
+    let value = text(value)?;
+    Ok(value
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .unwrap_or(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Normalize = fn(&str) -> Result<&str, String>;
+
+    const TEXT: Normalize = text;
+    const NAME: Normalize = name;
+    const ADDRESS: Normalize = address;
+
+    const CASES: &[(Normalize, &str, Option<&str>)] = &[
+        (TEXT, "user", Some("user")),
+        (TEXT, "  user\t", Some("user")),
+        (TEXT, "\nuser\r\n", Some("user")),
+        (TEXT, "", Some("")),
+        (TEXT, "   ", Some("")),
+        (TEXT, "user.", Some("user.")),
+        (TEXT, "[user]", Some("[user]")),
+        (TEXT, "us er", None),
+        (TEXT, "us\ter", None),
+        (TEXT, " us\u{a0}er ", None),
+        (NAME, "www", Some("www")),
+        (NAME, " www ", Some("www")),
+        (NAME, "www.", Some("www")),
+        (NAME, " example.com. ", Some("example.com")),
+        (NAME, "example.com..", Some("example.com.")),
+        (NAME, ".", Some("")),
+        (NAME, ".www", Some(".www")),
+        (NAME, "@", Some("@")),
+        (NAME, "*", Some("*")),
+        (NAME, "[www]", Some("[www]")),
+        (NAME, "www .", None),
+        (NAME, "ex ample.com", None),
+        (ADDRESS, "203.0.113.7", Some("203.0.113.7")),
+        (ADDRESS, " 203.0.113.7", Some("203.0.113.7")),
+        (ADDRESS, "203.0.113.7\n", Some("203.0.113.7")),
+        (ADDRESS, "[2001:db8::1]", Some("2001:db8::1")),
+        (ADDRESS, " [2001:db8::1] ", Some("2001:db8::1")),
+        (ADDRESS, "2001:db8::1", Some("2001:db8::1")),
+        (ADDRESS, "[2001:db8::1", Some("[2001:db8::1")),
+        (ADDRESS, "2001:db8::1]", Some("2001:db8::1]")),
+        (ADDRESS, "[[2001:db8::1]]", Some("[2001:db8::1]")),
+        (ADDRESS, "[]", Some("")),
+        (ADDRESS, "203.0.113.7.", Some("203.0.113.7.")),
+        (ADDRESS, "[ 2001:db8::1 ]", None),
+        (ADDRESS, "203.0.113.7 203.0.113.8", None),
+    ];
+
+    #[test]
+    fn table() {
+        for (normalize, input, expected) in CASES {
+            assert_eq!(*expected, normalize(input).ok(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_value() {
+        let e = address(" 203.0.113.7 x ").unwrap_err();
+        assert!(e.contains("\"203.0.113.7 x\""), "{}", e);
+    }
+}
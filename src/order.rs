@@ -0,0 +1,305 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node<'a> {
+    pub name: String,
+    pub priority: i64,
+    pub depends_on: &'a [String],
+}
+
+impl Node<'_> {
+    pub fn depends(&self, name: &str) -> bool {
+        self.depends_on
+            .iter()
+            .any(|dep| dep.eq_ignore_ascii_case(name))
+    }
+}
+
+fn find(nodes: &[Node], name: &str) -> Option<usize> {
+    nodes
+        .iter()
+        .position(|node| node.name.eq_ignore_ascii_case(name))
+}
+
+pub fn unknown<'a>(nodes: &'a [Node]) -> Vec<(&'a str, &'a str)> {
+    nodes
+        .iter()
+        .flat_map(|node| {
+            node.depends_on
+                .iter()
+                .filter(|dep| find(nodes, dep).is_none())
+                .map(move |dep| (node.name.as_str(), dep.as_str()))
+        })
+        .collect()
+}
+
+pub fn find_cycle(nodes: &[Node]) -> Option<Vec<String>> {
+    fn visit(
+        nodes: &[Node],
+        at: usize,
+        marks: &mut [u8],
+        path: &mut Vec<usize>,
+    ) -> Option<Vec<String>> {
+        match marks[at] {
+            2 => return None,
+            1 => {
+                let start = path.iter().position(|&index| index == at).unwrap_or(0);
+                let mut cycle: Vec<String> = path[start..]
+                    .iter()
+                    .map(|&i| nodes[i].name.clone())
+                    .collect();
+                cycle.push(nodes[at].name.clone());
+                return Some(cycle);
+            }
+            _ => {}
+        }
+        marks[at] = 1;
+        path.push(at);
+        for dep in nodes[at].depends_on {
+            if let Some(next) = find(nodes, dep) {
+                if let Some(cycle) = visit(nodes, next, marks, path) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        marks[at] = 2;
+        None
+    }
+    let mut marks = vec![0; nodes.len()];
+    (0..nodes.len()).find_map(|at| visit(nodes, at, &mut marks, &mut Vec::new()))
+}
+
+pub fn sort(nodes: &[Node]) -> Vec<usize> {
+    let mut done = vec![false; nodes.len()];
+    let mut order = Vec::with_capacity(nodes.len());
+    while order.len() < nodes.len() {
+        let ready = (0..nodes.len())
+            .filter(|&at| !done[at])
+            .filter(|&at| {
+                nodes[at]
+                    .depends_on
+                    .iter()
+                    .all(|dep| find(nodes, dep).is_none_or(|index| done[index]))
+            })
+            .min_by_key(|&at| (nodes[at].priority, at));
+        match ready {
+            Some(at) => {
+                done[at] = true;
+                order.push(at);
+            }
+            None => {
+                order.extend((0..nodes.len()).filter(|&at| !done[at]));
+                break;
+            }
+        }
+    }
+    order
+}
+
+pub fn group<K: PartialEq>(nodes: &[(K, Node)]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(&K, Vec<usize>)> = Vec::new();
+    for (index, (key, node)) in nodes.iter().enumerate() {
+        let floor = nodes[..index]
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, earlier))| {
+                earlier.priority < node.priority || node.depends(&earlier.name)
+            })
+            .filter_map(|(at, _)| groups.iter().position(|(_, members)| members.contains(&at)))
+            .max()
+            .unwrap_or(0);
+        match groups
+            .iter_mut()
+            .skip(floor)
+            .find(|(group, _)| *group == key)
+        {
+            Some((_, members)) => members.push(index),
+            None => groups.push((key, vec![index])),
+        }
+    }
+    groups.into_iter().map(|(_, members)| members).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Graph {
+        names: Vec<String>,
+        priorities: Vec<i64>,
+        deps: Vec<Vec<String>>,
+    }
+
+    impl Graph {
+        fn new(spec: &[(&str, i64, &[&str])]) -> Graph {
+            Graph {
+                names: spec.iter().map(|(name, ..)| name.to_string()).collect(),
+                priorities: spec.iter().map(|(_, priority, _)| *priority).collect(),
+                deps: spec
+                    .iter()
+                    .map(|(.., deps)| deps.iter().map(|dep| dep.to_string()).collect())
+                    .collect(),
+            }
+        }
+
+        fn nodes(&self) -> Vec<Node<'_>> {
+            (0..self.names.len())
+                .map(|at| Node {
+                    name: self.names[at].clone(),
+                    priority: self.priorities[at],
+                    depends_on: &self.deps[at],
+                })
+                .collect()
+        }
+    }
+
+    fn sorted(spec: &[(&str, i64, &[&str])]) -> Vec<String> {
+        let graph = Graph::new(spec);
+        let nodes = graph.nodes();
+        sort(&nodes)
+            .into_iter()
+            .map(|at| nodes[at].name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn sort_orders_by_priority_then_position() {
+        assert_eq!(
+            vec!["b", "d", "a", "c"],
+            sorted(&[("a", 1, &[]), ("b", 0, &[]), ("c", 1, &[]), ("d", 0, &[])])
+        );
+    }
+
+    #[test]
+    fn sort_puts_dependencies_first() {
+        assert_eq!(
+            vec!["d", "c", "b", "a"],
+            sorted(&[
+                ("a", 0, &["B"]),
+                ("b", 5, &["c"]),
+                ("c", 9, &[]),
+                ("d", 1, &[])
+            ])
+        );
+    }
+
+    #[test]
+    fn sort_ignores_unknown_dependencies() {
+        assert_eq!(
+            vec!["a", "b"],
+            sorted(&[("a", 0, &["missing"]), ("b", 0, &[])])
+        );
+    }
+
+    #[test]
+    fn sort_appends_cycles_in_position_order() {
+        assert_eq!(
+            vec!["c", "a", "b"],
+            sorted(&[("a", 0, &["b"]), ("b", 0, &["a"]), ("c", 0, &[])])
+        );
+    }
+
+    #[test]
+    fn find_cycle_reports_the_loop() {
+        let graph = Graph::new(&[
+            ("a", 0, &["b"]),
+            ("b", 0, &["c"]),
+            ("c", 0, &["A"]),
+            ("d", 0, &["a"]),
+        ]);
+        assert_eq!(
+            Some(vec!["a", "b", "c", "a"]),
+            find_cycle(&graph.nodes())
+                .as_ref()
+                .map(|cycle| cycle.iter().map(String::as_str).collect::<Vec<_>>())
+        );
+        let graph = Graph::new(&[("a", 0, &[]), ("self", 0, &["self"])]);
+        assert_eq!(
+            Some(vec!["self".to_owned(), "self".to_owned()]),
+            find_cycle(&graph.nodes())
+        );
+    }
+
+    #[test]
+    fn unknown_lists_missing_dependencies() {
+        let graph = Graph::new(&[("a", 0, &["b", "gone"]), ("b", 0, &["A"])]);
+        assert_eq!(vec![("a", "gone")], unknown(&graph.nodes()));
+    }
+
+    fn random_graph(seed: &mut u64, len: usize) -> Graph {
+        let mut next = |bound: u64| {
+            *seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (*seed >> 33) % bound
+        };
+        let names: Vec<String> = (0..len).map(|at| format!("n{}", at)).collect();
+        let mut graph = Graph {
+            priorities: (0..len).map(|_| next(4) as i64).collect(),
+            deps: vec![Vec::new(); len],
+            names,
+        };
+        for at in 1..len {
+            for dep in 0..at {
+                if 0 == next(3) {
+                    graph.deps[at].push(graph.names[dep].clone());
+                }
+            }
+        }
+        let mut order: Vec<usize> = (0..len).collect();
+        for at in (1..len).rev() {
+            order.swap(at, next(at as u64 + 1) as usize);
+        }
+        Graph {
+            names: order.iter().map(|&at| graph.names[at].clone()).collect(),
+            priorities: order.iter().map(|&at| graph.priorities[at]).collect(),
+            deps: order.iter().map(|&at| graph.deps[at].clone()).collect(),
+        }
+    }
+
+    #[test]
+    fn random_dags_sort_topologically() {
+        let mut seed = 7;
+        for len in (1..12).cycle().take(200) {
+            let graph = random_graph(&mut seed, len);
+            let nodes = graph.nodes();
+            assert_eq!(None, find_cycle(&nodes));
+            let order = sort(&nodes);
+            let mut seen = order.clone();
+            seen.sort_unstable();
+            assert_eq!((0..len).collect::<Vec<_>>(), seen);
+            let rank = |name: &str| order.iter().position(|&at| nodes[at].name == name).unwrap();
+            for node in &nodes {
+                for dep in node.depends_on {
+                    assert!(rank(dep) < rank(&node.name), "{} before {}", dep, node.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_back_edges_are_found() {
+        let mut seed = 11;
+        for len in (2..12).cycle().take(200) {
+            let mut graph = random_graph(&mut seed, len);
+            let first = graph.deps.iter().position(|deps| !deps.is_empty());
+            let at = match first {
+                Some(at) => at,
+                None => continue,
+            };
+            let target = graph
+                .names
+                .iter()
+                .position(|name| *name == graph.deps[at][0]);
+            let back = graph.names[at].clone();
+            graph.deps[target.unwrap()].push(back);
+            let nodes = graph.nodes();
+            let cycle = find_cycle(&nodes).expect("cycle");
+            assert_eq!(cycle.first(), cycle.last());
+            for pair in cycle.windows(2) {
+                let from = find(&nodes, &pair[0]).unwrap();
+                assert!(nodes[from].depends(&pair[1]), "{:?}", cycle);
+            }
+            assert_eq!(len, sort(&nodes).len());
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub static APP: &str = "ddns_client";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub state: PathBuf,
+    pub status: PathBuf,
+    pub history: PathBuf,
+    pub lock: PathBuf,
+}
+
+fn non_empty(value: Option<OsString>) -> Option<PathBuf> {
+    value.filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+fn systemd_dir(value: Option<OsString>) -> Option<PathBuf> {
+    let value = value?;
+    let first = value.to_str()?.split(':').next()?;
+    non_empty(Some(first.into()))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn base_dirs<F>(var: &F) -> Option<(PathBuf, PathBuf)>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let home = non_empty(var("HOME"));
+    let config =
+        non_empty(var("XDG_CONFIG_HOME")).or_else(|| Some(home.clone()?.join(".config")))?;
+    let state =
+        non_empty(var("XDG_STATE_HOME")).or_else(|| Some(home?.join(".local").join("state")))?;
+    Some((config, state))
+}
+
+#[cfg(target_os = "macos")]
+fn base_dirs<F>(var: &F) -> Option<(PathBuf, PathBuf)>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let support = non_empty(var("HOME")).map(|home| home.join("Library/Application Support"));
+    let config = non_empty(var("XDG_CONFIG_HOME")).or_else(|| support.clone())?;
+    let state = non_empty(var("XDG_STATE_HOME")).or(support)?;
+    Some((config, state))
+}
+
+#[cfg(windows)]
+fn base_dirs<F>(var: &F) -> Option<(PathBuf, PathBuf)>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let config = non_empty(var("XDG_CONFIG_HOME")).or_else(|| non_empty(var("APPDATA")))?;
+    let state = non_empty(var("XDG_STATE_HOME")).or_else(|| non_empty(var("LOCALAPPDATA")))?;
+    Some((config, state))
+}
+
+impl Paths {
+    pub fn from_env_with<F>(var: F) -> Option<Paths>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let managed = systemd_dir(var("STATE_DIRECTORY"));
+        let runtime = systemd_dir(var("RUNTIME_DIRECTORY"));
+        let (config, state) = match (base_dirs(&var), managed.clone()) {
+            (Some((config, state)), managed) => {
+                (config.join(APP), managed.unwrap_or_else(|| state.join(APP)))
+            }
+            (None, Some(managed)) => (managed.clone(), managed),
+            (None, None) => return None,
+        };
+        Some(Paths {
+            config: config.join("config.toml"),
+            state: state.join("state.json"),
+            status: state.join("status.json"),
+            history: state.join("history.jsonl"),
+            lock: runtime.unwrap_or(state).join("lock"),
+        })
+    }
+
+    pub fn from_env() -> Option<Paths> {
+        Paths::from_env_with(|name| env::var_os(name))
+    }
+}
+
+impl fmt::Display for Paths {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "config: {}", self.config.display())?;
+        writeln!(f, "state: {}", self.state.display())?;
+        writeln!(f, "status: {}", self.status.display())?;
+        writeln!(f, "history: {}", self.history.display())?;
+        write!(f, "lock: {}", self.lock.display())
+    }
+}
+
+pub fn default_paths() -> Option<&'static Paths> {
+    static PATHS: OnceLock<Option<Paths>> = OnceLock::new();
+    PATHS.get_or_init(Paths::from_env).as_ref()
+}
+
+pub fn create_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    if !dir.exists() {
+        create_dir(dir)?;
+    }
+    let probe = dir.join(format!(".{}.{}.probe", APP, std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+pub fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.exists() => create_dir(parent),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(vars: &[(&str, &str)]) -> Option<Paths> {
+        Paths::from_env_with(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| OsString::from(value))
+        })
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn home_fallbacks() {
+        let paths = paths(&[("HOME", "/home/u")]).unwrap();
+        assert_eq!(
+            Path::new("/home/u/.config/ddns_client/config.toml"),
+            paths.config
+        );
+        assert_eq!(
+            Path::new("/home/u/.local/state/ddns_client/state.json"),
+            paths.state
+        );
+        assert_eq!(
+            Path::new("/home/u/.local/state/ddns_client/history.jsonl"),
+            paths.history
+        );
+        assert_eq!(
+            Path::new("/home/u/.local/state/ddns_client/lock"),
+            paths.lock
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn xdg_variables_override_home() {
+        let vars = [
+            ("HOME", "/home/u"),
+            ("XDG_CONFIG_HOME", "/xdg/config"),
+            ("XDG_STATE_HOME", "/xdg/state"),
+        ];
+        let resolved = paths(&vars).unwrap();
+        assert_eq!(
+            Path::new("/xdg/config/ddns_client/config.toml"),
+            resolved.config
+        );
+        assert_eq!(
+            Path::new("/xdg/state/ddns_client/status.json"),
+            resolved.status
+        );
+        let only_config = [("XDG_CONFIG_HOME", "/xdg/config")];
+        assert_eq!(None, paths(&only_config));
+        let empty = [("HOME", "/home/u"), ("XDG_CONFIG_HOME", "")];
+        assert_eq!(
+            Path::new("/home/u/.config/ddns_client/config.toml"),
+            paths(&empty).unwrap().config
+        );
+    }
+
+    #[test]
+    fn systemd_directories_win_for_state_and_lock() {
+        let vars = [
+            ("STATE_DIRECTORY", "/var/lib/ddns_client:/var/lib/other"),
+            ("RUNTIME_DIRECTORY", "/run/ddns_client"),
+        ];
+        let managed = paths(&vars).unwrap();
+        assert_eq!(
+            Path::new("/var/lib/ddns_client/config.toml"),
+            managed.config
+        );
+        assert_eq!(Path::new("/var/lib/ddns_client/state.json"), managed.state);
+        assert_eq!(Path::new("/run/ddns_client/lock"), managed.lock);
+        assert_eq!(None, paths(&[("STATE_DIRECTORY", "")]));
+        assert_eq!(None, paths(&[]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directories_are_created_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = env::temp_dir().join(format!("ddns_client-paths-{}", std::process::id()));
+        let file = root.join("a/b/state.json");
+        create_parent(&file).unwrap();
+        for dir in &[root.join("a"), root.join("a/b")] {
+            let mode = fs::metadata(dir).unwrap().permissions().mode();
+            assert_eq!(0o700, mode & 0o777, "{}", dir.display());
+        }
+        create_parent(&file).unwrap();
+        check_writable(&root.join("c")).unwrap();
+        assert_eq!(0, fs::read_dir(root.join("c")).unwrap().count());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn systemd_directories_beat_xdg_but_keep_the_config() {
+        let vars = [
+            ("HOME", "/home/u"),
+            ("XDG_STATE_HOME", "/xdg/state"),
+            ("STATE_DIRECTORY", "/var/lib/ddns_client"),
+        ];
+        let managed = paths(&vars).unwrap();
+        assert_eq!(
+            Path::new("/home/u/.config/ddns_client/config.toml"),
+            managed.config
+        );
+        assert_eq!(
+            Path::new("/var/lib/ddns_client/history.jsonl"),
+            managed.history
+        );
+        assert_eq!(Path::new("/var/lib/ddns_client/lock"), managed.lock);
+        let runtime = [
+            ("HOME", "/home/u"),
+            ("RUNTIME_DIRECTORY", "/run/ddns_client"),
+        ];
+        let runtime = paths(&runtime).unwrap();
+        assert_eq!(
+            Path::new("/home/u/.local/state/ddns_client/state.json"),
+            runtime.state
+        );
+        assert_eq!(Path::new("/run/ddns_client/lock"), runtime.lock);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unwritable_state_directories_are_reported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = env::temp_dir().join(format!("ddns_client-ro-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let file = root.join("file");
+        fs::write(&file, "").unwrap();
+        assert!(check_writable(&file).is_err());
+        assert!(check_writable(&file.join("state")).is_err());
+
+        let read_only = root.join("read-only");
+        fs::create_dir(&read_only).unwrap();
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o500)).unwrap();
+        if 0 != unsafe { libc::geteuid() } {
+            let e = check_writable(&read_only).unwrap_err();
+            assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+            assert!(check_writable(&read_only.join("state")).is_err());
+        }
+        assert_eq!(0, fs::read_dir(&read_only).unwrap().count());
+        fs::set_permissions(&read_only, fs::Permissions::from_mode(0o700)).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub static DEFAULT_IDLE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub host: String,
+    pub port: u16,
+    pub config: u64,
+}
+
+impl PoolKey {
+    pub fn new(host: &str, port: u16, config: &impl Hash) -> PoolKey {
+        let mut hasher = DefaultHasher::new();
+        config.hash(&mut hasher);
+        PoolKey {
+            host: host.to_owned(),
+            port,
+            config: hasher.finish(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pool<T> {
+    size: usize,
+    idle: Duration,
+    conns: Mutex<HashMap<PoolKey, Vec<(T, Instant)>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T> Pool<T> {
+    pub fn new(size: usize, idle: Duration) -> Pool<T> {
+        Pool {
+            size,
+            idle,
+            conns: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        0 < self.size
+    }
+
+    pub fn take<V>(&self, key: &PoolKey, mut validate: V) -> Option<T>
+    where
+        V: FnMut(&mut T) -> bool,
+    {
+        if !self.is_enabled() {
+            return None;
+        }
+        let mut conns = self.conns.lock().unwrap();
+        let idle = conns.entry(key.clone()).or_default();
+        let mut found = None;
+        while let Some((mut conn, since)) = idle.pop() {
+            if since.elapsed() <= self.idle && validate(&mut conn) {
+                found = Some(conn);
+                break;
+            }
+        }
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn put(&self, key: &PoolKey, conn: T) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut conns = self.conns.lock().unwrap();
+        let idle = conns.entry(key.clone()).or_default();
+        idle.retain(|(_, since)| since.elapsed() <= self.idle);
+        if self.size <= idle.len() {
+            idle.remove(0);
+        }
+        idle.push((conn, Instant::now()));
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+pub fn alive(socket: &TcpStream) -> bool {
+    if socket.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0u8; 1];
+    let idle = matches!(socket.peek(&mut byte), Err(e) if io::ErrorKind::WouldBlock == e.kind());
+    socket.set_nonblocking(false).is_ok() && idle
+}
+
+pub struct Pooled<T> {
+    stream: Option<T>,
+    pool: Arc<Pool<T>>,
+    key: PoolKey,
+    reused: bool,
+    reusable: bool,
+}
+
+impl<T> Pooled<T> {
+    pub fn new(stream: T, pool: Arc<Pool<T>>, key: PoolKey, reused: bool) -> Pooled<T> {
+        Pooled {
+            stream: Some(stream),
+            pool,
+            key,
+            reused,
+            reusable: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.stream.as_ref().unwrap()
+    }
+
+    pub fn reused(&self) -> bool {
+        self.reused
+    }
+
+    pub fn set_reusable(&mut self, reusable: bool) {
+        self.reusable = reusable;
+    }
+}
+
+impl<T: Read> Read for Pooled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.as_mut().unwrap().read(buf)
+    }
+}
+
+impl<T: Write> Write for Pooled<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.as_mut().unwrap().flush()
+    }
+}
+
+impl<T> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            if self.reusable {
+                self.pool.put(&self.key, stream);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::{Client, Command};
+    use std::io::{BufRead, BufReader};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn serve(close: bool) -> (SocketAddr, Arc<AtomicUsize>, mpsc::Receiver<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let (closed, rx) = mpsc::channel();
+        let count = accepted.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                count.fetch_add(1, Ordering::SeqCst);
+                let closed = closed.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    while 0 < reader.read_line(&mut line).unwrap_or(0) {
+                        if ".\n" == line {
+                            let _ = stream.write_all(b"000 COMMAND SUCCESSFUL\n.\n");
+                            if close {
+                                break;
+                            }
+                        }
+                        line.clear();
+                    }
+                    drop((stream, reader));
+                    let _ = closed.send(());
+                });
+            }
+        });
+        (addr, accepted, rx)
+    }
+
+    fn session(pool: &Arc<Pool<TcpStream>>, key: &PoolKey, addr: SocketAddr) -> bool {
+        let pooled = pool.take(key, |stream| alive(stream));
+        let reused = pooled.is_some();
+        let stream = pooled.unwrap_or_else(|| TcpStream::connect(addr).unwrap());
+        let mut client = Client::new(Pooled::new(stream, pool.clone(), key.clone(), reused));
+        let response = client.call(&["NOOP"]).unwrap();
+        assert_eq!("COMMAND SUCCESSFUL", response.message);
+        client.get_mut().set_reusable(true);
+        client.get_ref().reused()
+    }
+
+    #[test]
+    fn sessions_reuse_a_pooled_connection() {
+        let (addr, accepted, _) = serve(false);
+        let pool = Arc::new(Pool::new(2, DEFAULT_IDLE));
+        let key = PoolKey::new("127.0.0.1", addr.port(), &"tls");
+        assert!(!session(&pool, &key, addr));
+        assert!(session(&pool, &key, addr));
+        assert!(session(&pool, &key, addr));
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+        assert_eq!((2, 1), (pool.hits(), pool.misses()));
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let (pool, key) = (pool.clone(), key.clone());
+                thread::spawn(move || session(&pool, &key, addr))
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(accepted.load(Ordering::SeqCst) <= 4);
+        assert_eq!(7, pool.hits() + pool.misses());
+        assert_eq!(accepted.load(Ordering::SeqCst) as u64, pool.misses());
+    }
+
+    #[test]
+    fn closed_connections_are_not_reused() {
+        let (addr, accepted, closed) = serve(true);
+        let pool = Arc::new(Pool::new(2, DEFAULT_IDLE));
+        let key = PoolKey::new("127.0.0.1", addr.port(), &"tls");
+        assert!(!session(&pool, &key, addr));
+        closed.recv().unwrap();
+        assert!(!session(&pool, &key, addr));
+        assert_eq!(2, accepted.load(Ordering::SeqCst));
+        assert_eq!((0, 2), (pool.hits(), pool.misses()));
+    }
+
+    #[test]
+    fn unfinished_sessions_are_not_returned() {
+        let pool = Arc::new(Pool::new(2, DEFAULT_IDLE));
+        let key = PoolKey::new("example.com", 443, &"tls");
+        drop(Pooled::new(1, pool.clone(), key.clone(), false));
+        assert_eq!(None, pool.take(&key, |_| true));
+        let mut pooled = Pooled::new(2, pool.clone(), key.clone(), false);
+        pooled.set_reusable(true);
+        drop(pooled);
+        assert_eq!(Some(2), pool.take(&key, |_| true));
+    }
+
+    #[test]
+    fn pool_keeps_the_newest_connections_per_key() {
+        let pool = Pool::new(2, DEFAULT_IDLE);
+        let key = PoolKey::new("example.com", 443, &"tls");
+        let other = PoolKey::new("example.com", 443, &"tls1.3");
+        assert_ne!(key, other);
+        assert_eq!(key, PoolKey::new("example.com", 443, &"tls"));
+        for conn in 1..=3 {
+            pool.put(&key, conn);
+        }
+        assert_eq!(None, pool.take(&other, |_| true));
+        assert_eq!(Some(3), pool.take(&key, |_| true));
+        assert_eq!(Some(2), pool.take(&key, |&mut conn| 2 == conn));
+        assert_eq!(None, pool.take(&key, |_| true));
+        assert_eq!((2, 2), (pool.hits(), pool.misses()));
+
+        pool.put(&key, 4);
+        pool.put(&key, 5);
+        assert_eq!(Some(4), pool.take(&key, |&mut conn| 4 == conn));
+        assert_eq!(None, pool.take(&key, |_| true));
+    }
+
+    #[test]
+    fn idle_connections_expire() {
+        let pool = Pool::new(2, Duration::from_millis(20));
+        let key = PoolKey::new("example.com", 443, &"tls");
+        pool.put(&key, 1);
+        thread::sleep(Duration::from_millis(50));
+        pool.put(&key, 2);
+        pool.put(&key, 3);
+        assert_eq!(Some(3), pool.take(&key, |_| true));
+        assert_eq!(Some(2), pool.take(&key, |_| true));
+        assert_eq!(None, pool.take(&key, |_| true));
+        pool.put(&key, 4);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(None, pool.take(&key, |_| true));
+    }
+
+    #[test]
+    fn disabled_pool_keeps_nothing() {
+        let pool = Pool::new(0, DEFAULT_IDLE);
+        let key = PoolKey::new("example.com", 443, &"tls");
+        assert!(!pool.is_enabled());
+        pool.put(&key, 1);
+        assert_eq!(None, pool.take(&key, |_| true));
+        assert_eq!((0, 0), (pool.hits(), pool.misses()));
+    }
+}
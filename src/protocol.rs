@@ -0,0 +1,463 @@
+use std::io;
+use std::mem;
+
+use crate::dice::{
+    check_command, ClientError, Direction, InvalidField, Pacing, ParseError, Response,
+    MAX_LINE_LEN, STATUS_DIGITS,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    GreetingReceived(Response),
+    ResponseReceived(Response),
+    NeedMoreData,
+}
+
+#[derive(Debug)]
+pub struct ProtocolMachine {
+    inbound: Vec<u8>,
+    lines: Vec<String>,
+    overflow: bool,
+    finished: bool,
+    commands: u64,
+    max_line: usize,
+    redact: bool,
+    transcript: Option<Vec<(Direction, String)>>,
+}
+
+impl Default for ProtocolMachine {
+    fn default() -> ProtocolMachine {
+        ProtocolMachine {
+            inbound: Vec::new(),
+            lines: Vec::new(),
+            overflow: false,
+            finished: false,
+            commands: 0,
+            max_line: MAX_LINE_LEN,
+            redact: true,
+            transcript: None,
+        }
+    }
+}
+
+fn parse_code(code: &str) -> Option<u32> {
+    if STATUS_DIGITS != code.len() || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    code.parse().ok()
+}
+
+pub fn parse_lines<S: AsRef<str>>(lines: &[S]) -> Result<Response, ParseError> {
+    let mut lines = lines
+        .iter()
+        .map(|line| line.as_ref().trim_end_matches('\r'))
+        .skip_while(|line| line.trim().is_empty());
+    let status = match lines.next() {
+        Some(line) if "." != line => line.trim_end(),
+        _ => return Err(ParseError::Empty),
+    };
+    let (code, message) = match status.find(' ') {
+        Some(index) => (&status[..index], &status[index + 1..]),
+        None => (status, ""),
+    };
+    let code = parse_code(code).ok_or_else(|| ParseError::InvalidCode(status.to_owned()))?;
+    let body = lines
+        .take_while(|line| "." != *line)
+        .map(|line| line.to_owned())
+        .collect();
+    Ok(Response {
+        code,
+        message: message.to_owned(),
+        body,
+    })
+}
+
+impl ProtocolMachine {
+    pub fn new() -> ProtocolMachine {
+        ProtocolMachine::default()
+    }
+
+    pub fn set_max_line_len(&mut self, max_len: usize) {
+        self.max_line = max_len;
+    }
+
+    pub fn set_redact(&mut self, redact: bool) {
+        self.redact = redact;
+    }
+
+    pub fn set_transcript(&mut self, enabled: bool) {
+        self.transcript = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn take_transcript(&mut self) -> Vec<(Direction, String)> {
+        self.transcript.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    pub fn commands(&self) -> u64 {
+        self.commands
+    }
+
+    fn log(&mut self, dir: Direction, line: &str) {
+        let redact = self.redact;
+        if let Some(transcript) = self.transcript.as_mut() {
+            let line = if redact {
+                crate::redact(line).into_owned()
+            } else {
+                line.to_owned()
+            };
+            transcript.push((dir, line));
+        }
+    }
+
+    pub fn send(&mut self, cmd: &[&str], pacing: Pacing) -> Result<Vec<Vec<u8>>, InvalidField> {
+        check_command(cmd)?;
+        let total = cmd.len() + 1;
+        let mut chunks = vec![Vec::new()];
+        for (index, line) in cmd.iter().chain(&["."]).enumerate() {
+            let chunk = chunks.last_mut().unwrap();
+            chunk.extend_from_slice(line.as_bytes());
+            chunk.push(b'\n');
+            if pacing.pause_after(index + 1, total) {
+                chunks.push(Vec::new());
+            }
+        }
+        for line in cmd.iter().chain(&["."]) {
+            self.log(Direction::Send, line);
+        }
+        self.commands += 1;
+        Ok(chunks)
+    }
+
+    pub fn receive(&mut self, data: &[u8]) {
+        self.inbound.extend_from_slice(data);
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        match self.inbound.iter().position(|&b| b'\n' == b) {
+            Some(at) => {
+                let mut line: Vec<u8> = self.inbound.drain(..=at).collect();
+                line.pop();
+                if Some(&b'\r') == line.last() {
+                    line.pop();
+                }
+                Some(line)
+            }
+            None if self.finished && !self.inbound.is_empty() => Some(mem::take(&mut self.inbound)),
+            None => None,
+        }
+    }
+
+    pub fn poll(&mut self) -> Result<Event, ClientError> {
+        loop {
+            let pending = self.inbound.len().saturating_sub(1);
+            let line = match self.next_line() {
+                Some(line) => line,
+                None => {
+                    if self.max_line < pending {
+                        self.overflow = true;
+                        self.inbound.clear();
+                    }
+                    return Ok(Event::NeedMoreData);
+                }
+            };
+            if mem::take(&mut self.overflow) || self.max_line < line.len() {
+                self.lines.clear();
+                return Err(ClientError::LineTooLong(self.max_line));
+            }
+            let line = String::from_utf8(line).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                )
+            })?;
+            if self.lines.is_empty() && line.trim().is_empty() {
+                continue;
+            }
+            self.log(Direction::Recv, &line);
+            if "." != line {
+                self.lines.push(line);
+                continue;
+            }
+            let response = parse_lines(&mem::take(&mut self.lines))?;
+            return Ok(match self.commands {
+                0 => Event::GreetingReceived(response),
+                _ => Event::ResponseReceived(response),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_codes() {
+        assert_eq!(Some(0), parse_code("000"));
+        assert_eq!(Some(2), parse_code("002"));
+        assert_eq!(Some(999), parse_code("999"));
+    }
+
+    #[test]
+    fn non_canonical_codes() {
+        let cases = [
+            "",
+            "0",
+            "00",
+            "0000",
+            "0007",
+            "+00",
+            "+0",
+            "-03",
+            "-3",
+            " 00",
+            "00 ",
+            "0x0",
+            "1e2",
+            "4294967296",
+            "99999999999999999999",
+            "٠٠٠",
+            "００２",
+            "0٠0",
+            "½00",
+        ];
+        for code in &cases {
+            assert_eq!(None, parse_code(code), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn status_line() {
+        let response = parse_lines(&["000 COMMAND SUCCESSFUL", "IPV4:203.0.113.7", "."]).unwrap();
+        assert_eq!(0, response.code);
+        assert_eq!("COMMAND SUCCESSFUL", response.message);
+        assert_eq!(vec!["IPV4:203.0.113.7".to_owned()], response.body);
+        let response = parse_lines(&["", "\r", "006\r", "."]).unwrap();
+        assert_eq!(6, response.code);
+        assert_eq!("", response.message);
+    }
+
+    #[test]
+    fn invalid_status_lines() {
+        for status in &["0007 anything", "+01 x", "-03 x", "1 x", "٠٠٠ x", "OK"] {
+            match parse_lines(&[*status, "."]) {
+                Err(ParseError::InvalidCode(line)) => assert_eq!(*status, line),
+                other => panic!("{:?}: {:?}", status, other),
+            }
+        }
+        assert!(matches!(parse_lines::<&str>(&[]), Err(ParseError::Empty)));
+        assert!(matches!(parse_lines(&["", "."]), Err(ParseError::Empty)));
+    }
+
+    fn poll_all(machine: &mut ProtocolMachine) -> Vec<Event> {
+        let mut events = Vec::new();
+        loop {
+            match machine.poll().unwrap() {
+                Event::NeedMoreData => return events,
+                event => events.push(event),
+            }
+        }
+    }
+
+    fn response(code: u32, message: &str, body: &[&str]) -> Response {
+        Response {
+            code,
+            message: message.to_owned(),
+            body: body.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn greeting_then_responses() {
+        let mut machine = ProtocolMachine::new();
+        machine.receive(b"000 READY\n.\n");
+        assert_eq!(
+            vec![Event::GreetingReceived(response(0, "READY", &[]))],
+            poll_all(&mut machine)
+        );
+        machine.send(&["LOGIN"], Pacing::default()).unwrap();
+        machine.receive(b"002 LOGIN ERROR\n.\n000 OK\n.\n");
+        assert_eq!(
+            vec![
+                Event::ResponseReceived(response(2, "LOGIN ERROR", &[])),
+                Event::ResponseReceived(response(0, "OK", &[])),
+            ],
+            poll_all(&mut machine)
+        );
+        assert_eq!(1, machine.commands());
+    }
+
+    #[test]
+    fn partial_input_needs_more_data() {
+        let mut machine = ProtocolMachine::new();
+        for byte in b"000 READY\nBODY\n".iter() {
+            machine.receive(&[*byte]);
+            assert_eq!(Event::NeedMoreData, machine.poll().unwrap());
+        }
+        machine.receive(b".");
+        assert_eq!(Event::NeedMoreData, machine.poll().unwrap());
+        machine.receive(b"\n");
+        assert_eq!(
+            Event::GreetingReceived(response(0, "READY", &["BODY"])),
+            machine.poll().unwrap()
+        );
+    }
+
+    #[test]
+    fn crlf_is_stripped() {
+        let mut machine = ProtocolMachine::new();
+        machine.receive(b"000 READY\r\nIPV4:203.0.113.7\r\n.\r\n");
+        assert_eq!(
+            vec![Event::GreetingReceived(response(
+                0,
+                "READY",
+                &["IPV4:203.0.113.7"]
+            ))],
+            poll_all(&mut machine)
+        );
+    }
+
+    #[test]
+    fn leading_blank_lines_are_skipped() {
+        let mut machine = ProtocolMachine::new();
+        machine.set_transcript(true);
+        machine.receive(b"\n\r\n  \n000 READY\n\n.\n");
+        assert_eq!(
+            vec![Event::GreetingReceived(response(0, "READY", &[""]))],
+            poll_all(&mut machine)
+        );
+        let lines: Vec<String> = machine
+            .take_transcript()
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(vec!["000 READY", "", "."], lines);
+    }
+
+    #[test]
+    fn unterminated_line_is_flushed_on_finish() {
+        let mut machine = ProtocolMachine::new();
+        machine.receive(b"000 READY\n.");
+        assert_eq!(Event::NeedMoreData, machine.poll().unwrap());
+        machine.finish();
+        assert!(matches!(
+            machine.poll().unwrap(),
+            Event::GreetingReceived(_)
+        ));
+        assert_eq!(Event::NeedMoreData, machine.poll().unwrap());
+    }
+
+    #[test]
+    fn long_line_is_rejected() {
+        let mut machine = ProtocolMachine::new();
+        machine.set_max_line_len(8);
+        machine.receive(b"000 TOO LONG\n");
+        assert!(matches!(machine.poll(), Err(ClientError::LineTooLong(8))));
+        machine.receive(b"000 OK\n.\n");
+        assert!(matches!(
+            machine.poll().unwrap(),
+            Event::GreetingReceived(_)
+        ));
+    }
+
+    #[test]
+    fn overflow_is_discarded() {
+        let mut machine = ProtocolMachine::new();
+        machine.set_max_line_len(8);
+        machine.receive(b"000 REA");
+        assert_eq!(Event::NeedMoreData, machine.poll().unwrap());
+        machine.receive(b"DY AND MORE");
+        assert_eq!(Event::NeedMoreData, machine.poll().unwrap());
+        machine.receive(b" STILL GOING\n000 OK\n.\n");
+        assert!(matches!(machine.poll(), Err(ClientError::LineTooLong(8))));
+        assert_eq!(
+            vec![Event::GreetingReceived(response(0, "OK", &[]))],
+            poll_all(&mut machine)
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut machine = ProtocolMachine::new();
+        machine.receive(b"000 \xff\n.\n");
+        assert!(matches!(machine.poll(), Err(ClientError::Io(_))));
+    }
+
+    #[test]
+    fn send_frames_commands() {
+        let mut machine = ProtocolMachine::new();
+        let chunks = machine
+            .send(&["MODIP", "HOSTNAME:www"], Pacing::default())
+            .unwrap();
+        assert_eq!(vec![b"MODIP\nHOSTNAME:www\n.\n".to_vec()], chunks);
+        let pacing = Pacing {
+            lines: 1,
+            delay: Default::default(),
+        };
+        let chunks = machine.send(&["MODIP", "HOSTNAME:www"], pacing).unwrap();
+        assert_eq!(
+            vec![
+                b"MODIP\n".to_vec(),
+                b"HOSTNAME:www\n".to_vec(),
+                b".\n".to_vec()
+            ],
+            chunks
+        );
+        assert_eq!(2, machine.commands());
+    }
+
+    #[test]
+    fn send_rejects_framing_injection() {
+        let mut machine = ProtocolMachine::new();
+        let cases: [&[&str]; 4] = [
+            &["MODIP", "."],
+            &["MODIP", "HOSTNAME:www\n.\nLOGOUT"],
+            &["MODIP", "HOSTNAME:www\r"],
+            &["MODIP\nLOGOUT"],
+        ];
+        for cmd in &cases {
+            let e = machine.send(cmd, Pacing::default()).unwrap_err();
+            assert_eq!(cmd[0].to_owned(), e.command, "{:?}", cmd);
+        }
+        assert_eq!(0, machine.commands());
+        machine
+            .send(&["MODIP", ".HOSTNAME:www", ".."], Pacing::default())
+            .unwrap();
+        assert_eq!(1, machine.commands());
+    }
+
+    #[test]
+    fn transcript_is_redacted() {
+        let mut machine = ProtocolMachine::new();
+        machine.set_transcript(true);
+        machine
+            .send(
+                &["LOGIN", "USERID:user", "PASSWORD:secret"],
+                Pacing::default(),
+            )
+            .unwrap();
+        let sent: Vec<String> = machine
+            .take_transcript()
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(4, sent.len());
+        assert!(
+            !sent.iter().any(|line| line.contains("secret")),
+            "{:?}",
+            sent
+        );
+        machine.set_redact(false);
+        machine
+            .send(&["LOGIN", "PASSWORD:secret"], Pacing::default())
+            .unwrap();
+        assert!(machine
+            .take_transcript()
+            .iter()
+            .any(|(_, line)| line.contains("secret")));
+    }
+}
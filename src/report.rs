@@ -0,0 +1,498 @@
+use std::fmt;
+use std::io;
+
+use serde_json::{json, Value};
+
+use crate::config::{ConfigError, Diagnostic};
+use crate::detect::DetectError;
+use crate::dice::{
+    self, ClientError, NotFoundScope, OpenError, ParseError, Response, ResponseError,
+};
+#[cfg(feature = "dns")]
+use crate::dns::DnsError;
+use crate::dsn::DsnError;
+use crate::input::InputError;
+use crate::secret::SecretError;
+use crate::signal;
+use crate::state::{Outcome, StateError};
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_AUTH_BREAKER: i32 = 4;
+pub const EXIT_SEND_TIMEOUT: i32 = 5;
+pub const EXIT_RECV_TIMEOUT: i32 = 6;
+
+pub const EXIT_CODES: &[(&str, i32)] = &[
+    ("success", 0),
+    ("failure", EXIT_FAILURE),
+    ("config", EXIT_CONFIG),
+    ("auth_breaker", EXIT_AUTH_BREAKER),
+    ("send_timeout", EXIT_SEND_TIMEOUT),
+    ("recv_timeout", EXIT_RECV_TIMEOUT),
+    ("interrupted", signal::EXIT_INTERRUPTED),
+];
+
+pub const OUTPUT_FORMATS: [&str; 2] = ["text", "json"];
+pub const CAPABILITIES_VERSION: u32 = 1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExitOptions {
+    pub unchanged: i32,
+}
+
+pub fn parse_exit_code(value: &str) -> Result<i32, String> {
+    let code: i32 = value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|code| (0..=255).contains(code))
+        .ok_or_else(|| "expected an exit code between 0 and 255".to_owned())?;
+    match EXIT_CODES
+        .iter()
+        .find(|(_, reserved)| 0 != code && *reserved == code)
+    {
+        Some((name, _)) => Err(format!("exit code {} already means {}", code, name)),
+        None => Ok(code),
+    }
+}
+
+pub fn compute_exit_code(outcomes: &[Outcome], failed: usize, options: &ExitOptions) -> i32 {
+    if 0 < failed {
+        EXIT_FAILURE
+    } else if outcomes
+        .iter()
+        .all(|outcome| Outcome::Unchanged == *outcome)
+    {
+        options.unchanged
+    } else {
+        0
+    }
+}
+
+pub fn exit_code(e: &ClientError) -> i32 {
+    match e {
+        ClientError::Cancelled => signal::EXIT_INTERRUPTED,
+        ClientError::SendTimeout(..) => EXIT_SEND_TIMEOUT,
+        ClientError::RecvTimeout(..) => EXIT_RECV_TIMEOUT,
+        _ => EXIT_FAILURE,
+    }
+}
+
+pub trait Report: fmt::Display {
+    fn kind(&self) -> &'static str;
+
+    fn detail(&self) -> Value {
+        Value::Null
+    }
+
+    fn to_json(&self, exit_code: i32) -> Value {
+        json!({
+            "error": {
+                "kind": self.kind(),
+                "message": self.to_string(),
+                "exit_code": exit_code,
+                "detail": self.detail(),
+            }
+        })
+    }
+}
+
+fn io_detail(e: &io::Error) -> Value {
+    json!({ "io_kind": format!("{:?}", e.kind()) })
+}
+
+fn response_detail(response: &Response) -> Value {
+    json!({
+        "code": response.code,
+        "response": response.message,
+        "body": response.body,
+    })
+}
+
+fn diagnostic_detail(diagnostic: &Diagnostic) -> Value {
+    json!({
+        "origin": diagnostic.origin,
+        "key": diagnostic.key,
+        "message": diagnostic.message,
+    })
+}
+
+impl Report for ResponseError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ResponseError::CommandError => "command_error",
+            ResponseError::LoginError => "login_error",
+            ResponseError::DbError => "db_error",
+            ResponseError::IpAddressError => "ip_address_error",
+            ResponseError::NoConnection => "no_connection",
+            ResponseError::NotFound => "not_found",
+        }
+    }
+}
+
+impl Report for ClientError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ClientError::Io(_) => "io",
+            ClientError::Parse(ParseError::Empty) => "empty_response",
+            ClientError::Parse(ParseError::InvalidCode(_)) => "invalid_response_code",
+            ClientError::Response(e, _) => e.kind(),
+            ClientError::Unexpected(_) => "unexpected_response",
+            ClientError::Open(e) => e.kind(),
+            ClientError::SendTimeout(..) => "send_timeout",
+            ClientError::RecvTimeout(..) => "recv_timeout",
+            ClientError::InvalidField(_) => "invalid_field",
+            ClientError::LineTooLong(_) => "line_too_long",
+            ClientError::ServerMismatch { .. } => "server_mismatch",
+            ClientError::Cancelled => "cancelled",
+        }
+    }
+
+    fn detail(&self) -> Value {
+        match self {
+            ClientError::Io(e) => io_detail(e),
+            ClientError::Parse(ParseError::InvalidCode(line)) => json!({ "line": line }),
+            ClientError::Response(ResponseError::NotFound, response) => {
+                let mut detail = response_detail(response);
+                detail["scope"] = json!(NotFoundScope::classify(&response.message).to_string());
+                detail
+            }
+            ClientError::Response(_, response) | ClientError::Unexpected(response) => {
+                response_detail(response)
+            }
+            ClientError::Open(e) => e.detail(),
+            ClientError::InvalidField(e) => json!({ "command": e.command, "line": e.line }),
+            ClientError::LineTooLong(limit) => json!({ "limit": limit }),
+            ClientError::ServerMismatch {
+                record,
+                sent,
+                acknowledged,
+            } => json!({
+                "record": record,
+                "sent": sent.to_string(),
+                "acknowledged": acknowledged.to_string(),
+            }),
+            ClientError::SendTimeout(command, after) | ClientError::RecvTimeout(command, after) => {
+                json!({
+                    "command": command,
+                    "elapsed": after.as_secs_f64(),
+                    "ambiguous": self.is_ambiguous(),
+                })
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Report for OpenError {
+    fn kind(&self) -> &'static str {
+        match self {
+            OpenError::Resolve(_) => "resolve",
+            OpenError::Bind(_) => "bind",
+            OpenError::PortsExhausted(..) => "ports_exhausted",
+            OpenError::NoFamily(_) => "no_family",
+            OpenError::Connect(_) => "connect",
+            OpenError::Tls(_) => "tls",
+        }
+    }
+
+    fn detail(&self) -> Value {
+        match self {
+            OpenError::NoFamily(family) => {
+                json!({ "host": dice::HOST, "port": dice::PORT, "family": family.to_string() })
+            }
+            _ => json!({ "host": dice::HOST, "port": dice::PORT }),
+        }
+    }
+}
+
+impl Report for ConfigError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ConfigError::Io(..) => "config_io",
+            ConfigError::Invalid(_) => "config_invalid",
+            ConfigError::Missing(_) | ConfigError::Unprompted(_) => "config_missing",
+        }
+    }
+
+    fn detail(&self) -> Value {
+        match self {
+            ConfigError::Io(path, e) => json!({
+                "path": path.display().to_string(),
+                "io_kind": format!("{:?}", e.kind()),
+            }),
+            ConfigError::Invalid(diagnostics) => json!({
+                "diagnostics": diagnostics.iter().map(diagnostic_detail).collect::<Vec<_>>(),
+            }),
+            ConfigError::Missing(keys) => json!({
+                "keys": keys.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+            }),
+            ConfigError::Unprompted(keys) => json!({
+                "keys": keys.iter().map(|(key, _)| key.to_string()).collect::<Vec<_>>(),
+                "consulted": keys
+                    .iter()
+                    .map(|(key, consulted)| (key.to_string(), json!(consulted)))
+                    .collect::<serde_json::Map<_, _>>(),
+            }),
+        }
+    }
+}
+
+impl Report for Diagnostic {
+    fn kind(&self) -> &'static str {
+        "config_invalid"
+    }
+
+    fn detail(&self) -> Value {
+        json!({ "diagnostics": [diagnostic_detail(self)] })
+    }
+}
+
+impl Report for StateError {
+    fn kind(&self) -> &'static str {
+        match self {
+            StateError::Io(..) => "state_io",
+            StateError::Parse(..) => "state_parse",
+            StateError::Invalid(..) => "state_invalid",
+            StateError::TooNew(..) => "state_too_new",
+        }
+    }
+
+    fn detail(&self) -> Value {
+        match self {
+            StateError::Io(path, _) | StateError::Parse(path, _) | StateError::Invalid(path, _) => {
+                json!({ "path": path.display().to_string() })
+            }
+            StateError::TooNew(path, version) => json!({
+                "path": path.display().to_string(),
+                "version": version,
+            }),
+        }
+    }
+}
+
+impl Report for SecretError {
+    fn kind(&self) -> &'static str {
+        match self {
+            SecretError::Io(..) => "secret_io",
+            SecretError::InvalidKey(_) => "secret_invalid_key",
+            SecretError::Malformed => "secret_malformed",
+            SecretError::Decrypt => "secret_decrypt",
+            SecretError::Crypto(_) => "crypto",
+            SecretError::Unsupported => "secret_unsupported",
+            SecretError::Fd(..)
+            | SecretError::FdStandard(_)
+            | SecretError::FdTooLong(_)
+            | SecretError::FdNotUtf8(_) => "secret_fd",
+            SecretError::FdUnsupported => "secret_fd_unsupported",
+        }
+    }
+
+    fn detail(&self) -> Value {
+        match self {
+            SecretError::Io(path, _) | SecretError::InvalidKey(path) => {
+                json!({ "path": path.display().to_string() })
+            }
+            SecretError::Fd(fd, _)
+            | SecretError::FdStandard(fd)
+            | SecretError::FdTooLong(fd)
+            | SecretError::FdNotUtf8(fd) => {
+                json!({ "fd": fd })
+            }
+            _ => Value::Null,
+        }
+    }
+}
+
+impl Report for DsnError {
+    fn kind(&self) -> &'static str {
+        "dsn_invalid"
+    }
+
+    fn detail(&self) -> Value {
+        json!({ "component": self.component })
+    }
+}
+
+impl Report for InputError {
+    fn kind(&self) -> &'static str {
+        match self {
+            InputError::Io(_) => "input_io",
+            InputError::Strict(_) => "input_strict",
+            InputError::Eof(_) => "input_eof",
+            InputError::Empty(_) => "input_empty",
+        }
+    }
+}
+
+impl Report for DetectError {
+    fn kind(&self) -> &'static str {
+        match self {
+            DetectError::Io(_) => "detect_io",
+            DetectError::Url(_) => "detect_url",
+            DetectError::Http(_) => "detect_http",
+            DetectError::NoAddress(_) => "detect_no_address",
+            DetectError::NotPublic(_) => "detect_not_public",
+            DetectError::Command { .. } => "detect_command",
+            DetectError::Fallback(_) => "detect_fallback",
+            DetectError::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[cfg(feature = "dns")]
+impl Report for DnsError {
+    fn kind(&self) -> &'static str {
+        match self {
+            DnsError::Io(_) => "dns_io",
+            DnsError::Format(_) => "dns_format",
+            DnsError::Rcode(_) => "dns_rcode",
+            DnsError::NoServers(_) => "dns_no_servers",
+            DnsError::Unsupported(..) => "dns_unsupported",
+        }
+    }
+}
+
+impl Report for str {
+    fn kind(&self) -> &'static str {
+        "general"
+    }
+}
+
+impl Report for String {
+    fn kind(&self) -> &'static str {
+        "general"
+    }
+}
+
+impl<T: Report + ?Sized> Report for &T {
+    fn kind(&self) -> &'static str {
+        (**self).kind()
+    }
+
+    fn detail(&self) -> Value {
+        (**self).detail()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const OUTCOMES: [Outcome; 4] = [
+        Outcome::Updated,
+        Outcome::Refreshed,
+        Outcome::Unchanged,
+        Outcome::Offline,
+    ];
+
+    fn runs(len: usize) -> Vec<Vec<Outcome>> {
+        (0..OUTCOMES.len().pow(len as u32))
+            .map(|mut n| {
+                (0..len)
+                    .map(|_| {
+                        let outcome = OUTCOMES[n % OUTCOMES.len()];
+                        n /= OUTCOMES.len();
+                        outcome
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn examples() {
+        let options = ExitOptions { unchanged: 3 };
+        let cases: &[(&[Outcome], usize, i32)] = &[
+            (&[], 0, 3),
+            (&[], 1, EXIT_FAILURE),
+            (&[Outcome::Unchanged], 0, 3),
+            (&[Outcome::Unchanged, Outcome::Unchanged], 0, 3),
+            (&[Outcome::Updated], 0, 0),
+            (&[Outcome::Refreshed], 0, 0),
+            (&[Outcome::Offline], 0, 0),
+            (&[Outcome::Unchanged, Outcome::Updated], 0, 0),
+            (&[Outcome::Updated, Outcome::Unchanged], 0, 0),
+            (&[Outcome::Unchanged], 1, EXIT_FAILURE),
+            (&[Outcome::Updated], 2, EXIT_FAILURE),
+        ];
+        for (outcomes, failed, expected) in cases {
+            assert_eq!(
+                *expected,
+                compute_exit_code(outcomes, *failed, &options),
+                "{:?} failed={}",
+                outcomes,
+                failed
+            );
+        }
+    }
+
+    #[test]
+    fn exhaustive() {
+        for unchanged in [0, 3, 255] {
+            let options = ExitOptions { unchanged };
+            for len in 0..=4 {
+                for outcomes in runs(len) {
+                    let all_unchanged = !outcomes.iter().any(|o| Outcome::Unchanged != *o);
+                    for failed in 0..=len + 1 {
+                        let expected = if 0 != failed {
+                            EXIT_FAILURE
+                        } else if all_unchanged {
+                            unchanged
+                        } else {
+                            0
+                        };
+                        assert_eq!(
+                            expected,
+                            compute_exit_code(&outcomes, failed, &options),
+                            "{:?} failed={} unchanged={}",
+                            outcomes,
+                            failed,
+                            unchanged
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn default_unchanged_is_success() {
+        let options = ExitOptions::default();
+        assert_eq!(0, compute_exit_code(&[Outcome::Unchanged], 0, &options));
+        assert_eq!(0, compute_exit_code(&[], 0, &options));
+    }
+
+    #[test]
+    fn client_error_codes() {
+        let after = Duration::from_secs(1);
+        assert_eq!(signal::EXIT_INTERRUPTED, exit_code(&ClientError::Cancelled));
+        assert_eq!(
+            EXIT_SEND_TIMEOUT,
+            exit_code(&ClientError::SendTimeout("MODIP".to_owned(), after))
+        );
+        assert_eq!(
+            EXIT_RECV_TIMEOUT,
+            exit_code(&ClientError::RecvTimeout("MODIP".to_owned(), after))
+        );
+        assert_eq!(
+            EXIT_FAILURE,
+            exit_code(&ClientError::Io(io::Error::other("reset")))
+        );
+    }
+
+    #[test]
+    fn parse_exit_codes() {
+        assert_eq!(Ok(0), parse_exit_code("0"));
+        assert_eq!(Ok(3), parse_exit_code(" 3 "));
+        assert_eq!(Ok(255), parse_exit_code("255"));
+        for bad in ["", "x", "-1", "256", "3.0"] {
+            assert!(parse_exit_code(bad).is_err(), "{:?}", bad);
+        }
+        for (name, code) in EXIT_CODES.iter().filter(|(_, code)| 0 != *code) {
+            let e = parse_exit_code(&code.to_string()).unwrap_err();
+            assert!(e.contains(name), "{}", e);
+        }
+    }
+}
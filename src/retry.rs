@@ -0,0 +1,259 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::clock::{Clock, SystemClock};
+use crate::dice::{Client, ClientError, OpenError};
+use crate::secret;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 0,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if !self.jitter {
+            return delay;
+        }
+        let mut byte = [0u8; 1];
+        if secret::random_bytes(&mut byte).is_err() {
+            return delay;
+        }
+        delay / 2 + delay.mul_f64(f64::from(byte[0]) / 510.0)
+    }
+}
+
+pub fn with_retries_notify<T, R, C, F, N>(
+    policy: &RetryPolicy,
+    clock: &dyn Clock,
+    cancel: &CancelToken,
+    mut connect: C,
+    mut op: F,
+    mut notify: N,
+) -> Result<R, ClientError>
+where
+    T: Read + Write,
+    C: FnMut() -> Result<Client<T>, OpenError>,
+    F: FnMut(&mut Client<T>) -> Result<R, ClientError>,
+    N: FnMut(u32, &ClientError, Duration),
+{
+    let mut retry = 0;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(ClientError::Cancelled);
+        }
+        let result = connect()
+            .map_err(ClientError::from)
+            .and_then(|mut client| op(&mut client));
+        match result {
+            Err(e) if e.is_retryable() && retry < policy.retries => {
+                retry += 1;
+                let delay = policy.delay(retry);
+                notify(retry, &e, delay);
+                if clock.sleep_cancellable(delay, cancel) {
+                    return Err(ClientError::Cancelled);
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+pub fn with_retries<T, R, C, F>(
+    policy: &RetryPolicy,
+    cancel: &CancelToken,
+    connect: C,
+    op: F,
+) -> Result<R, ClientError>
+where
+    T: Read + Write,
+    C: FnMut() -> Result<Client<T>, OpenError>,
+    F: FnMut(&mut Client<T>) -> Result<R, ClientError>,
+{
+    with_retries_notify(policy, &SystemClock, cancel, connect, op, |_, _, _| {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::dice::{ParseError, Response, ResponseError};
+    use std::io;
+
+    type Stream = io::Cursor<Vec<u8>>;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        retries: 3,
+        base_delay: Duration::from_secs(2),
+        max_delay: Duration::from_secs(5),
+        jitter: false,
+    };
+
+    fn response(error: ResponseError) -> ClientError {
+        let response = Response {
+            code: 1,
+            message: error.to_string(),
+            body: Vec::new(),
+        };
+        ClientError::Response(error, response)
+    }
+
+    fn refused() -> io::Error {
+        io::Error::from(io::ErrorKind::ConnectionRefused)
+    }
+
+    fn classes() -> Vec<(ClientError, bool)> {
+        vec![
+            (ClientError::Io(refused()), true),
+            (
+                ClientError::SendTimeout("LOGIN".to_owned(), POLICY.base_delay),
+                true,
+            ),
+            (
+                ClientError::RecvTimeout("MODIP".to_owned(), POLICY.base_delay),
+                true,
+            ),
+            (ClientError::Open(OpenError::Resolve(refused())), true),
+            (ClientError::Open(OpenError::Connect(refused())), true),
+            (
+                ClientError::Open(OpenError::PortsExhausted(1024, 1025)),
+                true,
+            ),
+            (ClientError::Open(OpenError::Bind(refused())), false),
+            (
+                ClientError::Open(OpenError::Tls("handshake".to_owned())),
+                false,
+            ),
+            (response(ResponseError::DbError), true),
+            (response(ResponseError::NoConnection), true),
+            (response(ResponseError::LoginError), false),
+            (response(ResponseError::CommandError), false),
+            (response(ResponseError::IpAddressError), false),
+            (response(ResponseError::NotFound), false),
+            (ClientError::Parse(ParseError::Empty), false),
+            (ClientError::LineTooLong(1024), false),
+        ]
+    }
+
+    fn flaky_connect(
+        failures: u32,
+        error: fn() -> OpenError,
+        attempts: &mut u32,
+    ) -> impl FnMut() -> Result<Client<Stream>, OpenError> + '_ {
+        move || {
+            *attempts += 1;
+            if *attempts <= failures {
+                return Err(error());
+            }
+            Ok(Client::new(Stream::default()))
+        }
+    }
+
+    #[test]
+    fn failing_connects_are_retried_with_backoff() {
+        let clock = MockClock::new(0);
+        let (mut attempts, mut notified) = (0, Vec::new());
+        let connect = flaky_connect(3, || OpenError::Connect(refused()), &mut attempts);
+        let result = with_retries_notify(
+            &POLICY,
+            &clock,
+            &CancelToken::new(),
+            connect,
+            |_| Ok("done"),
+            |retry, e, delay| notified.push((retry, e.is_retryable(), delay.as_secs())),
+        );
+        assert_eq!("done", result.unwrap());
+        assert_eq!(4, attempts);
+        assert_eq!(vec![(1, true, 2), (2, true, 4), (3, true, 5)], notified);
+        assert_eq!(Duration::from_secs(11), clock.elapsed());
+    }
+
+    #[test]
+    fn retries_stop_when_the_budget_is_spent() {
+        let clock = MockClock::new(0);
+        let mut attempts = 0;
+        let connect = flaky_connect(u32::MAX, || OpenError::Resolve(refused()), &mut attempts);
+        let result = with_retries_notify(
+            &POLICY,
+            &clock,
+            &CancelToken::new(),
+            connect,
+            |_| Ok(()),
+            |_, _, _| {},
+        );
+        assert!(matches!(
+            result,
+            Err(ClientError::Open(OpenError::Resolve(_)))
+        ));
+        assert_eq!(POLICY.retries + 1, attempts);
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        for (error, retryable) in classes() {
+            let clock = MockClock::new(0);
+            let name = error.to_string();
+            let (mut error, mut connects, mut calls) = (Some(error), 0, 0);
+            let result = with_retries_notify(
+                &POLICY,
+                &clock,
+                &CancelToken::new(),
+                || {
+                    connects += 1;
+                    Ok(Client::new(Stream::default()))
+                },
+                |_| {
+                    calls += 1;
+                    error.take().map_or(Ok(()), Err)
+                },
+                |_, _, _| {},
+            );
+            assert_eq!(retryable, result.is_ok(), "{}", name);
+            assert_eq!(1 + u32::from(retryable), calls, "{}", name);
+            assert_eq!(calls, connects, "{}", name);
+        }
+    }
+
+    #[test]
+    fn cancellation_stops_retrying() {
+        let clock = MockClock::new(0);
+        let cancel = CancelToken::new();
+        let mut attempts = 0;
+        let result = with_retries_notify(
+            &POLICY,
+            &clock,
+            &cancel,
+            || {
+                attempts += 1;
+                Err::<Client<Stream>, _>(OpenError::Connect(refused()))
+            },
+            |_| Ok(()),
+            |_, _, _| cancel.cancel(),
+        );
+        assert!(matches!(result, Err(ClientError::Cancelled)));
+        assert_eq!(1, attempts);
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt;
+use std::str::FromStr;
+
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    Disabled,
+    OutsideWindow,
+    DependencyFailed,
+    ByteBudget,
+    FilteredOut,
+}
+
+impl Skip {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Skip::Disabled => "disabled",
+            Skip::OutsideWindow => "outside_window",
+            Skip::DependencyFailed => "dependency_failed",
+            Skip::ByteBudget => "byte_budget",
+            Skip::FilteredOut => "filtered_out",
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Skip::Disabled => "disabled",
+            Skip::OutsideWindow => "outside window",
+            Skip::DependencyFailed => "dependency failed",
+            Skip::ByteBudget => "daily byte budget spent",
+            Skip::FilteredOut => "filtered out",
+        }
+    }
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "skipped ({})", self.reason())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub days: [bool; 7],
+    pub start: u16,
+    pub end: u16,
+}
+
+fn parse_day(text: &str) -> Result<usize, String> {
+    DAYS.iter()
+        .position(|day| day.eq_ignore_ascii_case(text))
+        .ok_or_else(|| {
+            format!(
+                "unknown day {:?}: expected one of {}",
+                text,
+                DAYS.join(", ")
+            )
+        })
+}
+
+fn parse_days(text: &str) -> Result<[bool; 7], String> {
+    let mut days = [false; 7];
+    for part in text.split(',').map(str::trim) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_day(first.trim())?, parse_day(last.trim())?);
+                let mut day = first;
+                loop {
+                    days[day] = true;
+                    if last == day {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => days[parse_day(part)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+fn parse_time(text: &str, end: bool) -> Result<u16, String> {
+    let invalid = || format!("invalid time {:?}: expected HH:MM", text);
+    let (hour, minute) = text.split_once(':').ok_or_else(invalid)?;
+    if 2 != hour.len() || 2 != minute.len() {
+        return Err(invalid());
+    }
+    let hour: u16 = hour.parse().map_err(|_| invalid())?;
+    let minute: u16 = minute.parse().map_err(|_| invalid())?;
+    match (hour, minute) {
+        (24, 0) if end => Ok(MINUTES_PER_DAY),
+        (0..=23, 0..=59) => Ok(hour * 60 + minute),
+        _ => Err(invalid()),
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Window, String> {
+        let s = s.trim();
+        let (days, times) = match s.rsplit_once(char::is_whitespace) {
+            Some((days, times)) => (parse_days(days.trim())?, times),
+            None => ([true; 7], s),
+        };
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| format!("invalid window {:?}: expected [DAYS ]HH:MM-HH:MM", s))?;
+        let start = parse_time(start, false)?;
+        let end = parse_time(end, true)?;
+        if start == end {
+            return Err(format!("invalid window {:?}: start and end are equal", s));
+        }
+        Ok(Window { days, start, end })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.days != [true; 7] {
+            let mut runs: Vec<(usize, usize)> = Vec::new();
+            for day in (0..7).filter(|&day| self.days[day]) {
+                match runs.last_mut() {
+                    Some((_, last)) if *last + 1 == day => *last = day,
+                    _ => runs.push((day, day)),
+                }
+            }
+            if 1 < runs.len() && 0 == runs[0].0 && Some(6) == runs.last().map(|run| run.1) {
+                let (first, _) = runs.pop().unwrap();
+                runs[0].0 = first;
+            }
+            let runs: Vec<String> = runs
+                .into_iter()
+                .map(|(first, last)| {
+                    if first == last {
+                        DAYS[first].to_owned()
+                    } else {
+                        format!("{}-{}", DAYS[first], DAYS[last])
+                    }
+                })
+                .collect();
+            write!(f, "{} ", runs.join(","))?;
+        }
+        let time = |minutes: u16| format!("{:02}:{:02}", minutes / 60, minutes % 60);
+        write!(f, "{}-{}", time(self.start), time(self.end))
+    }
+}
+
+impl Window {
+    pub fn contains(&self, weekday: usize, minute: u16) -> bool {
+        let yesterday = (weekday + 6) % 7;
+        if self.start < self.end {
+            self.days[weekday] && self.start <= minute && minute < self.end
+        } else {
+            (self.days[weekday] && self.start <= minute)
+                || (self.days[yesterday] && minute < self.end)
+        }
+    }
+
+    pub fn contains_at(&self, unix: u64) -> bool {
+        let (weekday, minute) = local_time(unix);
+        self.contains(weekday, minute)
+    }
+}
+
+pub fn local_time(unix: u64) -> (usize, u16) {
+    wall_time(unix, utc_offset(unix))
+}
+
+fn utc_offset(unix: u64) -> i64 {
+    let time = unix as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+fn wall_time(unix: u64, offset: i64) -> (usize, u16) {
+    let local = (unix as i64).saturating_add(offset).max(0) as u64;
+    let weekday = (local / 86400 + 3) % 7;
+    (weekday as usize, (local % 86400 / 60) as u16)
+}
+
+pub fn local_timestamp(unix: u64) -> String {
+    let time = unix as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return format!("@{}", unix);
+    }
+    let offset = tm.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use std::time::Duration;
+
+    const MONDAY: u64 = 1_791_763_200;
+    const SPRING_FORWARD: u64 = 1_774_746_000;
+    const FALL_BACK: u64 = 1_792_890_000;
+    const HOUR: u64 = 3600;
+
+    fn window(text: &str) -> Window {
+        text.parse().unwrap()
+    }
+
+    fn at(day: usize, time: &str) -> (usize, u16) {
+        (day, parse_time(time, false).unwrap())
+    }
+
+    fn berlin(unix: u64) -> i64 {
+        let summer = (SPRING_FORWARD..FALL_BACK).contains(&unix);
+        if summer {
+            7200
+        } else {
+            3600
+        }
+    }
+
+    fn inside(window: &Window, clock: &MockClock) -> bool {
+        let unix = clock.unix_now();
+        let (weekday, minute) = wall_time(unix, berlin(unix));
+        window.contains(weekday, minute)
+    }
+
+    #[test]
+    fn windows_parse_and_display() {
+        let cases = [
+            ("08:00-18:00", "08:00-18:00"),
+            (" Mon-Fri 08:00-18:00 ", "Mon-Fri 08:00-18:00"),
+            ("mon,wed,fri 09:30-10:00", "Mon,Wed,Fri 09:30-10:00"),
+            ("Sat-Mon 22:00-24:00", "Sat-Mon 22:00-24:00"),
+            ("Fri-Mon 22:00-06:00", "Fri-Mon 22:00-06:00"),
+            ("Mon-Sun 00:00-24:00", "00:00-24:00"),
+        ];
+        for &(text, shown) in &cases {
+            let parsed = window(text);
+            assert_eq!(shown, parsed.to_string(), "{}", text);
+            assert_eq!(parsed, window(shown), "{}", text);
+        }
+        assert_eq!(
+            [true, true, false, false, false, true, true],
+            window("Sat-Tue 08:00-09:00").days
+        );
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        for text in &[
+            "",
+            "08:00",
+            "8:00-18:00",
+            "08:00-18:60",
+            "24:00-08:00",
+            "08:00-25:00",
+            "08:00-08:00",
+            "Mon-Funday 08:00-18:00",
+            "Weekdays 08:00-18:00",
+            "Mon 0800-1800",
+        ] {
+            assert!(text.parse::<Window>().is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn daytime_windows_are_half_open() {
+        let office = window("Mon-Fri 08:00-18:00");
+        for &(day, time, expected) in &[
+            (0, "07:59", false),
+            (0, "08:00", true),
+            (2, "12:00", true),
+            (4, "17:59", true),
+            (4, "18:00", false),
+            (5, "12:00", false),
+            (6, "12:00", false),
+        ] {
+            let (day, minute) = at(day, time);
+            assert_eq!(
+                expected,
+                office.contains(day, minute),
+                "{} {}",
+                DAYS[day],
+                time
+            );
+        }
+        let evening = window("Sun 20:00-24:00");
+        assert!(evening.contains(6, MINUTES_PER_DAY - 1));
+        assert!(!evening.contains(0, 0));
+    }
+
+    #[test]
+    fn windows_cross_midnight() {
+        let night = window("Fri-Sat 22:00-06:00");
+        for &(day, time, expected) in &[
+            (4, "21:59", false),
+            (4, "22:00", true),
+            (4, "23:59", true),
+            (5, "00:00", true),
+            (5, "05:59", true),
+            (5, "06:00", false),
+            (5, "23:00", true),
+            (6, "03:00", true),
+            (6, "06:00", false),
+            (6, "23:00", false),
+            (0, "03:00", false),
+            (3, "23:00", false),
+            (4, "03:00", false),
+        ] {
+            let (day, minute) = at(day, time);
+            assert_eq!(
+                expected,
+                night.contains(day, minute),
+                "{} {}",
+                DAYS[day],
+                time
+            );
+        }
+        let sunday = window("Sun 23:00-01:00");
+        assert!(sunday.contains(6, at(6, "23:30").1));
+        assert!(sunday.contains(0, at(0, "00:30").1));
+        assert!(!sunday.contains(0, at(0, "23:30").1));
+    }
+
+    #[test]
+    fn wall_time_follows_the_offset() {
+        assert_eq!(at(0, "00:00"), wall_time(MONDAY, 0));
+        assert_eq!(at(0, "02:00"), wall_time(MONDAY, 7200));
+        assert_eq!(at(6, "19:00"), wall_time(MONDAY, -5 * 3600));
+        assert_eq!(at(0, "23:59"), wall_time(MONDAY + 86400 - 60, 0));
+        assert_eq!(at(3, "00:00"), wall_time(0, 0));
+        assert_eq!(at(3, "00:00"), wall_time(0, -3600));
+    }
+
+    #[test]
+    fn windows_use_wall_time_across_spring_forward() {
+        let clock = MockClock::new(SPRING_FORWARD - 2 * HOUR);
+        let early = window("Sun 01:00-04:00");
+        let gap = window("Sun 02:00-02:30");
+        let mut seen = Vec::new();
+        for _ in 0..8 {
+            let unix = clock.unix_now();
+            seen.push((wall_time(unix, berlin(unix)).1 / 60, inside(&early, &clock)));
+            assert!(!inside(&gap, &clock));
+            clock.advance(Duration::from_secs(HOUR / 2));
+        }
+        assert_eq!(
+            vec![
+                (0, false),
+                (0, false),
+                (1, true),
+                (1, true),
+                (3, true),
+                (3, true),
+                (4, false),
+                (4, false),
+            ],
+            seen
+        );
+    }
+
+    #[test]
+    fn windows_use_wall_time_across_fall_back() {
+        let clock = MockClock::new(FALL_BACK - 2 * HOUR);
+        let repeated = window("Sun 02:00-03:00");
+        let mut inside_for = 0;
+        for _ in 0..(6 * 60) {
+            if inside(&repeated, &clock) {
+                inside_for += 1;
+            }
+            clock.advance(Duration::from_secs(60));
+        }
+        assert_eq!(120, inside_for);
+        let unix = FALL_BACK - 1;
+        assert_eq!(at(6, "02:59"), wall_time(unix, berlin(unix)));
+        assert_eq!(at(6, "02:00"), wall_time(FALL_BACK, berlin(FALL_BACK)));
+    }
+
+    #[test]
+    fn local_time_matches_the_system_zone() {
+        let unix = MONDAY + 12 * HOUR;
+        assert_eq!(wall_time(unix, utc_offset(unix)), local_time(unix));
+        assert!(window("00:00-24:00").contains_at(unix));
+    }
+}
@@ -0,0 +1,428 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+#[cfg(feature = "tls-openssl")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "tls-openssl")]
+use openssl::base64;
+#[cfg(feature = "tls-openssl")]
+use openssl::error::ErrorStack;
+#[cfg(feature = "tls-openssl")]
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use crate::messages;
+
+const KEY_LEN: usize = 32;
+#[cfg(feature = "tls-openssl")]
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "tls-openssl")]
+const TAG_LEN: usize = 16;
+#[cfg(feature = "tls-openssl")]
+const AAD: &[u8] = b"ddns_client password v1";
+pub const FD_LIMIT: usize = 4096;
+
+#[derive(Debug)]
+pub enum SecretError {
+    Io(PathBuf, io::Error),
+    InvalidKey(PathBuf),
+    Malformed,
+    Decrypt,
+    Crypto(String),
+    Unsupported,
+    Fd(i32, io::Error),
+    FdStandard(i32),
+    FdTooLong(i32),
+    FdNotUtf8(i32),
+    FdUnsupported,
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SecretError::InvalidKey(path) => write!(
+                f,
+                "{}: expected {} raw bytes or their base64 encoding",
+                path.display(),
+                KEY_LEN
+            ),
+            SecretError::Malformed => {
+                write!(f, "encrypted password is not valid base64 ciphertext")
+            }
+            SecretError::Decrypt => write!(
+                f,
+                "could not decrypt the password: wrong key or corrupted ciphertext"
+            ),
+            SecretError::Crypto(e) => write!(f, "{}", e),
+            SecretError::Unsupported => write!(f, "{}", messages::crypto_unsupported()),
+            SecretError::Fd(fd, e) => write!(f, "file descriptor {}: {}", fd, e),
+            SecretError::FdStandard(fd) => write!(
+                f,
+                "file descriptor {} is a standard stream; pass an extra descriptor such as 3",
+                fd
+            ),
+            SecretError::FdTooLong(fd) => write!(
+                f,
+                "file descriptor {}: password is longer than {} bytes",
+                fd, FD_LIMIT
+            ),
+            SecretError::FdNotUtf8(fd) => {
+                write!(f, "file descriptor {}: password is not valid UTF-8", fd)
+            }
+            SecretError::FdUnsupported => {
+                write!(
+                    f,
+                    "reading from a file descriptor is only supported on Unix"
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for SecretError {}
+
+#[cfg(feature = "tls-openssl")]
+impl From<ErrorStack> for SecretError {
+    fn from(e: ErrorStack) -> SecretError {
+        SecretError::Crypto(e.to_string())
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    openssl::rand::rand_bytes(buf).map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    use std::io::Read;
+
+    fs::File::open("/dev/urandom")?.read_exact(buf)
+}
+
+pub fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub fn read_fd(fd: i32) -> Result<String, SecretError> {
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    if (0..=2).contains(&fd) {
+        return Err(SecretError::FdStandard(fd));
+    }
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if -1 == flags {
+        return Err(SecretError::Fd(fd, io::Error::last_os_error()));
+    }
+    if libc::O_WRONLY == flags & libc::O_ACCMODE {
+        let e = io::Error::new(io::ErrorKind::PermissionDenied, "not open for reading");
+        return Err(SecretError::Fd(fd, e));
+    }
+    let mut file = unsafe { fs::File::from_raw_fd(fd) };
+    let mut buf = Vec::with_capacity(FD_LIMIT + 1);
+    let mut chunk = [0u8; 256];
+    let result = loop {
+        match file.read(&mut chunk) {
+            Ok(0) => break Ok(()),
+            Ok(n) if FD_LIMIT < buf.len() + n => break Err(SecretError::FdTooLong(fd)),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if io::ErrorKind::Interrupted == e.kind() => {}
+            Err(e) => break Err(SecretError::Fd(fd, e)),
+        }
+    };
+    wipe(&mut chunk);
+    if let Err(e) = result {
+        wipe(&mut buf);
+        return Err(e);
+    }
+    if buf.ends_with(b"\n") {
+        buf.pop();
+    }
+    String::from_utf8(buf).map_err(|e| {
+        wipe(&mut e.into_bytes());
+        SecretError::FdNotUtf8(fd)
+    })
+}
+
+#[cfg(not(unix))]
+pub fn read_fd(_: i32) -> Result<String, SecretError> {
+    Err(SecretError::FdUnsupported)
+}
+
+pub fn read_key(path: &Path) -> Result<Vec<u8>, SecretError> {
+    let data = fs::read(path).map_err(|e| SecretError::Io(path.to_owned(), e))?;
+    if KEY_LEN == data.len() {
+        return Ok(data);
+    }
+    decode_key(&data).ok_or_else(|| SecretError::InvalidKey(path.to_owned()))
+}
+
+#[cfg(feature = "tls-openssl")]
+fn decode_key(data: &[u8]) -> Option<Vec<u8>> {
+    let text = String::from_utf8_lossy(data);
+    base64::decode_block(text.trim())
+        .ok()
+        .filter(|key| KEY_LEN == key.len())
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+fn decode_key(_: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn generate_key(_: &Path) -> Result<Vec<u8>, SecretError> {
+    Err(SecretError::Unsupported)
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn encrypt(_: &[u8], _: &str) -> Result<String, SecretError> {
+    Err(SecretError::Unsupported)
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn decrypt(_: &[u8], _: &str) -> Result<String, SecretError> {
+    Err(SecretError::Unsupported)
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn generate_key(path: &Path) -> Result<Vec<u8>, SecretError> {
+    let mut key = vec![0; KEY_LEN];
+    openssl::rand::rand_bytes(&mut key)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| SecretError::Io(path.to_owned(), e))?;
+    writeln!(file, "{}", base64::encode_block(&key))
+        .map_err(|e| SecretError::Io(path.to_owned(), e))?;
+    Ok(key)
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn encrypt(key: &[u8], plain: &str) -> Result<String, SecretError> {
+    let mut nonce = [0; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce)?;
+    let mut tag = [0; TAG_LEN];
+    let cipher = encrypt_aead(
+        Cipher::chacha20_poly1305(),
+        key,
+        Some(&nonce),
+        AAD,
+        plain.as_bytes(),
+        &mut tag,
+    )?;
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&cipher);
+    blob.extend_from_slice(&tag);
+    Ok(base64::encode_block(&blob))
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn decrypt(key: &[u8], encoded: &str) -> Result<String, SecretError> {
+    let blob = base64::decode_block(encoded.trim()).map_err(|_| SecretError::Malformed)?;
+    if blob.len() < NONCE_LEN + TAG_LEN {
+        return Err(SecretError::Malformed);
+    }
+    let (nonce, rest) = blob.split_at(NONCE_LEN);
+    let (cipher, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let plain = decrypt_aead(
+        Cipher::chacha20_poly1305(),
+        key,
+        Some(nonce),
+        AAD,
+        cipher,
+        tag,
+    )
+    .map_err(|_| SecretError::Decrypt)?;
+    String::from_utf8(plain).map_err(|_| SecretError::Decrypt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn pipe(data: &[u8]) -> (i32, i32) {
+        let mut fds = [0; 2];
+        assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+        let written = unsafe { libc::write(fds[1], data.as_ptr().cast(), data.len()) };
+        assert_eq!(data.len() as isize, written);
+        (fds[0], fds[1])
+    }
+
+    #[cfg(unix)]
+    fn is_open(fd: i32) -> bool {
+        -1 != unsafe { libc::fcntl(fd, libc::F_GETFL) }
+    }
+
+    #[cfg(unix)]
+    fn read_pipe(data: &[u8]) -> Result<String, SecretError> {
+        let (read, write) = pipe(data);
+        unsafe { libc::close(write) };
+        read_fd(read)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_fd_rejects_standard_streams() {
+        for fd in 0..=2 {
+            assert!(matches!(read_fd(fd), Err(SecretError::FdStandard(n)) if n == fd));
+            assert!(is_open(fd));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_fd_strips_one_newline() {
+        assert_eq!("hunter2", read_pipe(b"hunter2\n").unwrap());
+        assert_eq!("hunter2", read_pipe(b"hunter2").unwrap());
+        assert_eq!("hunter2\n", read_pipe(b"hunter2\n\n").unwrap());
+        assert_eq!(" spaced ", read_pipe(b" spaced \n").unwrap());
+        assert_eq!("", read_pipe(b"").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_fd_rejects_bad_input() {
+        let long = vec![b'x'; FD_LIMIT + 1];
+        assert!(matches!(read_pipe(&long), Err(SecretError::FdTooLong(_))));
+        assert_eq!(FD_LIMIT, read_pipe(&long[1..]).unwrap().len());
+        assert!(matches!(
+            read_pipe(b"\xff\n"),
+            Err(SecretError::FdNotUtf8(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_fd_rejects_unreadable_descriptors() {
+        let (read, write) = pipe(b"");
+        assert!(matches!(read_fd(write), Err(SecretError::Fd(..))));
+        assert!(is_open(write));
+        unsafe { libc::close(write) };
+        unsafe { libc::close(read) };
+        assert!(matches!(read_fd(1 << 20), Err(SecretError::Fd(..))));
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    #[cfg(feature = "tls-openssl")]
+    fn reencode(encoded: &str, edit: impl FnOnce(&mut Vec<u8>)) -> String {
+        let mut blob = base64::decode_block(encoded).unwrap();
+        edit(&mut blob);
+        base64::encode_block(&blob)
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn encrypt_round_trips() {
+        for &plain in &["hunter2", "", "pässwörd with spaces"] {
+            let encoded = encrypt(&KEY, plain).unwrap();
+            assert_eq!(plain, decrypt(&KEY, &encoded).unwrap());
+            assert_eq!(plain, decrypt(&KEY, &format!(" {}\n", encoded)).unwrap());
+        }
+        assert_ne!(
+            encrypt(&KEY, "hunter2").unwrap(),
+            encrypt(&KEY, "hunter2").unwrap()
+        );
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn ciphertext_is_nonce_then_cipher_then_tag() {
+        let encoded = encrypt(&KEY, "hunter2").unwrap();
+        let blob = base64::decode_block(&encoded).unwrap();
+        assert_eq!(NONCE_LEN + "hunter2".len() + TAG_LEN, blob.len());
+        let (nonce, rest) = blob.split_at(NONCE_LEN);
+        let (cipher, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let plain = decrypt_aead(
+            Cipher::chacha20_poly1305(),
+            &KEY,
+            Some(nonce),
+            AAD,
+            cipher,
+            tag,
+        )
+        .unwrap();
+        assert_eq!(b"hunter2", &plain[..]);
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn wrong_key_is_rejected() {
+        let encoded = encrypt(&KEY, "hunter2").unwrap();
+        let mut other = KEY;
+        other[0] ^= 1;
+        assert!(matches!(
+            decrypt(&other, &encoded),
+            Err(SecretError::Decrypt)
+        ));
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn corrupted_ciphertext_is_rejected() {
+        let encoded = encrypt(&KEY, "hunter2").unwrap();
+        let len = base64::decode_block(&encoded).unwrap().len();
+        for &at in &[0, NONCE_LEN, len - 1] {
+            let corrupted = reencode(&encoded, |blob| blob[at] ^= 0x80);
+            assert!(matches!(
+                decrypt(&KEY, &corrupted),
+                Err(SecretError::Decrypt)
+            ));
+        }
+        let dropped_byte = reencode(&encoded, |blob| blob.truncate(len - 1));
+        assert!(matches!(
+            decrypt(&KEY, &dropped_byte),
+            Err(SecretError::Decrypt)
+        ));
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn truncated_or_malformed_ciphertext_is_rejected() {
+        let encoded = encrypt(&KEY, "hunter2").unwrap();
+        let short = reencode(&encoded, |blob| blob.truncate(NONCE_LEN + TAG_LEN - 1));
+        assert!(matches!(decrypt(&KEY, &short), Err(SecretError::Malformed)));
+        let empty = reencode(&encoded, |blob| blob.truncate(NONCE_LEN + TAG_LEN));
+        assert!(matches!(decrypt(&KEY, &empty), Err(SecretError::Decrypt)));
+        assert!(matches!(
+            decrypt(&KEY, "not base64!"),
+            Err(SecretError::Malformed)
+        ));
+        assert!(matches!(decrypt(&KEY, ""), Err(SecretError::Malformed)));
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    #[test]
+    fn key_files_hold_raw_or_base64_keys() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-secret-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let generated = dir.join("generated");
+        let key = generate_key(&generated).unwrap();
+        assert_eq!(key, read_key(&generated).unwrap());
+        assert!(matches!(generate_key(&generated), Err(SecretError::Io(..))));
+        let raw = dir.join("raw");
+        fs::write(&raw, KEY).unwrap();
+        assert_eq!(KEY.to_vec(), read_key(&raw).unwrap());
+        let short = dir.join("short");
+        fs::write(&short, base64::encode_block(&KEY[1..])).unwrap();
+        assert!(matches!(read_key(&short), Err(SecretError::InvalidKey(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+
+pub const EXIT_INTERRUPTED: i32 = 130;
+const WATCHDOG_GRACE: Duration = Duration::from_secs(5);
+
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    if SIGNALLED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(EXIT_INTERRUPTED) };
+    }
+}
+
+pub fn install() -> CancelToken {
+    TOKEN
+        .get_or_init(|| {
+            #[cfg(unix)]
+            unsafe {
+                let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                libc::signal(libc::SIGINT, handler);
+                libc::signal(libc::SIGTERM, handler);
+            }
+            let token = CancelToken::new();
+            let cancel = token.clone();
+            thread::spawn(move || {
+                while !SIGNALLED.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                cancel.cancel();
+            });
+            spawn_watchdog(token.clone(), WATCHDOG_GRACE);
+            token
+        })
+        .clone()
+}
+
+#[cfg(unix)]
+static TERMINAL: OnceLock<libc::termios> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn on_prompt_signal(_: libc::c_int) {
+    if let Some(term) = TERMINAL.get() {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, term) };
+    }
+    unsafe { libc::_exit(EXIT_INTERRUPTED) };
+}
+
+#[cfg(unix)]
+pub struct TerminalGuard {
+    previous: [libc::sighandler_t; 2],
+}
+
+#[cfg(unix)]
+pub fn guard_terminal(term: libc::termios) -> TerminalGuard {
+    TERMINAL.get_or_init(|| term);
+    let handler = on_prompt_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    let previous = unsafe {
+        [
+            libc::signal(libc::SIGINT, handler),
+            libc::signal(libc::SIGTERM, handler),
+        ]
+    };
+    TerminalGuard { previous }
+}
+
+#[cfg(unix)]
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous[0]);
+            libc::signal(libc::SIGTERM, self.previous[1]);
+        }
+    }
+}
+
+fn spawn_watchdog(cancel: CancelToken, grace: Duration) {
+    thread::spawn(move || {
+        while !cancel.sleep(Duration::from_secs(3600)) {}
+        thread::sleep(grace);
+        process::exit(EXIT_INTERRUPTED);
+    });
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::os::unix::io::FromRawFd;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::dice::OpenError;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Family {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl Family {
+    pub fn matches(self, addr: &SocketAddr) -> bool {
+        match self {
+            Family::Any => true,
+            Family::V4 => addr.is_ipv4(),
+            Family::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Family::Any => write!(f, "any"),
+            Family::V4 => write!(f, "4"),
+            Family::V6 => write!(f, "6"),
+        }
+    }
+}
+
+impl FromStr for Family {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Family, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "any" => Ok(Family::Any),
+            "4" | "ipv4" => Ok(Family::V4),
+            "6" | "ipv6" => Ok(Family::V6),
+            _ => Err(format!(
+                "unsupported address family {:?}: expected 4, 6 or any",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocalBind {
+    pub address: Option<IpAddr>,
+    pub ports: Option<(u16, u16)>,
+    pub family: Family,
+}
+
+impl LocalBind {
+    pub fn is_default(&self) -> bool {
+        self.address.is_none() && self.ports.is_none() && Family::Any == self.family
+    }
+}
+
+pub fn parse_port_range(value: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("invalid port range {:?}: expected LOW-HIGH", value);
+    let (low, high) = value.split_once('-').ok_or_else(invalid)?;
+    let low: u16 = low.trim().parse().map_err(|_| invalid())?;
+    let high: u16 = high.trim().parse().map_err(|_| invalid())?;
+    if 0 == low || high < low {
+        return Err(invalid());
+    }
+    Ok((low, high))
+}
+
+fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+enum Attempt {
+    InUse,
+    Bind(io::Error),
+    Connect(io::Error),
+}
+
+fn connect_from(
+    local: SocketAddr,
+    remote: &SocketAddr,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, Attempt> {
+    let family = match remote {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(Attempt::Bind(io::Error::last_os_error()));
+    }
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &one as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(Attempt::Bind(io::Error::last_os_error()));
+    }
+    let (addr, len) = sockaddr(&local);
+    if unsafe { libc::bind(fd, &addr as *const _ as *const libc::sockaddr, len) } < 0 {
+        let e = io::Error::last_os_error();
+        return Err(match e.raw_os_error() {
+            Some(libc::EADDRINUSE) => Attempt::InUse,
+            _ => Attempt::Bind(e),
+        });
+    }
+    stream.set_write_timeout(timeout).map_err(Attempt::Bind)?;
+    let (addr, len) = sockaddr(remote);
+    if unsafe { libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len) } < 0 {
+        let e = io::Error::last_os_error();
+        return Err(match e.raw_os_error() {
+            Some(libc::EADDRINUSE) | Some(libc::EADDRNOTAVAIL) if 0 != local.port() => {
+                Attempt::InUse
+            }
+            Some(libc::EINPROGRESS) => Attempt::Connect(io::ErrorKind::TimedOut.into()),
+            _ => Attempt::Connect(e),
+        });
+    }
+    Ok(stream)
+}
+
+fn connect_any(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+    let mut last = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to")
+    }))
+}
+
+pub fn connect(
+    addrs: &[SocketAddr],
+    bind: &LocalBind,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, OpenError> {
+    match (bind.is_default(), timeout) {
+        (true, Some(timeout)) => return connect_any(addrs, timeout).map_err(OpenError::Connect),
+        (true, None) => return TcpStream::connect(addrs).map_err(OpenError::Connect),
+        _ => {}
+    }
+    let addrs: Vec<SocketAddr> = addrs
+        .iter()
+        .filter(|addr| bind.family.matches(addr))
+        .copied()
+        .collect();
+    if addrs.is_empty() && Family::Any != bind.family {
+        return Err(OpenError::NoFamily(bind.family));
+    }
+    let (low, high) = bind.ports.unwrap_or((0, 0));
+    let mut last = None;
+    for remote in &addrs {
+        let ip = match (bind.address, remote) {
+            (Some(ip), _) if ip.is_ipv4() == remote.is_ipv4() => ip,
+            (Some(_), _) => continue,
+            (None, SocketAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (None, SocketAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let mut failed = None;
+        for port in low..=high {
+            match connect_from(SocketAddr::new(ip, port), remote, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(Attempt::InUse) => continue,
+                Err(Attempt::Bind(e)) => return Err(OpenError::Bind(e)),
+                Err(Attempt::Connect(e)) => {
+                    failed = Some(e);
+                    break;
+                }
+            }
+        }
+        match failed {
+            Some(e) => last = Some(e),
+            None => return Err(OpenError::PortsExhausted(low, high)),
+        }
+    }
+    Err(OpenError::Connect(last.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no server address matches the bind address family",
+        )
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const LOOPBACK: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn free_range(len: u16) -> (u16, Vec<TcpListener>) {
+        let start = 20_000 + 1000 * len + (std::process::id() % 20_000) as u16;
+        for base in (start..60_000).step_by(usize::from(len)) {
+            let listeners: Result<Vec<_>, _> = (base..base + len)
+                .map(|port| TcpListener::bind((Ipv4Addr::LOCALHOST, port)))
+                .collect();
+            if let Ok(listeners) = listeners {
+                return (base, listeners);
+            }
+        }
+        panic!("no free port range");
+    }
+
+    fn bind(ports: (u16, u16)) -> LocalBind {
+        LocalBind {
+            address: Some(LOOPBACK),
+            ports: Some(ports),
+            family: Family::Any,
+        }
+    }
+
+    #[test]
+    fn port_ranges_parse() {
+        assert_eq!(Ok((1024, 1030)), parse_port_range("1024-1030"));
+        assert_eq!(Ok((5000, 5000)), parse_port_range(" 5000 - 5000 "));
+        for value in &["", "1024", "0-10", "20-10", "1-65536", "a-b", "1024:1030"] {
+            assert!(parse_port_range(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn connector_picks_the_free_port_in_the_range() {
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let remote = server.local_addr().unwrap();
+        let (base, mut listeners) = free_range(4);
+        listeners.pop();
+        let stream = connect(&[remote], &bind((base, base + 3)), None).unwrap();
+        assert_eq!(
+            SocketAddr::new(LOOPBACK, base + 3),
+            stream.local_addr().unwrap()
+        );
+    }
+
+    #[test]
+    fn exhausted_range_is_reported() {
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let remote = server.local_addr().unwrap();
+        let (base, _listeners) = free_range(3);
+        match connect(&[remote], &bind((base, base + 2)), None) {
+            Err(OpenError::PortsExhausted(low, high)) => assert_eq!((base, base + 2), (low, high)),
+            other => panic!("{:?}", other.map(|stream| stream.local_addr())),
+        }
+    }
+
+    #[test]
+    fn bind_address_must_match_the_server_family() {
+        let v6: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        let local = LocalBind {
+            address: Some(LOOPBACK),
+            ..LocalBind::default()
+        };
+        assert!(matches!(
+            connect(&[v6], &local, None),
+            Err(OpenError::Connect(e)) if io::ErrorKind::AddrNotAvailable == e.kind()
+        ));
+        let only_v4 = LocalBind {
+            family: Family::V4,
+            ..LocalBind::default()
+        };
+        assert!(matches!(
+            connect(&[v6], &only_v4, None),
+            Err(OpenError::NoFamily(Family::V4))
+        ));
+    }
+}
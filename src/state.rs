@@ -0,0 +1,1261 @@
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+
+use crate::addr;
+use crate::clock::{Clock, SystemClock};
+use crate::messages;
+use crate::paths;
+
+pub const VERSION: u64 = 1;
+pub const TIME_FLOOR: u64 = 1_735_689_600;
+pub const SKEW_TOLERANCE: u64 = 300;
+pub const DAY: u64 = 86_400;
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+#[cfg(target_os = "linux")]
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+pub fn unix_now() -> u64 {
+    SystemClock.unix_now()
+}
+
+pub fn time_floor() -> u64 {
+    option_env!("SOURCE_DATE_EPOCH")
+        .and_then(|epoch| epoch.parse().ok())
+        .map_or(TIME_FLOOR, |epoch: u64| epoch.max(TIME_FLOOR))
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ClockSkew {
+    BeforeFloor(u64),
+    Behind(u64),
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Invalid(PathBuf, String),
+    TooNew(PathBuf, u64),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            StateError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            StateError::Invalid(path, message) => write!(f, "{}: {}", path.display(), message),
+            StateError::TooNew(path, version) => write!(
+                f,
+                "{}: state file version {} was built by a newer ddns_client (supported: {})",
+                path.display(),
+                version,
+                VERSION
+            ),
+        }
+    }
+}
+
+impl error::Error for StateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StateError::Io(_, e) => Some(e),
+            StateError::Parse(_, e) => Some(e),
+            StateError::Invalid(..) | StateError::TooNew(..) => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BreakerEvent {
+    Opened,
+    Closed,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AuthBreaker {
+    pub failures: u32,
+    pub open_until: Option<u64>,
+}
+
+impl AuthBreaker {
+    pub fn is_open(&self, now: u64) -> bool {
+        self.open_until.is_some_and(|until| now < until)
+    }
+
+    pub fn check(&self, now: u64) -> Result<(), u64> {
+        match self.open_until {
+            Some(until) if now < until => Err(until - now),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&mut self, now: u64, limit: u32, cooldown: u64) -> Option<BreakerEvent> {
+        self.failures = self.failures.saturating_add(1);
+        if 0 == limit || self.failures < limit {
+            return None;
+        }
+        let was_open = self.is_open(now);
+        self.open_until = Some(now.saturating_add(cooldown));
+        if was_open {
+            None
+        } else {
+            Some(BreakerEvent::Opened)
+        }
+    }
+
+    pub fn record_success(&mut self) -> Option<BreakerEvent> {
+        let was_open = self.open_until.is_some();
+        self.reset();
+        if was_open {
+            Some(BreakerEvent::Closed)
+        } else {
+            None
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    fn from_json(value: Option<&Value>) -> AuthBreaker {
+        let field = |name| value.and_then(|v| v.get(name)).and_then(Value::as_u64);
+        AuthBreaker {
+            failures: field("failures").unwrap_or(0) as u32,
+            open_until: field("open_until"),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::json!({
+            "failures": self.failures,
+            "open_until": self.open_until,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Outcome {
+    Updated,
+    Refreshed,
+    Unchanged,
+    Offline,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Updated => "updated",
+            Outcome::Refreshed => "refreshed",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Offline => "offline",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Refreshed => write!(f, "refreshed (unchanged, max-age exceeded)"),
+            Outcome::Offline => write!(f, "set offline"),
+            other => write!(f, "{}", other.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecordState {
+    pub ip: Option<Ipv4Addr>,
+    pub last_success: Option<u64>,
+    pub offline: bool,
+}
+
+impl RecordState {
+    pub fn plan(&self, ip: Ipv4Addr, now: u64, max_age: Option<u64>) -> Outcome {
+        if Some(ip) != self.ip || self.offline {
+            return Outcome::Updated;
+        }
+        match (max_age, self.last_success) {
+            (None, _) => Outcome::Unchanged,
+            (Some(max_age), Some(last)) if now.saturating_sub(last) < max_age => Outcome::Unchanged,
+            _ => Outcome::Refreshed,
+        }
+    }
+
+    fn from_json(value: &Value) -> RecordState {
+        RecordState {
+            ip: value
+                .get("ip")
+                .and_then(Value::as_str)
+                .and_then(|ip| addr::parse_ipv4(ip).ok()),
+            last_success: value.get("last_success").and_then(Value::as_u64),
+            offline: value
+                .get("offline")
+                .and_then(Value::as_bool)
+                .unwrap_or_default(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut value = serde_json::json!({
+            "ip": self.ip.map(|ip| ip.to_string()),
+            "last_success": self.last_success,
+        });
+        if self.offline {
+            value["offline"] = Value::Bool(true);
+        }
+        value
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub enum SessionStrategy {
+    #[default]
+    Shared,
+    PerDomain,
+}
+
+impl SessionStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SessionStrategy::Shared => "shared",
+            SessionStrategy::PerDomain => "per_domain",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Traffic {
+    pub day: u64,
+    pub day_sent: u64,
+    pub day_received: u64,
+    pub total_sent: u64,
+    pub total_received: u64,
+    pub since: Option<u64>,
+    pub suspended_day: Option<u64>,
+}
+
+impl Traffic {
+    pub fn add(&mut self, now: u64, sent: u64, received: u64) {
+        if self.day != now / DAY {
+            self.day = now / DAY;
+            self.day_sent = 0;
+            self.day_received = 0;
+        }
+        self.day_sent = self.day_sent.saturating_add(sent);
+        self.day_received = self.day_received.saturating_add(received);
+        self.total_sent = self.total_sent.saturating_add(sent);
+        self.total_received = self.total_received.saturating_add(received);
+        self.since.get_or_insert(now);
+    }
+
+    pub fn today(&self, now: u64) -> (u64, u64) {
+        if self.day == now / DAY {
+            (self.day_sent, self.day_received)
+        } else {
+            (0, 0)
+        }
+    }
+
+    pub fn used_today(&self, now: u64) -> u64 {
+        let (sent, received) = self.today(now);
+        sent.saturating_add(received)
+    }
+
+    pub fn exceeded(&self, now: u64, limit: Option<u64>) -> bool {
+        limit.is_some_and(|limit| limit <= self.used_today(now))
+    }
+
+    pub fn mark_suspended(&mut self, now: u64) -> bool {
+        let first = self.suspended_day != Some(now / DAY);
+        self.suspended_day = Some(now / DAY);
+        first
+    }
+
+    pub fn summary_json(&self, now: u64) -> Value {
+        let (day_sent, day_received) = self.today(now);
+        serde_json::json!({
+            "day_sent": day_sent,
+            "day_received": day_received,
+            "total_sent": self.total_sent,
+            "total_received": self.total_received,
+            "since": self.since,
+        })
+    }
+
+    fn from_json(value: Option<&Value>) -> Traffic {
+        let field = |name| value.and_then(|v| v.get(name)).and_then(Value::as_u64);
+        Traffic {
+            day: field("day").unwrap_or(0),
+            day_sent: field("day_sent").unwrap_or(0),
+            day_received: field("day_received").unwrap_or(0),
+            total_sent: field("total_sent").unwrap_or(0),
+            total_received: field("total_received").unwrap_or(0),
+            since: field("since"),
+            suspended_day: field("suspended_day"),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        serde_json::json!({
+            "day": self.day,
+            "day_sent": self.day_sent,
+            "day_received": self.day_received,
+            "total_sent": self.total_sent,
+            "total_received": self.total_received,
+            "since": self.since,
+            "suspended_day": self.suspended_day,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct State {
+    pub auth: AuthBreaker,
+    pub records: BTreeMap<String, RecordState>,
+    pub legacy_ip: Option<Ipv4Addr>,
+    pub session_strategy: SessionStrategy,
+    pub verified_domains: BTreeMap<String, u64>,
+    pub clock_skew: Option<u64>,
+    pub traffic: Traffic,
+    other: Map<String, Value>,
+}
+
+impl State {
+    pub fn from_json(value: Value) -> Result<State, String> {
+        let mut other = match value {
+            Value::Object(map) => map,
+            Value::String(text) => {
+                let ip = addr::parse_ipv4(&text)
+                    .map_err(|_| format!("expected an IPv4 address, found {:?}", text))?;
+                return Ok(State {
+                    legacy_ip: Some(ip),
+                    ..State::default()
+                });
+            }
+            value => return Err(format!("expected an object, found {}", value)),
+        };
+        match other.remove("version") {
+            None => {}
+            Some(version) => match version.as_u64() {
+                Some(version) if version <= VERSION => {}
+                _ => return Err(format!("unsupported version: {}", version)),
+            },
+        }
+        let auth = AuthBreaker::from_json(other.get("auth"));
+        other.remove("auth");
+        let traffic = Traffic::from_json(other.get("traffic"));
+        other.remove("traffic");
+        let records = match other.remove("records") {
+            Some(Value::Object(map)) => map
+                .iter()
+                .map(|(name, value)| (name.clone(), RecordState::from_json(value)))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        let session_strategy = match other
+            .remove("session_strategy")
+            .as_ref()
+            .and_then(Value::as_str)
+        {
+            Some("per_domain") => SessionStrategy::PerDomain,
+            _ => SessionStrategy::Shared,
+        };
+        let legacy_ip = other
+            .remove("legacy_ip")
+            .and_then(|ip| ip.as_str().and_then(|ip| addr::parse_ipv4(ip).ok()));
+        let verified_domains = match other.remove("verified_domains") {
+            Some(Value::Object(map)) => map
+                .iter()
+                .filter_map(|(dom, at)| Some((dom.clone(), at.as_u64()?)))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        let clock_skew = other
+            .remove("clock_skew")
+            .and_then(|skew| skew.get("latest").and_then(Value::as_u64));
+        Ok(State {
+            auth,
+            records,
+            legacy_ip,
+            session_strategy,
+            verified_domains,
+            clock_skew,
+            traffic,
+            other,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        map.insert("version".to_owned(), Value::from(VERSION));
+        map.extend(self.other.clone());
+        map.insert("auth".to_owned(), self.auth.to_json());
+        let records = self
+            .records
+            .iter()
+            .map(|(name, record)| (name.clone(), record.to_json()))
+            .collect();
+        map.insert("records".to_owned(), Value::Object(records));
+        map.insert(
+            "session_strategy".to_owned(),
+            Value::from(self.session_strategy.as_str()),
+        );
+        if let Some(ip) = self.legacy_ip {
+            map.insert("legacy_ip".to_owned(), Value::from(ip.to_string()));
+        }
+        if !self.verified_domains.is_empty() {
+            let verified = self
+                .verified_domains
+                .iter()
+                .map(|(dom, at)| (dom.clone(), Value::from(*at)))
+                .collect();
+            map.insert("verified_domains".to_owned(), Value::Object(verified));
+        }
+        if let Some(latest) = self.clock_skew {
+            let skew = serde_json::json!({ "latest": latest });
+            map.insert("clock_skew".to_owned(), skew);
+        }
+        if Traffic::default() != self.traffic {
+            map.insert("traffic".to_owned(), self.traffic.to_json());
+        }
+        Value::Object(map)
+    }
+
+    pub fn record(&self, name: &str) -> Option<RecordState> {
+        match self.records.get(name) {
+            Some(record) => Some(record.clone()),
+            None => self.legacy_ip.map(|ip| RecordState {
+                ip: Some(ip),
+                last_success: None,
+                offline: false,
+            }),
+        }
+    }
+
+    pub fn plan(&self, name: &str, ip: Ipv4Addr, now: u64, max_age: Option<u64>) -> Outcome {
+        self.record(name)
+            .map_or(Outcome::Updated, |record| record.plan(ip, now, max_age))
+    }
+
+    pub fn plan_offline(&self, name: &str, ip: Ipv4Addr) -> Outcome {
+        match self.record(name) {
+            Some(record) if record.offline && Some(ip) == record.ip => Outcome::Unchanged,
+            _ => Outcome::Offline,
+        }
+    }
+
+    pub fn record_success(&mut self, name: &str, ip: Ipv4Addr, now: u64) {
+        let record = self.records.entry(name.to_owned()).or_default();
+        record.ip = Some(ip);
+        record.last_success = Some(now);
+        record.offline = false;
+        self.legacy_ip = None;
+    }
+
+    pub fn record_offline(&mut self, name: &str, ip: Ipv4Addr, now: u64) {
+        self.record_success(name, ip, now);
+        if let Some(record) = self.records.get_mut(name) {
+            record.offline = true;
+        }
+    }
+
+    pub fn latest_timestamp(&self) -> Option<u64> {
+        let recorded = self
+            .records
+            .values()
+            .filter_map(|record| record.last_success)
+            .chain(self.verified_domains.values().copied());
+        recorded.chain(self.clock_skew).max()
+    }
+
+    pub fn check_clock(&self, now: u64, floor: u64) -> Option<ClockSkew> {
+        if now < floor {
+            return Some(ClockSkew::BeforeFloor(floor));
+        }
+        match self.latest_timestamp() {
+            Some(latest) if now.saturating_add(SKEW_TOLERANCE) < latest => {
+                Some(ClockSkew::Behind(latest))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn mark_skew(&mut self, floor: u64) {
+        let latest = self.latest_timestamp().unwrap_or(floor).max(floor);
+        self.clock_skew = Some(latest);
+    }
+
+    pub fn repair_clock(&mut self, now: u64, cooldown: u64) -> bool {
+        let latest = match self.clock_skew.take() {
+            Some(latest) => latest,
+            None => return false,
+        };
+        for record in self.records.values_mut() {
+            if record.last_success.is_some_and(|at| at < latest) {
+                record.last_success = None;
+            }
+        }
+        self.verified_domains.retain(|_, at| latest <= *at);
+        if self.auth.open_until.is_some_and(|until| until < latest) {
+            self.auth.open_until = Some(now.saturating_add(cooldown));
+        }
+        true
+    }
+
+    pub fn load(path: &Path) -> Result<State, StateError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(State::default()),
+            Err(e) => return Err(StateError::Io(path.to_owned(), e)),
+        };
+        let value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(_) if addr::parse_ipv4(&text).is_ok() => Value::String(text),
+            Err(e) => return Err(StateError::Parse(path.to_owned(), e)),
+        };
+        match value.get("version").and_then(Value::as_u64) {
+            Some(version) if VERSION < version => Err(StateError::TooNew(path.to_owned(), version)),
+            _ => State::from_json(value).map_err(|e| StateError::Invalid(path.to_owned(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        write_json(path, &self.to_json())
+    }
+
+    pub fn apply(&mut self, before: &State, after: &State) {
+        for (name, record) in &after.records {
+            if before.records.get(name) != Some(record) {
+                self.records.insert(name.clone(), record.clone());
+            }
+        }
+        if before.auth != after.auth {
+            self.auth = after.auth.clone();
+        }
+        if before.legacy_ip != after.legacy_ip {
+            self.legacy_ip = after.legacy_ip;
+        }
+        if before.session_strategy != after.session_strategy {
+            self.session_strategy = after.session_strategy;
+        }
+        for (dom, at) in &after.verified_domains {
+            if before.verified_domains.get(dom) != Some(at) {
+                self.verified_domains.insert(dom.clone(), *at);
+            }
+        }
+        for dom in before.verified_domains.keys() {
+            if !after.verified_domains.contains_key(dom) {
+                self.verified_domains.remove(dom);
+            }
+        }
+        if before.clock_skew != after.clock_skew {
+            self.clock_skew = after.clock_skew;
+        }
+        if before.traffic != after.traffic {
+            self.traffic = after.traffic.clone();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StateStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl StateStore {
+    pub fn new(path: impl Into<PathBuf>) -> StateStore {
+        StateStore {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Result<State, StateError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        State::load(&self.path)
+    }
+
+    pub fn update<F, T>(&self, f: F) -> Result<T, StateError>
+    where
+        F: FnOnce(&mut State) -> T,
+    {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let _lock = self.lock_file()?;
+        let mut state = State::load(&self.path)?;
+        let before = state.clone();
+        let out = f(&mut state);
+        if state != before {
+            state.save(&self.path)?;
+        }
+        Ok(out)
+    }
+
+    fn lock_file(&self) -> Result<fs::File, StateError> {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(".lock");
+        let path = self.path.with_file_name(name);
+        let io_err = |e| StateError::Io(path.clone(), e);
+        paths::create_parent(&path).map_err(io_err)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(io_err)?;
+        lock_exclusive(&file).map_err(io_err)?;
+        Ok(file)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Persistence {
+    Files {
+        state: PathBuf,
+        status: PathBuf,
+        lock: PathBuf,
+    },
+    Disabled,
+}
+
+impl Persistence {
+    pub fn is_enabled(&self) -> bool {
+        matches!(self, Persistence::Files { .. })
+    }
+
+    pub fn state_dir(&self) -> Option<&Path> {
+        match self {
+            Persistence::Files { state, .. } => {
+                state.parent().filter(|dir| !dir.as_os_str().is_empty())
+            }
+            Persistence::Disabled => None,
+        }
+    }
+
+    pub fn status_path(&self) -> Option<&Path> {
+        match self {
+            Persistence::Files { status, .. } => Some(status),
+            Persistence::Disabled => None,
+        }
+    }
+
+    pub fn store(&self) -> Option<StateStore> {
+        match self {
+            Persistence::Files { state, .. } => Some(StateStore::new(state)),
+            Persistence::Disabled => None,
+        }
+    }
+
+    pub fn run_lock(
+        &self,
+        timeout: Duration,
+        waiting: impl FnOnce(),
+    ) -> io::Result<Option<RunLock>> {
+        match self {
+            Persistence::Files { lock, .. } => file_lock(lock, timeout, waiting)
+                .map(RunLock::File)
+                .map(Some),
+            Persistence::Disabled => Ok(socket_lock(timeout, waiting)?.map(RunLock::Socket)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RunLock {
+    File(fs::File),
+    Socket(SocketLock),
+}
+
+fn poll_lock<T, F>(timeout: Duration, waiting: impl FnOnce(), mut attempt: F) -> io::Result<T>
+where
+    F: FnMut() -> io::Result<Option<T>>,
+{
+    let start = std::time::Instant::now();
+    let mut waiting = Some(waiting);
+    loop {
+        if let Some(lock) = attempt()? {
+            return Ok(lock);
+        }
+        if timeout <= start.elapsed() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                messages::lock_timeout(timeout),
+            ));
+        }
+        if let Some(waiting) = waiting.take() {
+            waiting();
+        }
+        std::thread::sleep(LOCK_POLL.min(timeout));
+    }
+}
+
+fn file_lock(path: &Path, timeout: Duration, waiting: impl FnOnce()) -> io::Result<fs::File> {
+    paths::create_parent(path)?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    poll_lock(timeout, waiting, || {
+        try_lock_exclusive(&file).map(|locked| Some(&file).filter(|_| locked))
+    })?;
+    Ok(file)
+}
+
+#[derive(Debug)]
+pub struct SocketLock {
+    #[cfg(target_os = "linux")]
+    _listener: std::os::unix::net::UnixListener,
+}
+
+#[cfg(target_os = "linux")]
+pub fn socket_lock(timeout: Duration, waiting: impl FnOnce()) -> io::Result<Option<SocketLock>> {
+    let uid = unsafe { libc::getuid() };
+    bind_lock(&format!("{}.{}.lock", paths::APP, uid), timeout, waiting).map(Some)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_lock(name: &str, timeout: Duration, waiting: impl FnOnce()) -> io::Result<SocketLock> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener};
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    poll_lock(timeout, waiting, || match UnixListener::bind_addr(&addr) {
+        Ok(listener) => Ok(Some(SocketLock {
+            _listener: listener,
+        })),
+        Err(e) if io::ErrorKind::AddrInUse == e.kind() => Ok(None),
+        Err(e) => Err(e),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn socket_lock(_: Duration, _: impl FnOnce()) -> io::Result<Option<SocketLock>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn lock_exclusive(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    loop {
+        if 0 == unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if io::ErrorKind::Interrupted != e.kind() {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn try_lock_exclusive(file: &fs::File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    loop {
+        if 0 == unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+            return Ok(true);
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::Interrupted => {}
+            io::ErrorKind::WouldBlock => return Ok(false),
+            _ => return Err(e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_exclusive(_: &fs::File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn try_lock_exclusive(_: &fs::File) -> io::Result<bool> {
+    Ok(true)
+}
+
+pub fn write_json(path: &Path, value: &Value) -> Result<(), StateError> {
+    let io_err = |e| StateError::Io(path.to_owned(), e);
+    paths::create_parent(path).map_err(io_err)?;
+    let text = serde_json::to_string_pretty(value).unwrap();
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}.tmp", process::id()));
+    let tmp = path.with_file_name(name);
+    let written = fs::File::create(&tmp)
+        .and_then(|mut file| {
+            writeln!(file, "{}", text)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(io_err(e));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_800_000_000;
+    const DAY_30: u64 = 30 * DAY;
+
+    fn state_file(name: &str, records: Value) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ddns_client-state-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let value = serde_json::json!({ "version": VERSION, "records": records });
+        fs::write(&path, value.to_string()).unwrap();
+        path
+    }
+
+    fn plan(name: &str, last_success: Option<u64>, max_age: Option<u64>) -> Outcome {
+        let records = serde_json::json!({
+            "www.example.com": { "ip": "203.0.113.7", "last_success": last_success },
+        });
+        let path = state_file(name, records);
+        let state = State::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        state.plan(
+            "www.example.com",
+            "203.0.113.7".parse().unwrap(),
+            NOW,
+            max_age,
+        )
+    }
+
+    #[test]
+    fn unchanged_without_max_age() {
+        assert_eq!(
+            Outcome::Unchanged,
+            plan("plain", Some(NOW - DAY_30 * 12), None)
+        );
+        assert_eq!(Outcome::Unchanged, plan("no-timestamp", None, None));
+    }
+
+    #[test]
+    fn max_age_refreshes_old_records() {
+        assert_eq!(
+            Outcome::Unchanged,
+            plan("fresh", Some(NOW - DAY), Some(DAY_30))
+        );
+        assert_eq!(
+            Outcome::Refreshed,
+            plan("edge", Some(NOW - DAY_30), Some(DAY_30))
+        );
+        assert_eq!(
+            Outcome::Refreshed,
+            plan("stale", Some(NOW - DAY_30 - 1), Some(DAY_30))
+        );
+        assert_eq!(Outcome::Refreshed, plan("unknown-age", None, Some(DAY_30)));
+    }
+
+    #[test]
+    fn changed_or_unknown_records_update() {
+        let records = serde_json::json!({
+            "www.example.com": { "ip": "203.0.113.7", "last_success": NOW },
+            "dark.example.com": { "ip": "203.0.113.7", "last_success": NOW, "offline": true },
+        });
+        let path = state_file("changed", records);
+        let state = State::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let other = "203.0.113.8".parse().unwrap();
+        let same = "203.0.113.7".parse().unwrap();
+        assert_eq!(
+            Outcome::Updated,
+            state.plan("www.example.com", other, NOW, None)
+        );
+        assert_eq!(
+            Outcome::Updated,
+            state.plan("new.example.com", same, NOW, None)
+        );
+        assert_eq!(
+            Outcome::Updated,
+            state.plan("dark.example.com", same, NOW, None)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn socket_lock_waits_then_times_out() {
+        let name = format!("{}.test.{}.lock", paths::APP, process::id());
+        let held = bind_lock(&name, Duration::ZERO, || panic!("not contended")).unwrap();
+        let mut waited = 0;
+        let start = std::time::Instant::now();
+        let e = bind_lock(&name, Duration::from_millis(300), || waited += 1).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+        assert_eq!(1, waited);
+        assert!(Duration::from_millis(300) <= start.elapsed());
+        drop(held);
+        bind_lock(&name, Duration::ZERO, || panic!("released")).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_lock_waits_then_times_out() {
+        let dir = std::env::temp_dir().join(format!("ddns_client-lock-{}", process::id()));
+        let persistence = Persistence::Files {
+            state: dir.join("state.json"),
+            status: dir.join("status.json"),
+            lock: dir.join("lock"),
+        };
+        let held = persistence
+            .run_lock(Duration::ZERO, || panic!("not contended"))
+            .unwrap();
+        assert!(matches!(held, Some(RunLock::File(_))));
+        let mut waited = 0;
+        let start = std::time::Instant::now();
+        let e = persistence
+            .run_lock(Duration::from_millis(300), || waited += 1)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, e.kind());
+        assert_eq!(1, waited);
+        assert!(Duration::from_millis(300) <= start.elapsed());
+        drop(held);
+        let lock = persistence.run_lock(Duration::ZERO, || panic!("released"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(lock.unwrap().is_some());
+    }
+
+    fn raw_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ddns_client-state-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn round_trip(path: &Path) -> (State, Value) {
+        let state = State::load(path).unwrap();
+        state.save(path).unwrap();
+        let saved: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(State::load(path).unwrap(), state);
+        fs::remove_file(path).unwrap();
+        (state, saved)
+    }
+
+    #[test]
+    fn bare_address_file_migrates_to_current_version() {
+        let path = raw_file("bare", "203.0.113.7\n");
+        let (state, saved) = round_trip(&path);
+        assert_eq!(state.legacy_ip, Some("203.0.113.7".parse().unwrap()));
+        assert!(state.records.is_empty());
+        assert_eq!(saved["version"], VERSION);
+        assert_eq!(saved["legacy_ip"], "203.0.113.7");
+    }
+
+    #[test]
+    fn unversioned_file_migrates_to_current_version() {
+        let text = serde_json::json!({
+            "records": { "www.example.com": { "ip": "203.0.113.7", "last_success": NOW } },
+            "auth": { "failures": 2 },
+            "custom": { "kept": true },
+        });
+        let path = raw_file("v0", &text.to_string());
+        let (state, saved) = round_trip(&path);
+        let record = &state.records["www.example.com"];
+        assert_eq!(record.ip, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(record.last_success, Some(NOW));
+        assert_eq!(state.auth.failures, 2);
+        assert_eq!(state.session_strategy, SessionStrategy::Shared);
+        assert_eq!(saved["version"], VERSION);
+        assert_eq!(saved["custom"], serde_json::json!({ "kept": true }));
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let mut state = State {
+            auth: AuthBreaker {
+                failures: 3,
+                open_until: Some(NOW + 60),
+            },
+            legacy_ip: Some("198.51.100.1".parse().unwrap()),
+            session_strategy: SessionStrategy::PerDomain,
+            clock_skew: Some(NOW - 10),
+            ..State::default()
+        };
+        state.records.insert(
+            "www.example.com".to_owned(),
+            RecordState {
+                ip: Some("203.0.113.7".parse().unwrap()),
+                last_success: Some(NOW),
+                offline: true,
+            },
+        );
+        state.verified_domains.insert("example.com".to_owned(), NOW);
+        state.traffic.add(NOW, 100, 200);
+        let path = raw_file("v1", "");
+        state.save(&path).unwrap();
+        let (loaded, saved) = round_trip(&path);
+        assert_eq!(loaded, state);
+        assert_eq!(saved["version"], VERSION);
+        assert_eq!(saved["session_strategy"], "per_domain");
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let cases = vec![
+            ("truncated", "{\"version\": 1, \"records\": {"),
+            ("garbage", "not a state file"),
+            ("list", "[1, 2]"),
+            ("bad_address", "\"300.1.1.1\""),
+            ("bad_version", "{\"version\": \"one\"}"),
+            ("too_new", "{\"version\": 99}"),
+        ];
+        for (name, text) in cases {
+            let path = raw_file(name, text);
+            let error = State::load(&path).unwrap_err();
+            fs::remove_file(&path).unwrap();
+            match (name, &error) {
+                ("truncated", StateError::Parse(..))
+                | ("garbage", StateError::Parse(..))
+                | ("list", StateError::Invalid(..))
+                | ("bad_address", StateError::Invalid(..))
+                | ("bad_version", StateError::Invalid(..))
+                | ("too_new", StateError::TooNew(_, 99)) => {}
+                _ => panic!("{}: unexpected {:?}", name, error),
+            }
+            assert!(error.to_string().starts_with(&path.display().to_string()));
+        }
+    }
+
+    #[test]
+    fn interrupted_write_keeps_the_old_file() {
+        let path = state_file("interrupted", serde_json::json!({}));
+        let before = fs::read_to_string(&path).unwrap();
+        let mut name = path.file_name().unwrap().to_owned();
+        name.push(format!(".{}.tmp", process::id()));
+        let tmp = path.with_file_name(name);
+        fs::create_dir(&tmp).unwrap();
+        let mut state = State::load(&path).unwrap();
+        state.legacy_ip = Some("203.0.113.7".parse().unwrap());
+        assert!(matches!(state.save(&path), Err(StateError::Io(..))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+        fs::remove_dir(&tmp).unwrap();
+
+        fs::write(&tmp, "{\"version\": 1, \"rec").unwrap();
+        assert_eq!(State::load(&path).unwrap(), State::default());
+        state.save(&path).unwrap();
+        assert!(!tmp.exists());
+        assert_eq!(State::load(&path).unwrap(), state);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        const THREADS: usize = 12;
+        const UPDATES: usize = 20;
+        let path = raw_file("concurrent", "");
+        fs::remove_file(&path).unwrap();
+        let shared = StateStore::new(&path);
+        std::thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let (shared, path) = (&shared, &path);
+                scope.spawn(move || {
+                    let own = StateStore::new(path);
+                    let store = if 0 == thread % 2 { shared } else { &own };
+                    for update in 0..UPDATES {
+                        store
+                            .update(|state| {
+                                let record = RecordState {
+                                    last_success: Some(NOW),
+                                    ..RecordState::default()
+                                };
+                                let name = format!("{}-{}.example.com", thread, update);
+                                state.records.insert(name, record);
+                                state.traffic.add(NOW, 1, 0);
+                            })
+                            .unwrap();
+                    }
+                });
+            }
+        });
+        let text = fs::read_to_string(&path).unwrap();
+        serde_json::from_str::<Value>(&text).unwrap();
+        let state = shared.load().unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_file_name("concurrent.lock")).unwrap();
+        assert_eq!(state.records.len(), THREADS * UPDATES);
+        assert_eq!(state.traffic.total_sent, (THREADS * UPDATES) as u64);
+    }
+
+    const LIMIT: u32 = 3;
+    const COOLDOWN: u64 = 3600;
+
+    fn fail(breaker: &mut AuthBreaker, clock: &crate::clock::MockClock) -> Option<BreakerEvent> {
+        breaker.record_failure(clock.unix_now(), LIMIT, COOLDOWN)
+    }
+
+    #[test]
+    fn breaker_opens_then_half_opens_then_closes() {
+        let clock = crate::clock::MockClock::new(NOW);
+        let mut breaker = AuthBreaker::default();
+        assert_eq!(fail(&mut breaker, &clock), None);
+        assert_eq!(fail(&mut breaker, &clock), None);
+        assert_eq!(breaker.check(clock.unix_now()), Ok(()));
+        assert_eq!(fail(&mut breaker, &clock), Some(BreakerEvent::Opened));
+        assert_eq!(breaker.check(clock.unix_now()), Err(COOLDOWN));
+        assert_eq!(fail(&mut breaker, &clock), None);
+        clock.advance(Duration::from_secs(COOLDOWN - 1));
+        assert!(breaker.is_open(clock.unix_now()));
+        assert_eq!(breaker.check(clock.unix_now()), Err(1));
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!breaker.is_open(clock.unix_now()));
+        assert_eq!(breaker.check(clock.unix_now()), Ok(()));
+        assert_eq!(breaker.open_until, Some(NOW + COOLDOWN));
+        assert_eq!(breaker.record_success(), Some(BreakerEvent::Closed));
+        assert_eq!(breaker, AuthBreaker::default());
+        assert_eq!(breaker.record_success(), None);
+    }
+
+    #[test]
+    fn breaker_reopens_after_a_half_open_failure() {
+        let clock = crate::clock::MockClock::new(NOW);
+        let mut breaker = AuthBreaker::default();
+        for _ in 0..LIMIT {
+            fail(&mut breaker, &clock);
+        }
+        clock.advance(Duration::from_secs(COOLDOWN));
+        let path = raw_file("breaker", "");
+        let state = State {
+            auth: breaker,
+            ..State::default()
+        };
+        state.save(&path).unwrap();
+        let mut breaker = State::load(&path).unwrap().auth;
+        fs::remove_file(&path).unwrap();
+        assert_eq!(breaker.check(clock.unix_now()), Ok(()));
+        assert_eq!(fail(&mut breaker, &clock), Some(BreakerEvent::Opened));
+        assert_eq!(breaker.failures, LIMIT + 1);
+        assert_eq!(breaker.check(clock.unix_now()), Err(COOLDOWN));
+        assert_eq!(breaker.open_until, Some(NOW + 2 * COOLDOWN));
+    }
+
+    #[test]
+    fn breaker_without_a_limit_never_opens() {
+        let mut breaker = AuthBreaker::default();
+        for _ in 0..10 {
+            assert_eq!(breaker.record_failure(NOW, 0, COOLDOWN), None);
+        }
+        assert_eq!(breaker.check(NOW), Ok(()));
+        assert_eq!(breaker.record_success(), None);
+    }
+
+    #[test]
+    fn mapped_and_padded_addresses_load_as_ipv4() {
+        let ip: Ipv4Addr = "203.0.113.7".parse().unwrap();
+        let path = raw_file("mapped-bare", " ::ffff:203.0.113.7\n");
+        assert_eq!(Some(ip), round_trip(&path).0.legacy_ip);
+
+        let text = serde_json::json!({
+            "version": VERSION,
+            "records": {
+                "a.example.com": { "ip": "::FFFF:203.0.113.7" },
+                "b.example.com": { "ip": "fe80::1%eth0" },
+            },
+        });
+        let path = raw_file("mapped", &text.to_string());
+        let (state, saved) = round_trip(&path);
+        assert_eq!(Some(ip), state.record("a.example.com").unwrap().ip);
+        assert_eq!(None, state.record("b.example.com").unwrap().ip);
+        assert_eq!(saved["records"]["a.example.com"]["ip"], "203.0.113.7");
+    }
+
+    #[test]
+    fn parking_forces_the_next_normal_update() {
+        let live: Ipv4Addr = "203.0.113.7".parse().unwrap();
+        let parked = Ipv4Addr::UNSPECIFIED;
+        let name = "www.example.com";
+        let mut state = State::default();
+        assert_eq!(Outcome::Offline, state.plan_offline(name, parked));
+        state.record_success(name, live, NOW);
+        assert_eq!(Outcome::Unchanged, state.plan(name, live, NOW, None));
+        assert_eq!(Outcome::Offline, state.plan_offline(name, parked));
+
+        state.record_offline(name, parked, NOW);
+        assert!(state.record(name).unwrap().offline);
+        assert_eq!(Outcome::Unchanged, state.plan_offline(name, parked));
+        assert_eq!(Outcome::Offline, state.plan_offline(name, live));
+        assert_eq!(Outcome::Updated, state.plan(name, parked, NOW, None));
+        assert_eq!(Outcome::Updated, state.plan(name, live, NOW, Some(3600)));
+
+        let path = raw_file("offline", "");
+        state.save(&path).unwrap();
+        let (loaded, saved) = round_trip(&path);
+        assert_eq!(saved["records"][name]["offline"], true);
+        assert_eq!(Outcome::Updated, loaded.plan(name, parked, NOW, None));
+
+        state.record_success(name, live, NOW);
+        assert!(!state.record(name).unwrap().offline);
+        assert_eq!(Outcome::Unchanged, state.plan(name, live, NOW, None));
+        let path = raw_file("online", "");
+        state.save(&path).unwrap();
+        assert!(round_trip(&path).1["records"][name]
+            .get("offline")
+            .is_none());
+    }
+
+    #[test]
+    fn traffic_budget_resets_when_the_utc_day_rolls_over() {
+        let midnight = (NOW / DAY + 1) * DAY;
+        let clock = crate::clock::MockClock::new(midnight - 10);
+        let mut traffic = Traffic::default();
+        assert!(!traffic.exceeded(clock.unix_now(), Some(1000)));
+        traffic.add(clock.unix_now(), 300, 500);
+        assert_eq!(traffic.today(clock.unix_now()), (300, 500));
+        assert!(!traffic.exceeded(clock.unix_now(), Some(1000)));
+        assert!(!traffic.exceeded(clock.unix_now(), None));
+        traffic.add(clock.unix_now(), 100, 100);
+        assert!(traffic.exceeded(clock.unix_now(), Some(1000)));
+        assert!(traffic.mark_suspended(clock.unix_now()));
+        clock.advance(Duration::from_secs(9));
+        assert!(traffic.exceeded(clock.unix_now(), Some(1000)));
+        assert!(!traffic.mark_suspended(clock.unix_now()));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(traffic.today(clock.unix_now()), (0, 0));
+        assert!(!traffic.exceeded(clock.unix_now(), Some(1000)));
+        traffic.add(clock.unix_now(), 40, 60);
+        assert_eq!(traffic.used_today(clock.unix_now()), 100);
+        assert_eq!((traffic.total_sent, traffic.total_received), (440, 660));
+        assert_eq!(traffic.since, Some(midnight - 10));
+        traffic.add(clock.unix_now(), 1000, 0);
+        assert!(traffic.exceeded(clock.unix_now(), Some(1000)));
+        assert!(traffic.mark_suspended(clock.unix_now()));
+
+        let summary = traffic.summary_json(clock.unix_now());
+        assert_eq!(summary["day_sent"], 1040);
+        assert_eq!(summary["total_received"], 660);
+        clock.advance(Duration::from_secs(DAY));
+        let summary = traffic.summary_json(clock.unix_now());
+        assert_eq!(summary["day_sent"], 0);
+        assert_eq!(summary["total_sent"], 1440);
+
+        let state = State {
+            traffic: traffic.clone(),
+            ..State::default()
+        };
+        let path = raw_file("traffic", "{}");
+        state.save(&path).unwrap();
+        let (loaded, saved) = round_trip(&path);
+        assert_eq!(loaded.traffic, traffic);
+        assert_eq!(saved["traffic"]["suspended_day"], midnight / DAY);
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use serde_json::Value;
+
+pub const DEFAULT_WINDOW: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+    pub samples: usize,
+}
+
+impl Summary {
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "p50": self.p50.as_secs_f64(),
+            "p95": self.p95.as_secs_f64(),
+            "max": self.max.as_secs_f64(),
+            "samples": self.samples,
+        })
+    }
+}
+
+pub fn percentile(sorted: &[Duration], p: u32) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p as usize * sorted.len()).div_ceil(100);
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyWindow {
+    capacity: usize,
+    cycles: VecDeque<Vec<(String, Duration)>>,
+}
+
+impl Default for LatencyWindow {
+    fn default() -> LatencyWindow {
+        LatencyWindow::new(DEFAULT_WINDOW)
+    }
+}
+
+impl LatencyWindow {
+    pub fn new(capacity: usize) -> LatencyWindow {
+        LatencyWindow {
+            capacity: capacity.max(1),
+            cycles: VecDeque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cycles.is_empty()
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.capacity < self.cycles.len() {
+            self.cycles.pop_front();
+        }
+    }
+
+    pub fn push(&mut self, cycle: Vec<(String, Duration)>) {
+        self.cycles.push_back(cycle);
+        self.set_capacity(self.capacity);
+    }
+
+    pub fn summary(&self) -> BTreeMap<String, Summary> {
+        let mut samples: BTreeMap<String, Vec<Duration>> = BTreeMap::new();
+        for (stage, elapsed) in self.cycles.iter().flatten() {
+            samples.entry(stage.clone()).or_default().push(*elapsed);
+        }
+        samples
+            .into_iter()
+            .map(|(stage, mut durations)| {
+                durations.sort();
+                let summary = Summary {
+                    p50: percentile(&durations, 50),
+                    p95: percentile(&durations, 95),
+                    max: durations[durations.len() - 1],
+                    samples: durations.len(),
+                };
+                (stage, summary)
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let cycles: Vec<Value> = self
+            .cycles
+            .iter()
+            .map(|cycle| {
+                cycle
+                    .iter()
+                    .map(|(stage, elapsed)| serde_json::json!([stage, elapsed.as_secs_f64()]))
+                    .collect()
+            })
+            .collect();
+        let summary = self
+            .summary()
+            .into_iter()
+            .map(|(stage, summary)| (stage, summary.to_json()))
+            .collect();
+        serde_json::json!({
+            "window": self.capacity,
+            "cycles": cycles,
+            "summary": Value::Object(summary),
+        })
+    }
+
+    pub fn from_json(value: &Value) -> LatencyWindow {
+        let capacity = value
+            .get("window")
+            .and_then(Value::as_u64)
+            .map_or(DEFAULT_WINDOW, |n| n as usize);
+        let mut window = LatencyWindow::new(capacity);
+        let cycles = value.get("cycles").and_then(Value::as_array);
+        for cycle in cycles.into_iter().flatten() {
+            let samples = cycle
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|sample| {
+                    let stage = sample.get(0)?.as_str()?;
+                    let secs = sample.get(1)?.as_f64().filter(|secs| 0.0 <= *secs)?;
+                    Some((stage.to_owned(), Duration::from_secs_f64(secs)))
+                })
+                .collect();
+            window.push(samples);
+        }
+        window
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::state::{self, StateError, VERSION};
+use crate::stats::LatencyWindow;
+
+pub const DEFAULT_INTERVAL: u64 = 300;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Status {
+    pub last_cycle: u64,
+    pub interval: Option<u64>,
+    pub ip: Option<Ipv4Addr>,
+    pub failures: u32,
+    pub records: BTreeMap<String, Option<u64>>,
+    pub latency: LatencyWindow,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub config_hash: Option<String>,
+    pub detection: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Ok,
+    Failing(u32),
+    Stalled(u64),
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Health::Ok => write!(f, "ok"),
+            Health::Failing(n) => write!(f, "failing ({} consecutive failed runs)", n),
+            Health::Stalled(_) => write!(f, "daemon appears stalled"),
+        }
+    }
+}
+
+pub fn config_hash<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+    let mut names: Vec<&str> = names.into_iter().collect();
+    names.sort_unstable();
+    names.dedup();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in names.join("\n").bytes() {
+        hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+impl Status {
+    pub fn is_daemon(&self, now: u64) -> bool {
+        self.interval.is_some() && !matches!(self.health(now), Health::Stalled(_))
+    }
+
+    pub fn manages(&self, config_hash: &str) -> bool {
+        self.config_hash.as_deref() == Some(config_hash)
+    }
+
+    pub fn next_run(&self) -> u64 {
+        self.last_cycle + self.interval.unwrap_or(DEFAULT_INTERVAL)
+    }
+
+    pub fn health(&self, now: u64) -> Health {
+        let age = now.saturating_sub(self.last_cycle);
+        if 2 * self.interval.unwrap_or(DEFAULT_INTERVAL) < age {
+            Health::Stalled(age)
+        } else if 0 < self.failures {
+            Health::Failing(self.failures)
+        } else {
+            Health::Ok
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let records = self
+            .records
+            .iter()
+            .map(|(name, last)| (name.clone(), serde_json::json!({ "last_success": last })))
+            .collect();
+        serde_json::json!({
+            "version": VERSION,
+            "last_cycle": self.last_cycle,
+            "interval": self.interval,
+            "next_run": self.next_run(),
+            "ip": self.ip.map(|ip| ip.to_string()),
+            "failures": self.failures,
+            "records": Value::Object(records),
+            "latency": self.latency.to_json(),
+            "bytes_sent": self.bytes_sent,
+            "bytes_received": self.bytes_received,
+            "config_hash": self.config_hash,
+            "detection": self.detection,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Status, String> {
+        let map = match value {
+            Value::Object(map) => map,
+            value => return Err(format!("expected an object, found {}", value)),
+        };
+        match map.get("version").and_then(Value::as_u64) {
+            Some(version) if version <= VERSION => {}
+            version => return Err(format!("unsupported version: {:?}", version)),
+        }
+        let records = match map.get("records") {
+            Some(Value::Object(records)) => records
+                .iter()
+                .map(|(name, record)| {
+                    let last = record.get("last_success").and_then(Value::as_u64);
+                    (name.clone(), last)
+                })
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        let number = |map: &Map<String, Value>, key| map.get(key).and_then(Value::as_u64);
+        Ok(Status {
+            last_cycle: number(map, "last_cycle").ok_or("missing last_cycle")?,
+            interval: number(map, "interval"),
+            ip: map
+                .get("ip")
+                .and_then(Value::as_str)
+                .and_then(|ip| ip.parse().ok()),
+            failures: number(map, "failures").unwrap_or_default() as u32,
+            records,
+            latency: map
+                .get("latency")
+                .map_or_else(LatencyWindow::default, LatencyWindow::from_json),
+            bytes_sent: number(map, "bytes_sent").unwrap_or_default(),
+            bytes_received: number(map, "bytes_received").unwrap_or_default(),
+            config_hash: map
+                .get("config_hash")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            detection: match map.get("detection") {
+                Some(Value::Object(failed)) => failed
+                    .iter()
+                    .filter_map(|(source, at)| Some((source.clone(), at.as_u64()?)))
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Status>, StateError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if io::ErrorKind::NotFound == e.kind() => return Ok(None),
+            Err(e) => return Err(StateError::Io(path.to_owned(), e)),
+        };
+        let value: Value =
+            serde_json::from_str(&text).map_err(|e| StateError::Parse(path.to_owned(), e))?;
+        match value.get("version").and_then(Value::as_u64) {
+            Some(version) if VERSION < version => Err(StateError::TooNew(path.to_owned(), version)),
+            _ => Status::from_json(&value)
+                .map(Some)
+                .map_err(|e| StateError::Invalid(path.to_owned(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        state::write_json(path, &self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_800_000_000;
+
+    #[test]
+    fn config_hash_ignores_order_and_duplicates() {
+        let hash = config_hash(vec!["u@a.example.com", "u@b.example.com"]);
+        assert_eq!(16, hash.len());
+        assert_eq!(
+            hash,
+            config_hash(vec![
+                "u@b.example.com",
+                "u@a.example.com",
+                "u@b.example.com"
+            ])
+        );
+        assert_ne!(hash, config_hash(vec!["u@a.example.com"]));
+        assert_ne!(
+            hash,
+            config_hash(vec!["v@a.example.com", "u@b.example.com"])
+        );
+    }
+
+    #[test]
+    fn config_hash_round_trips() {
+        let status = Status {
+            last_cycle: NOW,
+            interval: Some(60),
+            config_hash: Some(config_hash(vec!["u@a.example.com"])),
+            detection: vec![("url:https://ip.example.net".to_owned(), NOW - 60)]
+                .into_iter()
+                .collect(),
+            ..Status::default()
+        };
+        let loaded = Status::from_json(&status.to_json()).unwrap();
+        assert_eq!(status, loaded);
+        assert!(loaded.manages(&config_hash(vec!["u@a.example.com"])));
+        assert!(!loaded.manages(&config_hash(vec!["u@b.example.com"])));
+        let old = serde_json::json!({ "version": VERSION, "last_cycle": NOW });
+        assert!(!Status::from_json(&old).unwrap().manages(""));
+    }
+
+    #[test]
+    fn only_live_interval_runs_are_daemons() {
+        let daemon = Status {
+            last_cycle: NOW,
+            interval: Some(60),
+            ..Status::default()
+        };
+        assert!(daemon.is_daemon(NOW + 60));
+        assert!(!daemon.is_daemon(NOW + 121));
+        let oneshot = Status {
+            interval: None,
+            ..daemon
+        };
+        assert!(!oneshot.is_daemon(NOW));
+    }
+
+    #[test]
+    fn health_goes_stale_after_two_intervals() {
+        let status = Status {
+            last_cycle: NOW,
+            interval: Some(60),
+            ..Status::default()
+        };
+        assert_eq!(NOW + 60, status.next_run());
+        assert_eq!(Health::Ok, status.health(NOW - 5));
+        assert_eq!(Health::Ok, status.health(NOW + 120));
+        assert_eq!(Health::Stalled(121), status.health(NOW + 121));
+        assert_eq!("daemon appears stalled", Health::Stalled(121).to_string());
+
+        let failing = Status {
+            failures: 2,
+            ..status.clone()
+        };
+        assert_eq!(Health::Failing(2), failing.health(NOW + 60));
+        assert_eq!(Health::Stalled(600), failing.health(NOW + 600));
+        assert_eq!(
+            "failing (2 consecutive failed runs)",
+            Health::Failing(2).to_string()
+        );
+
+        let oneshot = Status {
+            interval: None,
+            ..status
+        };
+        assert_eq!(NOW + DEFAULT_INTERVAL, oneshot.next_run());
+        assert_eq!(Health::Ok, oneshot.health(NOW + 2 * DEFAULT_INTERVAL));
+        assert!(matches!(
+            oneshot.health(NOW + 2 * DEFAULT_INTERVAL + 1),
+            Health::Stalled(_)
+        ));
+    }
+
+    #[test]
+    fn status_file_round_trips() {
+        let path =
+            std::env::temp_dir().join(format!("ddns_client-status-{}.json", std::process::id()));
+        assert_eq!(None, Status::load(&path).unwrap());
+        let status = Status {
+            last_cycle: NOW,
+            interval: Some(300),
+            ip: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            failures: 1,
+            records: vec![
+                ("a.example.com".to_owned(), Some(NOW - 300)),
+                ("b.example.com".to_owned(), None),
+            ]
+            .into_iter()
+            .collect(),
+            bytes_sent: 10,
+            bytes_received: 20,
+            ..Status::default()
+        };
+        status.save(&path).unwrap();
+        assert_eq!(Some(status), Status::load(&path).unwrap());
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(Some(VERSION), json["version"].as_u64());
+        assert_eq!(Some(NOW + 300), json["next_run"].as_u64());
+
+        fs::write(&path, format!("{{\"version\":{}}}", VERSION + 1)).unwrap();
+        assert!(matches!(Status::load(&path), Err(StateError::TooNew(_, _))));
+        fs::write(&path, format!("{{\"version\":{}}}", VERSION)).unwrap();
+        assert!(matches!(
+            Status::load(&path),
+            Err(StateError::Invalid(_, _))
+        ));
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(Status::load(&path), Err(StateError::Parse(_, _))));
+        fs::remove_file(&path).unwrap();
+    }
+}
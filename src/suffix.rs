@@ -0,0 +1,66 @@
+#[cfg(feature = "psl")]
+static LIST: &str = include_str!("public_suffix_list.dat");
+
+#[cfg(feature = "psl")]
+fn suffix_len(labels: &[&str]) -> Result<usize, String> {
+    let mut best = 1;
+    for rule in LIST.lines() {
+        let rule = match rule.split_whitespace().next() {
+            Some(rule) if !rule.starts_with("//") => rule,
+            _ => continue,
+        };
+        let (exception, rule) = match rule.strip_prefix('!') {
+            Some(rule) => (true, rule),
+            None => (false, rule),
+        };
+        let parts: Vec<&str> = rule.split('.').collect();
+        if labels.len() < parts.len() {
+            continue;
+        }
+        let matched = parts
+            .iter()
+            .rev()
+            .zip(labels.iter().rev())
+            .all(|(part, label)| "*" == *part || part.eq_ignore_ascii_case(label));
+        if !matched {
+            continue;
+        }
+        if exception {
+            return Ok(parts.len() - 1);
+        }
+        best = best.max(parts.len());
+    }
+    Ok(best)
+}
+
+#[cfg(not(feature = "psl"))]
+fn suffix_len(labels: &[&str]) -> Result<usize, String> {
+    let tld = labels[labels.len() - 1];
+    let second = labels[labels.len() - 2];
+    if 2 < labels.len() && 2 == tld.len() && second.len() <= 3 {
+        return Err(format!(
+            "cannot tell whether {}.{} is a public suffix without the psl feature",
+            second, tld
+        ));
+    }
+    Ok(1)
+}
+
+pub fn split_record(fqdn: &str) -> Result<(String, String), String> {
+    let fqdn = fqdn.trim_end_matches('.');
+    let labels: Vec<&str> = fqdn.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|label| label.is_empty()) {
+        return Err(format!("{:?} is not a fully qualified name", fqdn));
+    }
+    let suffix = suffix_len(&labels).map_err(|message| {
+        format!(
+            "{}; set dom to say where the registered domain of {:?} starts",
+            message, fqdn
+        )
+    })?;
+    if labels.len() <= suffix {
+        return Err(format!("{:?} is a public suffix", fqdn));
+    }
+    let split = labels.len() - suffix - 1;
+    Ok((labels[..split].join("."), labels[split..].join(".")))
+}
@@ -0,0 +1,414 @@
+#[cfg(feature = "tls-openssl")]
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "tls-openssl")]
+use std::fs;
+#[cfg(feature = "tls-openssl")]
+use std::net::IpAddr;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[cfg(feature = "tls-openssl")]
+use openssl::ssl::{HandshakeError, SslConnector, SslMethod, SslRef, SslStream, SslVersion};
+#[cfg(feature = "tls-openssl")]
+use openssl::x509::{X509NameRef, X509Ref, X509VerifyResult, X509};
+
+use crate::messages;
+
+#[cfg(feature = "tls-openssl")]
+pub const BACKEND: &str = "openssl";
+#[cfg(not(feature = "tls-openssl"))]
+pub const BACKEND: &str = "none";
+
+#[cfg(feature = "tls-openssl")]
+pub type Connector = SslConnector;
+#[cfg(feature = "tls-openssl")]
+pub type Stream = SslStream<TcpStream>;
+
+#[cfg(not(feature = "tls-openssl"))]
+#[derive(Debug, Clone)]
+pub struct Connector;
+#[cfg(not(feature = "tls-openssl"))]
+pub type Stream = TcpStream;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    pub names: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+impl fmt::Display for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = match self.names.len() {
+            0 => "-".to_owned(),
+            _ => self.names.join(","),
+        };
+        write!(
+            f,
+            "subject={} issuer={} san={} valid={}..{}",
+            self.subject, self.issuer, names, self.not_before, self.not_after
+        )
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+fn name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            format!(
+                "{}={}",
+                key,
+                String::from_utf8_lossy(entry.data().as_slice())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(feature = "tls-openssl")]
+impl From<&X509Ref> for Certificate {
+    fn from(cert: &X509Ref) -> Certificate {
+        let names = cert.subject_alt_names().map_or_else(Vec::new, |names| {
+            names
+                .iter()
+                .filter_map(|general| match (general.dnsname(), general.ipaddress()) {
+                    (Some(dns), _) => Some(format!("DNS:{}", dns)),
+                    (None, Some(ip)) => <[u8; 4]>::try_from(ip)
+                        .map(IpAddr::from)
+                        .or_else(|_| <[u8; 16]>::try_from(ip).map(IpAddr::from))
+                        .ok()
+                        .map(|ip| format!("IP:{}", ip)),
+                    (None, None) => None,
+                })
+                .collect()
+        });
+        Certificate {
+            subject: name(cert.subject_name()),
+            issuer: name(cert.issuer_name()),
+            names,
+            not_before: cert.not_before().to_string(),
+            not_after: cert.not_after().to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+fn chain(ssl: &SslRef) -> Vec<Certificate> {
+    match ssl.peer_cert_chain() {
+        Some(chain) => chain.iter().map(Certificate::from).collect(),
+        None => ssl
+            .peer_certificate()
+            .map_or_else(Vec::new, |cert| vec![Certificate::from(&*cert)]),
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn peer_certificates(stream: &Stream) -> Vec<Certificate> {
+    chain(stream.ssl())
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn peer_certificates(_: &Stream) -> Vec<Certificate> {
+    Vec::new()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TlsVersion {
+    #[default]
+    V1_2,
+    V1_3,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TlsVersion::V1_2 => write!(f, "1.2"),
+            TlsVersion::V1_3 => write!(f, "1.3"),
+        }
+    }
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TlsVersion, String> {
+        match s {
+            "1.2" => Ok(TlsVersion::V1_2),
+            "1.3" => Ok(TlsVersion::V1_3),
+            _ => Err(format!(
+                "unsupported TLS version {:?}: expected 1.2 or 1.3",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct TlsOptions {
+    pub min_version: TlsVersion,
+    pub ciphers: Option<String>,
+    pub ca_file: Option<PathBuf>,
+}
+
+impl TlsOptions {
+    pub fn connector(&self) -> Result<Connector, String> {
+        self.load().map(|(connector, _)| connector)
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    pub fn load(&self) -> Result<(Connector, usize), String> {
+        let mut loaded = 0;
+        let mut builder = self.build().map_err(|e| e.to_string())?;
+        if let Some(path) = &self.ca_file {
+            let failed = |e: &dyn fmt::Display| messages::ca_file_failed(path, e);
+            let pem = fs::read(path).map_err(|e| failed(&e))?;
+            let certs = X509::stack_from_pem(&pem).map_err(|e| failed(&e))?;
+            if certs.is_empty() {
+                return Err(failed(&"no PEM certificates found"));
+            }
+            loaded = certs.len();
+            for cert in certs {
+                builder
+                    .cert_store_mut()
+                    .add_cert(cert)
+                    .map_err(|e| failed(&e))?;
+            }
+        }
+        Ok((builder.build(), loaded))
+    }
+
+    #[cfg(not(feature = "tls-openssl"))]
+    pub fn load(&self) -> Result<(Connector, usize), String> {
+        Ok((Connector, 0))
+    }
+
+    #[cfg(feature = "tls-openssl")]
+    fn build(&self) -> Result<openssl::ssl::SslConnectorBuilder, openssl::error::ErrorStack> {
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        builder.set_min_proto_version(Some(match self.min_version {
+            TlsVersion::V1_2 => SslVersion::TLS1_2,
+            TlsVersion::V1_3 => SslVersion::TLS1_3,
+        }))?;
+        if let Some(ciphers) = &self.ciphers {
+            let (suites, list): (Vec<&str>, Vec<&str>) = ciphers
+                .split(':')
+                .filter(|name| !name.is_empty())
+                .partition(|name| name.starts_with("TLS_"));
+            if !suites.is_empty() {
+                builder.set_ciphersuites(&suites.join(":"))?;
+            }
+            if !list.is_empty() {
+                builder.set_cipher_list(&list.join(":"))?;
+            }
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn default_connector() -> Result<Connector, String> {
+    SslConnector::builder(SslMethod::tls())
+        .map(|builder| builder.build())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn default_connector() -> Result<Connector, String> {
+    Ok(Connector)
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn connect(connector: &Connector, domain: &str, stream: TcpStream) -> Result<Stream, String> {
+    connector.connect(domain, stream).map_err(|e| match e {
+        HandshakeError::Failure(mid) if X509VerifyResult::OK != mid.ssl().verify_result() => {
+            let presented = chain(mid.ssl());
+            messages::certificate_rejected(mid.ssl().verify_result().error_string(), &presented)
+        }
+        e => e.to_string(),
+    })
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn connect(_: &Connector, _: &str, _: TcpStream) -> Result<Stream, String> {
+    Err(messages::tls_unsupported())
+}
+
+#[cfg(feature = "tls-openssl")]
+pub fn tcp(stream: &Stream) -> &TcpStream {
+    stream.get_ref()
+}
+
+#[cfg(not(feature = "tls-openssl"))]
+pub fn tcp(stream: &Stream) -> &TcpStream {
+    stream
+}
+
+#[cfg(all(test, feature = "tls-openssl"))]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::ssl::SslAcceptor;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::X509NameBuilder;
+
+    fn certificate(common_name: &str) -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+            .unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&cert.x509v3_context(None, None))
+            .unwrap();
+        cert.append_extension(san).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        (cert.build(), key)
+    }
+
+    fn handshake(options: &TlsOptions, server_max: SslVersion) -> Result<Stream, String> {
+        let (cert, key) = certificate("localhost");
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&cert).unwrap();
+        acceptor.set_max_proto_version(Some(server_max)).unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            acceptor.accept(tcp).map(drop).is_ok()
+        });
+        let ca_file = std::env::temp_dir().join(format!(
+            "ddns_client-tls-{}-{}.pem",
+            std::process::id(),
+            addr.port()
+        ));
+        fs::write(&ca_file, cert.to_pem().unwrap()).unwrap();
+        let options = TlsOptions {
+            ca_file: Some(ca_file.clone()),
+            ..options.clone()
+        };
+        let connector = options.connector();
+        fs::remove_file(&ca_file).unwrap();
+        let result = connect(
+            &connector.unwrap(),
+            "localhost",
+            TcpStream::connect(addr).unwrap(),
+        );
+        let accepted = server.join().unwrap();
+        assert_eq!(accepted, result.is_ok());
+        result
+    }
+
+    #[test]
+    fn versions_parse() {
+        assert_eq!(Ok(TlsVersion::V1_2), "1.2".parse());
+        assert_eq!(Ok(TlsVersion::V1_3), "1.3".parse());
+        assert_eq!(TlsVersion::V1_2, TlsVersion::default());
+        for text in ["1.1", "1.0", "1", "tls1.3", " 1.3"] {
+            assert!(text.parse::<TlsVersion>().is_err(), "{:?}", text);
+        }
+        assert_eq!("1.3", TlsVersion::V1_3.to_string());
+    }
+
+    #[test]
+    fn invalid_ciphers_fail_when_building_the_connector() {
+        for ciphers in [
+            "NOT-A-CIPHER",
+            "TLS_NOT_A_SUITE",
+            "ECDHE-RSA-AES128-GCM-SHA256:TLS_BOGUS",
+        ] {
+            let options = TlsOptions {
+                ciphers: Some(ciphers.to_owned()),
+                ..TlsOptions::default()
+            };
+            assert!(options.connector().is_err(), "{}", ciphers);
+        }
+        let options = TlsOptions {
+            ciphers: Some("ECDHE-ECDSA-AES128-GCM-SHA256:TLS_AES_128_GCM_SHA256".to_owned()),
+            ..TlsOptions::default()
+        };
+        assert!(options.connector().is_ok());
+    }
+
+    #[test]
+    fn tls13_client_refuses_a_tls12_server() {
+        let modern = TlsOptions {
+            min_version: TlsVersion::V1_3,
+            ..TlsOptions::default()
+        };
+        let e = handshake(&modern, SslVersion::TLS1_2).unwrap_err();
+        assert!(e.contains("protocol version"), "{}", e);
+        let stream = handshake(&TlsOptions::default(), SslVersion::TLS1_2).unwrap();
+        assert!(Some(SslVersion::TLS1_2) == stream.ssl().version2());
+        assert!(handshake(&modern, SslVersion::TLS1_3).is_ok());
+    }
+
+    #[test]
+    fn reloaded_ca_file_trusts_the_rotated_certificate() {
+        let (old, _) = certificate("ddns_client old CA");
+        let (new, key) = certificate("ddns_client new CA");
+        let ca_file =
+            std::env::temp_dir().join(format!("ddns_client-tls-{}-rotate.pem", std::process::id()));
+        let options = TlsOptions {
+            ca_file: Some(ca_file.clone()),
+            ..TlsOptions::default()
+        };
+        let connect_to_new = |connector: &Connector| {
+            let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+            acceptor.set_private_key(&key).unwrap();
+            acceptor.set_certificate(&new).unwrap();
+            let acceptor = acceptor.build();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = thread::spawn(move || {
+                let (tcp, _) = listener.accept().unwrap();
+                let _ = acceptor.accept(tcp);
+            });
+            let result = connect(connector, "localhost", TcpStream::connect(addr).unwrap());
+            server.join().unwrap();
+            result.is_ok()
+        };
+
+        fs::write(&ca_file, old.to_pem().unwrap()).unwrap();
+        let (connector, count) = options.load().unwrap();
+        assert_eq!(1, count);
+        assert!(!connect_to_new(&connector));
+
+        let mut bundle = old.to_pem().unwrap();
+        bundle.extend(new.to_pem().unwrap());
+        fs::write(&ca_file, bundle).unwrap();
+        let (connector, count) = options.load().unwrap();
+        assert_eq!(2, count);
+        assert!(connect_to_new(&connector));
+
+        fs::write(&ca_file, "").unwrap();
+        let e = options.load().err().unwrap();
+        assert!(e.contains("no PEM certificates found"), "{}", e);
+        fs::remove_file(&ca_file).unwrap();
+        assert!(options.load().is_err());
+    }
+}
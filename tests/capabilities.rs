@@ -0,0 +1,77 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn snapshot_name() -> Option<&'static str> {
+    let features = (
+        cfg!(feature = "dns"),
+        cfg!(feature = "tls-openssl"),
+        cfg!(feature = "psl") || cfg!(feature = "ffi") || cfg!(feature = "testing"),
+    );
+    match features {
+        (true, true, false) => Some("capabilities-default.json"),
+        (false, false, false) => Some("capabilities-minimal.json"),
+        _ => None,
+    }
+}
+
+#[test]
+fn capabilities_match_snapshot() {
+    let output = Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .args(["capabilities", "--output", "json"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(env!("CARGO_PKG_VERSION"), document["version"]);
+    let name = match snapshot_name() {
+        Some(name) => name,
+        None => return,
+    };
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    let pretty = format!("{}\n", serde_json::to_string_pretty(&document).unwrap());
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &pretty).unwrap();
+    }
+    let snapshot = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        snapshot, pretty,
+        "capabilities changed; rerun with UPDATE_SNAPSHOTS=1 to accept"
+    );
+}
+
+#[test]
+fn capabilities_text_lists_every_key() {
+    let output = Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .arg("capabilities")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    for key in [
+        "capabilities_version",
+        "features",
+        "subcommands",
+        "exit_codes",
+    ] {
+        let prefix = format!("{}: ", key);
+        assert!(
+            text.lines().any(|line| line.starts_with(&prefix)),
+            "{}",
+            text
+        );
+    }
+    assert!(text.contains("capabilities"), "{}", text);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .args(["capabilities", "--output", "yaml"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
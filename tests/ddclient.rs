@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use ddns_client::config::{Config, Diagnostic};
+use ddns_client::ddclient;
+use ddns_client::detect::IpSource;
+use ddns_client::KEY;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/ddclient")
+        .join(name)
+}
+
+fn records(config: &Config) -> Vec<(String, String, String)> {
+    config
+        .records
+        .iter()
+        .map(|record| {
+            let dom = record.dom.as_ref().unwrap();
+            let ip = record.ip.as_ref().unwrap();
+            (
+                record.host.value.clone(),
+                dom.value.clone(),
+                ip.value.to_string(),
+            )
+        })
+        .collect()
+}
+
+fn warnings(warnings: &[Diagnostic]) -> Vec<String> {
+    warnings.iter().map(|w| w.to_string()).collect()
+}
+
+fn value(config: &Config, key: KEY) -> &str {
+    &config.get(&key).unwrap().value
+}
+
+#[test]
+fn web_fixture_maps_onto_records() {
+    let (config, found) = ddclient::from_file(&fixture("web.conf")).unwrap();
+    assert_eq!("alice", value(&config, KEY::USER));
+    assert_eq!("p@ss word#1", value(&config, KEY::PASS));
+    let web = "url:http://checkip.dyndns.org/".to_owned();
+    assert_eq!(
+        vec![
+            ("www".to_owned(), "example.com".to_owned(), web.clone()),
+            ("mail".to_owned(), "example.com".to_owned(), web.clone()),
+            ("home".to_owned(), "example.co.jp".to_owned(), web),
+        ],
+        records(&config)
+    );
+    let found = warnings(&found);
+    assert_eq!(5, found.len(), "{:?}", found);
+    for key in ["daemon", "syslog", "pid", "ssl", "web-skip"] {
+        let ignored = format!("{}: ddclient directive not supported; ignored", key);
+        assert!(found.iter().any(|w| w.ends_with(&ignored)), "{:?}", found);
+    }
+}
+
+#[test]
+fn mixed_fixture_warns_per_host() {
+    let (config, found) = ddclient::from_file(&fixture("mixed.conf")).unwrap();
+    assert_eq!("alice", value(&config, KEY::USER));
+    assert_eq!("secret", value(&config, KEY::PASS));
+    assert_eq!(
+        vec![
+            (
+                "www".to_owned(),
+                "example.com".to_owned(),
+                "interface:eth0".to_owned()
+            ),
+            (
+                "static".to_owned(),
+                "example.com".to_owned(),
+                "static:203.0.113.7".to_owned()
+            ),
+        ],
+        records(&config)
+    );
+    let found = warnings(&found);
+    let skipped: Vec<&String> = found.iter().filter(|w| w.contains("skipped")).collect();
+    assert_eq!(3, skipped.len(), "{:?}", found);
+    assert!(skipped[0].contains(":8:1: host: bob.dyndns.org skipped: protocol=dyndns2"));
+    assert!(skipped[1].contains("gw.example.com skipped: use=fw is not supported"));
+    assert!(skipped[2].contains("other.example.com skipped: its login differs"));
+    assert!(found
+        .iter()
+        .any(|w| w.contains("server: ddclient directive")));
+}
+
+#[test]
+fn errors_and_ip_sources() {
+    let path = Path::new("inline.conf");
+    let parse = |text: &str| ddclient::parse(text, path);
+
+    let (_, errors, _) = parse("login='alice\n");
+    assert_eq!(1, errors.len());
+    assert!(errors[0].to_string().contains("unterminated quote"));
+
+    let (config, errors, found) = parse(
+        "protocol=onamae login=u password=p\nuse=cmd, cmd='/usr/bin/myip -4' a.example.com\nuse=web, web=ipify-ipv4 b.example.com\nuse=web, web=https://ip.example.net/ c.example.com\nuse=web, web=checkip d.example.com\nuse=if e.example.com\n",
+    );
+    assert!(errors.is_empty(), "{:?}", errors);
+    let ips: Vec<IpSource> = config
+        .records
+        .iter()
+        .map(|record| record.ip.clone().unwrap().value)
+        .collect();
+    assert_eq!(
+        vec![
+            IpSource::Command {
+                argv: vec!["/usr/bin/myip".to_owned(), "-4".to_owned()],
+                clear_env: false,
+            },
+            IpSource::Url("https://api.ipify.org/".to_owned()),
+            IpSource::Url("https://ip.example.net/".to_owned()),
+        ],
+        ips
+    );
+    let found = warnings(&found);
+    assert!(
+        found[0].contains("built-in web service \"checkip\""),
+        "{:?}",
+        found
+    );
+    assert!(found[1].contains("use=if needs if="), "{:?}", found);
+
+    let (config, _, found) = parse("protocol=onamae login=u password=p example.com\n");
+    assert!(config.records.is_empty());
+    assert!(
+        warnings(&found)[0].contains("no use= directive"),
+        "{:?}",
+        found
+    );
+}
@@ -0,0 +1,132 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const SECRET: &str = "hunter2-Secret";
+
+fn scratch() -> PathBuf {
+    let dir = env::temp_dir().join(format!("ddns_client-diag-{}", std::process::id()));
+    let state = dir.join("state/ddns_client");
+    fs::create_dir_all(&state).unwrap();
+    fs::create_dir_all(dir.join("home")).unwrap();
+    let config = format!(
+        "user = \"alice\"\npass = \"{}\"\ndom = \"example.com\"\nhost = \"www\"\nip = \"203.0.113.7\"\n",
+        SECRET
+    );
+    fs::write(dir.join("config.toml"), config).unwrap();
+    let record = format!(
+        "{{\"version\":1,\"records\":{{\"www.example.com\":{{\"ip\":\"203.0.113.7\"}}}},\"note\":\"{}\"}}",
+        SECRET
+    );
+    fs::write(state.join("state.json"), record).unwrap();
+    let history = format!(
+        "{{\"record\":\"www.example.com\",\"error\":\"LOGIN failed for alice with {}\"}}\n",
+        SECRET
+    );
+    fs::write(state.join("history.jsonl"), history.repeat(3)).unwrap();
+    let trace = format!(
+        "{{\"dir\":\"send\",\"line\":\"PASSWORD:{0}\"}}\n{{\"dir\":\"send\",\"line\":\"X:{0}\"}}\n",
+        SECRET
+    );
+    fs::write(dir.join("trace.jsonl"), trace).unwrap();
+    dir
+}
+
+fn gunzip_stored(data: &[u8]) -> Vec<u8> {
+    assert_eq!(&[0x1f, 0x8b, 8], &data[..3]);
+    let mut at = 10;
+    let mut out = Vec::new();
+    loop {
+        let last = 1 == data[at] & 1;
+        let len = u16::from_le_bytes([data[at + 1], data[at + 2]]) as usize;
+        out.extend_from_slice(&data[at + 5..at + 5 + len]);
+        at += 5 + len;
+        if last {
+            break;
+        }
+    }
+    assert_eq!(at + 8, data.len());
+    out
+}
+
+fn untar(archive: &[u8]) -> Vec<(String, String)> {
+    let field = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| 0 == b).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..end].to_vec()).unwrap()
+    };
+    let mut files = Vec::new();
+    let mut at = 0;
+    while archive[at] != 0 {
+        let header = &archive[at..at + 512];
+        let size = usize::from_str_radix(field(&header[124..136]).trim(), 8).unwrap();
+        let body = &archive[at + 512..at + 512 + size];
+        files.push((
+            field(&header[..100]),
+            String::from_utf8(body.to_vec()).unwrap(),
+        ));
+        at += 512 + size.div_ceil(512) * 512;
+    }
+    files
+}
+
+#[test]
+fn bundle_never_contains_the_password() {
+    let dir = scratch();
+    let output = dir.join("bundle.tar.gz");
+    let run = Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .env_clear()
+        .env("PATH", env::var_os("PATH").unwrap_or_default())
+        .env("HOME", dir.join("home"))
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .current_dir(&dir)
+        .args(["--config", "config.toml", "--resolver", "udp:127.0.0.1"])
+        .args(["--trace-file", "trace.jsonl", "--output", "bundle.tar.gz"])
+        .arg("diag")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(run.stdout).unwrap();
+    assert!(run.status.success(), "{}", stdout);
+    assert!(stdout.contains("ddns_client-diag/state.json"), "{}", stdout);
+
+    let bundle = fs::read(&output).unwrap();
+    let needle = SECRET.as_bytes();
+    assert!(!bundle.windows(needle.len()).any(|window| needle == window));
+    let files = untar(&gunzip_stored(&bundle));
+    let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+    for name in [
+        "version.txt",
+        "paths.txt",
+        "config.toml",
+        "state.json",
+        "history.jsonl",
+        "check.txt",
+        "trace.jsonl",
+    ] {
+        let name = format!("ddns_client-diag/{}", name);
+        assert!(names.contains(&name.as_str()), "{:?}", names);
+    }
+    for (name, text) in &files {
+        assert!(!text.contains(SECRET), "{}: {}", name, text);
+        assert!(!text.contains("hunter2"), "{}: {}", name, text);
+    }
+    let file = |suffix: &str| {
+        &files
+            .iter()
+            .find(|(name, _)| name.ends_with(suffix))
+            .unwrap()
+            .1
+    };
+    assert!(
+        file("config.toml").contains("pass = \"***\""),
+        "{}",
+        file("config.toml")
+    );
+    assert!(file("state.json").contains("\"note\":\"***\""));
+    assert!(file("trace.jsonl").contains("PASSWORD:***"));
+    assert_eq!(3, file("history.jsonl").lines().count());
+    assert!(!file("history.jsonl").contains("alice"));
+    assert!(!file("check.txt").trim().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
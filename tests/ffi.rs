@@ -0,0 +1,180 @@
+#![cfg(all(feature = "ffi", feature = "tls-openssl"))]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslMethod};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+
+use ddns_client::dice;
+
+const OK: &str = "000 COMMAND SUCCESSFUL";
+
+struct Harness {
+    program: PathBuf,
+    cert_file: PathBuf,
+    cert: X509,
+    key: PKey<Private>,
+}
+
+fn certificate() -> (X509, PKey<Private>) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, dice::DOMAIN)
+        .unwrap();
+    let name = name.build();
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    cert.set_serial_number(&serial).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+    cert.append_extension(constraints).unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns(dice::DOMAIN)
+        .build(&cert.x509v3_context(None, None))
+        .unwrap();
+    cert.append_extension(san).unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+    (cert.build(), key)
+}
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap();
+    assert!(status.success(), "{:?}", command);
+}
+
+fn harness() -> &'static Harness {
+    static HARNESS: OnceLock<Harness> = OnceLock::new();
+    HARNESS.get_or_init(|| {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+        run(Command::new(env!("CARGO"))
+            .args(["rustc", "--quiet", "--lib", "--features", "ffi testing"])
+            .args(["--crate-type", "cdylib", "--target-dir"])
+            .arg(&dir)
+            .current_dir(root));
+        let lib = dir.join("debug");
+        let program = dir.join("update");
+        run(Command::new("cc")
+            .arg(root.join("tests/ffi/update.c"))
+            .arg("-I")
+            .arg(root.join("include"))
+            .arg("-L")
+            .arg(&lib)
+            .arg(format!("-Wl,-rpath,{}", lib.display()))
+            .arg("-lddns_client")
+            .arg("-o")
+            .arg(&program));
+        let (cert, key) = certificate();
+        let cert_file = dir.join("registrar.pem");
+        fs::write(&cert_file, cert.to_pem().unwrap()).unwrap();
+        Harness {
+            program,
+            cert_file,
+            cert,
+            key,
+        }
+    })
+}
+
+fn registrar(
+    replies: &'static [&'static str],
+    handshake: bool,
+) -> (SocketAddr, JoinHandle<String>) {
+    let harness = harness();
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_private_key(&harness.key).unwrap();
+    acceptor.set_certificate(&harness.cert).unwrap();
+    let acceptor = acceptor.build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (tcp, _) = listener.accept().unwrap();
+        if !handshake {
+            thread::sleep(Duration::from_secs(3));
+            return String::new();
+        }
+        let mut stream = BufReader::new(acceptor.accept(tcp).unwrap());
+        let mut received = String::new();
+        write!(stream.get_mut(), "{}\n.\n", OK).unwrap();
+        for reply in replies {
+            loop {
+                let mut line = String::new();
+                if 0 == stream.read_line(&mut line).unwrap() {
+                    return received;
+                }
+                received.push_str(&line);
+                if ".\n" == line {
+                    break;
+                }
+            }
+            write!(stream.get_mut(), "{}\n.\n", reply).unwrap();
+        }
+        received
+    });
+    (addr, server)
+}
+
+fn update(addr: SocketAddr, timeout_ms: u32) -> (String, Duration) {
+    let harness = harness();
+    let start = Instant::now();
+    let output = Command::new(&harness.program)
+        .args(["user", "secret", "www", "example.com", "203.0.113.7"])
+        .arg(timeout_ms.to_string())
+        .env("SSL_CERT_FILE", &harness.cert_file)
+        .env("DDNS_CLIENT_TEST_REGISTRAR", addr.to_string())
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    (stdout.trim_end().to_owned(), start.elapsed())
+}
+
+#[test]
+fn update_succeeds() {
+    let (addr, server) = registrar(&[OK, OK, OK], true);
+    let (stdout, _) = update(addr, 0);
+    assert_eq!("0 203.0.113.7", stdout);
+    let received = server.join().unwrap();
+    assert!(received.starts_with("LOGIN\nUSERID:user\nPASSWORD:secret\n.\n"));
+    assert!(received.contains("MODIP\nHOSTNAME:www\nDOMNAME:example.com\nIPV4:203.0.113.7\n.\n"));
+    assert!(received.ends_with("LOGOUT\n.\n"));
+}
+
+#[test]
+fn login_failure_is_reported() {
+    let (addr, server) = registrar(&["002 LOGIN ERROR", OK], true);
+    let (stdout, _) = update(addr, 0);
+    assert!(stdout.starts_with("1 "), "{}", stdout);
+    assert!(server.join().unwrap().ends_with("LOGOUT\n.\n"));
+}
+
+#[test]
+fn timeout_covers_the_handshake() {
+    let (addr, server) = registrar(&[], false);
+    let (stdout, elapsed) = update(addr, 300);
+    assert!(stdout.starts_with("1 "), "{}", stdout);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    server.join().unwrap();
+}
@@ -0,0 +1,33 @@
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "ddns_client.h"
+
+/* usage: update USER PASS HOST DOM IP TIMEOUT_MS; prints the code and the ip or error */
+int main(int argc, char **argv) {
+    if (7 != argc) {
+        fprintf(stderr, "usage: %s USER PASS HOST DOM IP TIMEOUT_MS\n", argv[0]);
+        return 64;
+    }
+    ddns_params params = {
+        .user = argv[1],
+        .pass = argv[2],
+        .host = argv[3],
+        .dom = argv[4],
+        .ip = argv[5],
+        .timeout_ms = (unsigned int)strtoul(argv[6], NULL, 10),
+    };
+    ddns_result result;
+    int code = ddns_update(&params, &result);
+    if (code != result.code) {
+        fprintf(stderr, "return value %d differs from result.code %d\n", code, result.code);
+        return 65;
+    }
+    if (DDNS_OK == code) {
+        printf("%d %s\n", code, result.ip);
+    } else {
+        printf("%d %s\n", code, result.error ? result.error : "(null)");
+        ddns_string_free(result.error);
+    }
+    return 0;
+}
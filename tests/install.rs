@@ -0,0 +1,94 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "ddns_client-install-{}-{}",
+        std::process::id(),
+        name
+    ));
+    fs::create_dir_all(dir.join("units")).unwrap();
+    let config = "user = \"u\"\ndom = \"example.com\"\n\n[[record]]\nhost = \"www\"\n";
+    fs::write(dir.join("config.toml"), config).unwrap();
+    fs::canonicalize(dir).unwrap()
+}
+
+fn install(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .env_clear()
+        .env("HOME", dir)
+        .arg("install")
+        .args(args)
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .args(["--timer", "10m", "--retries", "2", "--check-dns-first"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn normalized(dir: &Path, output: &Output) -> String {
+    assert!(output.status.success(), "{:?}", output);
+    let exe = fs::canonicalize(env!("CARGO_BIN_EXE_ddns_client")).unwrap();
+    String::from_utf8(output.stdout.clone())
+        .unwrap()
+        .replace(&exe.display().to_string(), "$EXE")
+        .replace(&dir.display().to_string(), "$DIR")
+}
+
+fn check_snapshot(name: &str, text: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, text).unwrap();
+    }
+    let snapshot = fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        snapshot, text,
+        "{} changed; rerun with UPDATE_SNAPSHOTS=1 to accept",
+        name
+    );
+}
+
+#[test]
+fn generated_entries_match_snapshots() {
+    let dir = scratch("snapshot");
+    let systemd = install(&dir, &["systemd"]);
+    check_snapshot("install-systemd.txt", &normalized(&dir, &systemd));
+    let cron = install(&dir, &["cron"]);
+    check_snapshot("install-cron.txt", &normalized(&dir, &cron));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn write_refuses_to_replace_files_without_force() {
+    let dir = scratch("write");
+    let units = dir.join("units");
+    let write = format!("--write={}", units.display());
+    let output = install(&dir, &["systemd", &write]);
+    assert!(output.status.success(), "{:?}", output);
+    let service = units.join("ddns_client.service");
+    let timer = fs::read_to_string(units.join("ddns_client.timer")).unwrap();
+    assert!(timer.contains("OnUnitActiveSec=600s"), "{}", timer);
+    fs::write(&service, "edited\n").unwrap();
+
+    let output = install(&dir, &["systemd", &write]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("already exists (use --force to replace it)"),
+        "{}",
+        stderr
+    );
+    assert_eq!("edited\n", fs::read_to_string(&service).unwrap());
+
+    let output = install(&dir, &["systemd", &write, "--force"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(fs::read_to_string(&service)
+        .unwrap()
+        .contains("DynamicUser=yes"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,77 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::SystemTime;
+
+fn scratch() -> PathBuf {
+    let dir = env::temp_dir().join(format!("ddns_client-no-state-{}", std::process::id()));
+    for sub in &["home", "config", "state", "run", "cwd"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    let config = "user = \"u\"\npass = \"p\"\ndom = \"example.com\"\nip = \"1.2.3.4\"\n\n[[record]]\nhost = \"a\"\n";
+    fs::write(dir.join("config/config.toml"), config).unwrap();
+    dir
+}
+
+fn snapshot(dir: &Path, out: &mut Vec<(PathBuf, u64, SystemTime)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let entry = entry.unwrap();
+        let meta = entry.metadata().unwrap();
+        out.push((entry.path(), meta.len(), meta.modified().unwrap()));
+        if meta.is_dir() {
+            snapshot(&entry.path(), out);
+        }
+    }
+    out.sort();
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .env_clear()
+        .env("PATH", env::var_os("PATH").unwrap_or_default())
+        .env("HOME", dir.join("home"))
+        .env("XDG_CONFIG_HOME", dir.join("home"))
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("XDG_RUNTIME_DIR", dir.join("run"))
+        .current_dir(dir.join("cwd"))
+        .arg("--config")
+        .arg(dir.join("config/config.toml"))
+        .arg("--no-state")
+        .arg("--resolver")
+        .arg("udp:127.0.0.1")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn no_state_writes_nothing() {
+    let dir = scratch();
+    let mut before = Vec::new();
+    snapshot(&dir, &mut before);
+    let flows: [&[&str]; 5] = [
+        &[],
+        &["--dry-run"],
+        &["--quiet-unchanged"],
+        &["--check-dns-first"],
+        &["--only", "nothing.example.com"],
+    ];
+    let mut outputs = Vec::new();
+    for args in &flows {
+        outputs.push((args, run(&dir, args)));
+    }
+    let mut after = Vec::new();
+    snapshot(&dir, &mut after);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(before, after);
+    for (args, output) in &outputs {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("panicked"), "{:?}: {}", args, stderr);
+        assert!(!stderr.contains("waiting"), "{:?}: {}", args, stderr);
+    }
+    let (_, dry_run) = &outputs[1];
+    assert_eq!(Some(0), dry_run.status.code());
+    assert!(String::from_utf8_lossy(&dry_run.stdout).contains("MODIP"));
+}
@@ -0,0 +1,110 @@
+#![cfg(target_os = "linux")]
+
+use std::env;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn scratch(name: &str, pass: bool) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "ddns_client-pass-fd-{}-{}",
+        std::process::id(),
+        name
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    let mut config = "user = \"u\"\ndom = \"example.com\"\nip = \"1.2.3.4\"\n".to_owned();
+    if pass {
+        config.push_str("pass = \"p\"\n");
+    }
+    fs::write(dir.join("config.toml"), config).unwrap();
+    dir
+}
+
+fn run(dir: &Path, fd: &str, secret: Option<&'static [u8]>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ddns_client"));
+    command
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .args([
+            "--no-state",
+            "--print-config",
+            "--output",
+            "json",
+            "--pass-fd",
+            fd,
+        ])
+        .stdin(Stdio::null());
+    if let Some(secret) = secret {
+        let mut fds = [0; 2];
+        assert_eq!(0, unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) });
+        let written = unsafe { libc::write(fds[1], secret.as_ptr().cast(), secret.len()) };
+        assert_eq!(secret.len() as isize, written);
+        unsafe { libc::close(fds[1]) };
+        let read = fds[0];
+        unsafe {
+            command.pre_exec(move || {
+                let status = match read {
+                    3 => libc::fcntl(3, libc::F_SETFD, 0),
+                    _ => libc::dup2(read, 3),
+                };
+                match status {
+                    -1 => Err(std::io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            });
+        }
+        let output = command.output().unwrap();
+        unsafe { libc::close(read) };
+        return output;
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn password_is_read_from_an_inherited_descriptor() {
+    let dir = scratch("read", false);
+    let output = run(&dir, "3", Some(b"hunter2\n"));
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(Some(0), output.status.code(), "{:?}", output);
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!("fd:3", config["pass"]["source"]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("hunter2"));
+}
+
+#[test]
+fn standard_streams_are_rejected() {
+    let dir = scratch("standard", false);
+    for fd in &["0", "1", "2"] {
+        let output = run(&dir, fd, None);
+        assert_ne!(Some(0), output.status.code(), "{}", fd);
+        assert!(output.stdout.is_empty(), "{}", fd);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("--pass-fd") || stderr.contains("standard stream"),
+            "{}",
+            stderr
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn closed_descriptor_is_an_error() {
+    let dir = scratch("closed", false);
+    let output = run(&dir, "9", None);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_ne!(Some(0), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("file descriptor 9"), "{}", stderr);
+}
+
+#[test]
+fn other_password_sources_conflict() {
+    let dir = scratch("conflict", true);
+    let output = run(&dir, "3", Some(b"hunter2\n"));
+    fs::remove_dir_all(&dir).unwrap();
+    assert_ne!(Some(0), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--pass-fd"), "{}", stderr);
+}
@@ -0,0 +1,221 @@
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use ddns_client::dice::{
+    Client, ClientError, Command, CommandModipExt, GreetingPolicy, Information, NotFoundScope,
+};
+use ddns_client::protocol::{Event, ProtocolMachine};
+use ddns_client::report::Report;
+
+const EXPECTED: [(&str, &str); 17] = [
+    ("command-error", "command_error"),
+    ("db-error", "db_error"),
+    ("greeting", "ok"),
+    ("ip-address-error", "ip_address_error"),
+    ("login-error", "login_error"),
+    ("modip", "ok"),
+    ("modip-ack-mismatch", "server_mismatch"),
+    ("modip-ack-repeated", "ok"),
+    ("modip-ack-unrelated", "ok"),
+    ("modip-apex-batch", "ok"),
+    ("modip-crlf", "ok"),
+    ("modip-multiline", "ok"),
+    ("no-connection", "no_connection"),
+    ("not-found", "not_found"),
+    ("not-found-domain", "not_found"),
+    ("not-found-host", "not_found"),
+    ("unexpected-code", "unexpected_response"),
+];
+
+struct Replay {
+    server: Cursor<Vec<u8>>,
+    sent: Vec<u8>,
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.server.read(buf)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sent.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn load(name: &str) -> (String, Vec<u8>) {
+    let dir = fixtures();
+    let client = fs::read_to_string(dir.join(format!("{}.client", name))).unwrap();
+    let server = fs::read(dir.join(format!("{}.server", name))).unwrap();
+    (client, server)
+}
+
+fn commands(client: &str) -> Vec<Vec<&str>> {
+    let mut commands = vec![Vec::new()];
+    for line in client.lines() {
+        if "." == line {
+            commands.push(Vec::new());
+        } else {
+            commands.last_mut().unwrap().push(line);
+        }
+    }
+    commands.pop();
+    commands
+}
+
+fn field<'a>(command: &[&'a str], name: &str) -> &'a str {
+    command
+        .iter()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .unwrap_or_default()
+}
+
+fn session(name: &str) -> (Result<(), ClientError>, Vec<u8>) {
+    let (client, server) = load(name);
+    let commands = commands(&client);
+    let login = commands.iter().find(|cmd| Some(&"LOGIN") == cmd.first());
+    let user = login.map_or("", |cmd| field(cmd, "USERID"));
+    let pass = login.map_or("", |cmd| field(cmd, "PASSWORD"));
+    let records: Vec<Information> = commands
+        .iter()
+        .filter(|cmd| Some(&"MODIP") == cmd.first())
+        .map(|cmd| {
+            let mut info = Information::try_new(
+                user,
+                pass,
+                field(cmd, "HOSTNAME"),
+                field(cmd, "DOMNAME"),
+                field(cmd, "IPV4"),
+            )
+            .unwrap();
+            info.extra = cmd[1..]
+                .iter()
+                .filter(|line| {
+                    !["HOSTNAME:", "DOMNAME:", "IPV4:"]
+                        .iter()
+                        .any(|prefix| line.starts_with(prefix))
+                })
+                .map(|line| line.to_string())
+                .collect();
+            info
+        })
+        .collect();
+    let mut client = Client::new(Replay {
+        server: Cursor::new(server),
+        sent: Vec::new(),
+    });
+    let result = client
+        .read_greeting(GreetingPolicy::Required)
+        .and_then(|()| {
+            if !records.is_empty() {
+                let outcomes = client.run_modip_batch(&records)?;
+                records
+                    .iter()
+                    .zip(&outcomes)
+                    .find_map(|(info, outcome)| outcome.mismatch(info))
+                    .map_or(Ok(()), Err)
+            } else if login.is_some() {
+                client.run_auth_check(user, pass)
+            } else {
+                Ok(())
+            }
+        });
+    (result, client.get_ref().sent.clone())
+}
+
+fn replay(name: &str) -> (String, Vec<u8>) {
+    let (result, sent) = session(name);
+    let kind = match result {
+        Ok(()) => "ok".to_owned(),
+        Err(e) => e.kind().to_owned(),
+    };
+    (kind, sent)
+}
+
+#[test]
+fn every_fixture_is_listed() {
+    let mut names: Vec<String> = fs::read_dir(fixtures())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| Some("server".as_ref()) == path.extension())
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    let listed: Vec<&str> = EXPECTED.iter().map(|(name, _)| *name).collect();
+    assert_eq!(listed, names);
+}
+
+#[test]
+fn client_replays_fixtures() {
+    for (name, expected) in &EXPECTED {
+        let (kind, sent) = replay(name);
+        assert_eq!(*expected, kind, "{}", name);
+        let (client, _) = load(name);
+        assert_eq!(client, String::from_utf8(sent).unwrap(), "{}", name);
+    }
+}
+
+#[test]
+fn machine_replays_fixtures_in_small_chunks() {
+    for (name, _) in &EXPECTED {
+        let (client, server) = load(name);
+        let expected = 1 + commands(&client).len();
+        let mut machine = ProtocolMachine::new();
+        let mut events = Vec::new();
+        for chunk in server.chunks(3) {
+            machine.receive(chunk);
+            loop {
+                match machine.poll().unwrap() {
+                    Event::NeedMoreData => break,
+                    event => {
+                        events.push(event);
+                        machine.send(&["NOOP"], Default::default()).unwrap();
+                    }
+                }
+            }
+        }
+        machine.finish();
+        assert_eq!(Event::NeedMoreData, machine.poll().unwrap(), "{}", name);
+        assert_eq!(expected, events.len(), "{}", name);
+        assert!(matches!(events[0], Event::GreetingReceived(_)), "{}", name);
+        assert!(
+            events[1..]
+                .iter()
+                .all(|event| matches!(event, Event::ResponseReceived(_))),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn not_found_fixtures_carry_the_scope() {
+    for (name, scope) in &[
+        ("not-found", NotFoundScope::Unknown),
+        ("not-found-domain", NotFoundScope::Domain),
+        ("not-found-host", NotFoundScope::Host),
+    ] {
+        let e = session(name).0.unwrap_err();
+        assert_eq!(Some(*scope), e.not_found_scope(), "{}", name);
+        let json = e.to_json(1);
+        assert_eq!(
+            scope.to_string(),
+            json["error"]["detail"]["scope"],
+            "{}",
+            name
+        );
+    }
+    let e = session("login-error").0.unwrap_err();
+    assert_eq!(None, e.not_found_scope());
+    assert!(e.to_json(1)["error"]["detail"].get("scope").is_none());
+}
@@ -0,0 +1,73 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ddns_client-quiet-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    let config = "user = \"u\"\npass = \"p\"\ndom = \"example.com\"\nip = \"1.2.3.4\"\n\n[[record]]\nhost = \"a\"\n";
+    fs::write(dir.join("config.toml"), config).unwrap();
+    let state = serde_json::json!({
+        "version": 1,
+        "records": { "a.example.com": { "ip": "1.2.3.4", "last_success": 1_800_000_000u64 } },
+    });
+    fs::write(dir.join("state.json"), state.to_string()).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .arg("--state-file")
+        .arg(dir.join("state.json"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn unchanged_run_is_silent() {
+    let dir = scratch("silent");
+    let output = run(&dir, &["--quiet-unchanged"]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("", String::from_utf8_lossy(&output.stdout));
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn unchanged_run_reports_without_quiet() {
+    let dir = scratch("loud");
+    let output = run(&dir, &[]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("a.example.com"), "{}", stdout);
+}
+
+#[test]
+fn failures_are_replayed() {
+    let dir = scratch("failure");
+    let output = run(
+        &dir,
+        &["--quiet-unchanged", "--only", "nothing.example.com"],
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    assert_ne!(Some(0), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("nothing.example.com"), "{}", stderr);
+}
+
+#[test]
+fn dry_run_is_replayed() {
+    let dir = scratch("dry-run");
+    fs::remove_file(dir.join("state.json")).unwrap();
+    let output = run(&dir, &["--quiet-unchanged", "--dry-run"]);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("MODIP"), "{}", stdout);
+}
@@ -0,0 +1,135 @@
+#![cfg(unix)]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ddns_client::status::{self, Status};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "ddns_client-run-lock-{}-{}",
+        std::process::id(),
+        name
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    let config = "user = \"u\"\npass = \"p\"\ndom = \"example.com\"\n\
+                  ip_source = { cmd = [\"sh\", \"-c\", \"sleep 2; echo 1.2.3.4\"] }\n\n\
+                  [[record]]\nhost = \"a\"\n";
+    fs::write(dir.join("config.toml"), config).unwrap();
+    dir
+}
+
+fn spawn(dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .arg("--state-file")
+        .arg(dir.join("state.json"))
+        .arg("--resolver")
+        .arg("udp:127.0.0.1")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn finish(run: Child) -> (Option<i32>, String) {
+    let output = run.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    (output.status.code(), stderr)
+}
+
+fn claim_daemon(dir: &Path, names: Vec<&str>) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let status = Status {
+        last_cycle: now,
+        interval: Some(60),
+        config_hash: Some(status::config_hash(names)),
+        ..Status::default()
+    };
+    status.save(&dir.join("status.json")).unwrap();
+}
+
+fn while_held(dir: &Path, args: &[&str]) -> (Duration, Option<i32>, String) {
+    let holder = spawn(dir, &[]);
+    thread::sleep(Duration::from_millis(500));
+    let started = Instant::now();
+    let (code, stderr) = finish(spawn(dir, args));
+    let elapsed = started.elapsed();
+    finish(holder);
+    fs::remove_dir_all(dir).unwrap();
+    (elapsed, code, stderr)
+}
+
+#[test]
+fn concurrent_stateful_runs_are_serialized() {
+    let dir = scratch("serial");
+    let runs = vec![spawn(&dir, &[]), spawn(&dir, &[])];
+    let stderr: Vec<String> = runs.into_iter().map(|run| finish(run).1).collect();
+    let lock = dir.join("lock").exists();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(lock);
+    let waited = stderr
+        .iter()
+        .filter(|stderr| stderr.contains("waiting up to"))
+        .count();
+    assert_eq!(1, waited, "{:?}", stderr);
+    for stderr in &stderr {
+        assert!(!stderr.contains("run lock"), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+}
+
+#[test]
+fn same_daemon_config_exits_quietly() {
+    let dir = scratch("same");
+    claim_daemon(&dir, vec!["u@a.example.com"]);
+    let (elapsed, code, stderr) = while_held(&dir, &[]);
+    assert_eq!(Some(0), code);
+    assert_eq!("daemon already managing these records\n", stderr);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[test]
+fn different_daemon_config_is_an_error() {
+    let dir = scratch("different");
+    claim_daemon(&dir, vec!["u@b.example.com"]);
+    let (elapsed, code, stderr) = while_held(&dir, &[]);
+    assert_eq!(Some(1), code);
+    assert!(stderr.contains("already running"), "{}", stderr);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+}
+
+#[test]
+fn force_waits_for_the_daemon() {
+    let dir = scratch("force");
+    claim_daemon(&dir, vec!["u@a.example.com"]);
+    let (_, _, stderr) = while_held(&dir, &["--force"]);
+    assert!(stderr.contains("waiting up to"), "{}", stderr);
+    assert!(!stderr.contains("daemon already"), "{}", stderr);
+}
+
+#[test]
+fn stale_daemon_claim_runs_normally() {
+    let dir = scratch("stale");
+    claim_daemon(&dir, vec!["u@a.example.com"]);
+    let (_, stderr) = finish(spawn(&dir, &[]));
+    let status = Status::load(&dir.join("status.json")).unwrap().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(!stderr.contains("daemon already"), "{}", stderr);
+    assert!(!stderr.contains("waiting up to"), "{}", stderr);
+    assert_eq!(None, status.interval);
+    assert_eq!(
+        Some(status::config_hash(vec!["u@a.example.com"])),
+        status.config_hash
+    );
+}
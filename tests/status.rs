@@ -0,0 +1,136 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "ddns_client-status-{}-{}",
+        std::process::id(),
+        name
+    ));
+    fs::create_dir_all(dir.join("home")).unwrap();
+    let config = format!(
+        "user = \"u\"\npass = \"p\"\ndom = \"example.com\"\nhost = \"a\"\nip = \"1.2.3.4\"\nstate_file = {:?}\nstatus_file = {:?}\n",
+        dir.join("state.json"),
+        dir.join("status.json")
+    );
+    fs::write(dir.join("config.toml"), config).unwrap();
+    dir
+}
+
+fn status(dir: &Path) -> (Output, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+        .env_clear()
+        .env("PATH", env::var_os("PATH").unwrap_or_default())
+        .env("HOME", dir.join("home"))
+        .arg("--config")
+        .arg(dir.join("config.toml"))
+        .arg("status")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+    (output, stdout)
+}
+
+fn write_status(dir: &Path, age: u64, failures: u32) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let status = format!(
+        "{{\"version\":1,\"last_cycle\":{},\"interval\":60,\"ip\":\"1.2.3.4\",\"failures\":{},\"records\":{{\"a.example.com\":{{\"last_success\":{}}}}}}}",
+        now - age,
+        failures,
+        now - age
+    );
+    fs::write(dir.join("status.json"), status).unwrap();
+}
+
+#[test]
+fn status_reports_fresh_failing_and_stalled_daemons() {
+    let dir = scratch("health");
+
+    let (output, stdout) = status(&dir);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("status.json"), "{}", stdout);
+
+    write_status(&dir, 10, 0);
+    let (output, stdout) = status(&dir);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.starts_with("ok (last run "), "{}", stdout);
+    assert!(stdout.contains("address: 1.2.3.4"), "{}", stdout);
+    assert!(stdout.contains("a.example.com"), "{}", stdout);
+
+    write_status(&dir, 10, 3);
+    let (output, stdout) = status(&dir);
+    assert!(!output.status.success());
+    assert!(
+        stdout.starts_with("failing (3 consecutive failed runs)"),
+        "{}",
+        stdout
+    );
+
+    write_status(&dir, 121, 0);
+    let (output, stdout) = status(&dir);
+    assert!(!output.status.success());
+    assert!(
+        stdout.starts_with("daemon appears stalled (last run 12"),
+        "{}",
+        stdout
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn status_shows_when_the_breaker_reopens() {
+    let dir = scratch("breaker");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let state = format!(
+        "{{\"auth\":{{\"failures\":3,\"open_until\":{}}}}}",
+        now + 3 * 3600 + 300
+    );
+    fs::write(dir.join("state.json"), state).unwrap();
+
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ddns_client"))
+            .env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or_default())
+            .env("HOME", dir.join("home"))
+            .env("TZ", "IST-5:30")
+            .arg("--config")
+            .arg(dir.join("config.toml"))
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let stdout = run(&["status"]);
+    assert!(
+        stdout.contains("auth circuit breaker: open after 3 login failures, retry in 3h "),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("+05:30)"), "{}", stdout);
+
+    let json: serde_json::Value =
+        serde_json::from_str(&run(&["--output", "json", "status"])).unwrap();
+    let breaker = &json["auth_breaker"];
+    assert_eq!(true, breaker["open"]);
+    let left = breaker["retry_after_seconds"].as_u64().unwrap();
+    assert!((3 * 3600..=3 * 3600 + 300).contains(&left), "{}", left);
+    assert!(breaker["retry_at"].as_str().unwrap().ends_with("+05:30"));
+
+    write_status(&dir, 10, 0);
+    let stdout = run(&["status"]);
+    assert!(stdout.contains("auth circuit breaker: open"), "{}", stdout);
+
+    fs::remove_dir_all(&dir).unwrap();
+}